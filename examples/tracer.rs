@@ -1,4 +1,3 @@
-use std::collections::HashSet;
 use std::env;
 use std::fs;
 use std::path::{Path, PathBuf};
@@ -6,31 +5,22 @@ use std::path::{Path, PathBuf};
 use argh::{self, FromArgs};
 use covidcotra::*;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
-use serde_json;
 
 #[derive(Serialize, Deserialize)]
 pub struct AuthorityDb {
     authority: Authority,
-    infected: HashSet<HashedIdentity>,
-    tainted: HashSet<HashedIdentity>,
+    registry: Registry,
 }
 
 impl Default for AuthorityDb {
     fn default() -> AuthorityDb {
         AuthorityDb {
             authority: Authority::unique(),
-            infected: HashSet::new(),
-            tainted: HashSet::new(),
+            registry: Registry::new(),
         }
     }
 }
 
-#[derive(Serialize, Deserialize, Default)]
-pub struct Me {
-    identities: Vec<Identity>,
-    contacts: ContactLog,
-}
-
 pub fn load<P: AsRef<Path>, D: DeserializeOwned + Default>(p: P) -> D {
     if fs::metadata(p.as_ref()).is_err() {
         D::default()
@@ -39,6 +29,49 @@ pub fn load<P: AsRef<Path>, D: DeserializeOwned + Default>(p: P) -> D {
     }
 }
 
+pub fn load_required<P: AsRef<Path>, D: DeserializeOwned>(p: P) -> D {
+    serde_json::from_slice(&fs::read(p.as_ref()).unwrap()).unwrap()
+}
+
+/// Picks the authority key from a key on the command line or a descriptor
+/// verified against a pinned root key.
+fn authority_public_key(
+    public_key: Option<String>,
+    descriptor_path: Option<PathBuf>,
+    root_key: Option<String>,
+) -> PublicKey {
+    if let Some(public_key) = public_key {
+        return public_key.parse().unwrap();
+    }
+    let descriptor_path = descriptor_path.expect("either --public-key or --descriptor is required");
+    let root: SigningPublicKey = root_key
+        .expect("--root-key is required with --descriptor")
+        .parse()
+        .unwrap();
+    let signed: SignedDescriptor = load_required(&descriptor_path);
+    let now = chrono::Utc::now();
+    *signed.verify(&root, now).unwrap().public_key_at(now)
+}
+
+fn wallet_key() -> WalletKey {
+    WalletKey::from_passphrase(
+        env::var("TRACER_PASSPHRASE").expect("TRACER_PASSPHRASE must be set to unlock the wallet"),
+    )
+}
+
+pub fn load_wallet<P: AsRef<Path>>(p: P) -> Wallet {
+    if fs::metadata(p.as_ref()).is_err() {
+        Wallet::new()
+    } else {
+        Wallet::load(p, &wallet_key()).unwrap()
+    }
+}
+
+pub fn save_wallet<P: AsRef<Path>>(p: P, wallet: &Wallet) {
+    wallet.save(p.as_ref(), &wallet_key()).unwrap();
+    eprintln!("Written to {}", p.as_ref().display());
+}
+
 pub fn save<P: AsRef<Path>, S: Serialize>(p: P, obj: &S) {
     let mut vec = serde_json::to_string_pretty(obj).unwrap();
     vec.push('\n');
@@ -47,6 +80,9 @@ pub fn save<P: AsRef<Path>, S: Serialize>(p: P, obj: &S) {
 }
 
 /// Example app for covidcotra
+///
+/// The identity wallet is encrypted with the passphrase in the
+/// TRACER_PASSPHRASE environment variable.
 #[derive(FromArgs, Debug)]
 struct Cli {
     #[argh(subcommand)]
@@ -57,11 +93,18 @@ struct Cli {
 #[argh(subcommand)]
 pub enum Command {
     CreateAuthority(CreateAuthorityCommand),
+    CreateRoot(CreateRootCommand),
+    SignDescriptor(SignDescriptorCommand),
     CheckStatus(CheckStatusCommand),
     ImportInfected(ImportInfectedCommand),
+    ImportExposed(ImportExposedCommand),
+    RevokeUpload(RevokeUploadCommand),
+    Statistics(StatisticsCommand),
     NewIdentity(NewIdentityCommand),
     NewShareIdentity(NewShareIdentityCommand),
     AddContact(AddContactCommand),
+    CreateUpload(CreateUploadCommand),
+    IssueCode(IssueCodeCommand),
 }
 
 /// Creates a new authority.
@@ -74,6 +117,54 @@ pub struct CreateAuthorityCommand {
         default = "env::current_dir().unwrap().join(\"authority.json\")"
     )]
     path: PathBuf,
+    /// maximum degree of exposure to record.
+    #[argh(option)]
+    max_depth: Option<u8>,
+    /// risk decay per degree of exposure.
+    #[argh(option)]
+    decay: Option<f64>,
+}
+
+/// Creates a new root key for signing authority descriptors.
+#[derive(FromArgs, Debug)]
+#[argh(subcommand, name = "create-root")]
+pub struct CreateRootCommand {
+    /// path to root key file.
+    #[argh(option, default = "env::current_dir().unwrap().join(\"root.json\")")]
+    path: PathBuf,
+}
+
+/// Signs a descriptor of the authority with the root key.
+#[derive(FromArgs, Debug)]
+#[argh(subcommand, name = "sign-descriptor")]
+pub struct SignDescriptorCommand {
+    /// path to authority file.
+    #[argh(
+        option,
+        default = "env::current_dir().unwrap().join(\"authority.json\")"
+    )]
+    authority_path: PathBuf,
+    /// path to root key file.
+    #[argh(option, default = "env::current_dir().unwrap().join(\"root.json\")")]
+    root_path: PathBuf,
+    /// path to the signed descriptor.
+    #[argh(
+        option,
+        default = "env::current_dir().unwrap().join(\"descriptor.json\")"
+    )]
+    descriptor_path: PathBuf,
+    /// the name of the authority.
+    #[argh(option)]
+    name: String,
+    /// the region of the authority.
+    #[argh(option)]
+    region: String,
+    /// a URL of a server of the authority.
+    #[argh(option)]
+    endpoint: Vec<String>,
+    /// number of days the descriptor stays valid.
+    #[argh(option, default = "30")]
+    valid_days: i64,
 }
 
 /// Adds contacts as taints
@@ -86,9 +177,84 @@ pub struct ImportInfectedCommand {
         default = "env::current_dir().unwrap().join(\"authority.json\")"
     )]
     authority_path: PathBuf,
-    /// path to the identity file.
-    #[argh(option, default = "env::current_dir().unwrap().join(\"me.json\")")]
-    identity_path: PathBuf,
+    /// path to the upload bundle.
+    #[argh(option, default = "env::current_dir().unwrap().join(\"upload.json\")")]
+    upload_path: PathBuf,
+    /// the authorization code for the upload.
+    #[argh(option)]
+    code: String,
+}
+
+/// Issues an authorization code for an upload.
+#[derive(FromArgs, Debug)]
+#[argh(subcommand, name = "issue-code")]
+pub struct IssueCodeCommand {
+    /// path to authority file.
+    #[argh(
+        option,
+        default = "env::current_dir().unwrap().join(\"authority.json\")"
+    )]
+    authority_path: PathBuf,
+    /// number of hours the code stays valid.
+    #[argh(option, default = "24")]
+    valid_hours: i64,
+}
+
+/// Adds contacts of an exposed user as indirect exposures
+#[derive(FromArgs, Debug)]
+#[argh(subcommand, name = "import-exposed")]
+pub struct ImportExposedCommand {
+    /// path to authority file.
+    #[argh(
+        option,
+        default = "env::current_dir().unwrap().join(\"authority.json\")"
+    )]
+    authority_path: PathBuf,
+    /// path to the upload bundle.
+    #[argh(option, default = "env::current_dir().unwrap().join(\"upload.json\")")]
+    upload_path: PathBuf,
+}
+
+/// Revokes a previously imported upload
+#[derive(FromArgs, Debug)]
+#[argh(subcommand, name = "revoke-upload")]
+pub struct RevokeUploadCommand {
+    /// path to authority file.
+    #[argh(
+        option,
+        default = "env::current_dir().unwrap().join(\"authority.json\")"
+    )]
+    authority_path: PathBuf,
+    /// the ID printed when the upload was imported.
+    #[argh(option)]
+    upload_id: String,
+}
+
+/// Exports aggregated statistics of the registry.
+#[derive(FromArgs, Debug)]
+#[argh(subcommand, name = "statistics")]
+pub struct StatisticsCommand {
+    /// path to authority file.
+    #[argh(
+        option,
+        default = "env::current_dir().unwrap().join(\"authority.json\")"
+    )]
+    authority_path: PathBuf,
+    /// number of days to report daily counts for.
+    #[argh(option, default = "14")]
+    days: i64,
+    /// add noise for this privacy parameter.
+    #[argh(option)]
+    epsilon: Option<f64>,
+    /// use gaussian instead of laplace noise with this delta.
+    #[argh(option)]
+    delta: Option<f64>,
+    /// the number of infected and exposed identities counted per upload.
+    #[argh(option, default = "50")]
+    max_per_upload: u64,
+    /// write the daily counts as CSV instead of JSON.
+    #[argh(switch)]
+    csv: bool,
 }
 
 /// Creates a new identity.
@@ -96,7 +262,7 @@ pub struct ImportInfectedCommand {
 #[argh(subcommand, name = "new-identity")]
 pub struct NewIdentityCommand {
     /// path to identity file.
-    #[argh(option, default = "env::current_dir().unwrap().join(\"me.json\")")]
+    #[argh(option, default = "env::current_dir().unwrap().join(\"me.wallet\")")]
     path: PathBuf,
 }
 
@@ -111,7 +277,7 @@ pub struct CheckStatusCommand {
     )]
     authority_path: PathBuf,
     /// path to identity file.
-    #[argh(option, default = "env::current_dir().unwrap().join(\"me.json\")")]
+    #[argh(option, default = "env::current_dir().unwrap().join(\"me.wallet\")")]
     path: PathBuf,
 }
 
@@ -120,88 +286,187 @@ pub struct CheckStatusCommand {
 #[argh(subcommand, name = "new-share-identity")]
 pub struct NewShareIdentityCommand {
     /// path to identity file.
-    #[argh(option, default = "env::current_dir().unwrap().join(\"me.json\")")]
+    #[argh(option, default = "env::current_dir().unwrap().join(\"me.wallet\")")]
     path: PathBuf,
     /// the authority public key.
     #[argh(option)]
-    public_key: String,
+    public_key: Option<String>,
+    /// path to a signed descriptor to take the authority key from.
+    #[argh(option)]
+    descriptor: Option<PathBuf>,
+    /// the pinned root key the descriptor is verified against.
+    #[argh(option)]
+    root_key: Option<String>,
 }
 
 /// Adds a contact.
 #[derive(FromArgs, Debug)]
 #[argh(subcommand, name = "add-contact")]
 pub struct AddContactCommand {
-    /// path to identity file.
-    #[argh(option, default = "env::current_dir().unwrap().join(\"me.json\")")]
-    path: PathBuf,
+    /// path to the contact log.
+    #[argh(option, default = "env::current_dir().unwrap().join(\"me.contacts\")")]
+    contacts_path: PathBuf,
     /// the identity to add
     #[argh(option)]
     share_id: String,
 }
 
+/// Creates an upload bundle after a positive test.
+#[derive(FromArgs, Debug)]
+#[argh(subcommand, name = "create-upload")]
+pub struct CreateUploadCommand {
+    /// path to identity file.
+    #[argh(option, default = "env::current_dir().unwrap().join(\"me.wallet\")")]
+    path: PathBuf,
+    /// path to the contact log.
+    #[argh(option, default = "env::current_dir().unwrap().join(\"me.contacts\")")]
+    contacts_path: PathBuf,
+    /// path to the upload bundle.
+    #[argh(option, default = "env::current_dir().unwrap().join(\"upload.json\")")]
+    upload_path: PathBuf,
+    /// the authority public key.
+    #[argh(option)]
+    public_key: Option<String>,
+    /// path to a signed descriptor to take the authority key from.
+    #[argh(option)]
+    descriptor: Option<PathBuf>,
+    /// the pinned root key the descriptor is verified against.
+    #[argh(option)]
+    root_key: Option<String>,
+}
+
 fn main() {
     let cli: Cli = argh::from_env();
     match cli.cmd {
         Command::CreateAuthority(subcmd) => {
-            let db: AuthorityDb = load(&subcmd.path);
+            let mut db: AuthorityDb = load(&subcmd.path);
+            let mut config = *db.registry.propagation();
+            if let Some(max_depth) = subcmd.max_depth {
+                config.max_depth = max_depth;
+            }
+            if let Some(decay) = subcmd.decay {
+                config.decay = decay;
+            }
+            db.registry.set_propagation(config);
             save(&subcmd.path, &db);
             println!("Public Key: {}", db.authority.public_key());
         }
+        Command::CreateRoot(subcmd) => {
+            let root = RootKey::unique();
+            save(&subcmd.path, &root);
+            println!("Root Key: {}", root.public_key());
+        }
+        Command::SignDescriptor(subcmd) => {
+            let db: AuthorityDb = load(&subcmd.authority_path);
+            let root: RootKey = load_required(&subcmd.root_path);
+            let mut descriptor = AuthorityDescriptor::new(
+                &subcmd.name,
+                &subcmd.region,
+                &db.authority,
+                chrono::Duration::days(subcmd.valid_days),
+            );
+            descriptor.endpoints = subcmd.endpoint;
+            save(&subcmd.descriptor_path, &root.sign(&descriptor));
+        }
         Command::ImportInfected(subcmd) => {
             let mut db: AuthorityDb = load(&subcmd.authority_path);
-            let user: Me = load(&subcmd.identity_path);
-            for identity in user.identities {
-                db.infected.insert(identity.hashed_id().clone());
-            }
-            for (contact, _) in user.contacts.decode(db.authority.secret_key()).unwrap() {
-                db.tainted.insert(contact.hash());
-            }
+            let sealed: SealedUploadBundle = load_required(&subcmd.upload_path);
+            let code: AuthorizationCode = subcmd.code.parse().unwrap();
+            let upload_id = db.registry.submit(&db.authority, &code, &sealed).unwrap();
+            save(&subcmd.authority_path, &db);
+            println!("Upload ID: {}", upload_id);
+        }
+        Command::ImportExposed(subcmd) => {
+            let mut db: AuthorityDb = load(&subcmd.authority_path);
+            let sealed: SealedUploadBundle = load_required(&subcmd.upload_path);
+            let upload_id = db.registry.submit_exposed(&db.authority, &sealed).unwrap();
+            save(&subcmd.authority_path, &db);
+            println!("Upload ID: {}", upload_id);
+        }
+        Command::RevokeUpload(subcmd) => {
+            let mut db: AuthorityDb = load(&subcmd.authority_path);
+            let upload_id: UploadId = subcmd.upload_id.parse().unwrap();
+            db.registry.revoke(&upload_id).unwrap();
             save(&subcmd.authority_path, &db);
-            println!("{}", db.authority.public_key());
+        }
+        Command::Statistics(subcmd) => {
+            let db: AuthorityDb = load(&subcmd.authority_path);
+            let noise = match (subcmd.epsilon, subcmd.delta) {
+                (None, _) => NoiseMechanism::None,
+                (Some(epsilon), None) => NoiseMechanism::Laplace { epsilon },
+                (Some(epsilon), Some(delta)) => NoiseMechanism::Gaussian { epsilon, delta },
+            };
+            let last_day = chrono::Utc::now().date_naive();
+            let first_day = last_day - chrono::Duration::days(subcmd.days.max(1) - 1);
+            let stats = db
+                .registry
+                .statistics(first_day, last_day, noise, subcmd.max_per_upload)
+                .unwrap();
+            if subcmd.csv {
+                print!("{}", stats.to_csv());
+            } else {
+                println!("{}", serde_json::to_string_pretty(&stats).unwrap());
+            }
         }
         Command::NewIdentity(subcmd) => {
-            let mut me: Me = load(&subcmd.path);
-            me.identities.push(Identity::unique());
-            save(&subcmd.path, &me);
+            let mut me = load_wallet(&subcmd.path);
+            me.add_identity(Identity::unique());
+            save_wallet(&subcmd.path, &me);
         }
         Command::CheckStatus(subcmd) => {
             let db: AuthorityDb = load(&subcmd.authority_path);
-            let me: Me = load(&subcmd.path);
-            let mut infected = false;
-            let mut tainted = false;
-            for identity in me.identities {
-                if db.infected.contains(identity.hashed_id()) {
-                    infected = true;
-                    break;
+            let me = load_wallet(&subcmd.path);
+            let status =
+                check_status(me.identities().iter().map(|x| x.hashed_id()), &db.registry).unwrap();
+            match status {
+                ExposureStatus::Clear => println!("You're clear"),
+                ExposureStatus::Exposed { at, degree: 1, .. } => {
+                    println!("You're tainted (last contact {})", at)
                 }
-                if db.tainted.contains(identity.hashed_id()) {
-                    tainted = true;
-                    break;
+                ExposureStatus::Exposed { at, risk, degree } => println!(
+                    "You're exposed to degree {} (last contact {}, risk {:.2})",
+                    degree, at, risk
+                ),
+                ExposureStatus::Infected { reported_at } => {
+                    println!("You're infected (reported {})", reported_at)
                 }
-            }
-            if infected {
-                println!("You're infected");
-            } else if tainted {
-                println!("You're tainted");
-            } else {
-                println!("You're clear");
+                ExposureStatus::Revoked => {
+                    println!("Your earlier report was revoked, you're clear")
+                }
+                ExposureStatus::Unknown => println!("Unknown status, create an identity first"),
             }
         }
         Command::NewShareIdentity(subcmd) => {
-            let public_key: PublicKey = subcmd.public_key.parse().unwrap();
-            let mut me: Me = load(&subcmd.path);
-            if me.identities.is_empty() {
-                me.identities.push(Identity::unique());
-                save(&subcmd.path, &me);
+            let public_key =
+                authority_public_key(subcmd.public_key, subcmd.descriptor, subcmd.root_key);
+            let mut me = load_wallet(&subcmd.path);
+            if me.identities().is_empty() {
+                me.add_identity(Identity::unique());
+                save_wallet(&subcmd.path, &me);
             }
-            let share_id = me.identities[me.identities.len() - 1].new_share_id(&public_key);
+            let share_id = me.current_identity().unwrap().new_share_id(&public_key);
             println!("{}", share_id);
         }
         Command::AddContact(subcmd) => {
-            let mut me: Me = load(&subcmd.path);
+            let mut journal = ContactJournal::open(&subcmd.contacts_path, &wallet_key()).unwrap();
             let identity: ShareIdentity = subcmd.share_id.parse().unwrap();
-            me.contacts.add(&identity);
-            save(&subcmd.path, &me);
+            journal.add(&identity).unwrap();
+        }
+        Command::IssueCode(subcmd) => {
+            let db: AuthorityDb = load(&subcmd.authority_path);
+            let code = db
+                .authority
+                .issue_code(chrono::Duration::hours(subcmd.valid_hours))
+                .unwrap();
+            println!("{}", code);
+        }
+        Command::CreateUpload(subcmd) => {
+            let public_key =
+                authority_public_key(subcmd.public_key, subcmd.descriptor, subcmd.root_key);
+            let me = load_wallet(&subcmd.path);
+            let journal = ContactJournal::open(&subcmd.contacts_path, &wallet_key()).unwrap();
+            let bundle = UploadBundle::new(me.identities(), journal.contacts());
+            save(&subcmd.upload_path, &bundle.seal(&public_key).unwrap());
         }
     }
 }
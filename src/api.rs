@@ -0,0 +1,279 @@
+//! Implements the messages exchanged with the authority server.
+use std::fmt;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::auth::HashedIdentity;
+use crate::authcode::AuthorizationCode;
+#[cfg(feature = "blind")]
+use crate::blind::AnonymousCredential;
+use crate::client::ClientError;
+use crate::crypto::{PublicKey, Signature, SigningPublicKey, SigningSecretKey};
+use crate::registry::UploadId;
+use crate::status::TaintList;
+use crate::subscription::SubscriptionToken;
+use crate::upload::SealedUploadBundle;
+
+const SIGNATURE_CONTEXT: &[u8] = b"covidcotra-taint-list\x00";
+
+/// The longest prefix of a hashed identity accepted by status queries.
+///
+/// Short prefixes match many hashed identities which hides the one a
+/// device is interested in.
+pub const MAX_PREFIX_LEN: usize = 4;
+
+/// The public keys of an authority.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AuthorityKeys {
+    /// The key upload bundles and share identities are sealed to.
+    pub public_key: PublicKey,
+    /// The key the authority signs with.
+    pub signing_public_key: SigningPublicKey,
+}
+
+/// An upload bundle together with its authorization.
+///
+/// Uploads without authorization are treated as uploads of exposed users.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct UploadRequest {
+    /// The sealed upload bundle.
+    pub bundle: SealedUploadBundle,
+    /// The authorization code issued after a positive test.
+    #[serde(default)]
+    pub code: Option<AuthorizationCode>,
+    /// The anonymous credential issued after a positive test.
+    ///
+    /// This requires the `blind` feature.
+    #[cfg(feature = "blind")]
+    #[serde(default)]
+    pub credential: Option<AnonymousCredential>,
+}
+
+/// The response to an accepted upload.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct UploadResponse {
+    /// The ID the registry assigned to the upload.
+    pub upload_id: UploadId,
+}
+
+/// The most subscription tokens accepted in a single request.
+pub const MAX_SUBSCRIPTION_TOKENS: usize = 64;
+
+/// Subscribes to push notifications.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SubscriptionRequest {
+    /// The tokens of the hashed identities to subscribe to (see
+    /// [`HashedIdentity::subscription_token`](struct.HashedIdentity.html#method.subscription_token)).
+    pub tokens: Vec<SubscriptionToken>,
+}
+
+/// Requests the transparency log entries of a hashed identity.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct InclusionRequest {
+    /// The hashed identity to look up.
+    pub hashed_id: HashedIdentity,
+    /// The size of the tree the proofs are for.
+    pub tree_size: u64,
+}
+
+/// Describes which part of the taint list a signed list covers.
+///
+/// The scope is part of the signature so that a list served for one request
+/// cannot be passed off as the answer to another (for instance a single page
+/// of a delta as the full list).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TaintListScope {
+    /// The full taint list.
+    Full,
+    /// A page of the changes since a point in time.
+    Delta {
+        /// The time the changes are relative to.
+        since: DateTime<Utc>,
+        /// The offset of the page.
+        offset: usize,
+    },
+    /// The hashed identities starting with a prefix (as returned by
+    /// [`encode_prefix`](fn.encode_prefix.html)).
+    Prefix(String),
+}
+
+impl fmt::Display for TaintListScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            TaintListScope::Full => write!(f, "full"),
+            TaintListScope::Delta { since, offset } => write!(
+                f,
+                "delta:{}:{}",
+                since.to_rfc3339_opts(SecondsFormat::Nanos, true),
+                offset
+            ),
+            TaintListScope::Prefix(ref prefix) => write!(f, "prefix:{}", prefix),
+        }
+    }
+}
+
+/// A taint list signed by the authority.
+///
+/// The list is kept in its serialized form so that the signature can be
+/// checked over the exact bytes the authority signed.  Besides the scope the
+/// signature covers the generation time of the list, so that clients can
+/// reject lists older than the ones they already have, and the total number
+/// of entries of the list a page was taken from, so that a server cannot
+/// silently stop paging early.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SignedTaintList {
+    scope: String,
+    generated: DateTime<Utc>,
+    total: usize,
+    payload: String,
+    signature: Signature,
+}
+
+impl SignedTaintList {
+    pub(crate) fn sign(
+        list: &TaintList,
+        scope: &TaintListScope,
+        total: usize,
+        secret_key: &SigningSecretKey,
+    ) -> SignedTaintList {
+        let scope = scope.to_string();
+        let generated = list.generated();
+        // hashed identities serialize to strings so this cannot fail
+        let payload = serde_json::to_string(list).unwrap();
+        let signature = secret_key.sign(&signed_message(&scope, generated, total, &payload));
+        SignedTaintList {
+            scope,
+            generated,
+            total,
+            payload,
+            signature,
+        }
+    }
+
+    /// Returns when the list was generated.
+    pub fn generated(&self) -> DateTime<Utc> {
+        self.generated
+    }
+
+    /// Returns the number of entries of the list this list is a page of.
+    ///
+    /// For lists that are not paged this is the length of the list.
+    pub fn total(&self) -> usize {
+        self.total
+    }
+
+    /// Verifies the signature and returns the list.
+    ///
+    /// This fails if the list was not signed by the given key or if it was
+    /// signed for a different scope.
+    pub fn verify(
+        &self,
+        signing_public_key: &SigningPublicKey,
+        scope: &TaintListScope,
+    ) -> Result<TaintList, ClientError> {
+        let message = signed_message(&self.scope, self.generated, self.total, &self.payload);
+        if self.scope != scope.to_string() || !signing_public_key.verify(&message, &self.signature)
+        {
+            return Err(ClientError::InvalidSignature);
+        }
+        let list: TaintList =
+            serde_json::from_str(&self.payload).map_err(ClientError::InvalidResponse)?;
+        if list.generated() != self.generated {
+            return Err(ClientError::InvalidSignature);
+        }
+        Ok(list)
+    }
+}
+
+fn signed_message(scope: &str, generated: DateTime<Utc>, total: usize, payload: &str) -> Vec<u8> {
+    let mut rv = SIGNATURE_CONTEXT.to_vec();
+    rv.extend_from_slice(scope.as_bytes());
+    rv.push(0);
+    rv.extend_from_slice(
+        generated
+            .to_rfc3339_opts(SecondsFormat::Nanos, true)
+            .as_bytes(),
+    );
+    rv.push(0);
+    rv.extend_from_slice(&(total as u64).to_be_bytes());
+    rv.extend_from_slice(payload.as_bytes());
+    rv
+}
+
+/// A page of changes to the taint list.
+///
+/// The first page is taken from a fresh delta and the later pages are taken
+/// from the same delta by passing its generation time along.  All pages of
+/// a delta are signed with the same generation time and total.  A device
+/// should continue with that generation time on its next request so that
+/// changes made while it was paging are not missed.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TaintListPage {
+    /// The changes on this page.
+    pub list: SignedTaintList,
+    /// The offset of the next page if there are more changes.
+    ///
+    /// This is not signed.  Clients check it against the signed
+    /// [`total`](struct.SignedTaintList.html#method.total) of the list.
+    pub next_offset: Option<usize>,
+}
+
+impl TaintListPage {
+    pub(crate) fn sign(
+        delta: &TaintList,
+        since: DateTime<Utc>,
+        offset: usize,
+        limit: usize,
+        secret_key: &SigningSecretKey,
+    ) -> TaintListPage {
+        let next_offset = offset.saturating_add(limit);
+        TaintListPage {
+            list: SignedTaintList::sign(
+                &delta.page(offset, limit),
+                &TaintListScope::Delta { since, offset },
+                delta.len(),
+                secret_key,
+            ),
+            next_offset: if next_offset < delta.len() {
+                Some(next_offset)
+            } else {
+                None
+            },
+        }
+    }
+}
+
+/// The response to a failed request.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ErrorResponse {
+    /// A description of the error.
+    pub error: String,
+}
+
+/// Encodes the first `len` bytes of a hashed identity for status queries.
+///
+/// The prefix is cut to [`MAX_PREFIX_LEN`](constant.MAX_PREFIX_LEN.html)
+/// bytes.
+pub fn encode_prefix(hashed_id: &HashedIdentity, len: usize) -> String {
+    hashed_id.as_bytes()[..len.min(MAX_PREFIX_LEN)]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Decodes a prefix of a status query.
+#[cfg(feature = "server")]
+pub(crate) fn decode_prefix(s: &str) -> Option<Vec<u8>> {
+    if s.is_empty()
+        || !s.len().is_multiple_of(2)
+        || s.len() > MAX_PREFIX_LEN * 2
+        || !s.chars().all(|c| c.is_ascii_hexdigit())
+    {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|idx| u8::from_str_radix(&s[idx..idx + 2], 16).ok())
+        .collect()
+}
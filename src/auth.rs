@@ -1,35 +1,97 @@
 //! Implements the authentication layer.
-use std::borrow::Cow;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
 
-use derive_more::{Display, Error};
 use hmac::Hmac;
 use pbkdf2::pbkdf2;
 use serde::{de, ser, Deserialize, Serialize};
-use serde_plain::{forward_display_to_serde, forward_from_str_to_serde};
-use sha2::Sha256;
+use serde_plain::forward_display_to_serde;
+use sha2::{Digest, Sha256};
 use uuid::Uuid;
+use zeroize::Zeroize;
 
-use crate::crypto::{seal, unseal, PublicKey, SecretKey};
-use crate::utils::base64;
+use crate::crypto::{ct_eq, seal, unseal, PublicKey, SecretKey};
+use crate::envelope::{self, ArtifactKind};
+use crate::error::Error;
+use crate::federation::{AuthorityId, AUTHORITY_ID_LEN};
+use crate::subscription::SubscriptionToken;
+
+const DECOY_CONTEXT: &[u8] = b"covidcotra-decoy\x00";
 
 const SHARED_SALT: &[u8; 16] = b"nX\xdfu\x1au=\xd7\xe3d.\x1c\xb2\x11P\x0b";
 
 /// Just the Unique ID detached from the authentication.
-#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Eq, Hash, Debug)]
-pub struct UniqueIdentity(Uuid);
+///
+/// The unique ID is secret.  It is zeroed out when dropped, redacted from
+/// debug output and has to be cloned explicitly.  Comparisons run in
+/// constant time.
+#[derive(Clone)]
+pub struct UniqueIdentity([u8; 16]);
+
+impl PartialEq for UniqueIdentity {
+    fn eq(&self, other: &UniqueIdentity) -> bool {
+        ct_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for UniqueIdentity {}
+
+impl Hash for UniqueIdentity {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash(state);
+    }
+}
+
+impl fmt::Debug for UniqueIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "UniqueIdentity(****)")
+    }
+}
+
+impl Drop for UniqueIdentity {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl Serialize for UniqueIdentity {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: ser::Serializer,
+    {
+        Uuid::from_bytes(self.0).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for UniqueIdentity {
+    fn deserialize<D>(deserializer: D) -> Result<UniqueIdentity, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        Uuid::deserialize(deserializer).map(|uuid| UniqueIdentity(*uuid.as_bytes()))
+    }
+}
 
 impl UniqueIdentity {
     /// Creates a new unique identity.
     pub fn unique() -> UniqueIdentity {
-        UniqueIdentity(Uuid::new_v4())
+        UniqueIdentity(*Uuid::new_v4().as_bytes())
     }
 
     /// Hashes this unique identity
     pub fn hash(&self) -> HashedIdentity {
         let mut hashed_id = [0u8; 32];
-        pbkdf2::<Hmac<Sha256>>(self.0.as_bytes(), SHARED_SALT, 50000, &mut hashed_id);
+        pbkdf2::<Hmac<Sha256>>(&self.0, SHARED_SALT, 50000, &mut hashed_id);
         HashedIdentity(hashed_id)
     }
+
+    pub(crate) fn new_share_id(&self, public_key: &PublicKey) -> ShareIdentity {
+        ShareIdentity {
+            authority_id: Some(public_key.authority_id()),
+            sealed: seal(&self.0, public_key),
+        }
+    }
 }
 
 /// Represents the unique identity of a person with auth info.
@@ -56,8 +118,8 @@ struct IdentitySmol {
 impl From<IdentitySmol> for Identity {
     fn from(smol: IdentitySmol) -> Identity {
         Identity {
-            unique_id: smol.unique_id,
             hashed_id: smol.unique_id.hash(),
+            unique_id: smol.unique_id,
         }
     }
 }
@@ -67,25 +129,44 @@ impl From<IdentitySmol> for Identity {
 /// This can be more freely shared with authorities for update purposes.  It's
 /// derived via PBKDF2-HMAC-SHA256 on 10.000 iterations and a well known salt.
 /// This is not ideal but it's a compromise.
-#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+///
+/// Hashed identities are looked up on behalf of untrusted clients, so
+/// comparisons run in constant time.  This does not make lookups in a
+/// [`RegistryStore`](trait.RegistryStore.html) constant time.
+#[derive(Copy, Clone, Debug)]
 pub struct HashedIdentity([u8; 32]);
 
-/// Error for invalid hashed identities.
-#[derive(Debug, Error, Display, Clone)]
-#[display(fmt = "cannot parse hashed identity")]
-pub struct HashedIdentityParseError;
+impl PartialEq for HashedIdentity {
+    fn eq(&self, other: &HashedIdentity) -> bool {
+        ct_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for HashedIdentity {}
+
+impl Hash for HashedIdentity {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash(state);
+    }
+}
 
 forward_display_to_serde!(HashedIdentity);
-forward_from_str_to_serde!(HashedIdentity, |_x| -> HashedIdentityParseError {
-    HashedIdentityParseError
-});
+
+impl FromStr for HashedIdentity {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<HashedIdentity, Error> {
+        let (_, bytes) = envelope::parse(ArtifactKind::HashedIdentity, s, Some(32))?;
+        HashedIdentity::from_slice(&bytes)
+    }
+}
 
 impl Serialize for HashedIdentity {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: ser::Serializer,
     {
-        base64::serialize(&self.0[..], serializer)
+        envelope::serialize(ArtifactKind::HashedIdentity, &self.0[..], serializer)
     }
 }
 
@@ -94,15 +175,44 @@ impl<'de> Deserialize<'de> for HashedIdentity {
     where
         D: de::Deserializer<'de>,
     {
-        let bytes: Cow<'de, [u8]> = base64::deserialize(deserializer)?;
-        if bytes.len() == 32 {
-            let mut id = [0u8; 32];
-            id.copy_from_slice(&bytes);
-            Ok(HashedIdentity(id))
-        } else {
-            use serde::de::Error;
-            Err(D::Error::custom("cannot deserialize hashed identity"))
-        }
+        let (_, bytes) =
+            envelope::deserialize(ArtifactKind::HashedIdentity, Some(32), deserializer)?;
+        HashedIdentity::from_slice(&bytes).map_err(de::Error::custom)
+    }
+}
+
+impl HashedIdentity {
+    /// Returns the bytes of the hashed identity.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Returns the token to subscribe to push notifications for this
+    /// hashed identity.
+    pub fn subscription_token(&self) -> SubscriptionToken {
+        SubscriptionToken::derive(self)
+    }
+
+    /// Derives a hashed identity to hide real ones among.
+    ///
+    /// The same secret, index and epoch always give the same decoy, so a
+    /// device can query its decoys as regularly as its real identities.
+    pub(crate) fn decoy(secret: &[u8; 32], idx: u64, epoch: i64) -> HashedIdentity {
+        let mut hasher = Sha256::new();
+        hasher.input(DECOY_CONTEXT);
+        hasher.input(secret);
+        hasher.input(idx.to_be_bytes());
+        hasher.input(epoch.to_be_bytes());
+        let mut id = [0u8; 32];
+        id.copy_from_slice(&hasher.result());
+        HashedIdentity(id)
+    }
+
+    fn from_slice(bytes: &[u8]) -> Result<HashedIdentity, Error> {
+        Error::check_length(32, bytes.len())?;
+        let mut id = [0u8; 32];
+        id.copy_from_slice(bytes);
+        Ok(HashedIdentity(id))
     }
 }
 
@@ -111,28 +221,114 @@ impl<'de> Deserialize<'de> for HashedIdentity {
 /// This identity should be rotated once every few minutes.  It's an encrypted
 /// version of the unique ID and sent to other devices.  Only the central
 /// authority's key can decode the contained identity.
-#[derive(Clone, Serialize, Deserialize, Hash, PartialEq, Eq)]
-pub struct ShareIdentity(#[serde(with = "base64")] Vec<u8>);
+///
+/// Share IDs are tagged with the [`AuthorityId`](struct.AuthorityId.html) of
+/// the authority they were sealed to, so that sightings of travellers from
+/// other regions can be forwarded to their authority.  Share IDs written
+/// before the tag was introduced have no authority ID.
+#[derive(Clone)]
+pub struct ShareIdentity {
+    authority_id: Option<AuthorityId>,
+    sealed: Vec<u8>,
+}
+
+impl PartialEq for ShareIdentity {
+    fn eq(&self, other: &ShareIdentity) -> bool {
+        self.authority_id == other.authority_id && ct_eq(&self.sealed, &other.sealed)
+    }
+}
 
-/// Error for invalid share identities.
-#[derive(Debug, Error, Display, Clone)]
-#[display(fmt = "cannot parse share identity")]
-pub struct ShareIdentityParseError;
+impl Eq for ShareIdentity {}
+
+impl Hash for ShareIdentity {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.authority_id.hash(state);
+        self.sealed.hash(state);
+    }
+}
+
+/// The length of a sealed unique ID (UUID plus sealed box overhead).
+const SHARE_ID_LEN: usize = 64;
 
 forward_display_to_serde!(ShareIdentity);
-forward_from_str_to_serde!(ShareIdentity, |_x| -> ShareIdentityParseError {
+
+impl FromStr for ShareIdentity {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<ShareIdentity, Error> {
+        let (version, bytes) = envelope::parse(ArtifactKind::ShareIdentity, s, Some(SHARE_ID_LEN))?;
+        ShareIdentity::from_payload(version, &bytes)
+    }
+}
+
+impl Serialize for ShareIdentity {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: ser::Serializer,
     {
-        dbg!(_x);
-        ShareIdentityParseError
+        let mut payload = Vec::with_capacity(AUTHORITY_ID_LEN + self.sealed.len());
+        // untagged share IDs are written with an all zero authority ID
+        payload.extend_from_slice(
+            self.authority_id
+                .as_ref()
+                .map_or(&[0u8; AUTHORITY_ID_LEN][..], |authority_id| {
+                    authority_id.as_bytes()
+                }),
+        );
+        payload.extend_from_slice(&self.sealed);
+        envelope::serialize(ArtifactKind::ShareIdentity, &payload, serializer)
     }
-});
+}
+
+impl<'de> Deserialize<'de> for ShareIdentity {
+    fn deserialize<D>(deserializer: D) -> Result<ShareIdentity, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        let (version, bytes) = envelope::deserialize(
+            ArtifactKind::ShareIdentity,
+            Some(SHARE_ID_LEN),
+            deserializer,
+        )?;
+        ShareIdentity::from_payload(version, &bytes).map_err(de::Error::custom)
+    }
+}
 
 impl ShareIdentity {
+    fn from_payload(version: u8, bytes: &[u8]) -> Result<ShareIdentity, Error> {
+        // versions 0 and 1 only contain the sealed unique ID
+        if version < 2 {
+            Error::check_length(SHARE_ID_LEN, bytes.len())?;
+            return Ok(ShareIdentity {
+                authority_id: None,
+                sealed: bytes.to_vec(),
+            });
+        }
+        Error::check_length(AUTHORITY_ID_LEN + SHARE_ID_LEN, bytes.len())?;
+        let (authority_id, sealed) = bytes.split_at(AUTHORITY_ID_LEN);
+        let authority_id = AuthorityId::from_slice(authority_id)?;
+        Ok(ShareIdentity {
+            authority_id: Some(authority_id).filter(|x| !x.is_unset()),
+            sealed: sealed.to_vec(),
+        })
+    }
+
+    /// Returns the ID of the authority the identity was sealed to.
+    ///
+    /// This is `None` for share IDs created before they were tagged.
+    pub fn authority_id(&self) -> Option<&AuthorityId> {
+        self.authority_id.as_ref()
+    }
+
     /// Reveals the unique identity behind a shared identity
-    pub fn reveal(&self, secret_key: &SecretKey) -> Option<UniqueIdentity> {
-        unseal(&self.0, secret_key)
-            .and_then(|x| Uuid::from_slice(&x).ok())
-            .map(UniqueIdentity)
+    ///
+    /// Fails with [`Error::Decryption`](enum.Error.html#variant.Decryption)
+    /// if the identity was not shared with the given key.
+    pub fn reveal(&self, secret_key: &SecretKey) -> Result<UniqueIdentity, Error> {
+        let mut bytes = unseal(&self.sealed, secret_key).ok_or(Error::Decryption)?;
+        let rv = Uuid::from_slice(&bytes).map(|uuid| UniqueIdentity(*uuid.as_bytes()));
+        bytes.zeroize();
+        Ok(rv?)
     }
 }
 
@@ -141,8 +337,8 @@ impl Identity {
     pub fn unique() -> Identity {
         let unique_id = UniqueIdentity::unique();
         Identity {
-            unique_id,
             hashed_id: unique_id.hash(),
+            unique_id,
         }
     }
 
@@ -158,6 +354,6 @@ impl Identity {
 
     /// Creates a new shareable identity.
     pub fn new_share_id(&self, public_key: &PublicKey) -> ShareIdentity {
-        ShareIdentity(seal(self.unique_id.0.as_bytes(), public_key))
+        self.unique_id.new_share_id(public_key)
     }
 }
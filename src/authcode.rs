@@ -0,0 +1,269 @@
+//! Implements upload authorization codes.
+use std::convert::TryInto;
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use derive_more::{Display, Error};
+use serde::{de, ser, Deserialize, Serialize};
+use serde_plain::forward_display_to_serde;
+use uuid::Uuid;
+
+use crate::crypto::{
+    gen_signing_keypair, random_below, Signature, SigningPublicKey, SigningSecretKey,
+};
+use crate::envelope::{self, ArtifactKind, EnvelopeError};
+use crate::error::Error;
+
+const SIGNATURE_CONTEXT: &[u8] = b"covidcotra-authcode\x00";
+const CODE_LEN: usize = 16 + 8 + 8 + 32 + 64;
+
+/// Identifies an authorization code.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CodeId(Uuid);
+
+impl fmt::Display for CodeId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+/// Error for authorization codes that are not accepted.
+#[derive(Debug, Error, Display, Clone, PartialEq, Eq)]
+pub enum AuthorizationError {
+    /// The code is past its expiration date.
+    #[display(fmt = "authorization code expired at {}", expired)]
+    Expired { expired: DateTime<Utc> },
+    /// The code was already used for another upload.
+    #[display(fmt = "authorization code was already redeemed at {}", redeemed)]
+    Reused { redeemed: DateTime<Utc> },
+    /// The signature on the code does not verify.
+    #[display(fmt = "authorization code has an invalid signature")]
+    Forged,
+    /// The code was signed by a key the registry does not trust.
+    #[display(fmt = "authorization code was issued by an untrusted key")]
+    UntrustedIssuer,
+    /// The requested validity does not give a representable expiration date.
+    #[display(fmt = "authorization code validity is out of range")]
+    InvalidValidity,
+}
+
+/// A single-use, expiring code that authorizes an upload.
+///
+/// Codes are issued by the [`Authority`](struct.Authority.html) or by a
+/// trusted [`Lab`](struct.Lab.html) when a user tests positive and have to
+/// accompany the upload bundle.  The registry tracks redeemed codes so that
+/// every code can only be used once.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AuthorizationCode {
+    id: CodeId,
+    issued: DateTime<Utc>,
+    expires: DateTime<Utc>,
+    issuer: SigningPublicKey,
+    signature: Signature,
+}
+
+forward_display_to_serde!(AuthorizationCode);
+
+impl FromStr for AuthorizationCode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<AuthorizationCode, Error> {
+        let (version, bytes) = envelope::parse(ArtifactKind::AuthorizationCode, s, None)?;
+        AuthorizationCode::from_bytes(version, &bytes)
+    }
+}
+
+impl AuthorizationCode {
+    pub(crate) fn issue(
+        public_key: &SigningPublicKey,
+        secret_key: &SigningSecretKey,
+        valid_for: Duration,
+    ) -> Result<AuthorizationCode, AuthorizationError> {
+        // codes only carry second precision
+        let issued = Utc.timestamp_opt(Utc::now().timestamp(), 0).unwrap();
+        let expires = issued
+            .checked_add_signed(valid_for)
+            .ok_or(AuthorizationError::InvalidValidity)?;
+        let id = CodeId(Uuid::new_v4());
+        let signature = secret_key.sign(&signed_message(&id, issued, expires, public_key));
+        Ok(AuthorizationCode {
+            id,
+            issued,
+            expires,
+            issuer: *public_key,
+            signature,
+        })
+    }
+
+    /// Creates a code that accompanies decoy uploads.
+    ///
+    /// The code names the given issuer and was issued at a random time
+    /// within the last day, but is signed by a throwaway key.  Only
+    /// verifying the signature with the key of the issuer tells it apart
+    /// from a real code.
+    pub(crate) fn decoy(issuer: &SigningPublicKey) -> AuthorizationCode {
+        let valid_for = Duration::days(1);
+        let age = i64::from(random_below(valid_for.num_seconds() as u32));
+        let issued = Utc.timestamp_opt(Utc::now().timestamp() - age, 0).unwrap();
+        let expires = issued + valid_for;
+        let id = CodeId(Uuid::new_v4());
+        let (_, secret_key) = gen_signing_keypair();
+        let signature = secret_key.sign(&signed_message(&id, issued, expires, issuer));
+        AuthorizationCode {
+            id,
+            issued,
+            expires,
+            issuer: *issuer,
+            signature,
+        }
+    }
+
+    /// Returns the ID of the code.
+    pub fn id(&self) -> CodeId {
+        self.id
+    }
+
+    /// Returns the timestamp of when the code was issued.
+    pub fn issued(&self) -> DateTime<Utc> {
+        self.issued
+    }
+
+    /// Returns the timestamp of when the code expires.
+    pub fn expires(&self) -> DateTime<Utc> {
+        self.expires
+    }
+
+    /// Returns the key that issued the code.
+    pub fn issuer(&self) -> &SigningPublicKey {
+        &self.issuer
+    }
+
+    /// Verifies the code against a list of trusted issuer keys.
+    ///
+    /// This checks the issuer, the signature and the expiration date but not
+    /// if the code was already redeemed.
+    pub fn verify(&self, trusted: &[SigningPublicKey]) -> Result<(), AuthorizationError> {
+        if !trusted.contains(&self.issuer) {
+            return Err(AuthorizationError::UntrustedIssuer);
+        }
+        let message = signed_message(&self.id, self.issued, self.expires, &self.issuer);
+        if !self.issuer.verify(&message, &self.signature) {
+            return Err(AuthorizationError::Forged);
+        }
+        if self.expires <= Utc::now() {
+            return Err(AuthorizationError::Expired {
+                expired: self.expires,
+            });
+        }
+        Ok(())
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut rv = encode_fields(&self.id, self.issued, self.expires, &self.issuer);
+        rv.extend_from_slice(self.signature.as_bytes());
+        rv
+    }
+
+    fn from_bytes(version: u8, bytes: &[u8]) -> Result<AuthorizationCode, Error> {
+        Error::check_length(CODE_LEN, bytes.len())?;
+        let timestamp = |offset: usize| {
+            let secs = i64::from_be_bytes(bytes[offset..offset + 8].try_into().unwrap());
+            Utc.timestamp_opt(secs, 0).single().ok_or({
+                Error::Envelope(EnvelopeError::InvalidPayload {
+                    kind: ArtifactKind::AuthorizationCode,
+                    version,
+                })
+            })
+        };
+        Ok(AuthorizationCode {
+            id: CodeId(Uuid::from_slice(&bytes[..16])?),
+            issued: timestamp(16)?,
+            expires: timestamp(24)?,
+            issuer: SigningPublicKey::from_slice(&bytes[32..64])?,
+            signature: Signature::from_slice(&bytes[64..])?,
+        })
+    }
+}
+
+fn encode_fields(
+    id: &CodeId,
+    issued: DateTime<Utc>,
+    expires: DateTime<Utc>,
+    issuer: &SigningPublicKey,
+) -> Vec<u8> {
+    let mut rv = Vec::with_capacity(CODE_LEN);
+    rv.extend_from_slice((id.0).as_bytes());
+    rv.extend_from_slice(&issued.timestamp().to_be_bytes());
+    rv.extend_from_slice(&expires.timestamp().to_be_bytes());
+    rv.extend_from_slice(issuer.as_bytes());
+    rv
+}
+
+fn signed_message(
+    id: &CodeId,
+    issued: DateTime<Utc>,
+    expires: DateTime<Utc>,
+    issuer: &SigningPublicKey,
+) -> Vec<u8> {
+    let mut rv = SIGNATURE_CONTEXT.to_vec();
+    rv.extend_from_slice(&encode_fields(id, issued, expires, issuer));
+    rv
+}
+
+impl Serialize for AuthorizationCode {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: ser::Serializer,
+    {
+        envelope::serialize(
+            ArtifactKind::AuthorizationCode,
+            &self.to_bytes(),
+            serializer,
+        )
+    }
+}
+
+impl<'de> Deserialize<'de> for AuthorizationCode {
+    fn deserialize<D>(deserializer: D) -> Result<AuthorizationCode, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        let (version, bytes) =
+            envelope::deserialize(ArtifactKind::AuthorizationCode, None, deserializer)?;
+        AuthorizationCode::from_bytes(version, &bytes).map_err(de::Error::custom)
+    }
+}
+
+/// Represents a testing lab that can issue authorization codes.
+///
+/// The public key of the lab has to be registered with the registry before
+/// codes issued by it are accepted.
+#[derive(Serialize, Deserialize)]
+pub struct Lab {
+    secret_key: SigningSecretKey,
+    public_key: SigningPublicKey,
+}
+
+impl Lab {
+    /// Creates a new lab.
+    pub fn unique() -> Lab {
+        let (public_key, secret_key) = gen_signing_keypair();
+        Lab {
+            secret_key,
+            public_key,
+        }
+    }
+
+    /// Returns the public key of the lab.
+    pub fn public_key(&self) -> &SigningPublicKey {
+        &self.public_key
+    }
+
+    /// Issues a single-use authorization code for an upload.
+    ///
+    /// Fails if the code would expire past the largest representable date.
+    pub fn issue_code(&self, valid_for: Duration) -> Result<AuthorizationCode, AuthorizationError> {
+        AuthorizationCode::issue(&self.public_key, &self.secret_key, valid_for)
+    }
+}
@@ -1,22 +1,104 @@
 //! Implements the central authority.
-use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
 
-use crate::crypto::{gen_keypair, PublicKey, SecretKey};
+use serde::{ser, Deserialize, Serialize};
+
+use chrono::{DateTime, Duration, Utc};
+
+use crate::api::{SignedTaintList, TaintListPage, TaintListScope};
+use crate::authcode::{AuthorizationCode, AuthorizationError};
+use crate::crypto::{
+    derive_signing_keypair, gen_keypair, PublicKey, SecretKey, SigningPublicKey, SigningSecretKey,
+};
+use crate::envelope::{self, ArtifactKind, EnvelopeError};
+use crate::federation::{AuthorityId, ForwardBatch, ForwardedContacts};
+use crate::status::TaintList;
+use crate::transparency::{SignedTreeHead, TreeHead};
 
 /// Represents the central authority.
-#[derive(Serialize, Deserialize)]
+///
+/// The authority serializes with a versioned envelope header (see
+/// [`ArtifactKind`](enum.ArtifactKind.html)).
+#[derive(Deserialize)]
+#[serde(try_from = "RawAuthority")]
 pub struct Authority {
     secret_key: SecretKey,
     public_key: PublicKey,
+    signing_secret_key: SigningSecretKey,
+    signing_public_key: SigningPublicKey,
+}
+
+#[derive(Serialize)]
+struct AuthorityRef<'a> {
+    magic: &'static str,
+    version: u8,
+    #[serde(rename = "type")]
+    kind: ArtifactKind,
+    secret_key: &'a SecretKey,
+    public_key: &'a PublicKey,
+}
+
+#[derive(Deserialize)]
+struct RawAuthority {
+    #[serde(default)]
+    magic: Option<String>,
+    #[serde(default)]
+    version: Option<u8>,
+    #[serde(default, rename = "type")]
+    kind: Option<ArtifactKind>,
+    #[serde(default)]
+    secret_key: Option<SecretKey>,
+    #[serde(default)]
+    public_key: Option<PublicKey>,
+}
+
+impl TryFrom<RawAuthority> for Authority {
+    type Error = EnvelopeError;
+
+    fn try_from(raw: RawAuthority) -> Result<Authority, EnvelopeError> {
+        let kind = ArtifactKind::Authority;
+        let version = envelope::check_header(kind, raw.magic.as_deref(), raw.version, raw.kind)?;
+        // version 0 (unversioned) and version 1 share the same layout
+        match (raw.secret_key, raw.public_key) {
+            (Some(secret_key), Some(public_key)) => {
+                Ok(Authority::from_keys(public_key, secret_key))
+            }
+            _ => Err(EnvelopeError::InvalidPayload { kind, version }),
+        }
+    }
+}
+
+impl Serialize for Authority {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: ser::Serializer,
+    {
+        let kind = ArtifactKind::Authority;
+        AuthorityRef {
+            magic: envelope::MAGIC_STR,
+            version: kind.current_version(),
+            kind,
+            secret_key: &self.secret_key,
+            public_key: &self.public_key,
+        }
+        .serialize(serializer)
+    }
 }
 
 impl Authority {
     /// Creates a new authority.
     pub fn unique() -> Authority {
         let (public_key, secret_key) = gen_keypair();
+        Authority::from_keys(public_key, secret_key)
+    }
+
+    fn from_keys(public_key: PublicKey, secret_key: SecretKey) -> Authority {
+        let (signing_public_key, signing_secret_key) = derive_signing_keypair(&secret_key);
         Authority {
             public_key,
             secret_key,
+            signing_secret_key,
+            signing_public_key,
         }
     }
 
@@ -29,4 +111,56 @@ impl Authority {
     pub fn public_key(&self) -> &PublicKey {
         &self.public_key
     }
+
+    /// Returns the ID of the authority.
+    pub fn id(&self) -> AuthorityId {
+        self.public_key.authority_id()
+    }
+
+    /// Returns the public key used to verify the authority's signatures.
+    ///
+    /// The signing key is derived from the secret key of the authority.
+    pub fn signing_public_key(&self) -> &SigningPublicKey {
+        &self.signing_public_key
+    }
+
+    /// Issues a single-use authorization code for an upload.
+    ///
+    /// Fails if the code would expire past the largest representable date.
+    pub fn issue_code(&self, valid_for: Duration) -> Result<AuthorizationCode, AuthorizationError> {
+        AuthorizationCode::issue(
+            &self.signing_public_key,
+            &self.signing_secret_key,
+            valid_for,
+        )
+    }
+
+    /// Signs a taint list for the given scope.
+    pub fn sign_taint_list(&self, list: &TaintList, scope: &TaintListScope) -> SignedTaintList {
+        SignedTaintList::sign(list, scope, list.len(), &self.signing_secret_key)
+    }
+
+    /// Signs a page of the changes since a point in time.
+    ///
+    /// The delta is the full list of changes as returned by
+    /// [`Registry::taint_delta`](struct.Registry.html#method.taint_delta).
+    pub fn sign_taint_list_page(
+        &self,
+        delta: &TaintList,
+        since: DateTime<Utc>,
+        offset: usize,
+        limit: usize,
+    ) -> TaintListPage {
+        TaintListPage::sign(delta, since, offset, limit, &self.signing_secret_key)
+    }
+
+    /// Signs the head of a transparency log.
+    pub fn sign_tree_head(&self, head: &TreeHead) -> SignedTreeHead {
+        SignedTreeHead::sign(head, &self.signing_secret_key)
+    }
+
+    /// Signs a batch of contacts forwarded to another authority.
+    pub fn sign_forward(&self, batch: &ForwardBatch) -> ForwardedContacts {
+        ForwardedContacts::sign(batch, &self.signing_secret_key)
+    }
 }
@@ -0,0 +1,155 @@
+//! Runs the registry of an authority as an HTTP server.
+//!
+//! Usage: `covidcotra-server [--listen ADDR] [--authority PATH] [--database PATH]
+//! [--registry PATH] [--webhook URL] [--lab PATH]... [--peer PATH[,URL]]...
+//! [--descriptor PATH]`
+//!
+//! The authority is loaded from the given JSON file and created if it does
+//! not exist yet.  With `--database` (which requires the `sqlite` feature)
+//! the registry is kept in an SQLite database, otherwise it is kept in
+//! memory and saved to the JSON file given with `--registry` (by default
+//! `registry.json`) after every change.  Every `--lab` names a JSON file
+//! with the signing public key of a lab whose authorization codes are
+//! accepted.  With `--webhook`
+//! notifications for subscribed hashed identities are posted to the URL
+//! from a background thread.
+//! Every `--peer` names a JSON file with the `AuthorityKeys` of another
+//! authority whose forwarded contacts are accepted, optionally followed by
+//! a comma and the URL of its server to which contacts sealed to it are
+//! forwarded.  With `--descriptor` the `SignedDescriptor` in the given JSON
+//! file is served at `/descriptor`.
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+use covidcotra::*;
+
+struct Args {
+    listen: String,
+    authority_path: PathBuf,
+    database_path: Option<PathBuf>,
+    registry_path: PathBuf,
+    webhook: Option<String>,
+    labs: Vec<PathBuf>,
+    peers: Vec<(PathBuf, Option<String>)>,
+    descriptor_path: Option<PathBuf>,
+}
+
+fn usage() -> ! {
+    eprintln!(
+        "usage: covidcotra-server [--listen ADDR] [--authority PATH] [--database PATH] \
+         [--registry PATH] [--webhook URL] [--lab PATH]... [--peer PATH[,URL]]... \
+         [--descriptor PATH]"
+    );
+    process::exit(2);
+}
+
+fn parse_args() -> Args {
+    let mut args = Args {
+        listen: "127.0.0.1:8080".into(),
+        authority_path: "authority.json".into(),
+        database_path: None,
+        registry_path: "registry.json".into(),
+        webhook: None,
+        labs: Vec::new(),
+        peers: Vec::new(),
+        descriptor_path: None,
+    };
+    let mut iter = env::args().skip(1);
+    while let Some(arg) = iter.next() {
+        let value = iter.next().unwrap_or_else(|| usage());
+        match arg.as_str() {
+            "--listen" => args.listen = value,
+            "--authority" => args.authority_path = value.into(),
+            "--database" => args.database_path = Some(value.into()),
+            "--registry" => args.registry_path = value.into(),
+            "--webhook" => args.webhook = Some(value),
+            "--lab" => args.labs.push(value.into()),
+            "--peer" => args.peers.push(match value.find(',') {
+                Some(idx) => (value[..idx].into(), Some(value[idx + 1..].into())),
+                None => (value.into(), None),
+            }),
+            "--descriptor" => args.descriptor_path = Some(value.into()),
+            _ => usage(),
+        }
+    }
+    args
+}
+
+fn load_authority(args: &Args) -> Result<Authority, Box<dyn std::error::Error>> {
+    if fs::metadata(&args.authority_path).is_ok() {
+        Ok(serde_json::from_slice(&fs::read(&args.authority_path)?)?)
+    } else {
+        let authority = Authority::unique();
+        fs::write(&args.authority_path, serde_json::to_vec_pretty(&authority)?)?;
+        eprintln!("Created authority in {}", args.authority_path.display());
+        Ok(authority)
+    }
+}
+
+fn serve<S: RegistryStore>(
+    args: &Args,
+    authority: Authority,
+    mut registry: Registry<S>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    eprintln!("Public key: {}", authority.public_key());
+    eprintln!("Authority ID: {}", authority.id());
+    if let Some(ref url) = args.webhook {
+        registry.set_notification_sink(BackgroundSink::spawn(WebhookSink::new(url)?));
+        // hand over notifications that were queued before a restart
+        registry.flush_notifications()?;
+    }
+    for path in &args.labs {
+        let public_key: SigningPublicKey = serde_json::from_slice(&fs::read(path)?)?;
+        registry.trust_lab(&public_key);
+    }
+    let mut peer_urls = Vec::new();
+    for (path, url) in &args.peers {
+        let keys: AuthorityKeys = serde_json::from_slice(&fs::read(path)?)?;
+        registry.trust_peer(FederationPeer::from(&keys));
+        if let Some(url) = url {
+            peer_urls.push((keys.public_key.authority_id(), url));
+        }
+    }
+    let mut server = Server::new(authority, registry);
+    for (authority_id, url) in peer_urls {
+        server.set_peer_url(authority_id, url)?;
+    }
+    if let Some(ref path) = args.descriptor_path {
+        server.set_descriptor(serde_json::from_slice(&fs::read(path)?)?);
+    }
+    let server = server.bind(&args.listen)?;
+    if let Some(addr) = server.local_addr() {
+        eprintln!("Listening on http://{}", addr);
+    }
+    server.run()?;
+    Ok(())
+}
+
+fn run(args: &Args) -> Result<(), Box<dyn std::error::Error>> {
+    let authority = load_authority(args)?;
+    match args.database_path {
+        #[cfg(feature = "sqlite")]
+        Some(ref path) => serve(
+            args,
+            authority,
+            Registry::with_store(SqliteStore::open(path)?),
+        ),
+        #[cfg(not(feature = "sqlite"))]
+        Some(_) => Err("--database requires the sqlite feature".into()),
+        None => serve(
+            args,
+            authority,
+            Registry::with_store(MemoryStore::open(&args.registry_path)?),
+        ),
+    }
+}
+
+fn main() {
+    let args = parse_args();
+    if let Err(err) = run(&args) {
+        eprintln!("error: {}", err);
+        process::exit(1);
+    }
+}
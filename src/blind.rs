@@ -0,0 +1,289 @@
+//! Implements anonymous upload credentials based on blind signatures.
+//!
+//! This is an alternative to [`AuthorizationCode`](struct.AuthorizationCode.html)
+//! that does not let the authority link an upload to the visit at the lab:
+//!
+//! 1. the device creates a [`CredentialRequest`](struct.CredentialRequest.html)
+//!    for the public key of a [`BlindIssuer`](struct.BlindIssuer.html) (the lab)
+//!    and hands the blinded token to the lab.
+//! 2. the lab signs the blinded token without learning the token itself.
+//! 3. the device unblinds the signature into an
+//!    [`AnonymousCredential`](struct.AnonymousCredential.html) which it
+//!    presents together with its upload.
+//!
+//! The signatures are RSA blind signatures (RSABSSA-SHA384-PSS-Randomized as
+//! per RFC 9474).  This requires the `blind` feature.
+use std::fmt;
+
+use blind_rsa_signatures as brsa;
+use derive_more::{Display, Error};
+use serde::{de, ser, Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sodiumoxide::randombytes::randombytes_into;
+
+use crate::authcode::AuthorizationError;
+use crate::utils::base64;
+
+const MODULUS_BITS: usize = 2048;
+
+/// Error for failures in the blind signature protocol.
+#[derive(Debug, Error, Display, Clone, PartialEq, Eq)]
+pub enum CredentialError {
+    /// The token could not be blinded for the issuer key.
+    #[display(fmt = "cannot blind token")]
+    Blinding,
+    /// The issuer could not sign the blinded token.
+    #[display(fmt = "cannot sign blinded token")]
+    Signing,
+    /// The blind signature does not verify against the issuer key.
+    #[display(fmt = "invalid blind signature")]
+    InvalidSignature,
+}
+
+/// The public key of a blind issuer.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BlindPublicKey(brsa::PublicKey);
+
+impl Serialize for BlindPublicKey {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: ser::Serializer,
+    {
+        let der = self.0.to_der().map_err(ser::Error::custom)?;
+        base64::serialize(&der, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for BlindPublicKey {
+    fn deserialize<D>(deserializer: D) -> Result<BlindPublicKey, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        let der: Vec<u8> = base64::deserialize(deserializer)?;
+        brsa::PublicKey::from_der(&der)
+            .map(BlindPublicKey)
+            .map_err(de::Error::custom)
+    }
+}
+
+/// A party (usually a lab) that blindly signs credential requests.
+pub struct BlindIssuer {
+    secret_key: brsa::SecretKey,
+    public_key: BlindPublicKey,
+}
+
+#[derive(Serialize, Deserialize)]
+struct BlindIssuerRepr {
+    #[serde(with = "base64")]
+    secret_key: Vec<u8>,
+}
+
+impl Serialize for BlindIssuer {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: ser::Serializer,
+    {
+        BlindIssuerRepr {
+            secret_key: self.secret_key.to_der().map_err(ser::Error::custom)?,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for BlindIssuer {
+    fn deserialize<D>(deserializer: D) -> Result<BlindIssuer, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        let repr = BlindIssuerRepr::deserialize(deserializer)?;
+        let secret_key = brsa::SecretKey::from_der(&repr.secret_key).map_err(de::Error::custom)?;
+        BlindIssuer::from_secret_key(secret_key).map_err(de::Error::custom)
+    }
+}
+
+impl BlindIssuer {
+    /// Creates a new issuer with a fresh RSA key.
+    pub fn unique() -> BlindIssuer {
+        let key_pair = brsa::KeyPair::generate(&mut rand::thread_rng(), MODULUS_BITS)
+            .expect("could not generate blind signing key");
+        BlindIssuer {
+            secret_key: key_pair.sk,
+            public_key: BlindPublicKey(key_pair.pk),
+        }
+    }
+
+    fn from_secret_key(secret_key: brsa::SecretKey) -> Result<BlindIssuer, brsa::Error> {
+        let public_key = BlindPublicKey(secret_key.public_key()?);
+        Ok(BlindIssuer {
+            secret_key,
+            public_key,
+        })
+    }
+
+    /// Returns the public key of the issuer.
+    pub fn public_key(&self) -> &BlindPublicKey {
+        &self.public_key
+    }
+
+    /// Signs a blinded token.
+    ///
+    /// The issuer learns nothing about the token it signs.  It is the
+    /// responsibility of the caller to only sign tokens for people that
+    /// tested positive.
+    pub fn sign(&self, token: &BlindedToken) -> Result<BlindedSignature, CredentialError> {
+        self.secret_key
+            .blind_sign(&mut rand::thread_rng(), &token.0, &options())
+            .map(|sig| BlindedSignature(sig.0))
+            .map_err(|_| CredentialError::Signing)
+    }
+}
+
+/// A blinded token sent from the device to the issuer.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BlindedToken(#[serde(with = "base64")] Vec<u8>);
+
+/// A blind signature sent from the issuer back to the device.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BlindedSignature(#[serde(with = "base64")] Vec<u8>);
+
+/// The device side state while a credential is being issued.
+///
+/// This must stay on the device: it contains the blinding secret which
+/// would let the issuer link the credential to the issuance.
+pub struct CredentialRequest {
+    issuer: BlindPublicKey,
+    token: [u8; 32],
+    secret: brsa::Secret,
+    randomizer: Option<brsa::MessageRandomizer>,
+    blinded: BlindedToken,
+}
+
+impl CredentialRequest {
+    /// Creates a new request for a random token.
+    pub fn new(issuer: &BlindPublicKey) -> Result<CredentialRequest, CredentialError> {
+        let mut token = [0u8; 32];
+        randombytes_into(&mut token);
+        let result = issuer
+            .0
+            .blind(&mut rand::thread_rng(), &token[..], true, &options())
+            .map_err(|_| CredentialError::Blinding)?;
+        Ok(CredentialRequest {
+            issuer: issuer.clone(),
+            token,
+            secret: result.secret,
+            randomizer: result.msg_randomizer,
+            blinded: BlindedToken(result.blind_msg.0),
+        })
+    }
+
+    /// Returns the blinded token to send to the issuer.
+    pub fn blinded_token(&self) -> &BlindedToken {
+        &self.blinded
+    }
+
+    /// Unblinds the signature of the issuer into a credential.
+    pub fn finalize(
+        self,
+        signature: &BlindedSignature,
+    ) -> Result<AnonymousCredential, CredentialError> {
+        let signature = self
+            .issuer
+            .0
+            .finalize(
+                &brsa::BlindSignature(signature.0.clone()),
+                &self.secret,
+                self.randomizer,
+                &self.token[..],
+                &options(),
+            )
+            .map_err(|_| CredentialError::InvalidSignature)?;
+        Ok(AnonymousCredential {
+            token: self.token.to_vec(),
+            randomizer: self.randomizer.map(|x| x.0.to_vec()),
+            signature: signature.0,
+        })
+    }
+}
+
+/// Identifies a redeemed anonymous credential.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CredentialId([u8; 32]);
+
+impl fmt::Display for CredentialId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&::base64::encode(&self.0[..]))
+    }
+}
+
+impl Serialize for CredentialId {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: ser::Serializer,
+    {
+        base64::serialize(&self.0[..], serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for CredentialId {
+    fn deserialize<D>(deserializer: D) -> Result<CredentialId, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        let bytes: Vec<u8> = base64::deserialize(deserializer)?;
+        if bytes.len() != 32 {
+            return Err(de::Error::custom("invalid credential id"));
+        }
+        let mut id = [0u8; 32];
+        id.copy_from_slice(&bytes);
+        Ok(CredentialId(id))
+    }
+}
+
+/// An unlinkable credential that authorizes an upload.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AnonymousCredential {
+    #[serde(with = "base64")]
+    token: Vec<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    randomizer: Option<Vec<u8>>,
+    #[serde(with = "base64")]
+    signature: Vec<u8>,
+}
+
+impl AnonymousCredential {
+    /// Returns the ID of the credential used to track redemption.
+    pub fn id(&self) -> CredentialId {
+        let mut id = [0u8; 32];
+        id.copy_from_slice(&Sha256::digest(&self.token));
+        CredentialId(id)
+    }
+
+    /// Verifies that the credential was signed by one of the trusted issuers.
+    ///
+    /// This does not check if the credential was already redeemed.
+    pub fn verify(&self, trusted: &[BlindPublicKey]) -> Result<(), AuthorizationError> {
+        let randomizer = match self.randomizer {
+            Some(ref bytes) if bytes.len() == 32 => {
+                let mut randomizer = [0u8; 32];
+                randomizer.copy_from_slice(bytes);
+                Some(brsa::MessageRandomizer(randomizer))
+            }
+            Some(_) => return Err(AuthorizationError::Forged),
+            None => None,
+        };
+        let signature = brsa::Signature(self.signature.clone());
+        if trusted.iter().any(|key| {
+            signature
+                .verify(&key.0, randomizer, &self.token, &options())
+                .is_ok()
+        }) {
+            Ok(())
+        } else {
+            Err(AuthorizationError::Forged)
+        }
+    }
+}
+
+fn options() -> brsa::Options {
+    brsa::Options::default()
+}
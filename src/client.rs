@@ -0,0 +1,921 @@
+//! Implements a client for the authority server.
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::convert::{TryFrom, TryInto};
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::thread;
+use std::time::Duration;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use derive_more::{Display, Error};
+use serde::de::{DeserializeOwned, IgnoredAny};
+use serde::Serialize;
+
+use crate::api::{
+    encode_prefix, AuthorityKeys, ErrorResponse, InclusionRequest, SignedTaintList,
+    SubscriptionRequest, TaintListPage, TaintListScope, UploadRequest, UploadResponse,
+};
+use crate::auth::HashedIdentity;
+use crate::authcode::AuthorizationCode;
+use crate::crypto::{random_below, random_bytes, shuffle, PublicKey};
+use crate::descriptor::{AuthorityDescriptor, DescriptorCache, DescriptorError, SignedDescriptor};
+use crate::federation::ForwardedContacts;
+use crate::registry::UploadId;
+use crate::status::{check_status, ExposureStatus, TaintList};
+use crate::transparency::{
+    ConsistencyProof, LogEntry, LogInclusion, SignedTreeHead, TransparencyError, TreeHead,
+};
+use crate::upload::{UploadBundle, UploadError};
+
+/// How often a failed request is retried by default.
+pub const DEFAULT_MAX_RETRIES: u32 = 3;
+
+/// The number of bytes of a hashed identity sent with status queries by
+/// default.
+pub const DEFAULT_PREFIX_LEN: usize = 2;
+
+/// The number of days a decoy prefix is queried before it is replaced.
+const DECOY_ROTATION_DAYS: i64 = 14;
+
+/// Error for requests to the authority server that failed.
+#[derive(Debug, Error, Display)]
+pub enum ClientError {
+    /// The server could not be reached.
+    #[display(fmt = "request failed: {}", _0)]
+    Transport(io::Error),
+    /// The server rejected the request.
+    #[display(fmt = "server responded with status {}: {}", status, message)]
+    Status { status: u16, message: String },
+    /// The server responded with something that cannot be decoded.
+    #[display(fmt = "invalid response: {}", _0)]
+    InvalidResponse(serde_json::Error),
+    /// An upload bundle cannot be sealed.
+    #[display(fmt = "{}", _0)]
+    Upload(UploadError),
+    /// A downloaded authority descriptor was not accepted.
+    #[display(fmt = "{}", _0)]
+    Descriptor(DescriptorError),
+    /// A tree head or proof of the transparency log does not verify.
+    #[display(fmt = "{}", _0)]
+    Transparency(TransparencyError),
+    /// A downloaded taint list was not signed by the authority.
+    #[display(fmt = "taint list has an invalid signature")]
+    InvalidSignature,
+    /// A downloaded taint list is older than the last one accepted.
+    #[display(fmt = "taint list generated at {} is outdated", generated)]
+    OutdatedTaintList { generated: DateTime<Utc> },
+    /// The pages of a taint list do not add up to its signed total.
+    #[display(fmt = "taint list pages are incomplete")]
+    IncompleteTaintList,
+    /// The URL of the server is not supported.
+    #[display(fmt = "invalid server url: {}", url)]
+    InvalidUrl {
+        #[error(not(source))]
+        url: String,
+    },
+}
+
+/// The method of a request.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Method {
+    /// Fetches a resource.
+    Get,
+    /// Submits data.
+    Post,
+    /// Removes a resource.
+    Delete,
+}
+
+impl Method {
+    fn as_str(self) -> &'static str {
+        match self {
+            Method::Get => "GET",
+            Method::Post => "POST",
+            Method::Delete => "DELETE",
+        }
+    }
+}
+
+/// A response to a request.
+#[derive(Clone, Debug)]
+pub struct Response {
+    /// The HTTP status code.
+    pub status: u16,
+    /// The body of the response.
+    pub body: Vec<u8>,
+}
+
+/// Configures the cover traffic a [`Client`](struct.Client.html) sends.
+///
+/// Status queries are padded with the prefixes of decoy hashed identities
+/// so that the number of queries does not reveal how many identities a
+/// device used.  Decoys are derived from a secret of the device (see
+/// [`Client::decoy_secret`](struct.Client.html#method.decoy_secret)) and
+/// are queried on every check and replaced one at a time, just like real
+/// identities, so that a server comparing checks cannot tell them apart.
+/// Decoy bundles are uploaded at random intervals so that
+/// real uploads do not stand out.  The authority answers decoys like real
+/// requests but does not store them.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CoverConfig {
+    /// Status checks query at least this many prefixes.
+    pub min_status_queries: usize,
+    /// Up to this many additional decoy prefixes are queried.  The number
+    /// is derived from the decoy secret so it stays the same between checks.
+    pub max_extra_queries: usize,
+    /// The shortest time between two decoy uploads.
+    pub min_upload_interval: chrono::Duration,
+    /// The longest time between two decoy uploads.
+    pub max_upload_interval: chrono::Duration,
+    /// The largest number of contacts in a decoy upload.
+    pub max_decoy_contacts: usize,
+}
+
+impl Default for CoverConfig {
+    fn default() -> CoverConfig {
+        CoverConfig {
+            min_status_queries: 16,
+            max_extra_queries: 8,
+            min_upload_interval: chrono::Duration::hours(12),
+            max_upload_interval: chrono::Duration::hours(72),
+            max_decoy_contacts: 200,
+        }
+    }
+}
+
+impl CoverConfig {
+    fn upload_interval(&self) -> chrono::Duration {
+        let spread = (self.max_upload_interval - self.min_upload_interval)
+            .num_seconds()
+            .clamp(0, i64::from(u32::MAX - 1));
+        self.min_upload_interval
+            + chrono::Duration::seconds(i64::from(random_below(spread as u32 + 1)))
+    }
+}
+
+/// Carries requests to the authority server.
+///
+/// The [`Client`](struct.Client.html) talks to the server through this
+/// trait.  [`HttpTransport`](struct.HttpTransport.html) sends requests over
+/// the network and [`MockTransport`](struct.MockTransport.html) answers them
+/// in-process for tests.
+pub trait Transport {
+    /// Sends a request and returns the response.
+    ///
+    /// The path includes the query string.  This only fails if no response
+    /// was received, error statuses are returned as responses.
+    fn send(&mut self, method: Method, path: &str, body: &[u8]) -> io::Result<Response>;
+}
+
+impl<T: Transport + ?Sized> Transport for &mut T {
+    fn send(&mut self, method: Method, path: &str, body: &[u8]) -> io::Result<Response> {
+        (**self).send(method, path, body)
+    }
+}
+
+/// A transport that sends requests over plain HTTP.
+///
+/// Every request opens a new connection.  TLS is not supported, so the
+/// server should be put behind a proxy that terminates it.
+#[derive(Clone, Debug)]
+pub struct HttpTransport {
+    host: String,
+    base_path: String,
+    timeout: Duration,
+}
+
+impl HttpTransport {
+    /// Creates a transport for a server URL (for instance
+    /// `http://127.0.0.1:8080`).
+    pub fn new(url: &str) -> Result<HttpTransport, ClientError> {
+        let invalid = || ClientError::InvalidUrl { url: url.into() };
+        let rest = url.strip_prefix("http://").ok_or_else(invalid)?;
+        let (host, base_path) = match rest.find('/') {
+            Some(idx) => (&rest[..idx], rest[idx..].trim_end_matches('/')),
+            None => (rest, ""),
+        };
+        if host.is_empty() {
+            return Err(invalid());
+        }
+        let host = if host.ends_with(']') || !host.contains(':') {
+            format!("{}:80", host)
+        } else {
+            host.to_string()
+        };
+        Ok(HttpTransport {
+            host,
+            base_path: base_path.into(),
+            timeout: Duration::from_secs(30),
+        })
+    }
+
+    /// Changes how long connecting, sending and receiving may take.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    fn connect(&self) -> io::Result<TcpStream> {
+        let mut last_err = None;
+        for addr in self.host.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, self.timeout) {
+                Ok(stream) => {
+                    stream.set_read_timeout(Some(self.timeout))?;
+                    stream.set_write_timeout(Some(self.timeout))?;
+                    return Ok(stream);
+                }
+                Err(err) => last_err = Some(err),
+            }
+        }
+        Err(last_err
+            .unwrap_or_else(|| io::Error::new(io::ErrorKind::NotFound, "host did not resolve")))
+    }
+}
+
+impl Transport for HttpTransport {
+    fn send(&mut self, method: Method, path: &str, body: &[u8]) -> io::Result<Response> {
+        let mut stream = self.connect()?;
+        let mut request = format!(
+            "{} {}{} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n",
+            method.as_str(),
+            self.base_path,
+            path,
+            self.host,
+            body.len()
+        )
+        .into_bytes();
+        request.extend_from_slice(body);
+        stream.write_all(&request)?;
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response)?;
+        parse_response(&response)
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn parse_response(response: &[u8]) -> io::Result<Response> {
+    let split = response
+        .windows(4)
+        .position(|x| x == b"\r\n\r\n")
+        .ok_or_else(|| invalid_data("incomplete response"))?;
+    let head = std::str::from_utf8(&response[..split])
+        .map_err(|_| invalid_data("invalid response header"))?;
+    let mut lines = head.split("\r\n");
+    let status = lines
+        .next()
+        .and_then(|line| line.split(' ').nth(1))
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(|| invalid_data("invalid status line"))?;
+    let mut body = &response[split + 4..];
+    let mut chunked = false;
+    for line in lines {
+        let idx = line
+            .find(':')
+            .ok_or_else(|| invalid_data("invalid header"))?;
+        let value = line[idx + 1..].trim();
+        match line[..idx].to_ascii_lowercase().as_str() {
+            "content-length" => {
+                let len = value
+                    .parse()
+                    .map_err(|_| invalid_data("invalid content length"))?;
+                body = body
+                    .get(..len)
+                    .ok_or_else(|| invalid_data("truncated response"))?;
+            }
+            "transfer-encoding" => chunked = value.eq_ignore_ascii_case("chunked"),
+            _ => {}
+        }
+    }
+    let body = if chunked {
+        decode_chunked(body)?
+    } else {
+        body.to_vec()
+    };
+    Ok(Response { status, body })
+}
+
+fn decode_chunked(mut data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut rv = Vec::new();
+    loop {
+        let idx = data
+            .windows(2)
+            .position(|x| x == b"\r\n")
+            .ok_or_else(|| invalid_data("truncated chunk"))?;
+        let size = std::str::from_utf8(&data[..idx])
+            .ok()
+            .and_then(|line| usize::from_str_radix(line.split(';').next()?.trim(), 16).ok())
+            .ok_or_else(|| invalid_data("invalid chunk size"))?;
+        data = &data[idx + 2..];
+        if size == 0 {
+            return Ok(rv);
+        }
+        rv.extend_from_slice(
+            data.get(..size)
+                .ok_or_else(|| invalid_data("truncated chunk"))?,
+        );
+        data = data.get(size + 2..).unwrap_or_default();
+    }
+}
+
+/// A request recorded by a [`MockTransport`](struct.MockTransport.html).
+#[derive(Clone, Debug)]
+pub struct MockRequest {
+    /// The method of the request.
+    pub method: Method,
+    /// The path of the request including the query string.
+    pub path: String,
+    /// The body of the request.
+    pub body: Vec<u8>,
+}
+
+/// A transport that answers requests in-process.
+///
+/// Responses are queued up front and handed out in order, and all requests
+/// are recorded.  Once the queue is empty requests go to the handler if one
+/// was set and otherwise fail as if the server could not be reached.
+#[derive(Debug, Default)]
+pub struct MockTransport {
+    responses: VecDeque<Result<Response, io::ErrorKind>>,
+    requests: Vec<MockRequest>,
+    handler: Option<MockHandler>,
+}
+
+struct MockHandler(Box<dyn FnMut(&MockRequest) -> Response + Send>);
+
+impl fmt::Debug for MockHandler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("MockHandler")
+    }
+}
+
+impl MockTransport {
+    /// Creates a transport without queued responses.
+    pub fn new() -> MockTransport {
+        MockTransport::default()
+    }
+
+    /// Queues a response.
+    pub fn push_response(&mut self, status: u16, body: Vec<u8>) {
+        self.responses.push_back(Ok(Response { status, body }));
+    }
+
+    /// Queues a successful response with a JSON body.
+    pub fn push_json<T: Serialize>(&mut self, value: &T) {
+        self.push_response(200, serde_json::to_vec(value).unwrap());
+    }
+
+    /// Queues a failure to reach the server.
+    pub fn push_error(&mut self, kind: io::ErrorKind) {
+        self.responses.push_back(Err(kind));
+    }
+
+    /// Answers requests with a function once the queue is empty.
+    ///
+    /// This is useful for requests that cannot be predicted (for instance
+    /// the decoy queries of cover traffic).
+    pub fn set_handler<F>(&mut self, handler: F)
+    where
+        F: FnMut(&MockRequest) -> Response + Send + 'static,
+    {
+        self.handler = Some(MockHandler(Box::new(handler)));
+    }
+
+    /// Returns the requests sent so far.
+    pub fn requests(&self) -> &[MockRequest] {
+        &self.requests
+    }
+}
+
+impl Transport for MockTransport {
+    fn send(&mut self, method: Method, path: &str, body: &[u8]) -> io::Result<Response> {
+        let request = MockRequest {
+            method,
+            path: path.into(),
+            body: body.to_vec(),
+        };
+        let rv = match (self.responses.pop_front(), &mut self.handler) {
+            (Some(Ok(response)), _) => Ok(response),
+            (Some(Err(kind)), _) => Err(kind.into()),
+            (None, Some(handler)) => Ok((handler.0)(&request)),
+            (None, None) => Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "no response queued",
+            )),
+        };
+        self.requests.push(request);
+        rv
+    }
+}
+
+/// A client for the authority server.
+///
+/// The client fetches the keys of the authority on first use unless they
+/// were supplied with [`with_keys`](#method.with_keys).  Every downloaded
+/// taint list is verified against the signing key of the authority, so an
+/// app that ships the keys does not have to trust the server it talks to.
+///
+/// The client remembers the generation time of the newest list it accepted
+/// and rejects older ones, so a server cannot replay a list from before
+/// identities were marked.  Apps should persist it with
+/// [`last_accepted`](#method.last_accepted) between runs.
+///
+/// Requests are retried when the server cannot be reached or fails with a
+/// server error.  Uploads are never retried since authorization codes can
+/// only be redeemed once.
+///
+/// Cover traffic is disabled by default and can be enabled with
+/// [`set_cover`](#method.set_cover).
+pub struct Client<T> {
+    transport: T,
+    keys: Option<AuthorityKeys>,
+    max_retries: u32,
+    retry_delay: Duration,
+    prefix_len: usize,
+    cover: Option<CoverConfig>,
+    next_decoy_upload: Option<DateTime<Utc>>,
+    decoy_secret: [u8; 32],
+    last_accepted: Option<DateTime<Utc>>,
+}
+
+impl<T: Transport> Client<T> {
+    /// Creates a client that fetches the keys of the authority.
+    pub fn new(transport: T) -> Client<T> {
+        let mut decoy_secret = [0u8; 32];
+        random_bytes(&mut decoy_secret);
+        Client {
+            transport,
+            keys: None,
+            max_retries: DEFAULT_MAX_RETRIES,
+            retry_delay: Duration::from_millis(500),
+            prefix_len: DEFAULT_PREFIX_LEN,
+            cover: None,
+            next_decoy_upload: None,
+            decoy_secret,
+            last_accepted: None,
+        }
+    }
+
+    /// Creates a client with known keys of the authority.
+    pub fn with_keys(transport: T, keys: AuthorityKeys) -> Client<T> {
+        Client {
+            keys: Some(keys),
+            ..Client::new(transport)
+        }
+    }
+
+    /// Returns the transport.
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    /// Returns the transport for modifications.
+    pub fn transport_mut(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    /// Changes how often a failed request is retried.
+    pub fn set_max_retries(&mut self, max_retries: u32) {
+        self.max_retries = max_retries;
+    }
+
+    /// Changes the delay before the first retry.
+    ///
+    /// The delay doubles with every further retry.
+    pub fn set_retry_delay(&mut self, retry_delay: Duration) {
+        self.retry_delay = retry_delay;
+    }
+
+    /// Changes how many bytes of a hashed identity are sent with status
+    /// queries.
+    ///
+    /// This is cut to [`MAX_PREFIX_LEN`](constant.MAX_PREFIX_LEN.html).
+    /// Shorter prefixes reveal less but download more of the list.
+    pub fn set_prefix_len(&mut self, prefix_len: usize) {
+        self.prefix_len = prefix_len.max(1);
+    }
+
+    /// Enables or disables cover traffic.
+    ///
+    /// Enabling it schedules the first decoy upload at a random time within
+    /// the configured interval.
+    pub fn set_cover(&mut self, cover: Option<CoverConfig>) {
+        self.next_decoy_upload = cover
+            .as_ref()
+            .map(|cover| Utc::now() + cover.upload_interval());
+        self.cover = cover;
+    }
+
+    /// Returns the cover traffic config.
+    pub fn cover(&self) -> Option<&CoverConfig> {
+        self.cover.as_ref()
+    }
+
+    /// Returns when the next decoy upload is due.
+    pub fn next_decoy_upload(&self) -> Option<DateTime<Utc>> {
+        self.next_decoy_upload
+    }
+
+    /// Returns the secret the decoy prefixes of status checks are derived
+    /// from.
+    ///
+    /// A new client picks a random secret.  Apps should persist it between
+    /// runs so that the same decoys keep being queried.
+    pub fn decoy_secret(&self) -> &[u8; 32] {
+        &self.decoy_secret
+    }
+
+    /// Restores the secret the decoy prefixes are derived from.
+    pub fn set_decoy_secret(&mut self, decoy_secret: [u8; 32]) {
+        self.decoy_secret = decoy_secret;
+    }
+
+    /// Returns the generation time of the newest taint list accepted.
+    pub fn last_accepted(&self) -> Option<DateTime<Utc>> {
+        self.last_accepted
+    }
+
+    /// Restores the generation time of the newest taint list accepted.
+    ///
+    /// Lists generated before it are rejected.
+    pub fn set_last_accepted(&mut self, last_accepted: Option<DateTime<Utc>>) {
+        self.last_accepted = last_accepted;
+    }
+
+    /// Returns the keys of the authority, fetching them if necessary.
+    pub fn keys(&mut self) -> Result<&AuthorityKeys, ClientError> {
+        if self.keys.is_none() {
+            self.keys = Some(self.get("/keys")?);
+        }
+        Ok(self.keys.as_ref().unwrap())
+    }
+
+    /// Downloads the descriptor of the authority and verifies it with a
+    /// cache.
+    ///
+    /// If the descriptor is accepted the client uses the keys it lists for
+    /// the current time from then on.  If the download fails or is rejected
+    /// a still valid cached descriptor is used instead, so a compromised
+    /// server cannot force the client back to an older descriptor.
+    pub fn update_descriptor(
+        &mut self,
+        cache: &mut DescriptorCache,
+    ) -> Result<AuthorityDescriptor, ClientError> {
+        let now = Utc::now();
+        let rv = self
+            .get::<SignedDescriptor>("/descriptor")
+            .and_then(|signed| cache.update(&signed, now).map_err(ClientError::Descriptor));
+        let descriptor = match rv {
+            Ok(descriptor) => descriptor,
+            Err(err) => cache.current(now).ok_or(err)?,
+        };
+        self.keys = Some(descriptor.keys_at(now).clone());
+        Ok(descriptor)
+    }
+
+    /// Returns the public key of the authority, fetching it if necessary.
+    ///
+    /// This is the key share identities and upload bundles are sealed to.
+    pub fn public_key(&mut self) -> Result<PublicKey, ClientError> {
+        Ok(self.keys()?.public_key)
+    }
+
+    /// Downloads and verifies the full taint list.
+    pub fn fetch_taint_list(&mut self) -> Result<TaintList, ClientError> {
+        let signed: SignedTaintList = self.get("/taint-list")?;
+        self.verify(&signed, &TaintListScope::Full)
+    }
+
+    /// Brings a downloaded taint list up to date.
+    ///
+    /// This downloads all pages of the changes since the list was generated.
+    /// All pages have to come from the delta of the first page, which is
+    /// checked with the generation time and total the authority signed.  The
+    /// list is only changed once all pages were downloaded and verified and
+    /// add up to that total.
+    pub fn update_taint_list(&mut self, list: &mut TaintList) -> Result<(), ClientError> {
+        let since = list.generated();
+        let mut pages = Vec::new();
+        let mut snapshot = None;
+        let mut offset = Some(0);
+        while let Some(current) = offset {
+            let generated = snapshot.map(|(generated, _)| generated);
+            let page: TaintListPage = self.get(&delta_path(since, current, generated))?;
+            let changes = self.verify(
+                &page.list,
+                &TaintListScope::Delta {
+                    since,
+                    offset: current,
+                },
+            )?;
+            let end = current + changes.len();
+            let total = page.list.total();
+            match snapshot {
+                None => snapshot = Some((page.list.generated(), total)),
+                Some(snapshot) if snapshot != (page.list.generated(), total) => {
+                    return Err(ClientError::IncompleteTaintList);
+                }
+                Some(_) => {}
+            }
+            offset = if end < total { Some(end) } else { None };
+            if end > total || (changes.is_empty() && end < total) || page.next_offset != offset {
+                return Err(ClientError::IncompleteTaintList);
+            }
+            pages.push(changes);
+        }
+        // pages never overlap, so applying the first page last only makes the
+        // list take over its generation time
+        for page in pages.iter().rev() {
+            list.apply(page);
+        }
+        Ok(())
+    }
+
+    /// Checks the exposure status of hashed identities with the server.
+    ///
+    /// Only short prefixes of the hashed identities are sent to the server
+    /// (see [`set_prefix_len`](#method.set_prefix_len)) and the status is
+    /// determined locally from the verified matches.  With cover traffic
+    /// enabled the queries are padded with decoys and sent in random order.
+    pub fn check_status<'a, I>(&mut self, hashed_ids: I) -> Result<ExposureStatus, ClientError>
+    where
+        I: IntoIterator<Item = &'a HashedIdentity>,
+    {
+        let hashed_ids: Vec<_> = hashed_ids.into_iter().collect();
+        let mut queries: Vec<_> = hashed_ids
+            .iter()
+            .map(|hashed_id| encode_prefix(hashed_id, self.prefix_len))
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .map(|prefix| (prefix, false))
+            .collect();
+        if let Some(ref cover) = self.cover {
+            let bound = u32::try_from(cover.max_extra_queries)
+                .unwrap_or(u32::MAX)
+                .saturating_add(1);
+            let seed = HashedIdentity::decoy(&self.decoy_secret, u64::MAX, 0);
+            let extra = u32::from_be_bytes(seed.as_bytes()[..4].try_into().unwrap()) % bound;
+            let target = queries
+                .len()
+                .max(cover.min_status_queries)
+                .saturating_add(extra as usize);
+            let day = Utc::now().timestamp().div_euclid(86400);
+            let mut idx = 0;
+            while queries.len() < target {
+                // every decoy is replaced on a different day, like the
+                // identities of a device
+                let offset = (idx % DECOY_ROTATION_DAYS as u64) as i64;
+                let epoch = (day + offset).div_euclid(DECOY_ROTATION_DAYS);
+                let decoy = HashedIdentity::decoy(&self.decoy_secret, idx, epoch);
+                queries.push((encode_prefix(&decoy, self.prefix_len), true));
+                idx += 1;
+            }
+            shuffle(&mut queries);
+        }
+        let mut matches = TaintList::new(HashMap::new(), HashMap::new(), HashMap::new());
+        for (prefix, decoy) in queries {
+            let signed: SignedTaintList = self.get(&format!("/status/{}", prefix))?;
+            let list = self.verify(&signed, &TaintListScope::Prefix(prefix))?;
+            if !decoy {
+                matches.apply(&list);
+            }
+        }
+        // checking against a taint list cannot fail
+        Ok(check_status(hashed_ids, &matches).unwrap())
+    }
+
+    /// Downloads and verifies the current head of the transparency log.
+    ///
+    /// Auditors should keep the heads they saw and check new ones with
+    /// [`verify_consistency`](#method.verify_consistency).
+    pub fn tree_head(&mut self) -> Result<TreeHead, ClientError> {
+        let signed: SignedTreeHead = self.get("/transparency/head")?;
+        let signing_public_key = self.keys()?.signing_public_key;
+        signed
+            .verify(&signing_public_key)
+            .map_err(ClientError::Transparency)
+    }
+
+    /// Downloads the transparency log entries of a hashed identity.
+    ///
+    /// Every entry is verified to be included in the tree of the head.
+    pub fn log_entries(
+        &mut self,
+        hashed_id: &HashedIdentity,
+        head: &TreeHead,
+    ) -> Result<Vec<LogEntry>, ClientError> {
+        // inclusion requests only consist of strings and numbers so this
+        // cannot fail
+        let body = serde_json::to_vec(&InclusionRequest {
+            hashed_id: *hashed_id,
+            tree_size: head.tree_size,
+        })
+        .unwrap();
+        let inclusions: Vec<LogInclusion> =
+            self.send(Method::Post, "/transparency/inclusion", &body)?;
+        inclusions
+            .into_iter()
+            .map(|inclusion| {
+                if inclusion.entry.hashed_id != *hashed_id {
+                    return Err(ClientError::Transparency(TransparencyError::InvalidProof));
+                }
+                inclusion
+                    .proof
+                    .verify(&inclusion.entry, head)
+                    .map_err(ClientError::Transparency)?;
+                Ok(inclusion.entry)
+            })
+            .collect()
+    }
+
+    /// Verifies that the transparency log only grew between two heads.
+    pub fn verify_consistency(
+        &mut self,
+        old: &TreeHead,
+        new: &TreeHead,
+    ) -> Result<(), ClientError> {
+        let proof: ConsistencyProof = self.get(&format!(
+            "/transparency/consistency?from={}&to={}",
+            old.tree_size, new.tree_size
+        ))?;
+        proof.verify(old, new).map_err(ClientError::Transparency)
+    }
+
+    /// Uploads a bundle and returns the ID the registry assigned to it.
+    pub fn upload(&mut self, request: &UploadRequest) -> Result<UploadId, ClientError> {
+        // upload requests only consist of strings so this cannot fail
+        let body = serde_json::to_vec(request).unwrap();
+        let response = self
+            .transport
+            .send(Method::Post, "/uploads", &body)
+            .map_err(ClientError::Transport)?;
+        let response: UploadResponse = decode(response)?;
+        Ok(response.upload_id)
+    }
+
+    /// Forwards contacts to the authority they were sealed to.
+    ///
+    /// This is used between authorities: the batches come from
+    /// [`Registry::take_forwards`](struct.Registry.html#method.take_forwards)
+    /// and are imported by the server of the receiving authority.
+    pub fn forward(&mut self, forwarded: &ForwardedContacts) -> Result<UploadId, ClientError> {
+        // forwarded contacts only consist of strings so this cannot fail
+        let body = serde_json::to_vec(forwarded).unwrap();
+        let response = self
+            .transport
+            .send(Method::Post, "/forwards", &body)
+            .map_err(ClientError::Transport)?;
+        let response: UploadResponse = decode(response)?;
+        Ok(response.upload_id)
+    }
+
+    /// Uploads a decoy bundle.
+    ///
+    /// The bundle carries a random number of contacts (up to
+    /// [`max_decoy_contacts`](struct.CoverConfig.html#structfield.max_decoy_contacts))
+    /// and is accompanied by an authorization code that names the authority
+    /// as its issuer but is signed by a throwaway key.
+    pub fn send_decoy_upload(&mut self) -> Result<(), ClientError> {
+        let max_contacts = self
+            .cover
+            .as_ref()
+            .map_or(CoverConfig::default().max_decoy_contacts, |cover| {
+                cover.max_decoy_contacts
+            });
+        let keys = self.keys()?.clone();
+        let public_key = keys.public_key;
+        let contacts = random_below(max_contacts.min(u32::MAX as usize - 1) as u32 + 1);
+        let bundle = UploadBundle::decoy(&public_key, contacts as usize)
+            .seal(&public_key)
+            .map_err(ClientError::Upload)?;
+        self.upload(&UploadRequest {
+            bundle,
+            code: Some(AuthorizationCode::decoy(&keys.signing_public_key)),
+            #[cfg(feature = "blind")]
+            credential: None,
+        })?;
+        Ok(())
+    }
+
+    /// Sends a decoy upload if one is due.
+    ///
+    /// Apps with cover traffic enabled should call this regularly (for
+    /// instance whenever they check the status).  Returns `true` if a decoy
+    /// was sent, in which case the next one is scheduled.
+    pub fn send_cover_traffic(&mut self) -> Result<bool, ClientError> {
+        let interval = match (&self.cover, self.next_decoy_upload) {
+            (Some(cover), Some(due)) if due <= Utc::now() => cover.upload_interval(),
+            _ => return Ok(false),
+        };
+        self.send_decoy_upload()?;
+        self.next_decoy_upload = Some(Utc::now() + interval);
+        Ok(true)
+    }
+
+    /// Subscribes to push notifications for hashed identities.
+    ///
+    /// Every token is sent in a request of its own and in random order, so
+    /// that the server cannot tell from a request which tokens belong to the
+    /// same device.
+    pub fn subscribe<'a, I>(&mut self, hashed_ids: I) -> Result<(), ClientError>
+    where
+        I: IntoIterator<Item = &'a HashedIdentity>,
+    {
+        let mut tokens: Vec<_> = hashed_ids
+            .into_iter()
+            .map(|hashed_id| hashed_id.subscription_token())
+            .collect();
+        shuffle(&mut tokens);
+        for token in tokens {
+            let request = SubscriptionRequest {
+                tokens: vec![token],
+            };
+            // subscription requests only consist of strings so this cannot fail
+            let body = serde_json::to_vec(&request).unwrap();
+            self.send::<IgnoredAny>(Method::Post, "/subscriptions", &body)?;
+        }
+        Ok(())
+    }
+
+    /// Removes the subscription for a hashed identity.
+    pub fn unsubscribe(&mut self, hashed_id: &HashedIdentity) -> Result<(), ClientError> {
+        let path = format!("/subscriptions/{}", hashed_id.subscription_token());
+        self.send::<IgnoredAny>(Method::Delete, &path, b"")?;
+        Ok(())
+    }
+
+    fn verify(
+        &mut self,
+        signed: &SignedTaintList,
+        scope: &TaintListScope,
+    ) -> Result<TaintList, ClientError> {
+        let signing_public_key = self.keys()?.signing_public_key;
+        let list = signed.verify(&signing_public_key, scope)?;
+        match self.last_accepted {
+            Some(last_accepted) if list.generated() < last_accepted => {
+                return Err(ClientError::OutdatedTaintList {
+                    generated: list.generated(),
+                });
+            }
+            _ => self.last_accepted = Some(list.generated()),
+        }
+        Ok(list)
+    }
+
+    fn get<D: DeserializeOwned>(&mut self, path: &str) -> Result<D, ClientError> {
+        self.send(Method::Get, path, b"")
+    }
+
+    fn send<D: DeserializeOwned>(
+        &mut self,
+        method: Method,
+        path: &str,
+        body: &[u8],
+    ) -> Result<D, ClientError> {
+        let mut delay = self.retry_delay;
+        let mut attempt = 0;
+        loop {
+            let rv = self.transport.send(method, path, body);
+            let retry = match rv {
+                Ok(ref response) => response.status >= 500,
+                Err(_) => true,
+            };
+            if !retry || attempt >= self.max_retries {
+                return decode(rv.map_err(ClientError::Transport)?);
+            }
+            attempt += 1;
+            thread::sleep(delay);
+            delay *= 2;
+        }
+    }
+}
+
+fn delta_path(since: DateTime<Utc>, offset: usize, generated: Option<DateTime<Utc>>) -> String {
+    let mut rv = format!(
+        "/taint-list/delta?since={}&offset={}",
+        since.to_rfc3339_opts(SecondsFormat::Nanos, true),
+        offset
+    );
+    if let Some(generated) = generated {
+        rv.push_str("&generated=");
+        rv.push_str(&generated.to_rfc3339_opts(SecondsFormat::Nanos, true));
+    }
+    rv
+}
+
+fn decode<D: DeserializeOwned>(response: Response) -> Result<D, ClientError> {
+    if response.status != 200 {
+        let message = match serde_json::from_slice::<ErrorResponse>(&response.body) {
+            Ok(err) => err.error,
+            Err(_) => String::from_utf8_lossy(&response.body).into_owned(),
+        };
+        return Err(ClientError::Status {
+            status: response.status,
+            message,
+        });
+    }
+    serde_json::from_slice(&response.body).map_err(ClientError::InvalidResponse)
+}
@@ -1,18 +1,85 @@
 //! Implements the contact log.
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use std::convert::TryFrom;
 
 use chrono::{DateTime, Utc};
-use serde::{Deserialize, Serialize};
+use serde::{ser, Deserialize, Serialize};
 
 use crate::auth::{ShareIdentity, UniqueIdentity};
 use crate::crypto::SecretKey;
+use crate::envelope::{self, ArtifactKind, EnvelopeError};
+use crate::error::Error;
+use crate::federation::AuthorityId;
 
 /// Represents contacts observed recently.
-#[derive(Serialize, Deserialize)]
+///
+/// The log serializes with a versioned envelope header (see
+/// [`ArtifactKind`](enum.ArtifactKind.html)).
+#[derive(Deserialize, Clone)]
+#[serde(try_from = "RawContactLog")]
 pub struct ContactLog {
     seen: HashMap<ShareIdentity, DateTime<Utc>>,
 }
 
+#[derive(Serialize)]
+struct ContactLogRef<'a> {
+    magic: &'static str,
+    version: u8,
+    #[serde(rename = "type")]
+    kind: ArtifactKind,
+    seen: &'a HashMap<ShareIdentity, DateTime<Utc>>,
+}
+
+#[derive(Deserialize)]
+struct RawContactLog {
+    #[serde(default)]
+    magic: Option<String>,
+    #[serde(default)]
+    version: Option<u8>,
+    #[serde(default, rename = "type")]
+    kind: Option<ArtifactKind>,
+    #[serde(default)]
+    seen: Option<HashMap<ShareIdentity, DateTime<Utc>>>,
+}
+
+impl TryFrom<RawContactLog> for ContactLog {
+    type Error = EnvelopeError;
+
+    fn try_from(raw: RawContactLog) -> Result<ContactLog, EnvelopeError> {
+        let kind = ArtifactKind::ContactLog;
+        let version = envelope::check_header(kind, raw.magic.as_deref(), raw.version, raw.kind)?;
+        // version 0 (unversioned) and version 1 share the same layout
+        match raw.seen {
+            Some(seen) => Ok(ContactLog { seen }),
+            None => Err(EnvelopeError::InvalidPayload { kind, version }),
+        }
+    }
+}
+
+impl fmt::Debug for ContactLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ContactLog({} contacts)", self.seen.len())
+    }
+}
+
+impl Serialize for ContactLog {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: ser::Serializer,
+    {
+        let kind = ArtifactKind::ContactLog;
+        ContactLogRef {
+            magic: envelope::MAGIC_STR,
+            version: kind.current_version(),
+            kind,
+            seen: &self.seen,
+        }
+        .serialize(serializer)
+    }
+}
+
 impl ContactLog {
     /// Creates an empty contact log.
     pub fn new() -> ContactLog {
@@ -23,21 +90,72 @@ impl ContactLog {
 
     /// Registers a contact and the current timestamp.
     pub fn add(&mut self, share_id: &ShareIdentity) {
-        self.seen.insert(share_id.clone(), Utc::now());
+        self.add_at(share_id, Utc::now());
+    }
+
+    /// Registers a contact seen at the given timestamp.
+    pub fn add_at(&mut self, share_id: &ShareIdentity, seen: DateTime<Utc>) {
+        self.seen.insert(share_id.clone(), seen);
+    }
+
+    /// Returns the number of contacts.
+    pub fn len(&self) -> usize {
+        self.seen.len()
+    }
+
+    /// Returns `true` if no contacts were registered.
+    pub fn is_empty(&self) -> bool {
+        self.seen.is_empty()
+    }
+
+    /// Adds the contacts of another log, keeping the latest timestamp of
+    /// contacts seen in both.
+    pub fn merge(&mut self, other: &ContactLog) {
+        for (share_id, &seen) in other.seen.iter() {
+            let entry = self.seen.entry(share_id.clone()).or_insert(seen);
+            if *entry < seen {
+                *entry = seen;
+            }
+        }
+    }
+
+    /// Partitions the contacts by the authority they were sealed to.
+    ///
+    /// Returns the contacts of the given authority and a log for every
+    /// other authority.  Contacts without an authority ID (from before share
+    /// IDs were tagged) are attributed to the given authority.
+    pub fn partition(&self, own: &AuthorityId) -> (ContactLog, BTreeMap<AuthorityId, ContactLog>) {
+        let mut mine = ContactLog::new();
+        let mut others: BTreeMap<AuthorityId, ContactLog> = BTreeMap::new();
+        for (share_id, &seen) in self.seen.iter() {
+            match share_id.authority_id() {
+                Some(authority_id) if authority_id != own => others
+                    .entry(*authority_id)
+                    .or_default()
+                    .add_at(share_id, seen),
+                _ => mine.add_at(share_id, seen),
+            }
+        }
+        (mine, others)
     }
 
     /// Decodes the contacts with the secret key of the authority.
     ///
-    /// This returns `None` if decoding fails (invalid key or data).
-    pub fn decode(&self, secret_key: &SecretKey) -> Option<Vec<(UniqueIdentity, DateTime<Utc>)>> {
+    /// This fails if any contact cannot be revealed (invalid key or data).
+    /// Use [`partition`](#method.partition) first to only decode the
+    /// contacts sealed to the authority.
+    pub fn decode(
+        &self,
+        secret_key: &SecretKey,
+    ) -> Result<Vec<(UniqueIdentity, DateTime<Utc>)>, Error> {
         let mut rv = HashMap::new();
         for (contact, &timestamp) in self.seen.iter() {
             let unique_id = contact.reveal(secret_key)?;
-            if rv.get(&unique_id).map_or(true, |old| *old < timestamp) {
+            if rv.get(&unique_id).is_none_or(|old| *old < timestamp) {
                 rv.insert(unique_id, timestamp);
             }
         }
-        Some(rv.into_iter().collect())
+        Ok(rv.into_iter().collect())
     }
 }
 
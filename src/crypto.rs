@@ -1,37 +1,205 @@
 //! Internal crypto abstractions.
-use derive_more::{Display, Error};
-use serde::{Deserialize, Serialize};
-use serde_plain::{forward_display_to_serde, forward_from_str_to_serde};
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{de, ser, Deserialize, Serialize};
+use serde_plain::forward_display_to_serde;
+use sha2::{Digest, Sha256};
 use sodiumoxide::crypto::box_::curve25519xsalsa20poly1305 as box_impl;
 use sodiumoxide::crypto::sealedbox::curve25519blake2bxsalsa20poly1305 as sealbox_impl;
+use sodiumoxide::crypto::sign::ed25519 as sign_impl;
+use zeroize::Zeroize;
 
-/// Represents a public key.
-#[derive(Serialize, Deserialize, Copy, Clone, Debug)]
-pub struct PublicKey(#[serde(with = "crate::utils::base64")] box_impl::PublicKey);
+use crate::envelope::{self, ArtifactKind};
+use crate::error::Error;
+use crate::federation::AuthorityId;
+use crate::utils::base64;
 
-/// Error for invalid public keys.
-#[derive(Debug, Error, Display, Clone)]
-#[display(fmt = "cannot parse public key")]
-pub struct PublicKeyParseError;
+/// Represents a public key.
+#[derive(Copy, Clone, Debug)]
+pub struct PublicKey(box_impl::PublicKey);
 
 forward_display_to_serde!(PublicKey);
-forward_from_str_to_serde!(PublicKey, |_x| -> PublicKeyParseError {
-    PublicKeyParseError
-});
+
+impl FromStr for PublicKey {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<PublicKey, Error> {
+        let (_, bytes) =
+            envelope::parse(ArtifactKind::PublicKey, s, Some(box_impl::PUBLICKEYBYTES))?;
+        PublicKey::from_slice(&bytes)
+    }
+}
+
+impl Serialize for PublicKey {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: ser::Serializer,
+    {
+        envelope::serialize(ArtifactKind::PublicKey, &(self.0).0[..], serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for PublicKey {
+    fn deserialize<D>(deserializer: D) -> Result<PublicKey, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        let (_, bytes) = envelope::deserialize(
+            ArtifactKind::PublicKey,
+            Some(box_impl::PUBLICKEYBYTES),
+            deserializer,
+        )?;
+        PublicKey::from_slice(&bytes).map_err(de::Error::custom)
+    }
+}
+
+impl PublicKey {
+    fn from_slice(bytes: &[u8]) -> Result<PublicKey, Error> {
+        Error::check_length(box_impl::PUBLICKEYBYTES, bytes.len())?;
+        Ok(PublicKey(box_impl::PublicKey::from_slice(bytes).unwrap()))
+    }
+
+    pub(crate) fn as_bytes(&self) -> &[u8] {
+        &(self.0).0[..]
+    }
+
+    /// Returns the identifier of the authority this key belongs to.
+    pub fn authority_id(&self) -> AuthorityId {
+        AuthorityId::derive(self)
+    }
+}
 
 /// Represents a secret key.
-#[derive(Serialize, Deserialize, Clone, Debug)]
+///
+/// The key is zeroed out when dropped and never shows up in debug output.
+#[derive(Serialize, Deserialize, Clone)]
 pub struct SecretKey(#[serde(with = "crate::utils::base64")] box_impl::SecretKey);
 
-/// Error for invalid secret keys.
-#[derive(Debug, Error, Display, Clone)]
-#[display(fmt = "cannot parse secret key")]
-pub struct SecretKeyParseError;
+impl fmt::Debug for SecretKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SecretKey(****)")
+    }
+}
 
 forward_display_to_serde!(SecretKey);
-forward_from_str_to_serde!(SecretKey, |_x| -> SecretKeyParseError {
-    SecretKeyParseError
-});
+
+impl FromStr for SecretKey {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<SecretKey, Error> {
+        let mut bytes = ::base64::decode(s)?;
+        let rv = Error::check_length(box_impl::SECRETKEYBYTES, bytes.len())
+            .map(|_| SecretKey(box_impl::SecretKey::from_slice(&bytes).unwrap()));
+        bytes.zeroize();
+        rv
+    }
+}
+
+/// Represents a public key used to verify signatures.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SigningPublicKey(sign_impl::PublicKey);
+
+forward_display_to_serde!(SigningPublicKey);
+
+impl FromStr for SigningPublicKey {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<SigningPublicKey, Error> {
+        let (_, bytes) = envelope::parse(ArtifactKind::SigningPublicKey, s, None)?;
+        SigningPublicKey::from_slice(&bytes)
+    }
+}
+
+impl Serialize for SigningPublicKey {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: ser::Serializer,
+    {
+        envelope::serialize(ArtifactKind::SigningPublicKey, &(self.0).0[..], serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for SigningPublicKey {
+    fn deserialize<D>(deserializer: D) -> Result<SigningPublicKey, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        let (_, bytes) = envelope::deserialize(ArtifactKind::SigningPublicKey, None, deserializer)?;
+        SigningPublicKey::from_slice(&bytes).map_err(de::Error::custom)
+    }
+}
+
+impl SigningPublicKey {
+    pub(crate) fn from_slice(bytes: &[u8]) -> Result<SigningPublicKey, Error> {
+        Error::check_length(sign_impl::PUBLICKEYBYTES, bytes.len())?;
+        Ok(SigningPublicKey(
+            sign_impl::PublicKey::from_slice(bytes).unwrap(),
+        ))
+    }
+
+    pub(crate) fn as_bytes(&self) -> &[u8] {
+        &(self.0).0[..]
+    }
+
+    /// Verifies a signature over a message.
+    pub fn verify(&self, message: &[u8], signature: &Signature) -> bool {
+        sign_impl::verify_detached(&signature.0, message, &self.0)
+    }
+}
+
+/// Represents a secret key used to create signatures.
+///
+/// Like [`SecretKey`](struct.SecretKey.html) it is zeroed out when dropped.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SigningSecretKey(#[serde(with = "crate::utils::base64")] sign_impl::SecretKey);
+
+impl fmt::Debug for SigningSecretKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SigningSecretKey(****)")
+    }
+}
+
+impl SigningSecretKey {
+    /// Signs a message.
+    pub fn sign(&self, message: &[u8]) -> Signature {
+        Signature(sign_impl::sign_detached(message, &self.0))
+    }
+}
+
+/// A detached signature.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Signature(sign_impl::Signature);
+
+impl Serialize for Signature {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: ser::Serializer,
+    {
+        base64::serialize(self.as_bytes(), serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Signature {
+    fn deserialize<D>(deserializer: D) -> Result<Signature, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        let bytes: Vec<u8> = base64::deserialize(deserializer)?;
+        Signature::from_slice(&bytes).map_err(de::Error::custom)
+    }
+}
+
+impl Signature {
+    pub(crate) fn from_slice(bytes: &[u8]) -> Result<Signature, Error> {
+        Error::check_length(sign_impl::SIGNATUREBYTES, bytes.len())?;
+        Ok(Signature(sign_impl::Signature::from_bytes(bytes).unwrap()))
+    }
+
+    pub(crate) fn as_bytes(&self) -> &[u8] {
+        self.0.as_ref()
+    }
+}
 
 /// Generates a new key pair.
 pub fn gen_keypair() -> (PublicKey, SecretKey) {
@@ -39,6 +207,57 @@ pub fn gen_keypair() -> (PublicKey, SecretKey) {
     (PublicKey(pk), SecretKey(sk))
 }
 
+/// Generates a new signing key pair.
+pub fn gen_signing_keypair() -> (SigningPublicKey, SigningSecretKey) {
+    let (pk, sk) = sign_impl::gen_keypair();
+    (SigningPublicKey(pk), SigningSecretKey(sk))
+}
+
+/// Derives the signing key pair that belongs to a secret key.
+///
+/// This lets an authority sign with a key that does not need to be stored
+/// separately from its encryption key.
+pub(crate) fn derive_signing_keypair(
+    secret_key: &SecretKey,
+) -> (SigningPublicKey, SigningSecretKey) {
+    let mut hasher = Sha256::new();
+    hasher.input(b"covidcotra signing key");
+    hasher.input(&(secret_key.0).0[..]);
+    let mut digest = hasher.result();
+    let seed = sign_impl::Seed::from_slice(&digest).unwrap();
+    digest.as_mut_slice().zeroize();
+    let (pk, sk) = sign_impl::keypair_from_seed(&seed);
+    (SigningPublicKey(pk), SigningSecretKey(sk))
+}
+
+/// Compares two byte strings in constant time.
+///
+/// The time taken only depends on the length of the inputs, not on where
+/// they differ.
+pub(crate) fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    sodiumoxide::utils::memcmp(a, b)
+}
+
+/// Fills a buffer with random bytes.
+pub(crate) fn random_bytes(buf: &mut [u8]) {
+    sodiumoxide::randombytes::randombytes_into(buf)
+}
+
+/// Returns a uniformly distributed random number below `upper_bound`.
+pub(crate) fn random_below(upper_bound: u32) -> u32 {
+    sodiumoxide::randombytes::randombytes_uniform(upper_bound)
+}
+
+/// Shuffles items into a uniformly random order.
+pub(crate) fn shuffle<T>(items: &mut [T]) {
+    for idx in (1..items.len()).rev() {
+        items.swap(idx, random_below(idx as u32 + 1) as usize);
+    }
+}
+
+/// The number of bytes sealing adds to a message.
+pub(crate) const SEAL_OVERHEAD: usize = sealbox_impl::SEALBYTES;
+
 /// Encrypts some bytes.
 pub(crate) fn seal(bytes: &[u8], receiver: &PublicKey) -> Vec<u8> {
     sealbox_impl::seal(bytes, &receiver.0)
@@ -0,0 +1,248 @@
+//! Implements signed authority descriptors.
+use chrono::{DateTime, Duration, Utc};
+use derive_more::{Display, Error};
+use serde::{Deserialize, Serialize};
+
+use crate::api::AuthorityKeys;
+use crate::authority::Authority;
+use crate::crypto::{
+    gen_signing_keypair, PublicKey, Signature, SigningPublicKey, SigningSecretKey,
+};
+
+const SIGNATURE_CONTEXT: &[u8] = b"covidcotra-descriptor\x00";
+
+/// Error for descriptors that are not accepted.
+#[derive(Debug, Error, Display, Clone, PartialEq, Eq)]
+pub enum DescriptorError {
+    /// The descriptor was not signed by the pinned root key.
+    #[display(fmt = "authority descriptor has an invalid signature")]
+    InvalidSignature,
+    /// The descriptor cannot be decoded.
+    #[display(fmt = "malformed authority descriptor")]
+    Malformed,
+    /// The descriptor is not valid yet.
+    #[display(fmt = "authority descriptor is not valid before {}", valid_from)]
+    NotYetValid { valid_from: DateTime<Utc> },
+    /// The descriptor has expired.
+    #[display(fmt = "authority descriptor expired at {}", valid_until)]
+    Expired { valid_until: DateTime<Utc> },
+    /// The descriptor is older than the one already cached.
+    #[display(fmt = "authority descriptor issued at {} is outdated", issued)]
+    Outdated { issued: DateTime<Utc> },
+}
+
+/// The key that signs authority descriptors.
+///
+/// The root key is kept offline by the operator of the authority.  Apps pin
+/// its public key and only accept descriptors signed by it.
+#[derive(Serialize, Deserialize)]
+pub struct RootKey {
+    secret_key: SigningSecretKey,
+    public_key: SigningPublicKey,
+}
+
+impl RootKey {
+    /// Creates a new root key.
+    pub fn unique() -> RootKey {
+        let (public_key, secret_key) = gen_signing_keypair();
+        RootKey {
+            secret_key,
+            public_key,
+        }
+    }
+
+    /// Returns the public key apps pin.
+    pub fn public_key(&self) -> &SigningPublicKey {
+        &self.public_key
+    }
+
+    /// Signs a descriptor.
+    pub fn sign(&self, descriptor: &AuthorityDescriptor) -> SignedDescriptor {
+        // descriptors only consist of strings and numbers so this cannot fail
+        let payload = serde_json::to_string(descriptor).unwrap();
+        let signature = self.secret_key.sign(&signed_message(&payload));
+        SignedDescriptor { payload, signature }
+    }
+}
+
+/// Keys that replace the current keys of an authority at a point in time.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct KeyRollover {
+    /// When the keys take over.
+    pub at: DateTime<Utc>,
+    /// The keys of the authority from then on.
+    pub keys: AuthorityKeys,
+}
+
+/// Describes an authority to the apps that talk to it.
+///
+/// Apps fetch a [`SignedDescriptor`](struct.SignedDescriptor.html), verify
+/// it against their pinned root key and use
+/// [`public_key_at`](#method.public_key_at) to pick the key to create share
+/// IDs with instead of hard-coding it.  Announcing the next keys ahead of
+/// time lets apps switch over without an update.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AuthorityDescriptor {
+    /// The name of the authority.
+    pub name: String,
+    /// The region the authority is responsible for (for instance `"AT"`).
+    pub region: String,
+    /// The current keys of the authority.
+    pub keys: AuthorityKeys,
+    /// The keys that replace the current ones.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next: Option<KeyRollover>,
+    /// The URLs of the servers of the authority.
+    #[serde(default)]
+    pub endpoints: Vec<String>,
+    /// When the descriptor was issued.
+    pub issued: DateTime<Utc>,
+    /// The descriptor is not valid before this.
+    pub valid_from: DateTime<Utc>,
+    /// The descriptor is not valid after this.
+    pub valid_until: DateTime<Utc>,
+}
+
+impl AuthorityDescriptor {
+    /// Creates a descriptor for the current keys of an authority that is
+    /// valid from now on.
+    pub fn new(
+        name: &str,
+        region: &str,
+        authority: &Authority,
+        valid_for: Duration,
+    ) -> AuthorityDescriptor {
+        let now = Utc::now();
+        AuthorityDescriptor {
+            name: name.into(),
+            region: region.into(),
+            keys: AuthorityKeys {
+                public_key: *authority.public_key(),
+                signing_public_key: *authority.signing_public_key(),
+            },
+            next: None,
+            endpoints: Vec::new(),
+            issued: now,
+            valid_from: now,
+            valid_until: now + valid_for,
+        }
+    }
+
+    /// Checks if the descriptor is valid at a point in time.
+    pub fn is_valid_at(&self, at: DateTime<Utc>) -> bool {
+        self.valid_from <= at && at <= self.valid_until
+    }
+
+    /// Returns the keys of the authority at a point in time.
+    pub fn keys_at(&self, at: DateTime<Utc>) -> &AuthorityKeys {
+        match self.next {
+            Some(ref next) if next.at <= at => &next.keys,
+            _ => &self.keys,
+        }
+    }
+
+    /// Returns the key to seal share IDs and upload bundles to at a point
+    /// in time.
+    pub fn public_key_at(&self, at: DateTime<Utc>) -> &PublicKey {
+        &self.keys_at(at).public_key
+    }
+}
+
+/// An [`AuthorityDescriptor`](struct.AuthorityDescriptor.html) signed by a
+/// [`RootKey`](struct.RootKey.html).
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SignedDescriptor {
+    payload: String,
+    signature: Signature,
+}
+
+impl SignedDescriptor {
+    /// Verifies the signature and validity period and returns the
+    /// descriptor.
+    pub fn verify(
+        &self,
+        root: &SigningPublicKey,
+        now: DateTime<Utc>,
+    ) -> Result<AuthorityDescriptor, DescriptorError> {
+        if !root.verify(&signed_message(&self.payload), &self.signature) {
+            return Err(DescriptorError::InvalidSignature);
+        }
+        let descriptor: AuthorityDescriptor =
+            serde_json::from_str(&self.payload).map_err(|_| DescriptorError::Malformed)?;
+        if now < descriptor.valid_from {
+            return Err(DescriptorError::NotYetValid {
+                valid_from: descriptor.valid_from,
+            });
+        }
+        if now > descriptor.valid_until {
+            return Err(DescriptorError::Expired {
+                valid_until: descriptor.valid_until,
+            });
+        }
+        Ok(descriptor)
+    }
+}
+
+fn signed_message(payload: &str) -> Vec<u8> {
+    let mut rv = SIGNATURE_CONTEXT.to_vec();
+    rv.extend_from_slice(payload.as_bytes());
+    rv
+}
+
+/// Keeps the latest verified descriptor of an authority.
+///
+/// The cache pins the root key and only accepts descriptors that are
+/// signed by it and not older than the cached one, so a server cannot roll
+/// an app back to keys it already retired.  It serializes together with
+/// the cached descriptor so it can be persisted between runs.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DescriptorCache {
+    root: SigningPublicKey,
+    cached: Option<SignedDescriptor>,
+}
+
+impl DescriptorCache {
+    /// Creates an empty cache for a pinned root key.
+    pub fn new(root: &SigningPublicKey) -> DescriptorCache {
+        DescriptorCache {
+            root: *root,
+            cached: None,
+        }
+    }
+
+    /// Returns the pinned root key.
+    pub fn root(&self) -> &SigningPublicKey {
+        &self.root
+    }
+
+    /// Verifies a descriptor and caches it.
+    ///
+    /// Fails if the descriptor does not verify or was issued before the
+    /// cached one, in which case the cache is left unchanged.
+    pub fn update(
+        &mut self,
+        signed: &SignedDescriptor,
+        now: DateTime<Utc>,
+    ) -> Result<AuthorityDescriptor, DescriptorError> {
+        let descriptor = signed.verify(&self.root, now)?;
+        if let Some(cached) = self.cached_descriptor() {
+            if descriptor.issued < cached.issued {
+                return Err(DescriptorError::Outdated {
+                    issued: descriptor.issued,
+                });
+            }
+        }
+        self.cached = Some(signed.clone());
+        Ok(descriptor)
+    }
+
+    /// Returns the cached descriptor if it is still valid.
+    pub fn current(&self, now: DateTime<Utc>) -> Option<AuthorityDescriptor> {
+        self.cached.as_ref()?.verify(&self.root, now).ok()
+    }
+
+    fn cached_descriptor(&self) -> Option<AuthorityDescriptor> {
+        let signed = self.cached.as_ref()?;
+        serde_json::from_str(&signed.payload).ok()
+    }
+}
@@ -0,0 +1,311 @@
+//! Implements a DP-3T style identity scheme with ephemeral IDs.
+//!
+//! This is an alternative to sealed [`ShareIdentity`](struct.ShareIdentity.html)
+//! values for interoperating with decentralized systems.  Instead of sealing
+//! its unique ID to the authority a device broadcasts ephemeral IDs derived
+//! from a secret [`DayKey`](struct.DayKey.html):
+//!
+//! 1. the key of a day is the SHA-256 hash of the key of the previous day,
+//!    so a [`DayKeyChain`](struct.DayKeyChain.html) only needs one random
+//!    starting key.
+//! 2. the ephemeral IDs of a day are the AES-256-CTR keystream under
+//!    `HMAC-SHA256(day key, "broadcast key")`, split into one 16 byte
+//!    [`EphemeralId`](struct.EphemeralId.html) per epoch of
+//!    [`EPOCH_MINUTES`](constant.EPOCH_MINUTES.html) minutes.
+//! 3. after a positive test the device publishes the key of the first day it
+//!    was contagious as a [`PublishedDayKey`](struct.PublishedDayKey.html) and
+//!    starts over with a fresh chain.
+//! 4. other devices recompute the ephemeral IDs of all following days from
+//!    the published keys and match them against what they recorded in their
+//!    [`EphemeralContactLog`](struct.EphemeralContactLog.html).
+//!
+//! Matching happens on the device, the authority never learns who was in
+//! contact with whom.  This requires the `dp3t` feature.
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use aes::cipher::{KeyIvInit, StreamCipher};
+use chrono::{DateTime, Duration, NaiveDate, Timelike, Utc};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use zeroize::Zeroize;
+
+use crate::crypto::{ct_eq, random_bytes};
+use crate::status::ExposureStatus;
+use crate::utils::base64;
+
+type Aes256Ctr = ctr::Ctr128BE<aes::Aes256>;
+
+/// The length of an epoch in minutes.
+pub const EPOCH_MINUTES: u32 = 15;
+
+/// The number of epochs (and ephemeral IDs) per day.
+pub const EPOCHS_PER_DAY: usize = 24 * 60 / EPOCH_MINUTES as usize;
+
+/// How many minutes an observation may be recorded before the start or after
+/// the end of the day of an ephemeral ID and still match (one epoch).
+pub const MATCH_TOLERANCE_MINUTES: i64 = EPOCH_MINUTES as i64;
+
+/// The number of days day keys and observations are kept for.
+pub const RETENTION_DAYS: i64 = 14;
+
+const BROADCAST_KEY: &[u8] = b"broadcast key";
+
+/// Returns the day and the epoch within the day of a point in time.
+pub fn epoch_of(at: DateTime<Utc>) -> (NaiveDate, usize) {
+    let minutes = at.hour() * 60 + at.minute();
+    (at.date_naive(), (minutes / EPOCH_MINUTES) as usize)
+}
+
+/// The secret key ephemeral IDs of a day are derived from.
+///
+/// The key is zeroed out when dropped and redacted from debug output.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct DayKey(#[serde(with = "base64")] [u8; 32]);
+
+impl fmt::Debug for DayKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "DayKey(****)")
+    }
+}
+
+impl PartialEq for DayKey {
+    fn eq(&self, other: &DayKey) -> bool {
+        ct_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for DayKey {}
+
+impl Drop for DayKey {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl DayKey {
+    /// Creates a random day key.
+    pub fn generate() -> DayKey {
+        let mut key = [0u8; 32];
+        random_bytes(&mut key);
+        DayKey(key)
+    }
+
+    /// Derives the key of the next day.
+    pub fn next(&self) -> DayKey {
+        let mut key = [0u8; 32];
+        key.copy_from_slice(&Sha256::digest(&self.0));
+        DayKey(key)
+    }
+
+    /// Derives the ephemeral IDs of the day, one per epoch.
+    pub fn ephemeral_ids(&self) -> Vec<EphemeralId> {
+        let mut mac = Hmac::<Sha256>::new_varkey(&self.0).unwrap();
+        mac.input(BROADCAST_KEY);
+        let mut prf = mac.result().code();
+        let mut cipher = Aes256Ctr::new(prf.as_slice().into(), &[0u8; 16].into());
+        prf.as_mut_slice().zeroize();
+        let mut stream = vec![0u8; EPOCHS_PER_DAY * 16];
+        cipher.apply_keystream(&mut stream);
+        stream
+            .chunks(16)
+            .map(|chunk| {
+                let mut id = [0u8; 16];
+                id.copy_from_slice(chunk);
+                EphemeralId(id)
+            })
+            .collect()
+    }
+}
+
+/// An ephemeral ID broadcast to other devices during one epoch.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct EphemeralId(#[serde(with = "base64")] [u8; 16]);
+
+impl EphemeralId {
+    /// Creates an ephemeral ID from received bytes.
+    pub fn from_bytes(bytes: [u8; 16]) -> EphemeralId {
+        EphemeralId(bytes)
+    }
+
+    /// Returns the bytes to broadcast.
+    pub fn as_bytes(&self) -> &[u8; 16] {
+        &self.0
+    }
+}
+
+/// A day key published after a positive test.
+///
+/// The ephemeral IDs of the day and of all following days can be derived
+/// from it.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PublishedDayKey {
+    /// The day the key belongs to.
+    pub day: NaiveDate,
+    /// The key of that day.
+    pub key: DayKey,
+}
+
+/// The chain of day keys of a device.
+///
+/// Keys older than [`RETENTION_DAYS`](constant.RETENTION_DAYS.html) are
+/// dropped as the chain advances.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DayKeyChain {
+    keys: BTreeMap<NaiveDate, DayKey>,
+}
+
+impl DayKeyChain {
+    /// Starts a chain with a random key for the given day.
+    pub fn new(day: NaiveDate) -> DayKeyChain {
+        let mut keys = BTreeMap::new();
+        keys.insert(day, DayKey::generate());
+        DayKeyChain { keys }
+    }
+
+    /// Returns the key of a day, advancing the chain if necessary.
+    ///
+    /// Returns `None` for days before the chain started or past the
+    /// retention period.
+    pub fn day_key(&mut self, day: NaiveDate) -> Option<&DayKey> {
+        let (mut latest_day, mut latest_key) = self
+            .keys
+            .iter()
+            .next_back()
+            .map(|(day, key)| (*day, key.clone()))?;
+        while latest_day < day {
+            latest_key = latest_key.next();
+            latest_day = latest_day.succ_opt()?;
+            self.keys.insert(latest_day, latest_key.clone());
+        }
+        let cutoff = latest_day - Duration::days(RETENTION_DAYS);
+        self.keys.retain(|day, _| *day >= cutoff);
+        self.keys.get(&day)
+    }
+
+    /// Returns the ephemeral ID to broadcast at a point in time.
+    pub fn ephemeral_id(&mut self, at: DateTime<Utc>) -> Option<EphemeralId> {
+        let (day, epoch) = epoch_of(at);
+        Some(self.day_key(day)?.ephemeral_ids()[epoch])
+    }
+
+    /// Returns the key to publish after a positive test.
+    ///
+    /// `first_day` is the first day the user was contagious.  Returns `None`
+    /// if the key of that day is no longer kept.
+    pub fn publish(&self, first_day: NaiveDate) -> Option<PublishedDayKey> {
+        self.keys.get(&first_day).map(|key| PublishedDayKey {
+            day: first_day,
+            key: key.clone(),
+        })
+    }
+
+    /// Replaces the chain with a fresh random key for the given day.
+    ///
+    /// This must be done after publishing a key since all later ephemeral IDs
+    /// of the old chain can be derived from it.
+    pub fn rotate(&mut self, day: NaiveDate) {
+        *self = DayKeyChain::new(day);
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct Observation {
+    ephemeral_id: EphemeralId,
+    at: DateTime<Utc>,
+}
+
+/// The ephemeral IDs a device received from other devices.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct EphemeralContactLog {
+    seen: Vec<Observation>,
+}
+
+impl EphemeralContactLog {
+    /// Creates an empty log.
+    pub fn new() -> EphemeralContactLog {
+        EphemeralContactLog::default()
+    }
+
+    /// Records an ephemeral ID received at a point in time.
+    pub fn add(&mut self, ephemeral_id: EphemeralId, at: DateTime<Utc>) {
+        self.seen.push(Observation { ephemeral_id, at });
+    }
+
+    /// Returns the number of recorded observations.
+    pub fn len(&self) -> usize {
+        self.seen.len()
+    }
+
+    /// Returns `true` if nothing was recorded.
+    pub fn is_empty(&self) -> bool {
+        self.seen.is_empty()
+    }
+
+    /// Drops observations older than the retention period.
+    pub fn prune(&mut self, now: DateTime<Utc>) {
+        let cutoff = now - Duration::days(RETENTION_DAYS);
+        self.seen.retain(|observation| observation.at >= cutoff);
+    }
+
+    /// Matches the log against published day keys.
+    ///
+    /// Returns the times of all observations of ephemeral IDs derived from
+    /// the keys, sorted from oldest to newest.  An observation only matches
+    /// on the day of the ephemeral ID (give or take
+    /// [`MATCH_TOLERANCE_MINUTES`](constant.MATCH_TOLERANCE_MINUTES.html)
+    /// across midnight), so that ephemeral IDs replayed on a later day are not
+    /// counted.  The epoch within the day is not checked as DP-3T devices may
+    /// broadcast the ephemeral IDs of a day in any order.
+    pub fn match_published(&self, keys: &[PublishedDayKey]) -> Vec<DateTime<Utc>> {
+        let last_day = match self.seen.iter().map(|x| x.at).max() {
+            Some(at) => at.date_naive(),
+            None => return Vec::new(),
+        };
+        let mut rv = Vec::new();
+        for published in keys {
+            let mut derived = HashMap::new();
+            let mut day = published.day;
+            let mut key = published.key.clone();
+            while day <= last_day && day < published.day + Duration::days(RETENTION_DAYS) {
+                for ephemeral_id in key.ephemeral_ids() {
+                    derived.insert(ephemeral_id, day);
+                }
+                key = key.next();
+                day = match day.succ_opt() {
+                    Some(day) => day,
+                    None => break,
+                };
+            }
+            for observation in &self.seen {
+                if let Some(&day) = derived.get(&observation.ephemeral_id) {
+                    let tolerance = Duration::minutes(MATCH_TOLERANCE_MINUTES);
+                    let start = day.and_hms_opt(0, 0, 0).unwrap().and_utc() - tolerance;
+                    let end = start + Duration::days(1) + tolerance * 2;
+                    if observation.at >= start && observation.at < end {
+                        rv.push(observation.at);
+                    }
+                }
+            }
+        }
+        rv.sort();
+        rv
+    }
+
+    /// Determines the exposure status from published day keys.
+    ///
+    /// A match counts as a direct contact with an infected user, so the
+    /// result uses the same [`ExposureStatus`](enum.ExposureStatus.html) as
+    /// checks against the registry.
+    pub fn exposure_status(&self, keys: &[PublishedDayKey]) -> ExposureStatus {
+        match self.match_published(keys).pop() {
+            Some(at) => ExposureStatus::Exposed {
+                at,
+                risk: 1.0,
+                degree: 1,
+            },
+            None => ExposureStatus::Clear,
+        }
+    }
+}
@@ -0,0 +1,274 @@
+//! Implements the versioned envelope around serialized artifacts.
+//!
+//! Every artifact that is persisted or transmitted carries a small header
+//! consisting of a magic marker, a format version and the type of the
+//! artifact.  For binary artifacts (the ones that end up as base64 strings)
+//! this is a five byte prefix, for structured artifacts (such as the contact
+//! log) the header is stored as `magic`, `version` and `type` keys next to
+//! the data.
+//!
+//! Artifacts written before the envelope existed are treated as version `0`
+//! and migrated transparently on load.
+use std::fmt;
+
+use derive_more::{Display, Error};
+use serde::de::{Deserializer, Error as _};
+use serde::ser::Serializer;
+use serde::{Deserialize, Serialize};
+
+use crate::error;
+use crate::utils::base64;
+
+/// The magic marker at the start of every binary envelope.
+pub const MAGIC: &[u8; 3] = b"CCT";
+
+/// The magic marker used for structured envelopes.
+pub const MAGIC_STR: &str = "covidcotra";
+
+pub(crate) const HEADER_LEN: usize = MAGIC.len() + 2;
+
+/// The kind of artifact contained in an envelope.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum ArtifactKind {
+    ShareIdentity,
+    HashedIdentity,
+    PublicKey,
+    ContactLog,
+    Authority,
+    UploadBundle,
+    SigningPublicKey,
+    AuthorizationCode,
+    TaintList,
+    Wallet,
+    ContactJournal,
+    SubscriptionToken,
+}
+
+impl ArtifactKind {
+    /// Returns the tag byte used in binary envelopes.
+    pub fn tag(self) -> u8 {
+        match self {
+            ArtifactKind::ShareIdentity => 1,
+            ArtifactKind::HashedIdentity => 2,
+            ArtifactKind::PublicKey => 3,
+            ArtifactKind::ContactLog => 4,
+            ArtifactKind::Authority => 5,
+            ArtifactKind::UploadBundle => 6,
+            ArtifactKind::SigningPublicKey => 7,
+            ArtifactKind::AuthorizationCode => 8,
+            ArtifactKind::TaintList => 9,
+            ArtifactKind::Wallet => 10,
+            ArtifactKind::ContactJournal => 11,
+            ArtifactKind::SubscriptionToken => 12,
+        }
+    }
+
+    /// Looks up an artifact kind by tag byte.
+    pub fn from_tag(tag: u8) -> Option<ArtifactKind> {
+        Some(match tag {
+            1 => ArtifactKind::ShareIdentity,
+            2 => ArtifactKind::HashedIdentity,
+            3 => ArtifactKind::PublicKey,
+            4 => ArtifactKind::ContactLog,
+            5 => ArtifactKind::Authority,
+            6 => ArtifactKind::UploadBundle,
+            7 => ArtifactKind::SigningPublicKey,
+            8 => ArtifactKind::AuthorizationCode,
+            9 => ArtifactKind::TaintList,
+            10 => ArtifactKind::Wallet,
+            11 => ArtifactKind::ContactJournal,
+            12 => ArtifactKind::SubscriptionToken,
+            _ => return None,
+        })
+    }
+
+    /// Returns the format version this library writes for the artifact.
+    pub fn current_version(self) -> u8 {
+        match self {
+            ArtifactKind::ShareIdentity | ArtifactKind::TaintList => 2,
+            _ => 1,
+        }
+    }
+}
+
+impl fmt::Display for ArtifactKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ArtifactKind::ShareIdentity => "share identity",
+            ArtifactKind::HashedIdentity => "hashed identity",
+            ArtifactKind::PublicKey => "public key",
+            ArtifactKind::ContactLog => "contact log",
+            ArtifactKind::Authority => "authority",
+            ArtifactKind::UploadBundle => "upload bundle",
+            ArtifactKind::SigningPublicKey => "signing public key",
+            ArtifactKind::AuthorizationCode => "authorization code",
+            ArtifactKind::TaintList => "taint list",
+            ArtifactKind::Wallet => "wallet",
+            ArtifactKind::ContactJournal => "contact journal",
+            ArtifactKind::SubscriptionToken => "subscription token",
+        })
+    }
+}
+
+/// Error for envelopes that cannot be opened.
+#[derive(Debug, Error, Display, Clone, PartialEq, Eq)]
+pub enum EnvelopeError {
+    /// The data does not start with the envelope magic.
+    #[display(fmt = "missing envelope magic for {}", kind)]
+    BadMagic { kind: ArtifactKind },
+    /// The envelope header is cut short.
+    #[display(fmt = "truncated envelope for {}", kind)]
+    Truncated { kind: ArtifactKind },
+    /// The envelope uses a type tag this library does not know.
+    #[display(fmt = "unknown artifact type tag {} (expected {})", tag, expected)]
+    UnknownKind { tag: u8, expected: ArtifactKind },
+    /// The envelope contains a different artifact than expected.
+    #[display(fmt = "expected {} but found {}", expected, found)]
+    WrongKind {
+        expected: ArtifactKind,
+        found: ArtifactKind,
+    },
+    /// The envelope was written by a newer version of the format.
+    #[display(
+        fmt = "unsupported {} version {} (supported up to {})",
+        kind,
+        version,
+        supported
+    )]
+    UnsupportedVersion {
+        kind: ArtifactKind,
+        version: u8,
+        supported: u8,
+    },
+    /// The payload inside the envelope is malformed.
+    #[display(fmt = "invalid {} payload (version {})", kind, version)]
+    InvalidPayload { kind: ArtifactKind, version: u8 },
+}
+
+/// Wraps a payload into a binary envelope of the current version.
+pub(crate) fn wrap(kind: ArtifactKind, payload: &[u8]) -> Vec<u8> {
+    let mut rv = Vec::with_capacity(HEADER_LEN + payload.len());
+    rv.extend_from_slice(&MAGIC[..]);
+    rv.push(kind.current_version());
+    rv.push(kind.tag());
+    rv.extend_from_slice(payload);
+    rv
+}
+
+/// Opens a binary envelope and returns the version and payload.
+///
+/// For artifacts that existed before the envelope, data of exactly
+/// `legacy_len` bytes is returned as version `0`.  Because the header makes
+/// every enveloped artifact longer than its legacy form the two can never be
+/// confused.
+pub(crate) fn open(
+    kind: ArtifactKind,
+    bytes: &[u8],
+    legacy_len: Option<usize>,
+) -> Result<(u8, &[u8]), EnvelopeError> {
+    if Some(bytes.len()) == legacy_len {
+        return Ok((0, bytes));
+    }
+    if bytes.len() < HEADER_LEN {
+        return Err(EnvelopeError::Truncated { kind });
+    }
+    if &bytes[..MAGIC.len()] != MAGIC {
+        return Err(EnvelopeError::BadMagic { kind });
+    }
+    let version = bytes[MAGIC.len()];
+    let tag = bytes[MAGIC.len() + 1];
+    check_kind(
+        kind,
+        ArtifactKind::from_tag(tag).ok_or(EnvelopeError::UnknownKind {
+            tag,
+            expected: kind,
+        })?,
+    )?;
+    check_version(kind, version)?;
+    Ok((version, &bytes[HEADER_LEN..]))
+}
+
+fn check_kind(expected: ArtifactKind, found: ArtifactKind) -> Result<(), EnvelopeError> {
+    if expected != found {
+        Err(EnvelopeError::WrongKind { expected, found })
+    } else {
+        Ok(())
+    }
+}
+
+fn check_version(kind: ArtifactKind, version: u8) -> Result<(), EnvelopeError> {
+    if version == 0 || version > kind.current_version() {
+        Err(EnvelopeError::UnsupportedVersion {
+            kind,
+            version,
+            supported: kind.current_version(),
+        })
+    } else {
+        Ok(())
+    }
+}
+
+/// Validates the header of a structured artifact and returns its version.
+///
+/// Artifacts written before the envelope existed carry none of the header
+/// fields and are reported as version `0`.
+pub(crate) fn check_header(
+    kind: ArtifactKind,
+    magic: Option<&str>,
+    version: Option<u8>,
+    found: Option<ArtifactKind>,
+) -> Result<u8, EnvelopeError> {
+    match (magic, version, found) {
+        (None, None, None) => Ok(0),
+        (Some(magic), _, _) if magic != MAGIC_STR => Err(EnvelopeError::BadMagic { kind }),
+        (Some(_), Some(version), Some(found)) => {
+            check_kind(kind, found)?;
+            check_version(kind, version)?;
+            Ok(version)
+        }
+        _ => Err(EnvelopeError::Truncated { kind }),
+    }
+}
+
+/// Serializes a binary artifact as enveloped base64.
+pub(crate) fn serialize<S>(
+    kind: ArtifactKind,
+    payload: &[u8],
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    base64::serialize(&wrap(kind, payload), serializer)
+}
+
+/// Parses a binary artifact from an enveloped base64 string.
+///
+/// Returns the version of the envelope and the contained payload.
+pub(crate) fn parse(
+    kind: ArtifactKind,
+    s: &str,
+    legacy_len: Option<usize>,
+) -> Result<(u8, Vec<u8>), error::Error> {
+    let bytes = ::base64::decode(s)?;
+    let (version, payload) = open(kind, &bytes, legacy_len)?;
+    Ok((version, payload.to_vec()))
+}
+
+/// Deserializes a binary artifact from enveloped base64.
+///
+/// Returns the version of the envelope and the contained payload.
+pub(crate) fn deserialize<'de, D>(
+    kind: ArtifactKind,
+    legacy_len: Option<usize>,
+    deserializer: D,
+) -> Result<(u8, Vec<u8>), D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let bytes: Vec<u8> = base64::deserialize(deserializer)?;
+    open(kind, &bytes, legacy_len)
+        .map(|(version, payload)| (version, payload.to_vec()))
+        .map_err(D::Error::custom)
+}
@@ -0,0 +1,113 @@
+//! Implements the error type shared by the whole crate.
+use std::io;
+
+use derive_more::{Display, Error, From};
+
+use crate::authcode::AuthorizationError;
+#[cfg(feature = "blind")]
+use crate::blind::CredentialError;
+use crate::client::ClientError;
+use crate::descriptor::DescriptorError;
+use crate::envelope::EnvelopeError;
+use crate::formats::FormatError;
+#[cfg(feature = "gaen")]
+use crate::gaen::GaenError;
+use crate::journal::JournalError;
+use crate::registry::{RevokeError, SubmitError};
+use crate::statistics::StatisticsError;
+use crate::store::StoreError;
+use crate::subscription::DeliveryError;
+use crate::transparency::TransparencyError;
+use crate::upload::UploadError;
+use crate::wallet::WalletError;
+
+/// The error type of this crate.
+///
+/// Parsing and decoding report the detailed cause directly.  The errors of
+/// higher level operations (such as [`SubmitError`](enum.SubmitError.html))
+/// convert into this type so that callers can use a single error type.
+#[derive(Debug, Error, Display, From)]
+pub enum Error {
+    /// The data is not valid base64.
+    #[display(fmt = "invalid base64: {}", _0)]
+    Base64(base64::DecodeError),
+    /// The data has the wrong length.
+    #[display(fmt = "invalid length {} (expected {})", found, expected)]
+    #[from(ignore)]
+    InvalidLength { expected: usize, found: usize },
+    /// The data cannot be decrypted with the given key.
+    #[display(fmt = "decryption failed")]
+    #[from(ignore)]
+    Decryption,
+    /// An authority ID is not valid hex.
+    #[display(fmt = "invalid authority id")]
+    #[from(ignore)]
+    InvalidAuthorityId,
+    /// A unique ID is not a valid UUID.
+    #[display(fmt = "invalid uuid: {}", _0)]
+    InvalidUuid(uuid::Error),
+    /// The envelope is missing, of the wrong type or of an unsupported
+    /// version, or the payload inside of it is malformed.
+    #[display(fmt = "{}", _0)]
+    Envelope(EnvelopeError),
+    /// Reading or writing a file failed.
+    #[display(fmt = "{}", _0)]
+    Io(io::Error),
+    /// An authority descriptor was not accepted.
+    #[display(fmt = "{}", _0)]
+    Descriptor(DescriptorError),
+    /// A binary serialization format failed.
+    #[display(fmt = "{}", _0)]
+    Format(FormatError),
+    /// An exposure key export cannot be parsed.
+    #[cfg(feature = "gaen")]
+    #[display(fmt = "{}", _0)]
+    Gaen(GaenError),
+    /// An authorization code was not accepted.
+    #[display(fmt = "{}", _0)]
+    Authorization(AuthorizationError),
+    /// An anonymous credential could not be issued or redeemed.
+    #[cfg(feature = "blind")]
+    #[display(fmt = "{}", _0)]
+    Credential(CredentialError),
+    /// An upload bundle cannot be created or opened.
+    #[display(fmt = "{}", _0)]
+    Upload(UploadError),
+    /// The registry rejected an upload.
+    #[display(fmt = "{}", _0)]
+    Submit(SubmitError),
+    /// The registry cannot revoke an upload.
+    #[display(fmt = "{}", _0)]
+    Revoke(RevokeError),
+    /// The store of the registry failed.
+    #[display(fmt = "{}", _0)]
+    Store(StoreError),
+    /// Statistics of the registry cannot be computed.
+    #[display(fmt = "{}", _0)]
+    Statistics(StatisticsError),
+    /// A wallet cannot be stored or opened.
+    #[display(fmt = "{}", _0)]
+    Wallet(WalletError),
+    /// A contact journal cannot be read or written.
+    #[display(fmt = "{}", _0)]
+    Journal(JournalError),
+    /// A request to the authority server failed.
+    #[display(fmt = "{}", _0)]
+    Client(ClientError),
+    /// A transparency proof or tree head does not verify.
+    #[display(fmt = "{}", _0)]
+    Transparency(TransparencyError),
+    /// A notification could not be delivered.
+    #[display(fmt = "{}", _0)]
+    Delivery(DeliveryError),
+}
+
+impl Error {
+    pub(crate) fn check_length(expected: usize, found: usize) -> Result<(), Error> {
+        if expected == found {
+            Ok(())
+        } else {
+            Err(Error::InvalidLength { expected, found })
+        }
+    }
+}
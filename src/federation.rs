@@ -0,0 +1,243 @@
+//! Implements the federation of authorities of different regions.
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
+use serde::{de, ser, Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::api::AuthorityKeys;
+use crate::authcode::AuthorizationError;
+use crate::contactlog::ContactLog;
+use crate::crypto::{PublicKey, Signature, SigningPublicKey, SigningSecretKey};
+use crate::error::Error;
+use crate::registry::SubmitError;
+use crate::utils::base64;
+
+/// The length of an authority ID in bytes.
+pub(crate) const AUTHORITY_ID_LEN: usize = 8;
+
+const ID_CONTEXT: &[u8] = b"covidcotra-authority\x00";
+const SIGNATURE_CONTEXT: &[u8] = b"covidcotra-forward\x00";
+const BATCH_ID_CONTEXT: &[u8] = b"covidcotra-forward-batch\x00";
+
+/// The number of days forwarded contacts are accepted for after their batch
+/// was created.
+pub const FORWARD_RETENTION_DAYS: i64 = 14;
+
+/// Identifies an authority.
+///
+/// The ID is derived from the public key of the authority (see
+/// [`PublicKey::authority_id`](struct.PublicKey.html#method.authority_id))
+/// and is written as 16 hex digits.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct AuthorityId([u8; AUTHORITY_ID_LEN]);
+
+impl fmt::Display for AuthorityId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in &self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for AuthorityId {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<AuthorityId, Error> {
+        Error::check_length(AUTHORITY_ID_LEN * 2, s.len())?;
+        let mut id = [0u8; AUTHORITY_ID_LEN];
+        for (idx, byte) in id.iter_mut().enumerate() {
+            *byte = s
+                .get(idx * 2..idx * 2 + 2)
+                .and_then(|x| u8::from_str_radix(x, 16).ok())
+                .ok_or(Error::InvalidAuthorityId)?;
+        }
+        Ok(AuthorityId(id))
+    }
+}
+
+impl Serialize for AuthorityId {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: ser::Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for AuthorityId {
+    fn deserialize<D>(deserializer: D) -> Result<AuthorityId, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+impl AuthorityId {
+    pub(crate) fn derive(public_key: &PublicKey) -> AuthorityId {
+        let mut hasher = Sha256::new();
+        hasher.input(ID_CONTEXT);
+        hasher.input(public_key.as_bytes());
+        let mut id = [0u8; AUTHORITY_ID_LEN];
+        id.copy_from_slice(&hasher.result()[..AUTHORITY_ID_LEN]);
+        AuthorityId(id)
+    }
+
+    pub(crate) fn from_slice(bytes: &[u8]) -> Result<AuthorityId, Error> {
+        Error::check_length(AUTHORITY_ID_LEN, bytes.len())?;
+        let mut id = [0u8; AUTHORITY_ID_LEN];
+        id.copy_from_slice(bytes);
+        Ok(AuthorityId(id))
+    }
+
+    pub(crate) fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Returns `true` for the all zero placeholder of untagged share IDs.
+    pub(crate) fn is_unset(&self) -> bool {
+        self.0 == [0u8; AUTHORITY_ID_LEN]
+    }
+}
+
+/// An authority of another region whose forwarded contacts are accepted.
+///
+/// Peers are registered with
+/// [`Registry::trust_peer`](struct.Registry.html#method.trust_peer).
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct FederationPeer {
+    /// The ID of the peer.
+    pub authority_id: AuthorityId,
+    /// The key the peer signs forwarded contacts with.
+    pub signing_public_key: SigningPublicKey,
+}
+
+impl From<&AuthorityKeys> for FederationPeer {
+    fn from(keys: &AuthorityKeys) -> FederationPeer {
+        FederationPeer {
+            authority_id: keys.public_key.authority_id(),
+            signing_public_key: keys.signing_public_key,
+        }
+    }
+}
+
+/// Identifies an imported batch of [`ForwardedContacts`](struct.ForwardedContacts.html).
+///
+/// The ID is the hash of the signed batch, so a batch that is sent again
+/// has the same ID.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct BatchId([u8; 32]);
+
+impl fmt::Display for BatchId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&::base64::encode(&self.0[..]))
+    }
+}
+
+impl Serialize for BatchId {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: ser::Serializer,
+    {
+        base64::serialize(&self.0[..], serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for BatchId {
+    fn deserialize<D>(deserializer: D) -> Result<BatchId, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        let bytes: Vec<u8> = base64::deserialize(deserializer)?;
+        if bytes.len() != 32 {
+            return Err(de::Error::custom("invalid batch id"));
+        }
+        let mut id = [0u8; 32];
+        id.copy_from_slice(&bytes);
+        Ok(BatchId(id))
+    }
+}
+
+/// The contents of [`ForwardedContacts`](struct.ForwardedContacts.html).
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ForwardBatch {
+    /// The authority that received the upload.
+    pub from: AuthorityId,
+    /// The authority the contacts were sealed to.
+    pub to: AuthorityId,
+    /// When the batch was created.
+    pub created: DateTime<Utc>,
+    /// The contacts, which only the receiving authority can decode.
+    pub contacts: ContactLog,
+}
+
+/// Contacts of infected users that were sealed to another authority.
+///
+/// When an upload contains sightings of share IDs tagged for another
+/// authority the registry cannot reveal them.  Instead it queues them and
+/// hands them out with
+/// [`Registry::take_forwards`](struct.Registry.html#method.take_forwards)
+/// signed by the authority, so that the responsible authority can import
+/// them with
+/// [`Registry::import_forwarded`](struct.Registry.html#method.import_forwarded).
+/// The contacts stay sealed, so the forwarding authority learns nothing
+/// about them.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ForwardedContacts {
+    payload: String,
+    signature: Signature,
+}
+
+impl ForwardedContacts {
+    pub(crate) fn sign(batch: &ForwardBatch, secret_key: &SigningSecretKey) -> ForwardedContacts {
+        // share IDs serialize to strings so this cannot fail
+        let payload = serde_json::to_string(batch).unwrap();
+        let signature = secret_key.sign(&signed_message(&payload));
+        ForwardedContacts { payload, signature }
+    }
+
+    /// Returns the ID the registry remembers the batch by once imported.
+    pub fn batch_id(&self) -> BatchId {
+        let mut hasher = Sha256::new();
+        hasher.input(BATCH_ID_CONTEXT);
+        hasher.input(self.payload.as_bytes());
+        let mut id = [0u8; 32];
+        id.copy_from_slice(&hasher.result());
+        BatchId(id)
+    }
+
+    /// Returns the batch without verifying the signature.
+    ///
+    /// This can be used to route the batch to the authority it is meant
+    /// for.
+    pub fn peek(&self) -> Result<ForwardBatch, SubmitError> {
+        serde_json::from_str(&self.payload).map_err(|_| SubmitError::UndecodableContacts)
+    }
+
+    /// Verifies the signature against the peer that sent the batch and
+    /// returns it.
+    pub fn verify(&self, peers: &[FederationPeer]) -> Result<ForwardBatch, SubmitError> {
+        let batch = self.peek()?;
+        let peer = peers
+            .iter()
+            .find(|peer| peer.authority_id == batch.from)
+            .ok_or(SubmitError::UntrustedPeer)?;
+        if !peer
+            .signing_public_key
+            .verify(&signed_message(&self.payload), &self.signature)
+        {
+            return Err(AuthorizationError::Forged.into());
+        }
+        Ok(batch)
+    }
+}
+
+fn signed_message(payload: &str) -> Vec<u8> {
+    let mut rv = SIGNATURE_CONTEXT.to_vec();
+    rv.extend_from_slice(payload.as_bytes());
+    rv
+}
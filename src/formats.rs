@@ -0,0 +1,66 @@
+//! Support for binary serialization formats.
+//!
+//! All types in this crate serialize their binary fields as base64 strings
+//! in human readable formats such as JSON and as native bytes in binary
+//! formats.  This module provides helpers for the binary formats that are
+//! supported out of the box, each behind its own cargo feature.
+use derive_more::{Display, Error};
+
+/// Error for values that cannot be encoded or decoded.
+#[derive(Debug, Error, Display, Clone)]
+#[display(fmt = "{} error: {}", format, message)]
+pub struct FormatError {
+    format: &'static str,
+    message: String,
+}
+
+impl FormatError {
+    #[cfg(any(feature = "cbor", feature = "msgpack"))]
+    fn new<E: std::fmt::Display>(format: &'static str, err: E) -> FormatError {
+        FormatError {
+            format,
+            message: err.to_string(),
+        }
+    }
+}
+
+/// Helpers for CBOR (requires the `cbor` feature).
+#[cfg(feature = "cbor")]
+pub mod cbor {
+    use serde::{de::DeserializeOwned, Serialize};
+
+    use super::FormatError;
+
+    /// Encodes a value as CBOR.
+    pub fn to_vec<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, FormatError> {
+        let mut rv = Vec::new();
+        ciborium::ser::into_writer(value, &mut rv).map_err(|err| FormatError::new("cbor", err))?;
+        Ok(rv)
+    }
+
+    /// Decodes a value from CBOR.
+    pub fn from_slice<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, FormatError> {
+        ciborium::de::from_reader(bytes).map_err(|err| FormatError::new("cbor", err))
+    }
+}
+
+/// Helpers for MessagePack (requires the `msgpack` feature).
+#[cfg(feature = "msgpack")]
+pub mod msgpack {
+    use serde::{de::DeserializeOwned, Serialize};
+
+    use super::FormatError;
+
+    /// Encodes a value as MessagePack.
+    ///
+    /// Structs are written as maps so that the envelope header of structured
+    /// artifacts remains self describing.
+    pub fn to_vec<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, FormatError> {
+        rmp_serde::to_vec_named(value).map_err(|err| FormatError::new("msgpack", err))
+    }
+
+    /// Decodes a value from MessagePack.
+    pub fn from_slice<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, FormatError> {
+        rmp_serde::from_slice(bytes).map_err(|err| FormatError::new("msgpack", err))
+    }
+}
@@ -0,0 +1,651 @@
+//! Implements compatibility with Google/Apple Exposure Notification keys.
+//!
+//! Regions using the Exposure Notification framework publish the temporary
+//! exposure keys of infected users in a key export file.  This module reads
+//! and writes the `export.bin` part of such a file as a
+//! [`KeyExport`](struct.KeyExport.html) and matches the keys against rolling
+//! proximity identifiers recorded in a [`SightingLog`](struct.SightingLog.html):
+//!
+//! 1. a [`TemporaryExposureKey`](struct.TemporaryExposureKey.html) is valid
+//!    for a rolling period of 10 minute intervals (usually a day).
+//! 2. the rolling proximity identifier key is derived from it with HKDF-SHA256
+//!    (info `EN-RPIK`) and each
+//!    [`RollingProximityId`](struct.RollingProximityId.html) is the AES-128
+//!    encryption of `EN-RPI`, six zero bytes and the little endian interval
+//!    number.
+//! 3. the associated encrypted metadata of a sighting is AES-128-CTR
+//!    encrypted under a key derived with info `EN-AEMK`, using the rolling
+//!    proximity identifier as IV.
+//!
+//! The signature file of an export (`export.sig`) and the zip container are
+//! not handled here.  This requires the `gaen` feature.
+use std::collections::HashMap;
+use std::fmt;
+
+use aes::cipher::{BlockEncrypt, KeyInit, KeyIvInit, StreamCipher};
+use aes::Aes128;
+use chrono::{DateTime, TimeZone, Utc};
+use derive_more::{Display, Error};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use zeroize::Zeroize;
+
+use crate::crypto::{ct_eq, random_bytes};
+use crate::status::ExposureStatus;
+use crate::utils::base64;
+
+type Aes128Ctr = ctr::Ctr128BE<Aes128>;
+
+/// The length of an interval in seconds.
+pub const INTERVAL_SECONDS: i64 = 600;
+
+/// The default number of intervals a temporary exposure key is valid for.
+pub const DEFAULT_ROLLING_PERIOD: u32 = 144;
+
+/// How many intervals a sighting may be off from the interval of the
+/// identifier and still match (two hours).
+pub const MATCH_TOLERANCE: u32 = 12;
+
+/// The kind of diagnosis a temporary exposure key was published for.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportType {
+    /// The report type was not set by the publishing region.
+    Unknown,
+    /// The diagnosis was confirmed by a test.
+    ConfirmedTest,
+    /// The diagnosis was confirmed by a clinician.
+    ConfirmedClinicalDiagnosis,
+    /// The user reported the diagnosis themselves.
+    SelfReport,
+    /// Reserved by the framework.
+    Recursive,
+    /// The diagnosis was withdrawn and the key must no longer match.
+    Revoked,
+}
+
+impl ReportType {
+    fn from_int(value: u64) -> Option<ReportType> {
+        Some(match value {
+            0 => ReportType::Unknown,
+            1 => ReportType::ConfirmedTest,
+            2 => ReportType::ConfirmedClinicalDiagnosis,
+            3 => ReportType::SelfReport,
+            4 => ReportType::Recursive,
+            5 => ReportType::Revoked,
+            _ => return None,
+        })
+    }
+
+    fn to_int(self) -> u64 {
+        match self {
+            ReportType::Unknown => 0,
+            ReportType::ConfirmedTest => 1,
+            ReportType::ConfirmedClinicalDiagnosis => 2,
+            ReportType::SelfReport => 3,
+            ReportType::Recursive => 4,
+            ReportType::Revoked => 5,
+        }
+    }
+}
+
+/// The header every `export.bin` starts with.
+pub const EXPORT_HEADER: &[u8; 16] = b"EK Export v1    ";
+
+/// Returns the interval number of a point in time.
+pub fn interval_number(at: DateTime<Utc>) -> u32 {
+    (at.timestamp() / INTERVAL_SECONDS) as u32
+}
+
+/// Error for key exports that cannot be parsed.
+#[derive(Debug, Error, Display, Clone, PartialEq, Eq)]
+pub enum GaenError {
+    /// The data does not start with [`EXPORT_HEADER`](constant.EXPORT_HEADER.html).
+    #[display(fmt = "not an exposure key export")]
+    InvalidHeader,
+    /// The protocol buffer is malformed.
+    #[display(fmt = "malformed exposure key export")]
+    Malformed,
+    /// A key does not have 16 bytes.
+    #[display(fmt = "invalid temporary exposure key length {}", _0)]
+    InvalidKeyLength(#[error(not(source))] usize),
+}
+
+fn hkdf(key: &[u8; 16], info: &[u8]) -> [u8; 16] {
+    let mut mac = Hmac::<Sha256>::new_varkey(&[0u8; 32]).unwrap();
+    mac.input(key);
+    let mut prk = mac.result().code();
+    let mut mac = Hmac::<Sha256>::new_varkey(&prk).unwrap();
+    prk.as_mut_slice().zeroize();
+    mac.input(info);
+    mac.input(&[1]);
+    let mut okm = mac.result().code();
+    let mut rv = [0u8; 16];
+    rv.copy_from_slice(&okm[..16]);
+    okm.as_mut_slice().zeroize();
+    rv
+}
+
+/// A rolling proximity identifier broadcast during one interval.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct RollingProximityId(#[serde(with = "base64")] [u8; 16]);
+
+impl RollingProximityId {
+    /// Creates an identifier from received bytes.
+    pub fn from_bytes(bytes: [u8; 16]) -> RollingProximityId {
+        RollingProximityId(bytes)
+    }
+
+    /// Returns the bytes of the identifier.
+    pub fn as_bytes(&self) -> &[u8; 16] {
+        &self.0
+    }
+}
+
+/// A temporary exposure key.
+///
+/// The key data is zeroed out when dropped and redacted from debug output.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TemporaryExposureKey {
+    #[serde(with = "base64")]
+    key_data: [u8; 16],
+    /// The interval number the key became valid at.
+    pub rolling_start_interval_number: u32,
+    /// The number of intervals the key is valid for.
+    pub rolling_period: u32,
+    /// The transmission risk level assigned by the publishing region.
+    pub transmission_risk_level: u32,
+    /// The kind of diagnosis the key was published for, if set.
+    #[serde(default)]
+    pub report_type: Option<ReportType>,
+    /// The number of days between the key becoming valid and the onset of
+    /// symptoms, if set.
+    #[serde(default)]
+    pub days_since_onset_of_symptoms: Option<i32>,
+}
+
+impl fmt::Debug for TemporaryExposureKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TemporaryExposureKey")
+            .field("key_data", &"****")
+            .field(
+                "rolling_start_interval_number",
+                &self.rolling_start_interval_number,
+            )
+            .field("rolling_period", &self.rolling_period)
+            .field("transmission_risk_level", &self.transmission_risk_level)
+            .field("report_type", &self.report_type)
+            .field(
+                "days_since_onset_of_symptoms",
+                &self.days_since_onset_of_symptoms,
+            )
+            .finish()
+    }
+}
+
+impl PartialEq for TemporaryExposureKey {
+    fn eq(&self, other: &TemporaryExposureKey) -> bool {
+        ct_eq(&self.key_data, &other.key_data)
+            && self.rolling_start_interval_number == other.rolling_start_interval_number
+            && self.rolling_period == other.rolling_period
+            && self.transmission_risk_level == other.transmission_risk_level
+            && self.report_type == other.report_type
+            && self.days_since_onset_of_symptoms == other.days_since_onset_of_symptoms
+    }
+}
+
+impl Eq for TemporaryExposureKey {}
+
+impl Drop for TemporaryExposureKey {
+    fn drop(&mut self) {
+        self.key_data.zeroize();
+    }
+}
+
+impl TemporaryExposureKey {
+    /// Creates a key from its parts.
+    pub fn new(key_data: [u8; 16], rolling_start_interval_number: u32) -> TemporaryExposureKey {
+        TemporaryExposureKey {
+            key_data,
+            rolling_start_interval_number,
+            rolling_period: DEFAULT_ROLLING_PERIOD,
+            transmission_risk_level: 0,
+            report_type: None,
+            days_since_onset_of_symptoms: None,
+        }
+    }
+
+    /// Creates a random key valid from the start of the day of `at`.
+    pub fn generate(at: DateTime<Utc>) -> TemporaryExposureKey {
+        let mut key_data = [0u8; 16];
+        random_bytes(&mut key_data);
+        let start = interval_number(at) / DEFAULT_ROLLING_PERIOD * DEFAULT_ROLLING_PERIOD;
+        TemporaryExposureKey::new(key_data, start)
+    }
+
+    /// Returns `true` if the diagnosis the key was published for was withdrawn.
+    pub fn is_revoked(&self) -> bool {
+        self.report_type == Some(ReportType::Revoked)
+    }
+
+    /// Returns the key data.
+    pub fn key_data(&self) -> &[u8; 16] {
+        &self.key_data
+    }
+
+    /// Returns `true` if the key is valid in an interval.
+    pub fn is_valid_at(&self, interval: u32) -> bool {
+        interval >= self.rolling_start_interval_number
+            && interval - self.rolling_start_interval_number < self.rolling_period
+    }
+
+    /// Derives the rolling proximity identifier of an interval.
+    pub fn rolling_proximity_id(&self, interval: u32) -> RollingProximityId {
+        let mut key = hkdf(&self.key_data, b"EN-RPIK");
+        let cipher = Aes128::new(&key.into());
+        key.zeroize();
+        RollingProximityId(encrypt_interval(&cipher, interval))
+    }
+
+    /// Derives the rolling proximity identifiers of all intervals the key is
+    /// valid for.
+    pub fn rolling_proximity_ids(&self) -> Vec<(u32, RollingProximityId)> {
+        let mut key = hkdf(&self.key_data, b"EN-RPIK");
+        let cipher = Aes128::new(&key.into());
+        key.zeroize();
+        (0..self.rolling_period)
+            .map(|offset| {
+                let interval = self.rolling_start_interval_number.wrapping_add(offset);
+                (
+                    interval,
+                    RollingProximityId(encrypt_interval(&cipher, interval)),
+                )
+            })
+            .collect()
+    }
+
+    /// Encrypts or decrypts the metadata broadcast with an identifier.
+    ///
+    /// The operation is its own inverse so the same call decrypts received
+    /// metadata.
+    pub fn apply_metadata_keystream(&self, rpi: &RollingProximityId, metadata: &mut [u8]) {
+        let mut key = hkdf(&self.key_data, b"EN-AEMK");
+        let mut cipher = Aes128Ctr::new(&key.into(), &rpi.0.into());
+        key.zeroize();
+        cipher.apply_keystream(metadata);
+    }
+}
+
+fn encrypt_interval(cipher: &Aes128, interval: u32) -> [u8; 16] {
+    let mut block = [0u8; 16];
+    block[..6].copy_from_slice(b"EN-RPI");
+    block[12..].copy_from_slice(&interval.to_le_bytes());
+    let mut block = block.into();
+    cipher.encrypt_block(&mut block);
+    block.into()
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn varint(&mut self) -> Result<u64, GaenError> {
+        let mut rv = 0u64;
+        for shift in (0..64).step_by(7) {
+            let (&byte, rest) = self.buf.split_first().ok_or(GaenError::Malformed)?;
+            self.buf = rest;
+            rv |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(rv);
+            }
+        }
+        Err(GaenError::Malformed)
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], GaenError> {
+        if len > self.buf.len() {
+            return Err(GaenError::Malformed);
+        }
+        let (rv, rest) = self.buf.split_at(len);
+        self.buf = rest;
+        Ok(rv)
+    }
+
+    /// Reads the next field, returning its number and value.
+    fn field(&mut self) -> Result<Option<(u64, Value<'a>)>, GaenError> {
+        if self.buf.is_empty() {
+            return Ok(None);
+        }
+        let tag = self.varint()?;
+        let value = match tag & 7 {
+            0 => Value::Int(self.varint()?),
+            1 => {
+                let mut bytes = [0u8; 8];
+                bytes.copy_from_slice(self.bytes(8)?);
+                Value::Int(u64::from_le_bytes(bytes))
+            }
+            2 => {
+                let len = self.varint()? as usize;
+                Value::Bytes(self.bytes(len)?)
+            }
+            5 => {
+                let mut bytes = [0u8; 4];
+                bytes.copy_from_slice(self.bytes(4)?);
+                Value::Int(u64::from(u32::from_le_bytes(bytes)))
+            }
+            _ => return Err(GaenError::Malformed),
+        };
+        Ok(Some((tag >> 3, value)))
+    }
+}
+
+enum Value<'a> {
+    Int(u64),
+    Bytes(&'a [u8]),
+}
+
+impl<'a> Value<'a> {
+    fn int(&self) -> Result<u64, GaenError> {
+        match *self {
+            Value::Int(value) => Ok(value),
+            Value::Bytes(_) => Err(GaenError::Malformed),
+        }
+    }
+
+    fn bytes(&self) -> Result<&'a [u8], GaenError> {
+        match *self {
+            Value::Bytes(value) => Ok(value),
+            Value::Int(_) => Err(GaenError::Malformed),
+        }
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn write_int(out: &mut Vec<u8>, field: u64, value: u64) {
+    write_varint(out, field << 3);
+    write_varint(out, value);
+}
+
+fn write_fixed64(out: &mut Vec<u8>, field: u64, value: u64) {
+    write_varint(out, field << 3 | 1);
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn write_bytes(out: &mut Vec<u8>, field: u64, value: &[u8]) {
+    write_varint(out, field << 3 | 2);
+    write_varint(out, value.len() as u64);
+    out.extend_from_slice(value);
+}
+
+fn parse_key(bytes: &[u8]) -> Result<TemporaryExposureKey, GaenError> {
+    let mut reader = Reader { buf: bytes };
+    let mut key = TemporaryExposureKey::new([0u8; 16], 0);
+    let mut has_data = false;
+    while let Some((field, value)) = reader.field()? {
+        match field {
+            1 => {
+                let data = value.bytes()?;
+                if data.len() != 16 {
+                    return Err(GaenError::InvalidKeyLength(data.len()));
+                }
+                key.key_data.copy_from_slice(data);
+                has_data = true;
+            }
+            2 => key.transmission_risk_level = value.int()? as u32,
+            3 => key.rolling_start_interval_number = value.int()? as u32,
+            4 => key.rolling_period = value.int()? as u32,
+            // unknown enum values are skipped like unknown fields
+            5 => key.report_type = ReportType::from_int(value.int()?),
+            6 => {
+                // sint32 is zigzag encoded
+                let value = value.int()? as u32;
+                key.days_since_onset_of_symptoms =
+                    Some((value >> 1) as i32 ^ -((value & 1) as i32));
+            }
+            _ => {}
+        }
+    }
+    // keys are valid for at most a day
+    if key.rolling_period > DEFAULT_ROLLING_PERIOD {
+        Err(GaenError::Malformed)
+    } else if has_data {
+        Ok(key)
+    } else {
+        Err(GaenError::InvalidKeyLength(0))
+    }
+}
+
+fn write_key(out: &mut Vec<u8>, field: u64, key: &TemporaryExposureKey) {
+    let mut msg = Vec::new();
+    write_bytes(&mut msg, 1, &key.key_data);
+    write_int(&mut msg, 2, key.transmission_risk_level.into());
+    write_int(&mut msg, 3, key.rolling_start_interval_number.into());
+    write_int(&mut msg, 4, key.rolling_period.into());
+    if let Some(report_type) = key.report_type {
+        write_int(&mut msg, 5, report_type.to_int());
+    }
+    if let Some(days) = key.days_since_onset_of_symptoms {
+        write_int(&mut msg, 6, ((days << 1) ^ (days >> 31)) as u32 as u64);
+    }
+    write_bytes(out, field, &msg);
+    msg.zeroize();
+}
+
+/// The contents of an `export.bin` file.
+///
+/// Signature infos are skipped when parsing and not written back.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct KeyExport {
+    /// The start of the time window the export covers.
+    pub start_timestamp: DateTime<Utc>,
+    /// The end of the time window the export covers.
+    pub end_timestamp: DateTime<Utc>,
+    /// The region the keys were published in (for instance `"DE"`).
+    pub region: String,
+    /// The number of this batch (starting at 1).
+    pub batch_num: u32,
+    /// The number of batches of the export.
+    pub batch_size: u32,
+    /// The published keys.
+    pub keys: Vec<TemporaryExposureKey>,
+    /// Keys that were published before and changed since.
+    pub revised_keys: Vec<TemporaryExposureKey>,
+}
+
+impl KeyExport {
+    /// Creates an export with a single batch.
+    pub fn new(
+        region: &str,
+        start_timestamp: DateTime<Utc>,
+        end_timestamp: DateTime<Utc>,
+        keys: Vec<TemporaryExposureKey>,
+    ) -> KeyExport {
+        KeyExport {
+            start_timestamp,
+            end_timestamp,
+            region: region.into(),
+            batch_num: 1,
+            batch_size: 1,
+            keys,
+            revised_keys: Vec::new(),
+        }
+    }
+
+    /// Parses the contents of an `export.bin` file.
+    pub fn parse(bytes: &[u8]) -> Result<KeyExport, GaenError> {
+        let body = bytes
+            .strip_prefix(&EXPORT_HEADER[..])
+            .ok_or(GaenError::InvalidHeader)?;
+        let timestamp = |value: u64| {
+            Utc.timestamp_opt(value as i64, 0)
+                .single()
+                .ok_or(GaenError::Malformed)
+        };
+        let mut rv = KeyExport::new("", timestamp(0)?, timestamp(0)?, Vec::new());
+        let mut reader = Reader { buf: body };
+        while let Some((field, value)) = reader.field()? {
+            match field {
+                1 => rv.start_timestamp = timestamp(value.int()?)?,
+                2 => rv.end_timestamp = timestamp(value.int()?)?,
+                3 => {
+                    rv.region = String::from_utf8(value.bytes()?.to_vec())
+                        .map_err(|_| GaenError::Malformed)?
+                }
+                4 => rv.batch_num = value.int()? as u32,
+                5 => rv.batch_size = value.int()? as u32,
+                7 => rv.keys.push(parse_key(value.bytes()?)?),
+                8 => rv.revised_keys.push(parse_key(value.bytes()?)?),
+                _ => {}
+            }
+        }
+        Ok(rv)
+    }
+
+    /// Writes the contents of an `export.bin` file.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut rv = EXPORT_HEADER.to_vec();
+        write_fixed64(&mut rv, 1, self.start_timestamp.timestamp() as u64);
+        write_fixed64(&mut rv, 2, self.end_timestamp.timestamp() as u64);
+        write_bytes(&mut rv, 3, self.region.as_bytes());
+        write_int(&mut rv, 4, self.batch_num.into());
+        write_int(&mut rv, 5, self.batch_size.into());
+        for key in &self.keys {
+            write_key(&mut rv, 7, key);
+        }
+        for key in &self.revised_keys {
+            write_key(&mut rv, 8, key);
+        }
+        rv
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct Sighting {
+    rpi: RollingProximityId,
+    #[serde(with = "base64")]
+    metadata: Vec<u8>,
+    at: DateTime<Utc>,
+}
+
+/// A sighting of a rolling proximity identifier derived from a published key.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GaenMatch {
+    /// When the identifier was seen.
+    pub at: DateTime<Utc>,
+    /// The decrypted metadata broadcast with the identifier.
+    pub metadata: Vec<u8>,
+    /// The transmission risk level of the matching key.
+    pub transmission_risk_level: u32,
+}
+
+/// The rolling proximity identifiers a device received.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct SightingLog {
+    sightings: Vec<Sighting>,
+}
+
+impl SightingLog {
+    /// Creates an empty log.
+    pub fn new() -> SightingLog {
+        SightingLog::default()
+    }
+
+    /// Records an identifier and its encrypted metadata received at a point
+    /// in time.
+    pub fn add(&mut self, rpi: RollingProximityId, metadata: &[u8], at: DateTime<Utc>) {
+        self.sightings.push(Sighting {
+            rpi,
+            metadata: metadata.to_vec(),
+            at,
+        });
+    }
+
+    /// Returns the number of recorded sightings.
+    pub fn len(&self) -> usize {
+        self.sightings.len()
+    }
+
+    /// Returns `true` if nothing was recorded.
+    pub fn is_empty(&self) -> bool {
+        self.sightings.is_empty()
+    }
+
+    /// Drops sightings before a point in time.
+    pub fn prune(&mut self, before: DateTime<Utc>) {
+        self.sightings.retain(|sighting| sighting.at >= before);
+    }
+
+    /// Matches the log against published keys.
+    ///
+    /// A sighting only matches if it was recorded within
+    /// [`MATCH_TOLERANCE`](constant.MATCH_TOLERANCE.html) intervals of the
+    /// interval of the identifier.  Matches are sorted from oldest to newest.
+    pub fn match_keys(&self, keys: &[TemporaryExposureKey]) -> Vec<GaenMatch> {
+        let mut rv = Vec::new();
+        for key in keys {
+            let derived: HashMap<_, _> = key
+                .rolling_proximity_ids()
+                .into_iter()
+                .map(|(interval, rpi)| (rpi, interval))
+                .collect();
+            for sighting in &self.sightings {
+                let seen = interval_number(sighting.at);
+                let found = derived.get(&sighting.rpi).is_some_and(|&interval| {
+                    seen.max(interval) - seen.min(interval) <= MATCH_TOLERANCE
+                });
+                if found {
+                    let mut metadata = sighting.metadata.clone();
+                    key.apply_metadata_keystream(&sighting.rpi, &mut metadata);
+                    rv.push(GaenMatch {
+                        at: sighting.at,
+                        metadata,
+                        transmission_risk_level: key.transmission_risk_level,
+                    });
+                }
+            }
+        }
+        rv.sort_by_key(|x| x.at);
+        rv
+    }
+
+    /// Determines the exposure status from the keys of an export.
+    ///
+    /// Both the keys and the revised keys of the export are matched, except
+    /// for keys whose diagnosis was revoked.  A revised key replaces the key
+    /// with the same key data, so a revocation also withdraws a key
+    /// published in the same export.
+    pub fn exposure_status(&self, export: &KeyExport) -> ExposureStatus {
+        let is_revoked = |key: &TemporaryExposureKey| {
+            key.is_revoked()
+                || export
+                    .revised_keys
+                    .iter()
+                    .any(|revised| revised.is_revoked() && ct_eq(&revised.key_data, &key.key_data))
+        };
+        let keys: Vec<_> = export
+            .keys
+            .iter()
+            .chain(export.revised_keys.iter())
+            .filter(|key| !is_revoked(key))
+            .cloned()
+            .collect();
+        let matches = self.match_keys(&keys);
+        match matches.into_iter().map(|x| x.at).max() {
+            Some(at) => ExposureStatus::Exposed {
+                at,
+                risk: 1.0,
+                degree: 1,
+            },
+            None => ExposureStatus::Clear,
+        }
+    }
+}
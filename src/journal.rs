@@ -0,0 +1,298 @@
+//! Implements an append-only journal of observed contacts.
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use derive_more::{Display, Error, From};
+use serde::{Deserialize, Serialize};
+use sodiumoxide::crypto::secretbox;
+
+use crate::auth::ShareIdentity;
+use crate::contactlog::ContactLog;
+use crate::envelope::{self, ArtifactKind, EnvelopeError};
+use crate::utils::{to_json_zeroizing, write_atomic};
+use crate::wallet::{SealingKey, WalletError, WalletKey};
+
+/// The number of journal records after which the journal is compacted.
+pub const DEFAULT_COMPACT_AFTER: usize = 1000;
+
+const FRAME_HEADER_LEN: usize = 4;
+
+/// The length of the sealed empty message that follows the key header of a
+/// journal.
+const KEY_CHECK_LEN: usize = secretbox::NONCEBYTES + secretbox::MACBYTES;
+
+/// Error for contact journals that cannot be read or written.
+#[derive(Debug, Error, Display, From)]
+pub enum JournalError {
+    /// The journal or snapshot cannot be read or written.
+    #[display(fmt = "cannot access contact journal: {}", _0)]
+    Io(io::Error),
+    /// The journal is not a contact journal of a supported version.
+    #[display(fmt = "{}", _0)]
+    Envelope(EnvelopeError),
+    /// The key does not unlock the journal or cannot be derived.
+    #[display(fmt = "{}", _0)]
+    Key(WalletError),
+    /// A record in the middle of the journal was damaged.
+    #[display(fmt = "corrupted journal record at offset {}", offset)]
+    #[from(ignore)]
+    CorruptedRecord { offset: usize },
+    /// The snapshot cannot be decoded.
+    #[display(fmt = "corrupted contact log snapshot")]
+    #[from(ignore)]
+    CorruptedSnapshot,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Record {
+    share_id: ShareIdentity,
+    seen: DateTime<Utc>,
+}
+
+/// A contact log that is persisted as a snapshot and an append-only journal.
+///
+/// Every sighting is appended to the journal and flushed to disk, so adding
+/// a contact never rewrites the whole log and a crash never loses recorded
+/// contacts.  A record torn by a crash while it was written is discarded
+/// when the journal is opened again.  After
+/// [`DEFAULT_COMPACT_AFTER`](constant.DEFAULT_COMPACT_AFTER.html) records
+/// the journal is compacted into the snapshot.
+///
+/// Like the [`Wallet`](struct.Wallet.html) the snapshot and every journal
+/// record are encrypted with a [`WalletKey`](enum.WalletKey.html), as the
+/// times and number of contacts are as sensitive as the contacts
+/// themselves.  The key is derived once when the journal is opened.
+pub struct ContactJournal {
+    snapshot_path: PathBuf,
+    journal_path: PathBuf,
+    file: File,
+    sealing_key: SealingKey,
+    contacts: ContactLog,
+    records: usize,
+    compact_after: usize,
+}
+
+impl ContactJournal {
+    /// Opens or creates a contact journal encrypted with the given key.
+    ///
+    /// The snapshot is stored at the given path and the journal next to it
+    /// with a `.journal` suffix.
+    pub fn open<P: AsRef<Path>>(path: P, key: &WalletKey) -> Result<ContactJournal, JournalError> {
+        let snapshot_path = path.as_ref().to_path_buf();
+        let mut journal_path = OsString::from(snapshot_path.as_os_str());
+        journal_path.push(".journal");
+        let journal_path = PathBuf::from(journal_path);
+
+        let mut sealing_key = None;
+        let mut contacts = match fs::read(&snapshot_path) {
+            Ok(bytes) => {
+                let (_, payload) = envelope::open(ArtifactKind::ContactJournal, &bytes, None)?;
+                let (snapshot_key, sealed) =
+                    SealingKey::from_header(key, payload).map_err(snapshot_error)?;
+                let plaintext = snapshot_key.open(sealed).map_err(snapshot_error)?;
+                sealing_key = Some(snapshot_key);
+                serde_json::from_slice(&plaintext).map_err(|_| JournalError::CorruptedSnapshot)?
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => ContactLog::new(),
+            Err(err) => return Err(err.into()),
+        };
+
+        let bytes = match fs::read(&journal_path) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err.into()),
+        };
+        let start = read_header(&bytes, key, &mut sealing_key)?;
+        let sealing_key = match sealing_key {
+            Some(sealing_key) => sealing_key,
+            None => SealingKey::generate(key)?,
+        };
+        let (records, valid_len) = match start {
+            Some(offset) => replay(&bytes, offset, &sealing_key, &mut contacts)?,
+            None => (0, 0),
+        };
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&journal_path)?;
+        if valid_len == 0 {
+            file.set_len(0)?;
+            file.write_all(&journal_header(&sealing_key))?;
+            file.sync_data()?;
+        } else if valid_len < bytes.len() {
+            file.set_len(valid_len as u64)?;
+            file.sync_data()?;
+        }
+
+        Ok(ContactJournal {
+            snapshot_path,
+            journal_path,
+            file,
+            sealing_key,
+            contacts,
+            records,
+            compact_after: DEFAULT_COMPACT_AFTER,
+        })
+    }
+
+    /// Changes after how many journal records the journal is compacted.
+    pub fn set_compact_after(&mut self, records: usize) {
+        self.compact_after = records;
+    }
+
+    /// Returns all recorded contacts.
+    pub fn contacts(&self) -> &ContactLog {
+        &self.contacts
+    }
+
+    /// Returns the number of records in the journal since the last compaction.
+    pub fn records(&self) -> usize {
+        self.records
+    }
+
+    /// Records a contact with the current timestamp.
+    pub fn add(&mut self, share_id: &ShareIdentity) -> Result<(), JournalError> {
+        self.add_at(share_id, Utc::now())
+    }
+
+    /// Records a contact seen at the given timestamp.
+    pub fn add_at(
+        &mut self,
+        share_id: &ShareIdentity,
+        seen: DateTime<Utc>,
+    ) -> Result<(), JournalError> {
+        let record = Record {
+            share_id: share_id.clone(),
+            seen,
+        };
+        let payload = to_json_zeroizing(&record).map_err(io::Error::from)?;
+        let sealed = self.sealing_key.seal(&payload);
+        let mut frame = Vec::with_capacity(FRAME_HEADER_LEN + sealed.len());
+        frame.extend_from_slice(&(sealed.len() as u32).to_le_bytes());
+        frame.extend_from_slice(&sealed);
+        self.file.write_all(&frame)?;
+        self.file.sync_data()?;
+
+        self.contacts.add_at(share_id, seen);
+        self.records += 1;
+        if self.records >= self.compact_after {
+            self.compact()?;
+        }
+        Ok(())
+    }
+
+    /// Writes all contacts into the snapshot and empties the journal.
+    ///
+    /// The snapshot is replaced atomically before the journal is truncated.
+    /// Replaying a journal onto a snapshot that already contains its records
+    /// yields the same log, so a crash in between loses nothing.
+    pub fn compact(&mut self) -> Result<(), JournalError> {
+        let plaintext = to_json_zeroizing(&self.contacts).map_err(io::Error::from)?;
+        let mut payload = self.sealing_key.header();
+        payload.extend_from_slice(&self.sealing_key.seal(&plaintext));
+        write_atomic(
+            &self.snapshot_path,
+            &envelope::wrap(ArtifactKind::ContactJournal, &payload),
+        )?;
+        self.file.set_len(0)?;
+        self.file.write_all(&journal_header(&self.sealing_key))?;
+        self.file.sync_data()?;
+        self.records = 0;
+        Ok(())
+    }
+
+    /// Returns the path of the journal file.
+    pub fn journal_path(&self) -> &Path {
+        &self.journal_path
+    }
+}
+
+fn snapshot_error(err: WalletError) -> JournalError {
+    match err {
+        WalletError::Corrupted => JournalError::CorruptedSnapshot,
+        err => JournalError::Key(err),
+    }
+}
+
+/// Returns the start of a journal: the envelope, the header of the key and
+/// an empty message sealed with the key to detect wrong keys.
+fn journal_header(sealing_key: &SealingKey) -> Vec<u8> {
+    let mut payload = sealing_key.header();
+    payload.extend_from_slice(&sealing_key.seal(&[]));
+    envelope::wrap(ArtifactKind::ContactJournal, &payload)
+}
+
+/// Reads the header of a journal and returns the offset of the first record.
+///
+/// The key of the journal replaces the given one unless they match.
+/// Returns `None` for journals torn while the header was written.
+fn read_header(
+    bytes: &[u8],
+    key: &WalletKey,
+    sealing_key: &mut Option<SealingKey>,
+) -> Result<Option<usize>, JournalError> {
+    if bytes.len() < envelope::HEADER_LEN {
+        return Ok(None);
+    }
+    let (_, payload) = envelope::open(ArtifactKind::ContactJournal, bytes, None)?;
+    let rest = match sealing_key {
+        Some(known) if payload.starts_with(&known.header()) => &payload[known.header().len()..],
+        _ => match SealingKey::from_header(key, payload) {
+            Ok((journal_key, rest)) => {
+                *sealing_key = Some(journal_key);
+                rest
+            }
+            Err(WalletError::Corrupted) => return Ok(None),
+            Err(err) => return Err(err.into()),
+        },
+    };
+    if rest.len() < KEY_CHECK_LEN {
+        return Ok(None);
+    }
+    // only the right key opens the sealed empty message
+    sealing_key.as_ref().unwrap().open(&rest[..KEY_CHECK_LEN])?;
+    Ok(Some(bytes.len() - rest.len() + KEY_CHECK_LEN))
+}
+
+/// Replays the records of a journal onto a contact log.
+///
+/// Returns the number of records and the length of the valid part of the
+/// journal.  A damaged record at the end is a torn write and is dropped,
+/// a damaged record before other data is an error.
+fn replay(
+    bytes: &[u8],
+    start: usize,
+    sealing_key: &SealingKey,
+    contacts: &mut ContactLog,
+) -> Result<(usize, usize), JournalError> {
+    let mut offset = start;
+    let mut records = 0;
+    while offset < bytes.len() {
+        let rest = &bytes[offset..];
+        if rest.len() < FRAME_HEADER_LEN {
+            break;
+        }
+        let mut len = [0u8; 4];
+        len.copy_from_slice(&rest[..4]);
+        let end = FRAME_HEADER_LEN + u32::from_le_bytes(len) as usize;
+        if rest.len() < end {
+            break;
+        }
+        let record = sealing_key
+            .open(&rest[FRAME_HEADER_LEN..end])
+            .ok()
+            .and_then(|x| serde_json::from_slice::<Record>(&x).ok());
+        match record {
+            Some(record) => contacts.add_at(&record.share_id, record.seen),
+            None if rest.len() == end => break,
+            None => return Err(JournalError::CorruptedRecord { offset }),
+        }
+        offset += end;
+        records += 1;
+    }
+    Ok((records, offset))
+}
@@ -48,6 +48,213 @@
 //! used in the last N days (for instanc 14 days) for tainted status.  If any
 //! show up as tained they should contact the authorities.
 //!
+//! Instead of polling a device can subscribe with the
+//! [`SubscriptionToken`](struct.SubscriptionToken.html) of each hashed ID.
+//! The registry then emits a [`Notification`](struct.Notification.html) to
+//! a [`NotificationSink`](trait.NotificationSink.html) (for instance a
+//! [`WebhookSink`](struct.WebhookSink.html) wrapped in a
+//! [`BackgroundSink`](struct.BackgroundSink.html)) whenever the status of
+//! one of them changes.  Subscriptions and undelivered notifications are
+//! kept in the store of the registry.
+//!
+//! The registry can optionally propagate a weaker indirect exposure: if a
+//! tainted user uploads their own contact log their contacts are recorded
+//! with the next degree of exposure and a decayed risk.  How far this goes is
+//! configured with a [`PropagationConfig`](struct.PropagationConfig.html).
+//!
+//! Devices determine their status with [`check_status`](fn.check_status.html)
+//! against either the registry or a downloaded [`TaintList`](struct.TaintList.html)
+//! which yields an [`ExposureStatus`](enum.ExposureStatus.html) that apps can
+//! render consistent guidance for.
+//!
+//! # Uploads
+//!
+//! When a user tests positive they create an
+//! [`UploadBundle`](struct.UploadBundle.html) from their identities and contact
+//! log.  The bundle only contains the unique IDs to mark as infected and the
+//! (still sealed) contacts, carries a checksum and a creation timestamp and is
+//! itself sealed to the public key of the authority before it is transmitted.
+//!
+//! Every accepted upload is identified by an upload ID.  If a test turns out
+//! to be a false positive the authority can
+//! [revoke](struct.Registry.html#method.revoke) the upload which removes the
+//! marks it caused (and those of uploads of exposed users that only stemmed
+//! from it), while marks shared with other uploads stay in place.
+//!
+//! # Authorization
+//!
+//! Uploads are only accepted together with an
+//! [`AuthorizationCode`](struct.AuthorizationCode.html).  Codes are single-use,
+//! expire after a configurable time and are signed either by the authority or by
+//! a [`Lab`](struct.Lab.html) whose key was registered with the
+//! [`Registry`](struct.Registry.html).  The registry tracks which codes were
+//! redeemed so a code cannot be used to upload twice.
+//!
+//! With the `blind` feature labs can instead blindly sign an
+//! [`AnonymousCredential`](struct.AnonymousCredential.html) so that the
+//! authority can verify an upload is legitimate without being able to link it
+//! to the visit at the lab.
+//!
+//! # Storage
+//!
+//! The [`Registry`](struct.Registry.html) keeps its records in a
+//! [`RegistryStore`](trait.RegistryStore.html).  By default this is a
+//! [`MemoryStore`](struct.MemoryStore.html) which is persisted by serializing
+//! the registry or by opening it from a file that every change is written
+//! to.  With the `sqlite` feature a
+//! [`SqliteStore`](struct.SqliteStore.html) keeps the records in an embedded
+//! database and looks them up by hashed identity without loading the whole
+//! registry.  All changes of an upload or revocation happen in a single
+//! transaction.
+//!
+//! # Statistics
+//!
+//! Health officials can export aggregated [`Statistics`](struct.Statistics.html)
+//! of a registry with
+//! [`Registry::statistics`](struct.Registry.html#method.statistics): totals
+//! and daily counts of infections, exposures and uploads.  Every upload only
+//! counts up to a bound of hashed identities and a
+//! [`NoiseMechanism`](enum.NoiseMechanism.html) adds Laplace or Gaussian
+//! noise calibrated to that bound and a configurable epsilon, so that the
+//! counts do not reveal whether a single upload is in the registry.  The
+//! statistics are exported as JSON or CSV.
+//!
+//! # Federation
+//!
+//! Share IDs are tagged with the [`AuthorityId`](struct.AuthorityId.html) of
+//! the authority they were sealed to.  When an upload contains contacts of
+//! travellers from another region the registry only decodes its own
+//! contacts and queues the others.  They are handed out as signed
+//! [`ForwardedContacts`](struct.ForwardedContacts.html) which the registry
+//! of the responsible authority imports if it trusts the sender as a
+//! [`FederationPeer`](struct.FederationPeer.html).  Every batch is imported
+//! at most once and only within
+//! [`FORWARD_RETENTION_DAYS`](constant.FORWARD_RETENTION_DAYS.html) of its
+//! creation, so a batch cannot be replayed.  The queued contacts are kept
+//! in the store together with the upload they came with, and the server
+//! forwards them to the servers of its peers at regular intervals.
+//!
+//! # Transparency
+//!
+//! So that users do not have to trust the authority not to silently taint
+//! people the registry appends every mark it adds or removes to a
+//! [`TransparencyLog`](struct.TransparencyLog.html).  The log is a Merkle
+//! tree like the one of certificate transparency.  The authority signs its
+//! [`TreeHead`](struct.TreeHead.html) and hands out an
+//! [`InclusionProof`](struct.InclusionProof.html) for the entries of a
+//! hashed identity, so a device can check that its status is on the public
+//! record.  Auditors keep the heads they saw and check with a
+//! [`ConsistencyProof`](struct.ConsistencyProof.html) that later heads only
+//! append to them.  Entries only carry the day of a change and are appended
+//! in batches in random order, so the public log does not tell which
+//! contacts were uploaded together.
+//!
+//! # Discovery
+//!
+//! Instead of configuring the keys of an authority by hand apps pin the
+//! public key of a [`RootKey`](struct.RootKey.html) which the operator keeps
+//! offline.  The root key signs an
+//! [`AuthorityDescriptor`](struct.AuthorityDescriptor.html) with the name,
+//! region, endpoints and current keys of the authority, optionally announcing
+//! the keys that replace them at a later point.  Apps verify the
+//! [`SignedDescriptor`](struct.SignedDescriptor.html) against the pinned key
+//! and keep it in a [`DescriptorCache`](struct.DescriptorCache.html) which
+//! refuses to go back to older descriptors.
+//!
+//! # Server
+//!
+//! With the `server` feature a [`Server`](struct.Server.html) exposes the
+//! registry over HTTP and the `covidcotra-server` binary runs it.  All
+//! messages are JSON:
+//!
+//! * `GET /keys`: the [`AuthorityKeys`](struct.AuthorityKeys.html)
+//! * `GET /descriptor`: the [`SignedDescriptor`](struct.SignedDescriptor.html)
+//!   of the authority if one was configured
+//! * `POST /uploads`: submits an [`UploadRequest`](struct.UploadRequest.html)
+//!   and responds with an [`UploadResponse`](struct.UploadResponse.html)
+//! * `GET /taint-list`: the full [`TaintList`](struct.TaintList.html)
+//! * `GET /taint-list/delta?since=<timestamp>&offset=<offset>&generated=<timestamp>`:
+//!   a [`TaintListPage`](struct.TaintListPage.html) of the changes since an
+//!   RFC 3339 timestamp.  Pages after the first name the generation time of
+//!   the first page so that they are taken from the same snapshot
+//! * `GET /status/<prefix>`: the part of the taint list whose hashed
+//!   identities start with a hex encoded prefix (see
+//!   [`encode_prefix`](fn.encode_prefix.html)) so that devices do not have
+//!   to reveal their hashed identities.  Every query compares the prefix
+//!   against all hashed identities of the list
+//! * `POST /subscriptions`: subscribes to push notifications with a
+//!   [`SubscriptionRequest`](struct.SubscriptionRequest.html)
+//! * `DELETE /subscriptions/<token>`: removes a subscription
+//! * `POST /forwards`: imports [`ForwardedContacts`](struct.ForwardedContacts.html)
+//!   of a trusted peer and responds with an
+//!   [`UploadResponse`](struct.UploadResponse.html)
+//! * `GET /transparency/head`: the [`SignedTreeHead`](struct.SignedTreeHead.html)
+//!   of the transparency log
+//! * `POST /transparency/inclusion`: responds to an
+//!   [`InclusionRequest`](struct.InclusionRequest.html) with the
+//!   [`LogInclusion`](struct.LogInclusion.html) of every entry of the hashed
+//!   identity
+//! * `GET /transparency/consistency?from=<size>&to=<size>`: a
+//!   [`ConsistencyProof`](struct.ConsistencyProof.html) between two tree
+//!   sizes
+//!
+//! Failed requests respond with an [`ErrorResponse`](struct.ErrorResponse.html).
+//! Taint lists are served as a [`SignedTaintList`](struct.SignedTaintList.html)
+//! signed by the authority for the [`TaintListScope`](enum.TaintListScope.html)
+//! of the request, together with its generation time and total number of
+//! entries.
+//!
+//! # Client
+//!
+//! Devices talk to the server with a [`Client`](struct.Client.html).  It
+//! fetches the keys of the authority, keeps a downloaded taint list up to
+//! date by paging through the changes, checks the status by prefix,
+//! subscribes to notifications and uploads bundles.  Every downloaded list
+//! is verified against the signing key of the authority.  Requests go
+//! through a
+//! [`Transport`](trait.Transport.html): an
+//! [`HttpTransport`](struct.HttpTransport.html) for the network or a
+//! [`MockTransport`](struct.MockTransport.html) with queued responses for
+//! tests.
+//!
+//! Optionally the client sends cover traffic configured by a
+//! [`CoverConfig`](struct.CoverConfig.html): status queries are padded with
+//! decoy prefixes and [decoy bundles](struct.UploadBundle.html#method.decoy)
+//! are uploaded at random intervals.  The registry processes decoys like
+//! real uploads and only discards them at the end, so that they take as
+//! long and leave nothing behind.
+//!
+//! # Ephemeral IDs
+//!
+//! With the `dp3t` feature devices can use a decentralized scheme next to
+//! share IDs.  A [`DayKeyChain`](struct.DayKeyChain.html) derives rotating
+//! [`EphemeralId`](struct.EphemeralId.html) values from a daily key and
+//! received ones are recorded in an
+//! [`EphemeralContactLog`](struct.EphemeralContactLog.html).  Infected users
+//! publish a [`PublishedDayKey`](struct.PublishedDayKey.html) instead of
+//! uploading their contacts and every device matches the published keys
+//! against its own log.
+//!
+//! With the `gaen` feature keys published by regions using the Google/Apple
+//! Exposure Notification framework can be read from a
+//! [`KeyExport`](struct.KeyExport.html) and matched against the rolling
+//! proximity identifiers recorded in a [`SightingLog`](struct.SightingLog.html).
+//!
+//! The [`Scheme`](trait.Scheme.html) trait abstracts over how identifiers are
+//! broadcast, recorded and matched so that applications can switch between
+//! the sealed share IDs of [`SealedScheme`](struct.SealedScheme.html) and the
+//! other schemes, or run two of them side by side as a pair.
+//!
+//! # Wallet
+//!
+//! On the device identities and the contact log are kept in a
+//! [`Wallet`](struct.Wallet.html).  It is only written to disk encrypted with a
+//! [`WalletKey`](enum.WalletKey.html), either derived from a passphrase or
+//! supplied by the device (for instance from a platform keystore).  Writes
+//! replace the file atomically and a damaged file is detected by a checksum.
+//! Contacts recorded one by one in a [`ContactJournal`](struct.ContactJournal.html) are
+//! encrypted with the same key.
+//!
 //! # ID Behavior
 //!
 //! * unique ID: you can make multiple but not rotate them too often.  You should
@@ -74,15 +281,84 @@
 //! to poll the hashed ID for each generated ID until the infection window
 //! (~14 days?) made an ID expire naturally.
 //!
+//! # Serialization
+//!
+//! All persisted and transmitted artifacts (share IDs, hashed IDs, public
+//! keys, contact logs and authorities) are wrapped in a versioned envelope
+//! which records a magic marker, the format version and the type of the
+//! artifact.  Decoders reject versions they do not understand with an
+//! [`EnvelopeError`](enum.EnvelopeError.html) and transparently migrate
+//! artifacts written before the envelope was introduced.
+//!
+//! Binary fields are written as base64 strings in human readable formats
+//! such as JSON and as native bytes in binary formats.  Helpers for CBOR
+//! and MessagePack are available in the [`formats`](formats/index.html) module
+//! behind the `cbor` and `msgpack` features.
+//!
 //! This is a proof of concept [for this blog post about contact
 //! tracing](https://lucumr.pocoo.org/2020/4/3/contact-tracing/).
+mod api;
 mod auth;
+mod authcode;
 mod authority;
+#[cfg(feature = "blind")]
+mod blind;
+mod client;
 mod contactlog;
 mod crypto;
+mod descriptor;
+#[cfg(feature = "dp3t")]
+mod dp3t;
+mod envelope;
+mod error;
+mod federation;
+pub mod formats;
+#[cfg(feature = "gaen")]
+mod gaen;
+mod journal;
+mod registry;
+mod scheme;
+#[cfg(feature = "server")]
+mod server;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+mod statistics;
+mod status;
+mod store;
+mod subscription;
+mod transparency;
+mod upload;
 mod utils;
+mod wallet;
 
+pub use crate::api::*;
 pub use crate::auth::*;
+pub use crate::authcode::*;
 pub use crate::authority::*;
+#[cfg(feature = "blind")]
+pub use crate::blind::*;
+pub use crate::client::*;
 pub use crate::contactlog::*;
 pub use crate::crypto::*;
+pub use crate::descriptor::*;
+#[cfg(feature = "dp3t")]
+pub use crate::dp3t::*;
+pub use crate::envelope::*;
+pub use crate::error::*;
+pub use crate::federation::*;
+#[cfg(feature = "gaen")]
+pub use crate::gaen::*;
+pub use crate::journal::*;
+pub use crate::registry::*;
+pub use crate::scheme::*;
+#[cfg(feature = "server")]
+pub use crate::server::*;
+#[cfg(feature = "sqlite")]
+pub use crate::sqlite::*;
+pub use crate::statistics::*;
+pub use crate::status::*;
+pub use crate::store::*;
+pub use crate::subscription::*;
+pub use crate::transparency::*;
+pub use crate::upload::*;
+pub use crate::wallet::*;
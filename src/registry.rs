@@ -0,0 +1,1038 @@
+//! Implements the registry of infected and tainted identities.
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::str::FromStr;
+
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use derive_more::{Display, Error, From};
+use serde::{Deserialize, Serialize};
+use serde_plain::forward_display_to_serde;
+use uuid::Uuid;
+
+use crate::auth::HashedIdentity;
+use crate::authcode::{AuthorizationCode, AuthorizationError, CodeId};
+use crate::authority::Authority;
+#[cfg(feature = "blind")]
+use crate::blind::{AnonymousCredential, BlindPublicKey};
+use crate::contactlog::ContactLog;
+use crate::crypto::{shuffle, SigningPublicKey};
+use crate::error::Error;
+use crate::federation::{
+    AuthorityId, FederationPeer, ForwardBatch, ForwardedContacts, FORWARD_RETENTION_DAYS,
+};
+use crate::statistics::{NoiseMechanism, Statistics, StatisticsError};
+use crate::status::{StatusSource, TaintList};
+use crate::store::{
+    ExposureRecord, InfectionRecord, MemoryStore, RegistryStore, StoreError, StoreTransaction,
+    UploadRecord, UploadSource,
+};
+use crate::subscription::{DeliveryError, Notification, NotificationSink, SubscriptionToken};
+use crate::transparency::{LogAction, LogEntry, TransparencyLog};
+use crate::upload::{SealedUploadBundle, UploadBundle, UploadError};
+
+/// Error for uploads rejected by the registry.
+#[derive(Debug, Error, Display, Clone, PartialEq, Eq, From)]
+pub enum SubmitError {
+    /// The authorization code was not accepted.
+    #[display(fmt = "{}", _0)]
+    Authorization(AuthorizationError),
+    /// The upload bundle could not be opened.
+    #[display(fmt = "{}", _0)]
+    Upload(UploadError),
+    /// The contacts in the upload could not be decoded.
+    #[display(fmt = "cannot decode contacts in upload")]
+    #[from(ignore)]
+    UndecodableContacts,
+    /// An exposed user uploaded contacts but none of their identities is
+    /// exposed closely enough to propagate further.
+    #[display(fmt = "uploader is not exposed within the propagation depth")]
+    #[from(ignore)]
+    NotExposed,
+    /// Forwarded contacts were sent by an authority that is not a trusted
+    /// peer.
+    #[display(fmt = "forwarded contacts from untrusted authority")]
+    #[from(ignore)]
+    UntrustedPeer,
+    /// Forwarded contacts are meant for another authority.
+    #[display(fmt = "forwarded contacts are meant for another authority")]
+    #[from(ignore)]
+    Misdirected,
+    /// Forwarded contacts were already imported.
+    #[display(fmt = "forwarded contacts were already imported at {}", imported)]
+    #[from(ignore)]
+    ReplayedForward { imported: DateTime<Utc> },
+    /// Forwarded contacts were created too long ago to be accepted.
+    #[display(fmt = "forwarded contacts created at {} are too old", created)]
+    #[from(ignore)]
+    ExpiredForward { created: DateTime<Utc> },
+    /// The store of the registry failed.
+    #[display(fmt = "{}", _0)]
+    Store(StoreError),
+}
+
+/// Error for uploads that cannot be revoked.
+#[derive(Debug, Error, Display, Clone, PartialEq, Eq)]
+pub enum RevokeError {
+    /// The registry does not know the upload.
+    #[display(fmt = "unknown upload")]
+    UnknownUpload,
+    /// The upload was already revoked.
+    #[display(fmt = "upload was already revoked at {}", revoked)]
+    AlreadyRevoked { revoked: DateTime<Utc> },
+    /// The store of the registry failed.
+    #[display(fmt = "{}", _0)]
+    Store(StoreError),
+}
+
+impl From<StoreError> for RevokeError {
+    fn from(err: StoreError) -> RevokeError {
+        RevokeError::Store(err)
+    }
+}
+
+/// Identifies an upload accepted by the registry.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct UploadId(Uuid);
+
+forward_display_to_serde!(UploadId);
+
+impl UploadId {
+    fn new() -> UploadId {
+        UploadId(Uuid::new_v4())
+    }
+}
+
+impl FromStr for UploadId {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<UploadId, Error> {
+        Ok(UploadId(s.parse()?))
+    }
+}
+
+/// Configures how exposure propagates through uploaded contact logs.
+///
+/// The default only records direct contacts of infected users.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+pub struct PropagationConfig {
+    /// The maximum degree of exposure that is recorded.
+    ///
+    /// `1` only records direct contacts of infected users, `2` also records
+    /// contacts of those contacts and so on.
+    pub max_depth: u8,
+    /// The factor the risk is multiplied with for every degree.
+    pub decay: f64,
+}
+
+impl Default for PropagationConfig {
+    fn default() -> PropagationConfig {
+        PropagationConfig {
+            max_depth: 1,
+            decay: 0.5,
+        }
+    }
+}
+
+/// Describes how a hashed identity was exposed to an infection.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+pub struct Exposure {
+    degree: u8,
+    risk: f64,
+    at: DateTime<Utc>,
+}
+
+impl Exposure {
+    /// Returns the degree of the exposure.
+    ///
+    /// A degree of `1` means direct contact with an infected user, higher
+    /// degrees are indirect exposures through other exposed users.
+    pub fn degree(&self) -> u8 {
+        self.degree
+    }
+
+    /// Returns `true` if this was a direct contact with an infected user.
+    pub fn is_direct(&self) -> bool {
+        self.degree == 1
+    }
+
+    /// Returns the estimated risk between `0.0` and `1.0`.
+    pub fn risk(&self) -> f64 {
+        self.risk
+    }
+
+    /// Returns the time of the most recent contact that caused the exposure.
+    pub fn at(&self) -> DateTime<Utc> {
+        self.at
+    }
+
+    pub(crate) fn merge(&mut self, other: Exposure) {
+        if other.risk > self.risk {
+            self.degree = other.degree;
+            self.risk = other.risk;
+        }
+        if other.at > self.at {
+            self.at = other.at;
+        }
+    }
+}
+
+/// The registry kept by the central authority.
+///
+/// It records which hashed identities are infected and which are exposed
+/// (tainted) and keeps track of the authorization codes that were already
+/// redeemed.  The records are kept in a [`RegistryStore`](trait.RegistryStore.html)
+/// which defaults to the [`MemoryStore`](struct.MemoryStore.html).
+///
+/// Every accepted upload gets an [`UploadId`](struct.UploadId.html) and
+/// every mark in the registry remembers the uploads it originated from, so
+/// that an upload can later be [revoked](#method.revoke) (for instance after
+/// a false positive test) without affecting marks from other uploads.
+///
+/// Devices can [subscribe](#method.subscribe) to push notifications with the
+/// [`SubscriptionToken`](struct.SubscriptionToken.html) of their hashed
+/// identities.  Whenever an upload or revocation changes the status of a
+/// subscribed hashed identity a [`Notification`](struct.Notification.html)
+/// is queued in the store and handed to the
+/// [`NotificationSink`](trait.NotificationSink.html) of the registry.
+///
+/// Every mark added or removed is also recorded in the
+/// [`TransparencyLog`](struct.TransparencyLog.html) of the registry, which
+/// is kept in the store together with the marks.  Changes are queued and
+/// only appended to the log when it is [flushed](#method.flush_log).
+#[derive(Serialize, Deserialize, Default)]
+pub struct Registry<S = MemoryStore> {
+    store: S,
+    #[serde(default)]
+    propagation: PropagationConfig,
+    trusted_labs: Vec<SigningPublicKey>,
+    #[cfg(feature = "blind")]
+    #[serde(default)]
+    blind_issuers: Vec<BlindPublicKey>,
+    #[serde(default)]
+    peers: Vec<FederationPeer>,
+    #[serde(skip)]
+    sink: Option<Box<dyn NotificationSink + Send>>,
+}
+
+impl Registry {
+    /// Creates an empty registry that keeps its records in memory.
+    pub fn new() -> Registry {
+        Registry::default()
+    }
+}
+
+impl<S: RegistryStore> Registry<S> {
+    /// Creates a registry on top of a store.
+    pub fn with_store(store: S) -> Registry<S> {
+        Registry {
+            store,
+            propagation: PropagationConfig::default(),
+            trusted_labs: Vec::new(),
+            #[cfg(feature = "blind")]
+            blind_issuers: Vec::new(),
+            peers: Vec::new(),
+            sink: None,
+        }
+    }
+
+    /// Returns the store of the registry.
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    /// Returns the propagation config.
+    pub fn propagation(&self) -> &PropagationConfig {
+        &self.propagation
+    }
+
+    /// Changes how exposure propagates for future uploads.
+    pub fn set_propagation(&mut self, config: PropagationConfig) {
+        self.propagation = config;
+    }
+
+    /// Trusts authorization codes issued by a lab.
+    pub fn trust_lab(&mut self, public_key: &SigningPublicKey) {
+        if !self.trusted_labs.contains(public_key) {
+            self.trusted_labs.push(*public_key);
+        }
+    }
+
+    /// Submits an upload bundle together with its authorization code.
+    ///
+    /// The code must have been issued by the authority or a trusted lab, must
+    /// not be expired and must not have been redeemed before.  The code is
+    /// only redeemed if the upload is accepted.
+    ///
+    /// [Decoy](struct.UploadBundle.html#method.decoy) bundles are accepted
+    /// whatever code accompanies them.  They go through the same lookups,
+    /// hashing and writes as real uploads, which are then rolled back, so
+    /// that they take as long and leave nothing behind.  This applies to all
+    /// ways of submitting.
+    pub fn submit(
+        &mut self,
+        authority: &Authority,
+        code: &AuthorizationCode,
+        bundle: &SealedUploadBundle,
+    ) -> Result<UploadId, SubmitError> {
+        let bundle = bundle.open(authority.secret_key())?;
+        let mut tx = self.store.transaction()?;
+        let redeemed = tx.code_redeemed_at(&code.id())?;
+        let mut trusted = self.trusted_labs.clone();
+        trusted.push(*authority.signing_public_key());
+        let verified = code.verify(&trusted);
+        if !bundle.is_decoy() {
+            if let Some(redeemed) = redeemed {
+                return Err(AuthorizationError::Reused { redeemed }.into());
+            }
+            verified?;
+        }
+        let source = UploadSource::Authorized {
+            issuer: *code.issuer(),
+        };
+        let (upload_id, infected, exposed, foreign) = import(&mut *tx, authority, &bundle, source)?;
+        tx.put_code_redeemed(&code.id(), Utc::now())?;
+        queue_notifications(&mut *tx, infected.iter().chain(&exposed))?;
+        queue_forwards(&mut *tx, &foreign)?;
+        if bundle.is_decoy() {
+            // dropping the transaction rolls back everything the decoy wrote
+            drop(tx);
+        } else {
+            tx.commit()?;
+        }
+        self.deliver_notifications();
+        Ok(upload_id)
+    }
+
+    /// Trusts anonymous credentials signed by a blind issuer.
+    ///
+    /// This requires the `blind` feature.
+    #[cfg(feature = "blind")]
+    pub fn trust_blind_issuer(&mut self, public_key: &BlindPublicKey) {
+        if !self.blind_issuers.contains(public_key) {
+            self.blind_issuers.push(public_key.clone());
+        }
+    }
+
+    /// Submits an upload bundle together with an anonymous credential.
+    ///
+    /// Works like [`submit`](#method.submit) but the upload cannot be linked
+    /// to the issuance of the credential.  This requires the `blind` feature.
+    #[cfg(feature = "blind")]
+    pub fn submit_anonymous(
+        &mut self,
+        authority: &Authority,
+        credential: &AnonymousCredential,
+        bundle: &SealedUploadBundle,
+    ) -> Result<UploadId, SubmitError> {
+        let bundle = bundle.open(authority.secret_key())?;
+        let mut tx = self.store.transaction()?;
+        let redeemed = tx.credential_redeemed_at(&credential.id())?;
+        let verified = credential.verify(&self.blind_issuers);
+        if !bundle.is_decoy() {
+            if let Some(redeemed) = redeemed {
+                return Err(AuthorizationError::Reused { redeemed }.into());
+            }
+            verified?;
+        }
+        let (upload_id, infected, exposed, foreign) =
+            import(&mut *tx, authority, &bundle, UploadSource::Anonymous)?;
+        tx.put_credential_redeemed(&credential.id(), Utc::now())?;
+        queue_notifications(&mut *tx, infected.iter().chain(&exposed))?;
+        queue_forwards(&mut *tx, &foreign)?;
+        if bundle.is_decoy() {
+            drop(tx);
+        } else {
+            tx.commit()?;
+        }
+        self.deliver_notifications();
+        Ok(upload_id)
+    }
+
+    /// Submits the upload bundle of an exposed user.
+    ///
+    /// If indirect exposure is enabled in the
+    /// [`PropagationConfig`](struct.PropagationConfig.html) users that were
+    /// exposed can upload their contacts, which are then recorded as exposed
+    /// with the next degree and a decayed risk.  No authorization code is
+    /// needed as only the owner of an exposed identity knows the unique ID
+    /// behind its hashed ID.
+    ///
+    /// Contacts sealed to other authorities are not forwarded since the
+    /// exposure of the uploader cannot be passed along with them.
+    pub fn submit_exposed(
+        &mut self,
+        authority: &Authority,
+        bundle: &SealedUploadBundle,
+    ) -> Result<UploadId, SubmitError> {
+        let bundle = bundle.open(authority.secret_key())?;
+        let max_depth = self.propagation.max_depth;
+        let decay = self.propagation.decay;
+        let mut tx = self.store.transaction()?;
+        let mut records = Vec::new();
+        for unique_id in bundle.unique_ids() {
+            if let Some(record) = tx.exposure(&unique_id.hash())? {
+                if record.exposure.degree < max_depth {
+                    records.push(record);
+                }
+            }
+        }
+        let source = records
+            .iter()
+            .map(|record| record.exposure)
+            .min_by_key(|exposure| exposure.degree);
+        // decoy identities are never exposed, they continue as if they were
+        // directly exposed to cost the same as real uploads
+        let source = match source {
+            Some(source) => source,
+            None if bundle.is_decoy() => Exposure {
+                degree: 1,
+                risk: 1.0,
+                at: Utc::now(),
+            },
+            None => return Err(SubmitError::NotExposed),
+        };
+        let origins = records
+            .iter()
+            .flat_map(|record| record.sources.keys().copied())
+            .collect();
+        let contacts = bundle
+            .contacts()
+            .partition(&authority.id())
+            .0
+            .decode(authority.secret_key())
+            .map_err(|_| SubmitError::UndecodableContacts)?;
+
+        let upload_id = UploadId::new();
+        let degree = source.degree + 1;
+        let mut exposed = Vec::new();
+        for (contact, at) in contacts {
+            let hashed_id = contact.hash();
+            expose(
+                &mut *tx,
+                upload_id,
+                hashed_id,
+                Exposure {
+                    degree,
+                    risk: source.risk * decay,
+                    at,
+                },
+            )?;
+            exposed.push(hashed_id);
+        }
+        tx.put_upload(
+            &upload_id,
+            &UploadRecord {
+                received: Utc::now(),
+                source: Some(UploadSource::Exposed),
+                revoked: None,
+                infected: Vec::new(),
+                exposed: exposed.clone(),
+                origins,
+            },
+        )?;
+        log_changes(&mut *tx, LogAction::Exposed { degree }, &exposed)?;
+        queue_notifications(&mut *tx, &exposed)?;
+        if bundle.is_decoy() {
+            drop(tx);
+        } else {
+            tx.commit()?;
+        }
+        self.deliver_notifications();
+        Ok(upload_id)
+    }
+
+    /// Trusts contacts forwarded by the authority of another region.
+    pub fn trust_peer(&mut self, peer: FederationPeer) {
+        if !self.peers.contains(&peer) {
+            self.peers.push(peer);
+        }
+    }
+
+    /// Returns the trusted peers.
+    pub fn peers(&self) -> &[FederationPeer] {
+        &self.peers
+    }
+
+    /// Returns the contacts waiting to be forwarded by authority.
+    ///
+    /// The contacts are queued in the store together with the upload they
+    /// came with.
+    pub fn pending_forwards(&self) -> Result<BTreeMap<AuthorityId, ContactLog>, StoreError> {
+        self.store.pending_forwards()
+    }
+
+    /// Returns the transparency log of all changes to the registry.
+    ///
+    /// Changes only show up once they were [flushed](#method.flush_log).
+    pub fn transparency_log(&self) -> Result<TransparencyLog, StoreError> {
+        Ok(TransparencyLog::from_entries(self.store.log_entries()?))
+    }
+
+    /// Appends the queued changes to the transparency log in random order.
+    ///
+    /// Changes are queued rather than appended as they happen, so that the
+    /// entries of one upload end up mixed with those of all other uploads
+    /// since the last flush.  This should be called at regular intervals
+    /// (the [`Server`](struct.Server.html) does so every
+    /// [`DEFAULT_LOG_FLUSH_MINUTES`](constant.DEFAULT_LOG_FLUSH_MINUTES.html)
+    /// unless configured otherwise).
+    /// Returns the number of appended entries.
+    pub fn flush_log(&mut self) -> Result<usize, StoreError> {
+        let mut tx = self.store.transaction()?;
+        let mut entries = tx.take_pending_log_entries()?;
+        shuffle(&mut entries);
+        for entry in &entries {
+            tx.append_log_entry(entry)?;
+        }
+        tx.commit()?;
+        Ok(entries.len())
+    }
+
+    /// Removes the contacts waiting to be forwarded and returns them signed
+    /// by the authority, one batch per receiving authority.
+    ///
+    /// The batches are meant to be sent to the authorities they are
+    /// addressed to (see [`ForwardBatch::to`](struct.ForwardBatch.html#structfield.to)).
+    pub fn take_forwards(
+        &mut self,
+        authority: &Authority,
+    ) -> Result<Vec<ForwardedContacts>, StoreError> {
+        let mut tx = self.store.transaction()?;
+        let forwards = tx.take_pending_forwards()?;
+        tx.commit()?;
+        let created = Utc::now();
+        Ok(forwards
+            .into_iter()
+            .map(|(to, contacts)| {
+                authority.sign_forward(&ForwardBatch {
+                    from: authority.id(),
+                    to,
+                    created,
+                    contacts,
+                })
+            })
+            .collect())
+    }
+
+    /// Hands the contacts waiting to be forwarded to a function that sends
+    /// them, one signed batch per receiving authority.
+    ///
+    /// Contacts of batches the function fails to send (by returning
+    /// `false`) stay queued for the next attempt.  Returns the number of
+    /// sent batches.
+    pub fn send_forwards<F>(
+        &mut self,
+        authority: &Authority,
+        mut send: F,
+    ) -> Result<usize, StoreError>
+    where
+        F: FnMut(&ForwardedContacts) -> bool,
+    {
+        let mut tx = self.store.transaction()?;
+        let created = Utc::now();
+        let mut sent = 0;
+        for (to, contacts) in tx.take_pending_forwards()? {
+            let batch = ForwardBatch {
+                from: authority.id(),
+                to,
+                created,
+                contacts,
+            };
+            if send(&authority.sign_forward(&batch)) {
+                sent += 1;
+            } else {
+                tx.push_pending_forward(&to, &batch.contacts)?;
+            }
+        }
+        tx.commit()?;
+        Ok(sent)
+    }
+
+    /// Imports contacts forwarded by a trusted peer.
+    ///
+    /// The contacts are recorded like the contacts of an infected user that
+    /// uploaded to this registry and can be revoked with the returned upload
+    /// ID.  Every batch is only imported once and only within
+    /// [`FORWARD_RETENTION_DAYS`](constant.FORWARD_RETENTION_DAYS.html) of
+    /// its creation.
+    pub fn import_forwarded(
+        &mut self,
+        authority: &Authority,
+        forwarded: &ForwardedContacts,
+    ) -> Result<UploadId, SubmitError> {
+        let batch = forwarded.verify(&self.peers)?;
+        if batch.to != authority.id() {
+            return Err(SubmitError::Misdirected);
+        }
+        let now = Utc::now();
+        if batch.created < now - Duration::days(FORWARD_RETENTION_DAYS) {
+            return Err(SubmitError::ExpiredForward {
+                created: batch.created,
+            });
+        }
+        let contacts = batch
+            .contacts
+            .decode(authority.secret_key())
+            .map_err(|_| SubmitError::UndecodableContacts)?;
+
+        let mut tx = self.store.transaction()?;
+        let batch_id = forwarded.batch_id();
+        if let Some(imported) = tx.forward_imported_at(&batch_id)? {
+            return Err(SubmitError::ReplayedForward { imported });
+        }
+        tx.put_forward_imported(&batch_id, now)?;
+        let upload_id = UploadId::new();
+        let mut exposed = Vec::new();
+        for (contact, at) in contacts {
+            let hashed_id = contact.hash();
+            expose(
+                &mut *tx,
+                upload_id,
+                hashed_id,
+                Exposure {
+                    degree: 1,
+                    risk: 1.0,
+                    at,
+                },
+            )?;
+            exposed.push(hashed_id);
+        }
+        tx.put_upload(
+            &upload_id,
+            &UploadRecord {
+                received: now,
+                source: Some(UploadSource::Forwarded { from: batch.from }),
+                revoked: None,
+                infected: Vec::new(),
+                exposed: exposed.clone(),
+                origins: BTreeSet::new(),
+            },
+        )?;
+        log_changes(&mut *tx, LogAction::Exposed { degree: 1 }, &exposed)?;
+        queue_notifications(&mut *tx, &exposed)?;
+        tx.commit()?;
+        self.deliver_notifications();
+        Ok(upload_id)
+    }
+
+    /// Revokes an upload (for instance after a false positive test).
+    ///
+    /// This removes the infection marks and exposures that originated from
+    /// the upload.  Marks that were also caused by other uploads stay in
+    /// place.  Uploads of exposed users whose exposure only came from the
+    /// revoked upload are revoked as well.  Hashed identities that lose all
+    /// their marks are reported as revoked in the published lists.
+    pub fn revoke(&mut self, upload_id: &UploadId) -> Result<(), RevokeError> {
+        let mut tx = self.store.transaction()?;
+        match tx.upload(upload_id)? {
+            None => return Err(RevokeError::UnknownUpload),
+            Some(UploadRecord {
+                revoked: Some(revoked),
+                ..
+            }) => {
+                return Err(RevokeError::AlreadyRevoked { revoked });
+            }
+            Some(_) => {}
+        }
+
+        let now = Utc::now();
+        let mut changed = Vec::new();
+        let mut pending = vec![*upload_id];
+        while let Some(upload_id) = pending.pop() {
+            let mut record = match tx.upload(&upload_id)? {
+                Some(record) if record.revoked.is_none() => record,
+                _ => continue,
+            };
+            record.revoked = Some(now);
+            tx.put_upload(&upload_id, &record)?;
+            let hashed_ids: Vec<_> = record
+                .infected
+                .iter()
+                .chain(record.exposed.iter())
+                .copied()
+                .collect();
+            log_changes(&mut *tx, LogAction::Revoked, &hashed_ids)?;
+            changed.extend(hashed_ids);
+
+            for hashed_id in &record.infected {
+                if let Some(mut infection) = tx.infection(hashed_id)? {
+                    infection.sources.remove(&upload_id);
+                    if infection.sources.is_empty() {
+                        tx.remove_infection(hashed_id)?;
+                        mark_revoked(&mut *tx, hashed_id, now)?;
+                    } else {
+                        tx.put_infection(hashed_id, &infection)?;
+                    }
+                }
+            }
+            for hashed_id in &record.exposed {
+                if let Some(mut exposure) = tx.exposure(hashed_id)? {
+                    exposure.sources.remove(&upload_id);
+                    if exposure.update() {
+                        tx.put_exposure(hashed_id, &exposure)?;
+                    } else {
+                        tx.remove_exposure(hashed_id)?;
+                        mark_revoked(&mut *tx, hashed_id, now)?;
+                    }
+                }
+            }
+
+            for other_id in tx.uploads_from(&upload_id)? {
+                if let Some(mut other) = tx.upload(&other_id)? {
+                    if other.revoked.is_none() && other.origins.remove(&upload_id) {
+                        tx.put_upload(&other_id, &other)?;
+                        if other.origins.is_empty() {
+                            pending.push(other_id);
+                        }
+                    }
+                }
+            }
+        }
+        queue_notifications(&mut *tx, &changed)?;
+        tx.commit()?;
+        self.deliver_notifications();
+        Ok(())
+    }
+
+    /// Computes aggregated statistics of the registry.
+    ///
+    /// Daily counts are reported for every day from `first_day` to
+    /// `last_day`.  Every upload counts at most `max_per_upload` infected and
+    /// exposed hashed identities and every count has noise of the given
+    /// mechanism added, scaled to how much a single upload can change the
+    /// counts.
+    pub fn statistics(
+        &self,
+        first_day: NaiveDate,
+        last_day: NaiveDate,
+        noise: NoiseMechanism,
+        max_per_upload: u64,
+    ) -> Result<Statistics, StatisticsError> {
+        Statistics::collect(&self.store, first_day, last_day, noise, max_per_upload)
+    }
+
+    /// Checks if an upload was revoked.
+    pub fn is_upload_revoked(&self, upload_id: &UploadId) -> Result<bool, StoreError> {
+        Ok(self
+            .store
+            .upload(upload_id)?
+            .is_some_and(|record| record.revoked.is_some()))
+    }
+
+    /// Checks if a hashed identity was reported as infected.
+    pub fn is_infected(&self, hashed_id: &HashedIdentity) -> Result<bool, StoreError> {
+        Ok(self.store.infection(hashed_id)?.is_some())
+    }
+
+    /// Returns when a hashed identity was reported as infected.
+    pub fn reported_at(
+        &self,
+        hashed_id: &HashedIdentity,
+    ) -> Result<Option<DateTime<Utc>>, StoreError> {
+        Ok(self.store.infection(hashed_id)?.map(|x| x.reported_at))
+    }
+
+    /// Checks if all marks of a hashed identity were revoked.
+    pub fn is_revoked(&self, hashed_id: &HashedIdentity) -> Result<bool, StoreError> {
+        Ok(self.store.revoked_at(hashed_id)?.is_some())
+    }
+
+    /// Creates the list of infected, exposed and revoked identities for
+    /// download.
+    pub fn taint_list(&self) -> Result<TaintList, StoreError> {
+        Ok(TaintList::new(
+            self.store
+                .infections()?
+                .into_iter()
+                .map(|(hashed_id, record)| (hashed_id, record.reported_at))
+                .collect(),
+            self.store
+                .exposures()?
+                .into_iter()
+                .map(|(hashed_id, record)| (hashed_id, record.exposure))
+                .collect(),
+            self.store.revocations()?.into_iter().collect(),
+        ))
+    }
+
+    /// Creates the list of changes since the given time for download.
+    ///
+    /// The delta carries the current state of every hashed identity that was
+    /// affected by an upload accepted or revoked since then.  Devices merge
+    /// it into their copy with [`TaintList::apply`](struct.TaintList.html#method.apply)
+    /// and use its [`generated`](struct.TaintList.html#method.generated)
+    /// timestamp for the next request.
+    pub fn taint_delta(&self, since: DateTime<Utc>) -> Result<TaintList, StoreError> {
+        let mut changed = HashSet::new();
+        for (_, record) in self.store.uploads()? {
+            if record.received >= since || record.revoked.is_some_and(|x| x >= since) {
+                changed.extend(record.infected.iter().chain(record.exposed.iter()).copied());
+            }
+        }
+        let mut infected = HashMap::new();
+        let mut exposures = HashMap::new();
+        let mut revoked = HashMap::new();
+        for hashed_id in changed {
+            if let Some(record) = self.store.infection(&hashed_id)? {
+                infected.insert(hashed_id, record.reported_at);
+            }
+            if let Some(record) = self.store.exposure(&hashed_id)? {
+                exposures.insert(hashed_id, record.exposure);
+            }
+            if let Some(revoked_at) = self.store.revoked_at(&hashed_id)? {
+                revoked.insert(hashed_id, revoked_at);
+            }
+        }
+        Ok(TaintList::new(infected, exposures, revoked))
+    }
+
+    /// Checks if a hashed identity was in direct contact with an infected user.
+    pub fn is_tainted(&self, hashed_id: &HashedIdentity) -> Result<bool, StoreError> {
+        Ok(self.exposure(hashed_id)?.is_some_and(|x| x.is_direct()))
+    }
+
+    /// Returns the direct or indirect exposure of a hashed identity.
+    pub fn exposure(&self, hashed_id: &HashedIdentity) -> Result<Option<Exposure>, StoreError> {
+        Ok(self.store.exposure(hashed_id)?.map(|x| x.exposure))
+    }
+
+    /// Checks if an authorization code was already redeemed.
+    pub fn is_redeemed(&self, code_id: &CodeId) -> Result<bool, StoreError> {
+        Ok(self.store.code_redeemed_at(code_id)?.is_some())
+    }
+
+    /// Subscribes to notifications for the hashed identity behind a token.
+    ///
+    /// Subscriptions are kept in the store of the registry.
+    pub fn subscribe(&mut self, token: SubscriptionToken) -> Result<(), StoreError> {
+        let mut tx = self.store.transaction()?;
+        tx.put_subscription(&token)?;
+        tx.commit()
+    }
+
+    /// Removes a subscription.
+    ///
+    /// Returns `false` if there was no subscription for the token.
+    pub fn unsubscribe(&mut self, token: &SubscriptionToken) -> Result<bool, StoreError> {
+        let mut tx = self.store.transaction()?;
+        if !tx.is_subscribed(token)? {
+            return Ok(false);
+        }
+        tx.remove_subscription(token)?;
+        tx.commit()?;
+        Ok(true)
+    }
+
+    /// Checks if there is a subscription for a token.
+    pub fn is_subscribed(&self, token: &SubscriptionToken) -> Result<bool, StoreError> {
+        self.store.is_subscribed(token)
+    }
+
+    /// Installs the sink notifications are delivered to.
+    ///
+    /// Notifications are queued in the store together with the change that
+    /// caused them and handed to the sink once the change is committed.
+    /// Without a sink they stay queued until a sink is installed and the
+    /// notifications are [flushed](#method.flush_notifications).  Sinks that block
+    /// (such as the [`WebhookSink`](struct.WebhookSink.html)) should be
+    /// wrapped in a [`BackgroundSink`](struct.BackgroundSink.html).  The
+    /// sink is not persisted with the registry and has to be installed again
+    /// after the registry was loaded.
+    pub fn set_notification_sink<N: NotificationSink + Send + 'static>(&mut self, sink: N) {
+        self.sink = Some(Box::new(sink));
+    }
+
+    /// Returns the notifications that could not be delivered yet.
+    pub fn pending_notifications(&self) -> Result<Vec<Notification>, StoreError> {
+        self.store.notifications()
+    }
+
+    /// Delivers the notifications that could not be delivered yet.
+    ///
+    /// The queued notifications are handed to the sink and only the ones it
+    /// reports as delivered are removed from the queue (see
+    /// [`NotificationSink::deliver_queued`](trait.NotificationSink.html#method.deliver_queued)).
+    /// Queued notifications are also retried whenever new notifications are
+    /// emitted.
+    pub fn flush_notifications(&mut self) -> Result<(), DeliveryError> {
+        let sink = match self.sink {
+            Some(ref mut sink) => sink,
+            None => return Ok(()),
+        };
+        let queued = self.store.notifications().map_err(DeliveryError::Store)?;
+        if queued.is_empty() {
+            return Ok(());
+        }
+        let delivered = sink.deliver_queued(&queued)?;
+        let mut tx = self.store.transaction().map_err(DeliveryError::Store)?;
+        for _ in 0..delivered {
+            tx.pop_notification().map_err(DeliveryError::Store)?;
+        }
+        tx.commit().map_err(DeliveryError::Store)
+    }
+
+    fn deliver_notifications(&mut self) {
+        // the change is already committed, failed deliveries stay queued
+        let _ = self.flush_notifications();
+    }
+}
+
+/// Queues a notification for every subscribed hashed identity that changed.
+fn queue_notifications<'a, I>(tx: &mut dyn StoreTransaction, changed: I) -> Result<(), StoreError>
+where
+    I: IntoIterator<Item = &'a HashedIdentity>,
+{
+    let now = Utc::now();
+    let mut seen = HashSet::new();
+    for hashed_id in changed {
+        let token = hashed_id.subscription_token();
+        if seen.insert(token) && tx.is_subscribed(&token)? {
+            tx.push_notification(&Notification {
+                token,
+                changed: now,
+            })?;
+        }
+    }
+    Ok(())
+}
+
+/// Queues contacts sealed to other authorities to be forwarded.
+fn queue_forwards(
+    tx: &mut dyn StoreTransaction,
+    foreign: &BTreeMap<AuthorityId, ContactLog>,
+) -> Result<(), StoreError> {
+    for (authority_id, contacts) in foreign {
+        tx.push_pending_forward(authority_id, contacts)?;
+    }
+    Ok(())
+}
+
+/// Queues a change of hashed identities for the transparency log.
+fn log_changes(
+    tx: &mut dyn StoreTransaction,
+    action: LogAction,
+    hashed_ids: &[HashedIdentity],
+) -> Result<(), StoreError> {
+    let at = Utc::now()
+        .date_naive()
+        .and_hms_opt(0, 0, 0)
+        .unwrap()
+        .and_utc();
+    for hashed_id in hashed_ids {
+        tx.push_pending_log_entry(&LogEntry {
+            hashed_id: *hashed_id,
+            action,
+            at,
+        })?;
+    }
+    Ok(())
+}
+
+type Imported = (
+    UploadId,
+    Vec<HashedIdentity>,
+    Vec<HashedIdentity>,
+    BTreeMap<AuthorityId, ContactLog>,
+);
+
+fn import(
+    tx: &mut dyn StoreTransaction,
+    authority: &Authority,
+    bundle: &UploadBundle,
+    source: UploadSource,
+) -> Result<Imported, SubmitError> {
+    let (own, foreign) = bundle.contacts().partition(&authority.id());
+    let contacts = own
+        .decode(authority.secret_key())
+        .map_err(|_| SubmitError::UndecodableContacts)?;
+
+    let upload_id = UploadId::new();
+    let received = Utc::now();
+    let mut infected = Vec::new();
+    for unique_id in bundle.unique_ids() {
+        let hashed_id = unique_id.hash();
+        let mut infection = tx
+            .infection(&hashed_id)?
+            .unwrap_or_else(|| InfectionRecord {
+                reported_at: received,
+                sources: BTreeSet::new(),
+            });
+        infection.sources.insert(upload_id);
+        tx.put_infection(&hashed_id, &infection)?;
+        tx.remove_revoked(&hashed_id)?;
+        infected.push(hashed_id);
+    }
+    let mut exposed = Vec::new();
+    for (contact, at) in contacts {
+        let hashed_id = contact.hash();
+        expose(
+            tx,
+            upload_id,
+            hashed_id,
+            Exposure {
+                degree: 1,
+                risk: 1.0,
+                at,
+            },
+        )?;
+        exposed.push(hashed_id);
+    }
+    tx.put_upload(
+        &upload_id,
+        &UploadRecord {
+            received,
+            source: Some(source),
+            revoked: None,
+            infected: infected.clone(),
+            exposed: exposed.clone(),
+            origins: BTreeSet::new(),
+        },
+    )?;
+    log_changes(tx, LogAction::Infected, &infected)?;
+    log_changes(tx, LogAction::Exposed { degree: 1 }, &exposed)?;
+    Ok((upload_id, infected, exposed, foreign))
+}
+
+fn expose(
+    tx: &mut dyn StoreTransaction,
+    upload_id: UploadId,
+    hashed_id: HashedIdentity,
+    exposure: Exposure,
+) -> Result<(), StoreError> {
+    let mut record = tx.exposure(&hashed_id)?.unwrap_or_else(|| ExposureRecord {
+        exposure,
+        sources: HashMap::new(),
+    });
+    record
+        .sources
+        .entry(upload_id)
+        .and_modify(|old| old.merge(exposure))
+        .or_insert(exposure);
+    record.update();
+    tx.put_exposure(&hashed_id, &record)?;
+    tx.remove_revoked(&hashed_id)
+}
+
+fn mark_revoked(
+    tx: &mut dyn StoreTransaction,
+    hashed_id: &HashedIdentity,
+    now: DateTime<Utc>,
+) -> Result<(), StoreError> {
+    if tx.infection(hashed_id)?.is_none() && tx.exposure(hashed_id)?.is_none() {
+        tx.put_revoked(hashed_id, now)?;
+    }
+    Ok(())
+}
+
+impl<S: RegistryStore> StatusSource for Registry<S> {
+    fn reported_at(&self, hashed_id: &HashedIdentity) -> Result<Option<DateTime<Utc>>, StoreError> {
+        Registry::reported_at(self, hashed_id)
+    }
+
+    fn exposure(&self, hashed_id: &HashedIdentity) -> Result<Option<Exposure>, StoreError> {
+        Registry::exposure(self, hashed_id)
+    }
+
+    fn is_revoked(&self, hashed_id: &HashedIdentity) -> Result<bool, StoreError> {
+        Registry::is_revoked(self, hashed_id)
+    }
+}
@@ -0,0 +1,264 @@
+//! Implements the abstraction over identity schemes.
+use chrono::{DateTime, Utc};
+
+use crate::auth::{Identity, ShareIdentity};
+use crate::contactlog::ContactLog;
+use crate::crypto::PublicKey;
+#[cfg(feature = "dp3t")]
+use crate::dp3t::{DayKeyChain, EphemeralContactLog, EphemeralId, PublishedDayKey};
+use crate::error::Error;
+#[cfg(feature = "gaen")]
+use crate::gaen::{
+    interval_number, KeyExport, RollingProximityId, SightingLog, TemporaryExposureKey,
+    DEFAULT_ROLLING_PERIOD,
+};
+use crate::status::{check_status, ExposureStatus, TaintList};
+
+/// Defines how broadcast identifiers are generated, recorded and matched.
+///
+/// [`SealedScheme`](struct.SealedScheme.html) implements the design of this
+/// crate where share IDs are sealed to the authority.  With the `dp3t` and
+/// `gaen` features [`Dp3tScheme`](struct.Dp3tScheme.html) and
+/// [`GaenScheme`](struct.GaenScheme.html) are available.  A pair of schemes
+/// is a scheme itself which runs both side by side.
+pub trait Scheme {
+    /// The secret state of a device identifiers are generated from.
+    type State;
+    /// An identifier broadcast to other devices.
+    type Broadcast;
+    /// The identifiers a device received from other devices.
+    type Log: Default;
+    /// What is published about infected users.
+    type Published;
+
+    /// Returns the identifier to broadcast at a point in time.
+    ///
+    /// Returns `None` if the state cannot produce an identifier for that time
+    /// (for instance because it lies before the state was created).
+    fn broadcast(&self, state: &mut Self::State, at: DateTime<Utc>) -> Option<Self::Broadcast>;
+
+    /// Records an identifier received at a point in time.
+    fn record(&self, log: &mut Self::Log, broadcast: &Self::Broadcast, at: DateTime<Utc>);
+
+    /// Determines the exposure status of a device from published data.
+    fn exposure_status(
+        &self,
+        state: &Self::State,
+        log: &Self::Log,
+        published: &Self::Published,
+    ) -> Result<ExposureStatus, Error>;
+}
+
+/// The scheme of sealing share IDs to the authority.
+///
+/// Devices broadcast a fresh [`ShareIdentity`](struct.ShareIdentity.html)
+/// and record received ones in a [`ContactLog`](struct.ContactLog.html).  The
+/// log is matched by the authority after it was uploaded, so the status is
+/// determined from the [`TaintList`](struct.TaintList.html) alone.
+#[derive(Clone, Debug)]
+pub struct SealedScheme {
+    public_key: PublicKey,
+}
+
+impl SealedScheme {
+    /// Creates the scheme for the public key of an authority.
+    pub fn new(public_key: &PublicKey) -> SealedScheme {
+        SealedScheme {
+            public_key: *public_key,
+        }
+    }
+}
+
+impl Scheme for SealedScheme {
+    type State = Identity;
+    type Broadcast = ShareIdentity;
+    type Log = ContactLog;
+    type Published = TaintList;
+
+    fn broadcast(&self, state: &mut Identity, _at: DateTime<Utc>) -> Option<ShareIdentity> {
+        Some(state.new_share_id(&self.public_key))
+    }
+
+    fn record(&self, log: &mut ContactLog, broadcast: &ShareIdentity, at: DateTime<Utc>) {
+        log.add_at(broadcast, at);
+    }
+
+    fn exposure_status(
+        &self,
+        state: &Identity,
+        _log: &ContactLog,
+        published: &TaintList,
+    ) -> Result<ExposureStatus, Error> {
+        Ok(check_status(Some(state.hashed_id()), published)?)
+    }
+}
+
+/// The DP-3T style scheme of ephemeral IDs derived from day keys.
+///
+/// This requires the `dp3t` feature.
+#[cfg(feature = "dp3t")]
+#[derive(Clone, Debug, Default)]
+pub struct Dp3tScheme;
+
+#[cfg(feature = "dp3t")]
+impl Scheme for Dp3tScheme {
+    type State = DayKeyChain;
+    type Broadcast = EphemeralId;
+    type Log = EphemeralContactLog;
+    type Published = Vec<PublishedDayKey>;
+
+    fn broadcast(&self, state: &mut DayKeyChain, at: DateTime<Utc>) -> Option<EphemeralId> {
+        state.ephemeral_id(at)
+    }
+
+    fn record(&self, log: &mut EphemeralContactLog, broadcast: &EphemeralId, at: DateTime<Utc>) {
+        log.add(*broadcast, at);
+    }
+
+    fn exposure_status(
+        &self,
+        _state: &DayKeyChain,
+        log: &EphemeralContactLog,
+        published: &Vec<PublishedDayKey>,
+    ) -> Result<ExposureStatus, Error> {
+        Ok(log.exposure_status(published))
+    }
+}
+
+/// A rolling proximity identifier together with its encrypted metadata.
+#[cfg(feature = "gaen")]
+#[derive(Clone, Debug, PartialEq)]
+pub struct GaenBroadcast {
+    /// The identifier.
+    pub rpi: RollingProximityId,
+    /// The encrypted metadata.
+    pub metadata: Vec<u8>,
+}
+
+/// The scheme of the Google/Apple Exposure Notification framework.
+///
+/// The state is the list of temporary exposure keys of the device.  A new
+/// key is generated whenever the current one expires and keys older than 14
+/// days are dropped.  This requires the `gaen` feature.
+#[cfg(feature = "gaen")]
+#[derive(Clone, Debug, Default)]
+pub struct GaenScheme {
+    metadata: Vec<u8>,
+}
+
+#[cfg(feature = "gaen")]
+impl GaenScheme {
+    /// Creates the scheme with the metadata to broadcast (for instance the
+    /// version and transmit power).
+    pub fn new(metadata: &[u8]) -> GaenScheme {
+        GaenScheme {
+            metadata: metadata.to_vec(),
+        }
+    }
+}
+
+#[cfg(feature = "gaen")]
+impl Scheme for GaenScheme {
+    type State = Vec<TemporaryExposureKey>;
+    type Broadcast = GaenBroadcast;
+    type Log = SightingLog;
+    type Published = KeyExport;
+
+    fn broadcast(
+        &self,
+        state: &mut Vec<TemporaryExposureKey>,
+        at: DateTime<Utc>,
+    ) -> Option<GaenBroadcast> {
+        let interval = interval_number(at);
+        if !state.iter().any(|key| key.is_valid_at(interval)) {
+            let cutoff = interval.saturating_sub(14 * DEFAULT_ROLLING_PERIOD);
+            state.retain(|key| key.rolling_start_interval_number >= cutoff);
+            state.push(TemporaryExposureKey::generate(at));
+        }
+        let key = state.iter().find(|key| key.is_valid_at(interval))?;
+        let rpi = key.rolling_proximity_id(interval);
+        let mut metadata = self.metadata.clone();
+        key.apply_metadata_keystream(&rpi, &mut metadata);
+        Some(GaenBroadcast { rpi, metadata })
+    }
+
+    fn record(&self, log: &mut SightingLog, broadcast: &GaenBroadcast, at: DateTime<Utc>) {
+        log.add(broadcast.rpi, &broadcast.metadata, at);
+    }
+
+    fn exposure_status(
+        &self,
+        _state: &Vec<TemporaryExposureKey>,
+        log: &SightingLog,
+        published: &KeyExport,
+    ) -> Result<ExposureStatus, Error> {
+        Ok(log.exposure_status(published))
+    }
+}
+
+fn severity(status: &ExposureStatus) -> u8 {
+    match status {
+        ExposureStatus::Unknown => 0,
+        ExposureStatus::Clear => 1,
+        ExposureStatus::Revoked => 2,
+        ExposureStatus::Exposed { .. } => 3,
+        ExposureStatus::Infected { .. } => 4,
+    }
+}
+
+/// Combines the status of two schemes, preferring the more severe one.
+fn combine(a: ExposureStatus, b: ExposureStatus) -> ExposureStatus {
+    match (&a, &b) {
+        (
+            ExposureStatus::Exposed { at, risk, .. },
+            ExposureStatus::Exposed {
+                at: other_at,
+                risk: other_risk,
+                ..
+            },
+        ) => {
+            if risk < other_risk || (risk == other_risk && at < other_at) {
+                b
+            } else {
+                a
+            }
+        }
+        _ if severity(&b) > severity(&a) => b,
+        _ => a,
+    }
+}
+
+/// Runs two schemes side by side.
+///
+/// Both identifiers are broadcast together and the more severe of the two
+/// statuses is reported.
+impl<A: Scheme, B: Scheme> Scheme for (A, B) {
+    type State = (A::State, B::State);
+    type Broadcast = (A::Broadcast, B::Broadcast);
+    type Log = (A::Log, B::Log);
+    type Published = (A::Published, B::Published);
+
+    fn broadcast(&self, state: &mut Self::State, at: DateTime<Utc>) -> Option<Self::Broadcast> {
+        Some((
+            self.0.broadcast(&mut state.0, at)?,
+            self.1.broadcast(&mut state.1, at)?,
+        ))
+    }
+
+    fn record(&self, log: &mut Self::Log, broadcast: &Self::Broadcast, at: DateTime<Utc>) {
+        self.0.record(&mut log.0, &broadcast.0, at);
+        self.1.record(&mut log.1, &broadcast.1, at);
+    }
+
+    fn exposure_status(
+        &self,
+        state: &Self::State,
+        log: &Self::Log,
+        published: &Self::Published,
+    ) -> Result<ExposureStatus, Error> {
+        Ok(combine(
+            self.0.exposure_status(&state.0, &log.0, &published.0)?,
+            self.1.exposure_status(&state.1, &log.1, &published.1)?,
+        ))
+    }
+}
@@ -0,0 +1,549 @@
+//! Implements an HTTP server that exposes the registry.
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{self, Read};
+use std::net::{SocketAddr, ToSocketAddrs};
+
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+
+use crate::api::{
+    decode_prefix, AuthorityKeys, ErrorResponse, InclusionRequest, SubscriptionRequest,
+    TaintListScope, UploadRequest, UploadResponse, MAX_SUBSCRIPTION_TOKENS,
+};
+use crate::authority::Authority;
+use crate::client::{Client, ClientError, HttpTransport};
+use crate::descriptor::SignedDescriptor;
+use crate::error::Error;
+use crate::federation::{AuthorityId, ForwardedContacts};
+use crate::registry::{Registry, SubmitError, UploadId};
+use crate::status::TaintList;
+use crate::store::{MemoryStore, RegistryStore};
+use crate::upload::{UploadError, MAX_UPLOAD_SIZE};
+
+/// The number of hashed identities on a page of a taint list delta.
+pub const DEFAULT_PAGE_SIZE: usize = 1000;
+
+/// The number of minutes after which the changes queued for the
+/// transparency log are flushed.
+pub const DEFAULT_LOG_FLUSH_MINUTES: i64 = 60;
+
+/// The number of minutes between two attempts to forward contacts to the
+/// servers of peers.
+pub const DEFAULT_FORWARD_MINUTES: i64 = 10;
+
+/// The number of paged deltas the server keeps for later pages.
+const MAX_DELTA_SNAPSHOTS: usize = 16;
+
+/// The largest request body the server accepts.
+///
+/// Sealed bundles are base64 encoded which grows them by a third.
+const MAX_BODY_SIZE: usize = MAX_UPLOAD_SIZE * 2;
+
+/// A response of the server.
+struct Reply {
+    status: u16,
+    body: Vec<u8>,
+}
+
+impl Reply {
+    fn json<T: Serialize>(value: &T) -> Reply {
+        match serde_json::to_vec(value) {
+            Ok(body) => Reply { status: 200, body },
+            Err(err) => Reply::error(500, err),
+        }
+    }
+
+    fn empty() -> Reply {
+        Reply {
+            status: 200,
+            body: b"{}".to_vec(),
+        }
+    }
+
+    fn error<E: ToString>(status: u16, err: E) -> Reply {
+        Reply {
+            status,
+            body: serde_json::to_vec(&ErrorResponse {
+                error: err.to_string(),
+            })
+            .unwrap_or_default(),
+        }
+    }
+}
+
+fn upload_reply(rv: Result<UploadId, SubmitError>) -> Reply {
+    match rv {
+        Ok(upload_id) => Reply::json(&UploadResponse { upload_id }),
+        Err(err) => {
+            let status = match err {
+                SubmitError::Authorization(_)
+                | SubmitError::NotExposed
+                | SubmitError::UntrustedPeer
+                | SubmitError::ReplayedForward { .. }
+                | SubmitError::ExpiredForward { .. } => 403,
+                SubmitError::Upload(UploadError::TooLarge { .. }) => 413,
+                SubmitError::Upload(_)
+                | SubmitError::UndecodableContacts
+                | SubmitError::Misdirected => 400,
+                SubmitError::Store(_) => 500,
+            };
+            Reply::error(status, err)
+        }
+    }
+}
+
+/// An HTTP server for an authority and its registry.
+///
+/// The server handles one request at a time.  The routes are documented in
+/// the [crate documentation](index.html#server).  This requires the `server`
+/// feature.
+///
+/// Status queries are answered from a copy of the taint list that is only
+/// rebuilt after the registry was changed through the server, so every query
+/// does the same work regardless of its prefix and no query touches the
+/// store.  The server must therefore be the only writer of its store.
+///
+/// The changes queued for the transparency log are
+/// [flushed](struct.Registry.html#method.flush_log) on the first request
+/// after the [flush interval](#method.set_log_flush_interval) has passed.
+pub struct Server<S = MemoryStore> {
+    authority: Authority,
+    registry: Registry<S>,
+    page_size: usize,
+    descriptor: Option<SignedDescriptor>,
+    snapshots: VecDeque<(DateTime<Utc>, TaintList)>,
+    status_list: Option<TaintList>,
+    log_flush_interval: Duration,
+    log_flushed: DateTime<Utc>,
+    peer_urls: BTreeMap<AuthorityId, HttpTransport>,
+    forward_interval: Duration,
+    forwarded: DateTime<Utc>,
+}
+
+impl<S: RegistryStore> Server<S> {
+    /// Creates a server for an authority and its registry.
+    pub fn new(authority: Authority, registry: Registry<S>) -> Server<S> {
+        Server {
+            authority,
+            registry,
+            page_size: DEFAULT_PAGE_SIZE,
+            descriptor: None,
+            snapshots: VecDeque::new(),
+            status_list: None,
+            log_flush_interval: Duration::minutes(DEFAULT_LOG_FLUSH_MINUTES),
+            log_flushed: Utc::now(),
+            peer_urls: BTreeMap::new(),
+            forward_interval: Duration::minutes(DEFAULT_FORWARD_MINUTES),
+            forwarded: Utc::now(),
+        }
+    }
+
+    /// Returns the authority.
+    pub fn authority(&self) -> &Authority {
+        &self.authority
+    }
+
+    /// Returns the registry.
+    pub fn registry(&self) -> &Registry<S> {
+        &self.registry
+    }
+
+    /// Returns the registry for modifications.
+    pub fn registry_mut(&mut self) -> &mut Registry<S> {
+        self.status_list = None;
+        &mut self.registry
+    }
+
+    /// Changes the number of hashed identities on a page of a delta.
+    pub fn set_page_size(&mut self, page_size: usize) {
+        self.page_size = page_size.max(1);
+    }
+
+    /// Changes how often the changes queued for the transparency log are
+    /// flushed.
+    ///
+    /// Longer intervals mix the entries of more uploads but delay when
+    /// changes show up in the log.  Defaults to
+    /// [`DEFAULT_LOG_FLUSH_MINUTES`](constant.DEFAULT_LOG_FLUSH_MINUTES.html).
+    pub fn set_log_flush_interval(&mut self, interval: Duration) {
+        self.log_flush_interval = interval;
+    }
+
+    /// Sets the URL of the server of another authority.
+    ///
+    /// Contacts sealed to the authority are forwarded to its `/forwards`
+    /// route every [forward interval](#method.set_forward_interval).
+    /// Contacts for authorities without a URL stay queued in the store
+    /// until they are taken with
+    /// [`Registry::take_forwards`](struct.Registry.html#method.take_forwards).
+    pub fn set_peer_url(
+        &mut self,
+        authority_id: AuthorityId,
+        url: &str,
+    ) -> Result<(), ClientError> {
+        self.peer_urls
+            .insert(authority_id, HttpTransport::new(url)?);
+        Ok(())
+    }
+
+    /// Changes how often contacts are forwarded to the servers of peers.
+    ///
+    /// Defaults to
+    /// [`DEFAULT_FORWARD_MINUTES`](constant.DEFAULT_FORWARD_MINUTES.html).
+    pub fn set_forward_interval(&mut self, interval: Duration) {
+        self.forward_interval = interval;
+    }
+
+    /// Sets the descriptor served at `/descriptor`.
+    pub fn set_descriptor(&mut self, descriptor: SignedDescriptor) {
+        self.descriptor = Some(descriptor);
+    }
+
+    /// Binds the server to an address.
+    pub fn bind<A: ToSocketAddrs>(self, addr: A) -> Result<BoundServer<S>, Error> {
+        let http =
+            tiny_http::Server::http(addr).map_err(|err| io::Error::other(err.to_string()))?;
+        Ok(BoundServer { server: self, http })
+    }
+
+    fn handle(&mut self, method: &tiny_http::Method, url: &str, body: &[u8]) -> Reply {
+        use tiny_http::Method::{Delete, Get, Post};
+
+        self.flush_log();
+        let (path, query) = match url.find('?') {
+            Some(idx) => (&url[..idx], &url[idx + 1..]),
+            None => (url, ""),
+        };
+        match (method, path) {
+            (Get, "/keys") => Reply::json(&AuthorityKeys {
+                public_key: *self.authority.public_key(),
+                signing_public_key: *self.authority.signing_public_key(),
+            }),
+            (Get, "/descriptor") => match self.descriptor {
+                Some(ref descriptor) => Reply::json(descriptor),
+                None => Reply::error(404, "no descriptor configured"),
+            },
+            (Post, "/uploads") => {
+                self.status_list = None;
+                self.upload(body)
+            }
+            (Get, "/taint-list") => match self.registry.taint_list() {
+                Ok(list) => {
+                    Reply::json(&self.authority.sign_taint_list(&list, &TaintListScope::Full))
+                }
+                Err(err) => Reply::error(500, err),
+            },
+            (Get, "/taint-list/delta") => self.delta(query),
+            (Get, _) if path.starts_with("/status/") => {
+                self.status(path["/status/".len()..].to_ascii_lowercase())
+            }
+            (Post, "/subscriptions") => self.subscribe(body),
+            (Post, "/forwards") => {
+                self.status_list = None;
+                self.import_forwarded(body)
+            }
+            (Get, "/transparency/head") => match self.registry.transparency_log() {
+                Ok(log) => Reply::json(&self.authority.sign_tree_head(&log.head())),
+                Err(err) => Reply::error(500, err),
+            },
+            (Post, "/transparency/inclusion") => self.prove_inclusion(body),
+            (Get, "/transparency/consistency") => self.prove_consistency(query),
+            (Delete, _) if path.starts_with("/subscriptions/") => {
+                match path["/subscriptions/".len()..].parse() {
+                    Ok(token) => match self.registry.unsubscribe(&token) {
+                        Ok(_) => Reply::empty(),
+                        Err(err) => Reply::error(500, err),
+                    },
+                    Err(err) => Reply::error(400, err),
+                }
+            }
+            (_, "/keys")
+            | (_, "/descriptor")
+            | (_, "/uploads")
+            | (_, "/taint-list")
+            | (_, "/taint-list/delta")
+            | (_, "/subscriptions")
+            | (_, "/forwards")
+            | (_, "/transparency/head")
+            | (_, "/transparency/inclusion")
+            | (_, "/transparency/consistency") => Reply::error(405, "method not allowed"),
+            _ => Reply::error(404, "not found"),
+        }
+    }
+
+    fn flush_log(&mut self) {
+        let now = Utc::now();
+        if now - self.log_flushed < self.log_flush_interval {
+            return;
+        }
+        // entries that cannot be appended stay queued for the next flush
+        if self.registry.flush_log().is_ok() {
+            self.log_flushed = now;
+        }
+    }
+
+    fn send_forwards(&mut self) {
+        let now = Utc::now();
+        if self.peer_urls.is_empty() || now - self.forwarded < self.forward_interval {
+            return;
+        }
+        self.forwarded = now;
+        let peer_urls = &self.peer_urls;
+        // batches that cannot be sent stay queued for the next attempt
+        let _ = self.registry.send_forwards(&self.authority, |forwarded| {
+            let transport = match forwarded
+                .peek()
+                .ok()
+                .and_then(|batch| peer_urls.get(&batch.to))
+            {
+                Some(transport) => transport.clone(),
+                None => return false,
+            };
+            Client::new(transport).forward(forwarded).is_ok()
+        });
+    }
+
+    fn upload(&mut self, body: &[u8]) -> Reply {
+        let request: UploadRequest = match serde_json::from_slice(body) {
+            Ok(request) => request,
+            Err(err) => return Reply::error(400, err),
+        };
+        let rv = match request.code {
+            Some(ref code) => self.registry.submit(&self.authority, code, &request.bundle),
+            #[cfg(feature = "blind")]
+            None if request.credential.is_some() => self.registry.submit_anonymous(
+                &self.authority,
+                request.credential.as_ref().unwrap(),
+                &request.bundle,
+            ),
+            None => self
+                .registry
+                .submit_exposed(&self.authority, &request.bundle),
+        };
+        upload_reply(rv)
+    }
+
+    fn import_forwarded(&mut self, body: &[u8]) -> Reply {
+        let forwarded: ForwardedContacts = match serde_json::from_slice(body) {
+            Ok(forwarded) => forwarded,
+            Err(err) => return Reply::error(400, err),
+        };
+        upload_reply(self.registry.import_forwarded(&self.authority, &forwarded))
+    }
+
+    fn prove_inclusion(&self, body: &[u8]) -> Reply {
+        let request: InclusionRequest = match serde_json::from_slice(body) {
+            Ok(request) => request,
+            Err(err) => return Reply::error(400, err),
+        };
+        let log = match self.registry.transparency_log() {
+            Ok(log) => log,
+            Err(err) => return Reply::error(500, err),
+        };
+        match log.prove_entries(&request.hashed_id, request.tree_size) {
+            Ok(entries) => Reply::json(&entries),
+            Err(err) => Reply::error(400, err),
+        }
+    }
+
+    fn prove_consistency(&self, query: &str) -> Reply {
+        let mut from = None;
+        let mut to = None;
+        for (key, value) in query.split('&').filter_map(|pair| {
+            let idx = pair.find('=')?;
+            Some((&pair[..idx], &pair[idx + 1..]))
+        }) {
+            let target = match key {
+                "from" => &mut from,
+                "to" => &mut to,
+                _ => continue,
+            };
+            match value.parse::<u64>() {
+                Ok(value) => *target = Some(value),
+                Err(err) => return Reply::error(400, err),
+            }
+        }
+        let (from, to) = match (from, to) {
+            (Some(from), Some(to)) => (from, to),
+            _ => return Reply::error(400, "missing from or to parameter"),
+        };
+        let log = match self.registry.transparency_log() {
+            Ok(log) => log,
+            Err(err) => return Reply::error(500, err),
+        };
+        match log.consistency_proof(from, to) {
+            Ok(proof) => Reply::json(&proof),
+            Err(err) => Reply::error(400, err),
+        }
+    }
+
+    fn subscribe(&mut self, body: &[u8]) -> Reply {
+        let request: SubscriptionRequest = match serde_json::from_slice(body) {
+            Ok(request) => request,
+            Err(err) => return Reply::error(400, err),
+        };
+        if request.tokens.len() > MAX_SUBSCRIPTION_TOKENS {
+            return Reply::error(400, "too many subscription tokens");
+        }
+        for token in request.tokens {
+            if let Err(err) = self.registry.subscribe(token) {
+                return Reply::error(500, err);
+            }
+        }
+        Reply::empty()
+    }
+
+    /// Serves the part of the taint list whose hashed identities start with
+    /// a hex encoded prefix.
+    ///
+    /// The answer comes from the cached copy of the list, which is built
+    /// from the store on the first query after a change.
+    fn status(&mut self, encoded: String) -> Reply {
+        let prefix = match decode_prefix(&encoded) {
+            Some(prefix) => prefix,
+            None => return Reply::error(400, "invalid prefix"),
+        };
+        if self.status_list.is_none() {
+            match self.registry.taint_list() {
+                Ok(list) => self.status_list = Some(list),
+                Err(err) => return Reply::error(500, err),
+            }
+        }
+        // nothing changed since the copy was made, so it is still current
+        let list = self.status_list.as_ref().unwrap().with_prefix(&prefix);
+        Reply::json(&self.authority.sign_taint_list(
+            &list.refreshed(Utc::now()),
+            &TaintListScope::Prefix(encoded),
+        ))
+    }
+
+    /// Serves a page of the changes since a point in time.
+    ///
+    /// The first page is taken from a fresh delta which is kept so that the
+    /// later pages, which name its generation time, come from the same
+    /// snapshot even if the registry changes in between.
+    fn delta(&mut self, query: &str) -> Reply {
+        let mut since = None;
+        let mut generated = None;
+        let mut offset = 0;
+        for (key, value) in query.split('&').filter_map(|pair| {
+            let idx = pair.find('=')?;
+            Some((&pair[..idx], percent_decode(&pair[idx + 1..])?))
+        }) {
+            match key {
+                "since" => match DateTime::parse_from_rfc3339(&value) {
+                    Ok(value) => since = Some(value.with_timezone(&Utc)),
+                    Err(err) => return Reply::error(400, err),
+                },
+                "generated" => match DateTime::parse_from_rfc3339(&value) {
+                    Ok(value) => generated = Some(value.with_timezone(&Utc)),
+                    Err(err) => return Reply::error(400, err),
+                },
+                "offset" => match value.parse() {
+                    Ok(value) => offset = value,
+                    Err(err) => return Reply::error(400, err),
+                },
+                _ => {}
+            }
+        }
+        let since = match since {
+            Some(since) => since,
+            None => return Reply::error(400, "missing since parameter"),
+        };
+        let delta = match generated {
+            Some(generated) => match self
+                .snapshots
+                .iter()
+                .find(|(x, delta)| *x == since && delta.generated() == generated)
+            {
+                Some((_, delta)) => delta,
+                None => return Reply::error(410, "delta expired, start again at the first page"),
+            },
+            None if offset > 0 => return Reply::error(400, "missing generated parameter"),
+            None => {
+                let delta = match self.registry.taint_delta(since) {
+                    Ok(delta) => delta,
+                    Err(err) => return Reply::error(500, err),
+                };
+                if delta.len() <= self.page_size {
+                    return Reply::json(&self.authority.sign_taint_list_page(
+                        &delta,
+                        since,
+                        0,
+                        self.page_size,
+                    ));
+                }
+                if self.snapshots.len() >= MAX_DELTA_SNAPSHOTS {
+                    self.snapshots.pop_front();
+                }
+                self.snapshots.push_back((since, delta));
+                &self.snapshots.back().unwrap().1
+            }
+        };
+        Reply::json(
+            &self
+                .authority
+                .sign_taint_list_page(delta, since, offset, self.page_size),
+        )
+    }
+}
+
+/// A [`Server`](struct.Server.html) that is bound to an address.
+pub struct BoundServer<S = MemoryStore> {
+    server: Server<S>,
+    http: tiny_http::Server,
+}
+
+impl<S: RegistryStore> BoundServer<S> {
+    /// Returns the address the server listens on.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.http.server_addr().to_ip()
+    }
+
+    /// Serves requests until the process is terminated.
+    pub fn run(mut self) -> Result<(), Error> {
+        loop {
+            let mut request = self.http.recv()?;
+            let mut body = Vec::new();
+            request
+                .as_reader()
+                .take(MAX_BODY_SIZE as u64 + 1)
+                .read_to_end(&mut body)?;
+            let reply = if body.len() > MAX_BODY_SIZE {
+                Reply::error(413, "request body too large")
+            } else {
+                let method = request.method().clone();
+                let url = request.url().to_string();
+                self.server.handle(&method, &url, &body)
+            };
+            let header =
+                tiny_http::Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..])
+                    .unwrap();
+            let response = tiny_http::Response::from_data(reply.body)
+                .with_status_code(reply.status)
+                .with_header(header);
+            // a client that went away must not stop the server
+            let _ = request.respond(response);
+            // learn about notifications a background sink delivered since
+            // the last request, failed deliveries stay queued
+            let _ = self.server.registry.flush_notifications();
+            self.server.send_forwards();
+        }
+    }
+}
+
+/// Decodes a percent encoded query parameter.
+fn percent_decode(s: &str) -> Option<String> {
+    let mut rv = Vec::with_capacity(s.len());
+    let mut bytes = s.bytes();
+    while let Some(byte) = bytes.next() {
+        match byte {
+            b'%' => {
+                let hex = [bytes.next()?, bytes.next()?];
+                rv.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+            }
+            byte => rv.push(byte),
+        }
+    }
+    String::from_utf8(rv).ok()
+}
@@ -0,0 +1,665 @@
+//! Implements a registry store on top of SQLite.
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::auth::HashedIdentity;
+use crate::authcode::CodeId;
+#[cfg(feature = "blind")]
+use crate::blind::CredentialId;
+use crate::contactlog::ContactLog;
+use crate::federation::{AuthorityId, BatchId};
+use crate::registry::UploadId;
+use crate::store::{
+    ExposureRecord, InfectionRecord, RegistryStore, StoreError, StoreRead, StoreTransaction,
+    UploadRecord,
+};
+use crate::subscription::{Notification, SubscriptionToken};
+use crate::transparency::LogEntry;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS infections (
+        hashed_id TEXT PRIMARY KEY,
+        record TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS exposures (
+        hashed_id TEXT PRIMARY KEY,
+        record TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS revocations (
+        hashed_id TEXT PRIMARY KEY,
+        revoked_at TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS uploads (
+        upload_id TEXT PRIMARY KEY,
+        record TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS upload_origins (
+        origin TEXT NOT NULL,
+        upload_id TEXT NOT NULL,
+        PRIMARY KEY (origin, upload_id)
+    );
+    CREATE TABLE IF NOT EXISTS redemptions (
+        kind TEXT NOT NULL,
+        id TEXT NOT NULL,
+        redeemed_at TEXT NOT NULL,
+        PRIMARY KEY (kind, id)
+    );
+    CREATE TABLE IF NOT EXISTS subscriptions (
+        token TEXT PRIMARY KEY
+    );
+    CREATE TABLE IF NOT EXISTS notifications (
+        seq INTEGER PRIMARY KEY AUTOINCREMENT,
+        record TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS log_entries (
+        idx INTEGER PRIMARY KEY,
+        record TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS pending_log_entries (
+        seq INTEGER PRIMARY KEY AUTOINCREMENT,
+        record TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS pending_forwards (
+        seq INTEGER PRIMARY KEY AUTOINCREMENT,
+        authority_id TEXT NOT NULL,
+        record TEXT NOT NULL
+    );
+";
+
+impl From<rusqlite::Error> for StoreError {
+    fn from(err: rusqlite::Error) -> StoreError {
+        StoreError::Backend {
+            message: err.to_string(),
+        }
+    }
+}
+
+fn encode<T: Serialize>(value: &T) -> Result<String, StoreError> {
+    serde_json::to_string(value).map_err(|err| StoreError::Corrupted {
+        message: err.to_string(),
+    })
+}
+
+fn decode<T: DeserializeOwned>(value: &str) -> Result<T, StoreError> {
+    serde_json::from_str(value).map_err(|err| StoreError::Corrupted {
+        message: err.to_string(),
+    })
+}
+
+fn parse_key<T>(value: &str) -> Result<T, StoreError>
+where
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    value.parse().map_err(|err| StoreError::Corrupted {
+        message: format!("invalid key {:?}: {}", value, err),
+    })
+}
+
+fn parse_time(value: &str) -> Result<DateTime<Utc>, StoreError> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|x| x.with_timezone(&Utc))
+        .map_err(|err| StoreError::Corrupted {
+            message: err.to_string(),
+        })
+}
+
+fn get_record<T: DeserializeOwned>(
+    conn: &Connection,
+    sql: &str,
+    key: &str,
+) -> Result<Option<T>, StoreError> {
+    let value: Option<String> = conn
+        .query_row(sql, params![key], |row| row.get(0))
+        .optional()?;
+    value.as_deref().map(decode).transpose()
+}
+
+fn get_time(
+    conn: &Connection,
+    sql: &str,
+    key: &[&str],
+) -> Result<Option<DateTime<Utc>>, StoreError> {
+    let value: Option<String> = conn
+        .query_row(sql, rusqlite::params_from_iter(key), |row| row.get(0))
+        .optional()?;
+    value.as_deref().map(parse_time).transpose()
+}
+
+fn all_rows(conn: &Connection, sql: &str) -> Result<Vec<(String, String)>, StoreError> {
+    let mut stmt = conn.prepare(sql)?;
+    let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    Ok(rows.collect::<Result<_, _>>()?)
+}
+
+fn infection(
+    conn: &Connection,
+    hashed_id: &HashedIdentity,
+) -> Result<Option<InfectionRecord>, StoreError> {
+    get_record(
+        conn,
+        "SELECT record FROM infections WHERE hashed_id = ?1",
+        &hashed_id.to_string(),
+    )
+}
+
+fn exposure(
+    conn: &Connection,
+    hashed_id: &HashedIdentity,
+) -> Result<Option<ExposureRecord>, StoreError> {
+    get_record(
+        conn,
+        "SELECT record FROM exposures WHERE hashed_id = ?1",
+        &hashed_id.to_string(),
+    )
+}
+
+fn revoked_at(
+    conn: &Connection,
+    hashed_id: &HashedIdentity,
+) -> Result<Option<DateTime<Utc>>, StoreError> {
+    get_time(
+        conn,
+        "SELECT revoked_at FROM revocations WHERE hashed_id = ?1",
+        &[&hashed_id.to_string()],
+    )
+}
+
+fn upload(conn: &Connection, upload_id: &UploadId) -> Result<Option<UploadRecord>, StoreError> {
+    get_record(
+        conn,
+        "SELECT record FROM uploads WHERE upload_id = ?1",
+        &upload_id.to_string(),
+    )
+}
+
+fn uploads_from(conn: &Connection, origin: &UploadId) -> Result<Vec<UploadId>, StoreError> {
+    let mut stmt = conn.prepare("SELECT upload_id FROM upload_origins WHERE origin = ?1")?;
+    let rows = stmt.query_map(params![origin.to_string()], |row| row.get::<_, String>(0))?;
+    let mut rv = Vec::new();
+    for row in rows {
+        rv.push(parse_key(&row?)?);
+    }
+    Ok(rv)
+}
+
+fn redeemed_at(
+    conn: &Connection,
+    kind: &str,
+    id: &str,
+) -> Result<Option<DateTime<Utc>>, StoreError> {
+    get_time(
+        conn,
+        "SELECT redeemed_at FROM redemptions WHERE kind = ?1 AND id = ?2",
+        &[kind, id],
+    )
+}
+
+fn infections(conn: &Connection) -> Result<Vec<(HashedIdentity, InfectionRecord)>, StoreError> {
+    all_rows(conn, "SELECT hashed_id, record FROM infections")?
+        .iter()
+        .map(|(key, record)| Ok((parse_key(key)?, decode(record)?)))
+        .collect()
+}
+
+fn exposures(conn: &Connection) -> Result<Vec<(HashedIdentity, ExposureRecord)>, StoreError> {
+    all_rows(conn, "SELECT hashed_id, record FROM exposures")?
+        .iter()
+        .map(|(key, record)| Ok((parse_key(key)?, decode(record)?)))
+        .collect()
+}
+
+fn revocations(conn: &Connection) -> Result<Vec<(HashedIdentity, DateTime<Utc>)>, StoreError> {
+    all_rows(conn, "SELECT hashed_id, revoked_at FROM revocations")?
+        .iter()
+        .map(|(key, revoked_at)| Ok((parse_key(key)?, parse_time(revoked_at)?)))
+        .collect()
+}
+
+fn uploads(conn: &Connection) -> Result<Vec<(UploadId, UploadRecord)>, StoreError> {
+    all_rows(conn, "SELECT upload_id, record FROM uploads")?
+        .iter()
+        .map(|(key, record)| Ok((parse_key(key)?, decode(record)?)))
+        .collect()
+}
+
+fn is_subscribed(conn: &Connection, token: &SubscriptionToken) -> Result<bool, StoreError> {
+    let found: Option<i64> = conn
+        .query_row(
+            "SELECT 1 FROM subscriptions WHERE token = ?1",
+            params![token.to_string()],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(found.is_some())
+}
+
+fn notifications(conn: &Connection) -> Result<Vec<Notification>, StoreError> {
+    let mut stmt = conn.prepare("SELECT record FROM notifications ORDER BY seq")?;
+    let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+    let mut rv = Vec::new();
+    for row in rows {
+        rv.push(decode(&row?)?);
+    }
+    Ok(rv)
+}
+
+fn log_entries(conn: &Connection) -> Result<Vec<LogEntry>, StoreError> {
+    let mut stmt = conn.prepare("SELECT record FROM log_entries ORDER BY idx")?;
+    let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+    let mut rv = Vec::new();
+    for row in rows {
+        rv.push(decode(&row?)?);
+    }
+    Ok(rv)
+}
+
+fn pending_log_entries(conn: &Connection) -> Result<Vec<LogEntry>, StoreError> {
+    let mut stmt = conn.prepare("SELECT record FROM pending_log_entries ORDER BY seq")?;
+    let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+    let mut rv = Vec::new();
+    for row in rows {
+        rv.push(decode(&row?)?);
+    }
+    Ok(rv)
+}
+
+fn pending_forwards(conn: &Connection) -> Result<BTreeMap<AuthorityId, ContactLog>, StoreError> {
+    let mut stmt =
+        conn.prepare("SELECT authority_id, record FROM pending_forwards ORDER BY seq")?;
+    let rows = stmt.query_map([], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+    })?;
+    let mut rv = BTreeMap::<AuthorityId, ContactLog>::new();
+    for row in rows {
+        let (authority_id, record) = row?;
+        let contacts: ContactLog = decode(&record)?;
+        rv.entry(parse_key(&authority_id)?)
+            .or_default()
+            .merge(&contacts);
+    }
+    Ok(rv)
+}
+
+macro_rules! impl_store_read {
+    ($ty:ty, $conn:ident => $expr:expr) => {
+        impl StoreRead for $ty {
+            fn infection(
+                &self,
+                hashed_id: &HashedIdentity,
+            ) -> Result<Option<InfectionRecord>, StoreError> {
+                let $conn = self;
+                infection($expr, hashed_id)
+            }
+
+            fn exposure(
+                &self,
+                hashed_id: &HashedIdentity,
+            ) -> Result<Option<ExposureRecord>, StoreError> {
+                let $conn = self;
+                exposure($expr, hashed_id)
+            }
+
+            fn revoked_at(
+                &self,
+                hashed_id: &HashedIdentity,
+            ) -> Result<Option<DateTime<Utc>>, StoreError> {
+                let $conn = self;
+                revoked_at($expr, hashed_id)
+            }
+
+            fn upload(&self, upload_id: &UploadId) -> Result<Option<UploadRecord>, StoreError> {
+                let $conn = self;
+                upload($expr, upload_id)
+            }
+
+            fn uploads_from(&self, origin: &UploadId) -> Result<Vec<UploadId>, StoreError> {
+                let $conn = self;
+                uploads_from($expr, origin)
+            }
+
+            fn code_redeemed_at(
+                &self,
+                code_id: &CodeId,
+            ) -> Result<Option<DateTime<Utc>>, StoreError> {
+                let $conn = self;
+                redeemed_at($expr, "code", &code_id.to_string())
+            }
+
+            #[cfg(feature = "blind")]
+            fn credential_redeemed_at(
+                &self,
+                credential_id: &CredentialId,
+            ) -> Result<Option<DateTime<Utc>>, StoreError> {
+                let $conn = self;
+                redeemed_at($expr, "credential", &credential_id.to_string())
+            }
+
+            fn forward_imported_at(
+                &self,
+                batch_id: &BatchId,
+            ) -> Result<Option<DateTime<Utc>>, StoreError> {
+                let $conn = self;
+                redeemed_at($expr, "forward", &batch_id.to_string())
+            }
+
+            fn infections(&self) -> Result<Vec<(HashedIdentity, InfectionRecord)>, StoreError> {
+                let $conn = self;
+                infections($expr)
+            }
+
+            fn exposures(&self) -> Result<Vec<(HashedIdentity, ExposureRecord)>, StoreError> {
+                let $conn = self;
+                exposures($expr)
+            }
+
+            fn revocations(&self) -> Result<Vec<(HashedIdentity, DateTime<Utc>)>, StoreError> {
+                let $conn = self;
+                revocations($expr)
+            }
+
+            fn uploads(&self) -> Result<Vec<(UploadId, UploadRecord)>, StoreError> {
+                let $conn = self;
+                uploads($expr)
+            }
+
+            fn is_subscribed(&self, token: &SubscriptionToken) -> Result<bool, StoreError> {
+                let $conn = self;
+                is_subscribed($expr, token)
+            }
+
+            fn notifications(&self) -> Result<Vec<Notification>, StoreError> {
+                let $conn = self;
+                notifications($expr)
+            }
+
+            fn log_entries(&self) -> Result<Vec<LogEntry>, StoreError> {
+                let $conn = self;
+                log_entries($expr)
+            }
+
+            fn pending_log_entries(&self) -> Result<Vec<LogEntry>, StoreError> {
+                let $conn = self;
+                pending_log_entries($expr)
+            }
+
+            fn pending_forwards(&self) -> Result<BTreeMap<AuthorityId, ContactLog>, StoreError> {
+                let $conn = self;
+                pending_forwards($expr)
+            }
+        }
+    };
+}
+
+/// A store that keeps the records of the registry in a SQLite database.
+///
+/// Records are looked up through indexes on the hashed identity and upload
+/// ID so that the registry does not need to be loaded into memory.  Like
+/// with the [`MemoryStore`](struct.MemoryStore.html) lookups of present and
+/// absent records take different times.  This requires the `sqlite`
+/// feature.
+pub struct SqliteStore {
+    conn: Connection,
+}
+
+impl SqliteStore {
+    /// Opens or creates a database at the given path.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<SqliteStore, StoreError> {
+        SqliteStore::from_connection(Connection::open(path)?)
+    }
+
+    /// Creates a database that only lives in memory.
+    pub fn open_in_memory() -> Result<SqliteStore, StoreError> {
+        SqliteStore::from_connection(Connection::open_in_memory()?)
+    }
+
+    fn from_connection(conn: Connection) -> Result<SqliteStore, StoreError> {
+        conn.execute_batch(SCHEMA)?;
+        Ok(SqliteStore { conn })
+    }
+}
+
+impl_store_read!(SqliteStore, store => &store.conn);
+
+impl RegistryStore for SqliteStore {
+    fn transaction(&mut self) -> Result<Box<dyn StoreTransaction + '_>, StoreError> {
+        Ok(Box::new(SqliteTransaction {
+            tx: self.conn.transaction()?,
+        }))
+    }
+}
+
+/// A transaction on a [`SqliteStore`](struct.SqliteStore.html).
+struct SqliteTransaction<'a> {
+    tx: Transaction<'a>,
+}
+
+impl_store_read!(SqliteTransaction<'_>, tx => &tx.tx);
+
+impl SqliteTransaction<'_> {
+    fn put(&self, table: &str, column: &str, key: &str, value: &str) -> Result<(), StoreError> {
+        self.tx.execute(
+            &format!(
+                "INSERT OR REPLACE INTO {} (hashed_id, {}) VALUES (?1, ?2)",
+                table, column
+            ),
+            params![key, value],
+        )?;
+        Ok(())
+    }
+
+    fn remove(&self, table: &str, key: &str) -> Result<(), StoreError> {
+        self.tx.execute(
+            &format!("DELETE FROM {} WHERE hashed_id = ?1", table),
+            params![key],
+        )?;
+        Ok(())
+    }
+
+    fn put_redeemed(
+        &self,
+        kind: &str,
+        id: &str,
+        redeemed_at: DateTime<Utc>,
+    ) -> Result<(), StoreError> {
+        self.tx.execute(
+            "INSERT OR REPLACE INTO redemptions (kind, id, redeemed_at) VALUES (?1, ?2, ?3)",
+            params![kind, id, redeemed_at.to_rfc3339()],
+        )?;
+        Ok(())
+    }
+}
+
+impl StoreTransaction for SqliteTransaction<'_> {
+    fn put_infection(
+        &mut self,
+        hashed_id: &HashedIdentity,
+        record: &InfectionRecord,
+    ) -> Result<(), StoreError> {
+        self.put(
+            "infections",
+            "record",
+            &hashed_id.to_string(),
+            &encode(record)?,
+        )
+    }
+
+    fn remove_infection(&mut self, hashed_id: &HashedIdentity) -> Result<(), StoreError> {
+        self.remove("infections", &hashed_id.to_string())
+    }
+
+    fn put_exposure(
+        &mut self,
+        hashed_id: &HashedIdentity,
+        record: &ExposureRecord,
+    ) -> Result<(), StoreError> {
+        self.put(
+            "exposures",
+            "record",
+            &hashed_id.to_string(),
+            &encode(record)?,
+        )
+    }
+
+    fn remove_exposure(&mut self, hashed_id: &HashedIdentity) -> Result<(), StoreError> {
+        self.remove("exposures", &hashed_id.to_string())
+    }
+
+    fn put_revoked(
+        &mut self,
+        hashed_id: &HashedIdentity,
+        revoked_at: DateTime<Utc>,
+    ) -> Result<(), StoreError> {
+        self.put(
+            "revocations",
+            "revoked_at",
+            &hashed_id.to_string(),
+            &revoked_at.to_rfc3339(),
+        )
+    }
+
+    fn remove_revoked(&mut self, hashed_id: &HashedIdentity) -> Result<(), StoreError> {
+        self.remove("revocations", &hashed_id.to_string())
+    }
+
+    fn put_upload(
+        &mut self,
+        upload_id: &UploadId,
+        record: &UploadRecord,
+    ) -> Result<(), StoreError> {
+        let key = upload_id.to_string();
+        self.tx.execute(
+            "INSERT OR REPLACE INTO uploads (upload_id, record) VALUES (?1, ?2)",
+            params![key, encode(record)?],
+        )?;
+        self.tx.execute(
+            "DELETE FROM upload_origins WHERE upload_id = ?1",
+            params![key],
+        )?;
+        for origin in &record.origins {
+            self.tx.execute(
+                "INSERT INTO upload_origins (origin, upload_id) VALUES (?1, ?2)",
+                params![origin.to_string(), key],
+            )?;
+        }
+        Ok(())
+    }
+
+    fn put_code_redeemed(
+        &mut self,
+        code_id: &CodeId,
+        redeemed_at: DateTime<Utc>,
+    ) -> Result<(), StoreError> {
+        self.put_redeemed("code", &code_id.to_string(), redeemed_at)
+    }
+
+    #[cfg(feature = "blind")]
+    fn put_credential_redeemed(
+        &mut self,
+        credential_id: &CredentialId,
+        redeemed_at: DateTime<Utc>,
+    ) -> Result<(), StoreError> {
+        self.put_redeemed("credential", &credential_id.to_string(), redeemed_at)
+    }
+
+    fn put_forward_imported(
+        &mut self,
+        batch_id: &BatchId,
+        imported_at: DateTime<Utc>,
+    ) -> Result<(), StoreError> {
+        self.put_redeemed("forward", &batch_id.to_string(), imported_at)
+    }
+
+    fn put_subscription(&mut self, token: &SubscriptionToken) -> Result<(), StoreError> {
+        self.tx.execute(
+            "INSERT OR IGNORE INTO subscriptions (token) VALUES (?1)",
+            params![token.to_string()],
+        )?;
+        Ok(())
+    }
+
+    fn remove_subscription(&mut self, token: &SubscriptionToken) -> Result<(), StoreError> {
+        self.tx.execute(
+            "DELETE FROM subscriptions WHERE token = ?1",
+            params![token.to_string()],
+        )?;
+        Ok(())
+    }
+
+    fn push_notification(&mut self, notification: &Notification) -> Result<(), StoreError> {
+        self.tx.execute(
+            "INSERT INTO notifications (record) VALUES (?1)",
+            params![encode(notification)?],
+        )?;
+        Ok(())
+    }
+
+    fn pop_notification(&mut self) -> Result<Option<Notification>, StoreError> {
+        let row: Option<(i64, String)> = self
+            .tx
+            .query_row(
+                "SELECT seq, record FROM notifications ORDER BY seq LIMIT 1",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+        let (seq, record) = match row {
+            Some(row) => row,
+            None => return Ok(None),
+        };
+        self.tx
+            .execute("DELETE FROM notifications WHERE seq = ?1", params![seq])?;
+        decode(&record).map(Some)
+    }
+
+    fn append_log_entry(&mut self, entry: &LogEntry) -> Result<(), StoreError> {
+        // entries are never removed, so the next index is the number of
+        // entries in the log
+        self.tx.execute(
+            "INSERT INTO log_entries (idx, record) SELECT COUNT(*), ?1 FROM log_entries",
+            params![encode(entry)?],
+        )?;
+        Ok(())
+    }
+
+    fn push_pending_log_entry(&mut self, entry: &LogEntry) -> Result<(), StoreError> {
+        self.tx.execute(
+            "INSERT INTO pending_log_entries (record) VALUES (?1)",
+            params![encode(entry)?],
+        )?;
+        Ok(())
+    }
+
+    fn take_pending_log_entries(&mut self) -> Result<Vec<LogEntry>, StoreError> {
+        let entries = pending_log_entries(&self.tx)?;
+        self.tx.execute("DELETE FROM pending_log_entries", [])?;
+        Ok(entries)
+    }
+
+    fn push_pending_forward(
+        &mut self,
+        to: &AuthorityId,
+        contacts: &ContactLog,
+    ) -> Result<(), StoreError> {
+        self.tx.execute(
+            "INSERT INTO pending_forwards (authority_id, record) VALUES (?1, ?2)",
+            params![to.to_string(), encode(contacts)?],
+        )?;
+        Ok(())
+    }
+
+    fn take_pending_forwards(&mut self) -> Result<BTreeMap<AuthorityId, ContactLog>, StoreError> {
+        let forwards = pending_forwards(&self.tx)?;
+        self.tx.execute("DELETE FROM pending_forwards", [])?;
+        Ok(forwards)
+    }
+
+    fn commit(self: Box<Self>) -> Result<(), StoreError> {
+        self.tx.commit()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,275 @@
+//! Implements aggregated statistics over the records of the registry.
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::f64::consts::PI;
+use std::fmt::Write;
+
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use derive_more::{Display, Error, From};
+use serde::{Deserialize, Serialize};
+
+use crate::crypto::random_bytes;
+use crate::store::{RegistryStore, StoreError};
+
+/// Error for statistics that cannot be computed.
+#[derive(Debug, Error, Display, Clone, PartialEq, From)]
+pub enum StatisticsError {
+    /// The privacy parameter epsilon is out of range.
+    #[display(fmt = "invalid epsilon {}", _0)]
+    #[from(ignore)]
+    InvalidEpsilon(#[error(not(source))] f64),
+    /// The privacy parameter delta is out of range.
+    #[display(fmt = "invalid delta {}", _0)]
+    #[from(ignore)]
+    InvalidDelta(#[error(not(source))] f64),
+    /// The last day of the range lies before the first day.
+    #[display(fmt = "invalid day range")]
+    #[from(ignore)]
+    InvalidRange,
+    /// The store of the registry failed.
+    #[display(fmt = "{}", _0)]
+    Store(StoreError),
+}
+
+/// The noise added to every count.
+///
+/// The unit of privacy is a single upload, which stands for one infected or
+/// exposed person.  Every upload counts at most `max_per_upload` infected and
+/// at most `max_per_upload` exposed hashed identities, so adding or removing
+/// it changes all counts of the statistics together by at most their
+/// [`sensitivity`](#method.sensitivity).  The noise is scaled by it, so the
+/// statistics as a whole are `epsilon`-differentially private.  Every
+/// release spends the budget again: releasing statistics `n` times costs
+/// `n * epsilon` in total.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case", tag = "mechanism")]
+pub enum NoiseMechanism {
+    /// Exact counts.
+    None,
+    /// Laplace noise giving `epsilon`-differential privacy.
+    Laplace {
+        /// The privacy parameter, larger values add less noise.
+        epsilon: f64,
+    },
+    /// Gaussian noise giving `(epsilon, delta)`-differential privacy.
+    ///
+    /// This uses the classic calibration which requires `epsilon < 1`.
+    Gaussian {
+        /// The privacy parameter, larger values add less noise.
+        epsilon: f64,
+        /// The probability the privacy guarantee fails.
+        delta: f64,
+    },
+}
+
+impl NoiseMechanism {
+    /// Checks that the parameters are in range.
+    pub fn validate(&self) -> Result<(), StatisticsError> {
+        match *self {
+            NoiseMechanism::None => Ok(()),
+            NoiseMechanism::Laplace { epsilon } => {
+                if epsilon.is_finite() && epsilon > 0.0 {
+                    Ok(())
+                } else {
+                    Err(StatisticsError::InvalidEpsilon(epsilon))
+                }
+            }
+            NoiseMechanism::Gaussian { epsilon, delta } => {
+                if !(epsilon > 0.0 && epsilon < 1.0) {
+                    Err(StatisticsError::InvalidEpsilon(epsilon))
+                } else if !(delta > 0.0 && delta < 1.0) {
+                    Err(StatisticsError::InvalidDelta(delta))
+                } else {
+                    Ok(())
+                }
+            }
+        }
+    }
+
+    /// Returns how much a single upload changes the counts of the
+    /// statistics at most.
+    ///
+    /// An upload that was not revoked adds up to `max_per_upload` to the
+    /// total and the daily infections and exposures and one to the total and
+    /// the daily uploads.  A revoked upload instead adds up to twice
+    /// `max_per_upload` to the revocations and one to the total, daily and
+    /// revoked uploads.  This returns the L1 norm of that change for Laplace
+    /// noise and the L2 norm for Gaussian noise.
+    pub fn sensitivity(&self, max_per_upload: u64) -> f64 {
+        let k = max_per_upload as f64;
+        match *self {
+            NoiseMechanism::None => 0.0,
+            NoiseMechanism::Laplace { .. } => (4.0 * k + 2.0).max(2.0 * k + 3.0),
+            NoiseMechanism::Gaussian { .. } => (4.0 * k * k + 3.0).sqrt(),
+        }
+    }
+
+    /// Returns a noisy version of a count.
+    ///
+    /// The noise is scaled by the `sensitivity` of the released counts.  The
+    /// result is rounded and cut off at zero, which does not weaken the
+    /// privacy guarantee.
+    pub fn apply(&self, count: u64, sensitivity: f64) -> u64 {
+        let noise = match *self {
+            NoiseMechanism::None => return count,
+            NoiseMechanism::Laplace { epsilon } => {
+                let u = random_unit() - 0.5;
+                -u.signum() * (1.0 - 2.0 * u.abs()).ln() * sensitivity / epsilon
+            }
+            NoiseMechanism::Gaussian { epsilon, delta } => {
+                let sigma = (2.0 * (1.25 / delta).ln()).sqrt() * sensitivity / epsilon;
+                let (u1, u2) = (random_unit(), random_unit());
+                sigma * (-2.0 * u1.ln()).sqrt() * (2.0 * PI * u2).cos()
+            }
+        };
+        (count as f64 + noise).round().max(0.0) as u64
+    }
+}
+
+/// Returns a uniformly distributed number in the open interval (0, 1).
+fn random_unit() -> f64 {
+    let mut buf = [0u8; 8];
+    random_bytes(&mut buf);
+    ((u64::from_le_bytes(buf) >> 11) as f64 + 0.5) / (1u64 << 53) as f64
+}
+
+/// The counts of a single day.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct DailyStatistics {
+    /// The day (in UTC).
+    pub day: NaiveDate,
+    /// The number of hashed identities reported as infected by uploads
+    /// received on the day that were not revoked.
+    pub infections: u64,
+    /// The number of hashed identities exposed on the day by uploads that
+    /// were not revoked.
+    pub exposures: u64,
+    /// The number of uploads received.
+    pub uploads: u64,
+}
+
+/// Aggregated counts over the records of a registry.
+///
+/// The statistics are created with
+/// [`Registry::statistics`](struct.Registry.html#method.statistics) and
+/// contain no per-person data.  Hashed identities are counted per upload,
+/// so an identity marked by two uploads counts twice, and every upload
+/// counts at most `max_per_upload` infected and exposed hashed identities.
+/// They are exported as JSON by serializing them or as CSV with
+/// [`to_csv`](#method.to_csv).
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Statistics {
+    /// When the statistics were created.
+    pub generated: DateTime<Utc>,
+    /// The noise added to the counts.
+    pub noise: NoiseMechanism,
+    /// The number of infected and exposed hashed identities counted per
+    /// upload.
+    pub max_per_upload: u64,
+    /// The number of hashed identities marked as infected by uploads that
+    /// were not revoked.
+    pub infections: u64,
+    /// The number of hashed identities marked as exposed by uploads that
+    /// were not revoked.
+    pub exposures: u64,
+    /// The number of hashed identities marked by uploads that were revoked.
+    pub revocations: u64,
+    /// The number of uploads received.
+    pub uploads: u64,
+    /// The number of uploads that were revoked.
+    pub revoked_uploads: u64,
+    /// The counts of every day of the requested range.
+    pub days: Vec<DailyStatistics>,
+}
+
+impl Statistics {
+    pub(crate) fn collect<S: RegistryStore>(
+        store: &S,
+        first_day: NaiveDate,
+        last_day: NaiveDate,
+        noise: NoiseMechanism,
+        max_per_upload: u64,
+    ) -> Result<Statistics, StatisticsError> {
+        noise.validate()?;
+        if last_day < first_day {
+            return Err(StatisticsError::InvalidRange);
+        }
+
+        // every day of the range is reported, even without records, so
+        // that the days present do not give anything away
+        let mut days = BTreeMap::new();
+        let mut day = first_day;
+        while day <= last_day {
+            days.insert(day, [0u64; 3]);
+            day += Duration::days(1);
+        }
+        let mut count = |at: DateTime<Utc>, idx: usize, n: u64| {
+            if let Some(counts) = days.get_mut(&at.date_naive()) {
+                counts[idx] += n;
+            }
+        };
+
+        // every upload only counts up to the bound, which limits how much a
+        // single upload can change the statistics
+        let limit = usize::try_from(max_per_upload).unwrap_or(usize::MAX);
+        let uploads = store.uploads()?;
+        let (mut infections, mut exposures, mut revocations) = (0, 0, 0);
+        let mut revoked_uploads = 0;
+        for (upload_id, record) in &uploads {
+            count(record.received, 2, 1);
+            let infected = &record.infected[..record.infected.len().min(limit)];
+            let exposed = &record.exposed[..record.exposed.len().min(limit)];
+            if record.revoked.is_some() {
+                revoked_uploads += 1;
+                revocations += (infected.len() + exposed.len()) as u64;
+                continue;
+            }
+            infections += infected.len() as u64;
+            count(record.received, 0, infected.len() as u64);
+            exposures += exposed.len() as u64;
+            for hashed_id in exposed {
+                if let Some(exposure) = store
+                    .exposure(hashed_id)?
+                    .and_then(|x| x.sources.get(upload_id).copied())
+                {
+                    count(exposure.at(), 1, 1);
+                }
+            }
+        }
+
+        let sensitivity = noise.sensitivity(max_per_upload);
+        Ok(Statistics {
+            generated: Utc::now(),
+            noise,
+            max_per_upload,
+            infections: noise.apply(infections, sensitivity),
+            exposures: noise.apply(exposures, sensitivity),
+            revocations: noise.apply(revocations, sensitivity),
+            uploads: noise.apply(uploads.len() as u64, sensitivity),
+            revoked_uploads: noise.apply(revoked_uploads, sensitivity),
+            days: days
+                .into_iter()
+                .map(|(day, [infections, exposures, uploads])| DailyStatistics {
+                    day,
+                    infections: noise.apply(infections, sensitivity),
+                    exposures: noise.apply(exposures, sensitivity),
+                    uploads: noise.apply(uploads, sensitivity),
+                })
+                .collect(),
+        })
+    }
+
+    /// Writes the daily counts as CSV with a header row.
+    pub fn to_csv(&self) -> String {
+        let mut rv = String::from("day,infections,exposures,uploads\n");
+        for day in &self.days {
+            writeln!(
+                rv,
+                "{},{},{},{}",
+                day.day, day.infections, day.exposures, day.uploads
+            )
+            .unwrap();
+        }
+        rv
+    }
+}
@@ -0,0 +1,343 @@
+//! Implements exposure status checks.
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+
+use chrono::{DateTime, Utc};
+use serde::{ser, Deserialize, Serialize};
+
+use crate::auth::HashedIdentity;
+use crate::crypto::ct_eq;
+use crate::envelope::{self, ArtifactKind, EnvelopeError};
+use crate::registry::Exposure;
+use crate::store::StoreError;
+
+/// The exposure status of a device.
+///
+/// This is the result of [`check_status`](fn.check_status.html) and what
+/// apps should base the guidance shown to the user on.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum ExposureStatus {
+    /// No infection or exposure is known.
+    Clear,
+    /// The user was in contact with an infected user.
+    Exposed {
+        /// The time of the most recent contact.
+        at: DateTime<Utc>,
+        /// The estimated risk between `0.0` and `1.0`.
+        risk: f64,
+        /// The degree of the exposure, `1` for direct contact with an
+        /// infected user.
+        #[serde(default = "direct_degree")]
+        degree: u8,
+    },
+    /// The user was reported as infected.
+    Infected {
+        /// When the infection was reported.
+        reported_at: DateTime<Utc>,
+    },
+    /// A previous infection or exposure was revoked.
+    Revoked,
+    /// The status cannot be determined (for instance because no identities
+    /// were checked).
+    Unknown,
+}
+
+/// A source of infection and exposure records.
+///
+/// This is implemented by the [`Registry`](struct.Registry.html) of the
+/// authority and by a downloaded [`TaintList`](struct.TaintList.html).
+pub trait StatusSource {
+    /// Returns when a hashed identity was reported as infected.
+    fn reported_at(&self, hashed_id: &HashedIdentity) -> Result<Option<DateTime<Utc>>, StoreError>;
+
+    /// Returns the exposure of a hashed identity.
+    fn exposure(&self, hashed_id: &HashedIdentity) -> Result<Option<Exposure>, StoreError>;
+
+    /// Checks if an infection or exposure of a hashed identity was revoked.
+    fn is_revoked(&self, _hashed_id: &HashedIdentity) -> Result<bool, StoreError> {
+        Ok(false)
+    }
+}
+
+fn direct_degree() -> u8 {
+    1
+}
+
+/// Checks the exposure status of a set of hashed identities.
+///
+/// A device should pass the hashed identities of all unique IDs it used
+/// within the infection window.  An infection takes precedence over an
+/// exposure, and the exposure with the highest risk is reported.  This only
+/// fails if the store behind the source fails.
+///
+/// Every hashed identity is looked up in full, so the time the check takes
+/// does not reveal which of them is marked.
+pub fn check_status<'a, I, S>(hashed_ids: I, source: &S) -> Result<ExposureStatus, StoreError>
+where
+    I: IntoIterator<Item = &'a HashedIdentity>,
+    S: StatusSource + ?Sized,
+{
+    let mut checked = false;
+    let mut revoked = false;
+    let mut infected: Option<DateTime<Utc>> = None;
+    let mut exposure: Option<Exposure> = None;
+    for hashed_id in hashed_ids {
+        checked = true;
+        let reported_at = source.reported_at(hashed_id)?;
+        let new_exposure = source.exposure(hashed_id)?;
+        let is_revoked = source.is_revoked(hashed_id)?;
+        if infected.is_none() {
+            infected = reported_at;
+        }
+        if let Some(new) = new_exposure {
+            if exposure.is_none_or(|old| {
+                old.risk() < new.risk() || (old.risk() == new.risk() && old.at() < new.at())
+            }) {
+                exposure = Some(new);
+            }
+        } else if is_revoked {
+            revoked = true;
+        }
+    }
+    if let Some(reported_at) = infected {
+        return Ok(ExposureStatus::Infected { reported_at });
+    }
+    Ok(match exposure {
+        Some(exposure) => ExposureStatus::Exposed {
+            at: exposure.at(),
+            risk: exposure.risk(),
+            degree: exposure.degree(),
+        },
+        None if revoked => ExposureStatus::Revoked,
+        None if checked => ExposureStatus::Clear,
+        None => ExposureStatus::Unknown,
+    })
+}
+
+/// A downloadable list of infected and exposed hashed identities.
+///
+/// Devices can check their status against this list locally without
+/// revealing their hashed identities to the authority.  Since version 2 the
+/// list also carries the hashed identities whose marks were revoked.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(try_from = "RawTaintList")]
+pub struct TaintList {
+    generated: DateTime<Utc>,
+    infected: HashMap<HashedIdentity, DateTime<Utc>>,
+    exposures: HashMap<HashedIdentity, Exposure>,
+    revoked: HashMap<HashedIdentity, DateTime<Utc>>,
+}
+
+#[derive(Serialize)]
+struct TaintListRef<'a> {
+    magic: &'static str,
+    version: u8,
+    #[serde(rename = "type")]
+    kind: ArtifactKind,
+    generated: DateTime<Utc>,
+    infected: &'a HashMap<HashedIdentity, DateTime<Utc>>,
+    exposures: &'a HashMap<HashedIdentity, Exposure>,
+    revoked: &'a HashMap<HashedIdentity, DateTime<Utc>>,
+}
+
+#[derive(Deserialize)]
+struct RawTaintList {
+    #[serde(default)]
+    magic: Option<String>,
+    #[serde(default)]
+    version: Option<u8>,
+    #[serde(default, rename = "type")]
+    kind: Option<ArtifactKind>,
+    #[serde(default)]
+    generated: Option<DateTime<Utc>>,
+    #[serde(default)]
+    infected: Option<HashMap<HashedIdentity, DateTime<Utc>>>,
+    #[serde(default)]
+    exposures: Option<HashMap<HashedIdentity, Exposure>>,
+    #[serde(default)]
+    revoked: Option<HashMap<HashedIdentity, DateTime<Utc>>>,
+}
+
+impl TryFrom<RawTaintList> for TaintList {
+    type Error = EnvelopeError;
+
+    fn try_from(raw: RawTaintList) -> Result<TaintList, EnvelopeError> {
+        let kind = ArtifactKind::TaintList;
+        let version = envelope::check_header(kind, raw.magic.as_deref(), raw.version, raw.kind)?;
+        match (
+            version,
+            raw.generated,
+            raw.infected,
+            raw.exposures,
+            raw.revoked,
+        ) {
+            // taint lists never existed without an envelope
+            (0, ..) => Err(EnvelopeError::BadMagic { kind }),
+            // version 1 did not carry revocations
+            (1, Some(generated), Some(infected), Some(exposures), None) => Ok(TaintList {
+                generated,
+                infected,
+                exposures,
+                revoked: HashMap::new(),
+            }),
+            (2, Some(generated), Some(infected), Some(exposures), Some(revoked)) => Ok(TaintList {
+                generated,
+                infected,
+                exposures,
+                revoked,
+            }),
+            _ => Err(EnvelopeError::InvalidPayload { kind, version }),
+        }
+    }
+}
+
+impl Serialize for TaintList {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: ser::Serializer,
+    {
+        let kind = ArtifactKind::TaintList;
+        TaintListRef {
+            magic: envelope::MAGIC_STR,
+            version: kind.current_version(),
+            kind,
+            generated: self.generated,
+            infected: &self.infected,
+            exposures: &self.exposures,
+            revoked: &self.revoked,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl TaintList {
+    pub(crate) fn new(
+        infected: HashMap<HashedIdentity, DateTime<Utc>>,
+        exposures: HashMap<HashedIdentity, Exposure>,
+        revoked: HashMap<HashedIdentity, DateTime<Utc>>,
+    ) -> TaintList {
+        TaintList {
+            generated: Utc::now(),
+            infected,
+            exposures,
+            revoked,
+        }
+    }
+
+    /// Returns when the list was generated.
+    pub fn generated(&self) -> DateTime<Utc> {
+        self.generated
+    }
+
+    /// Returns the number of hashed identities in the list.
+    pub fn len(&self) -> usize {
+        self.hashed_ids().len()
+    }
+
+    /// Returns `true` if the list contains no hashed identities.
+    pub fn is_empty(&self) -> bool {
+        self.infected.is_empty() && self.exposures.is_empty() && self.revoked.is_empty()
+    }
+
+    /// Merges a delta created by [`Registry::taint_delta`](struct.Registry.html#method.taint_delta).
+    ///
+    /// Every hashed identity in the delta replaces what this list knew about
+    /// it.  The list takes over the generation time of the delta.
+    pub fn apply(&mut self, delta: &TaintList) {
+        for hashed_id in delta.hashed_ids() {
+            self.infected.remove(&hashed_id);
+            self.exposures.remove(&hashed_id);
+            self.revoked.remove(&hashed_id);
+        }
+        self.infected
+            .extend(delta.infected.iter().map(|(k, v)| (*k, *v)));
+        self.exposures
+            .extend(delta.exposures.iter().map(|(k, v)| (*k, *v)));
+        self.revoked
+            .extend(delta.revoked.iter().map(|(k, v)| (*k, *v)));
+        self.generated = delta.generated;
+    }
+
+    /// Returns the part of the list whose hashed identities start with the
+    /// given bytes.
+    ///
+    /// This lets a device query its status by only revealing a short prefix
+    /// of its hashed identities.  Every hashed identity of the list is
+    /// compared in constant time, so the time this takes does not depend on
+    /// how close the prefix is to the stored identities.
+    pub fn with_prefix(&self, prefix: &[u8]) -> TaintList {
+        self.filter(|hashed_id| {
+            let bytes = hashed_id.as_bytes();
+            prefix.len() <= bytes.len() && ct_eq(&bytes[..prefix.len()], prefix)
+        })
+    }
+
+    /// Marks the list as current at a later point in time.
+    #[cfg(feature = "server")]
+    pub(crate) fn refreshed(mut self, generated: DateTime<Utc>) -> TaintList {
+        self.generated = generated;
+        self
+    }
+
+    /// Returns a page of the list.
+    ///
+    /// Hashed identities are ordered by their bytes, so the pages of a list
+    /// never overlap.
+    pub fn page(&self, offset: usize, limit: usize) -> TaintList {
+        let page: HashSet<_> = self
+            .hashed_ids()
+            .into_iter()
+            .skip(offset)
+            .take(limit)
+            .collect();
+        self.filter(|hashed_id| page.contains(hashed_id))
+    }
+
+    fn hashed_ids(&self) -> Vec<HashedIdentity> {
+        let mut rv: Vec<_> = self
+            .infected
+            .keys()
+            .chain(self.exposures.keys())
+            .chain(self.revoked.keys())
+            .copied()
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        rv.sort_by(|a, b| a.as_bytes().cmp(b.as_bytes()));
+        rv
+    }
+
+    fn filter<F: Fn(&HashedIdentity) -> bool>(&self, f: F) -> TaintList {
+        TaintList {
+            generated: self.generated,
+            infected: filter_map(&self.infected, &f),
+            exposures: filter_map(&self.exposures, &f),
+            revoked: filter_map(&self.revoked, &f),
+        }
+    }
+}
+
+fn filter_map<V: Copy, F: Fn(&HashedIdentity) -> bool>(
+    map: &HashMap<HashedIdentity, V>,
+    f: F,
+) -> HashMap<HashedIdentity, V> {
+    map.iter()
+        .filter(|(hashed_id, _)| f(hashed_id))
+        .map(|(hashed_id, value)| (*hashed_id, *value))
+        .collect()
+}
+
+impl StatusSource for TaintList {
+    fn reported_at(&self, hashed_id: &HashedIdentity) -> Result<Option<DateTime<Utc>>, StoreError> {
+        Ok(self.infected.get(hashed_id).copied())
+    }
+
+    fn exposure(&self, hashed_id: &HashedIdentity) -> Result<Option<Exposure>, StoreError> {
+        Ok(self.exposures.get(hashed_id).copied())
+    }
+
+    fn is_revoked(&self, hashed_id: &HashedIdentity) -> Result<bool, StoreError> {
+        Ok(self.revoked.contains_key(hashed_id))
+    }
+}
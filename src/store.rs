@@ -0,0 +1,791 @@
+//! Implements storage for the records of the registry.
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use derive_more::{Display, Error};
+use serde::{Deserialize, Serialize};
+
+use crate::auth::HashedIdentity;
+use crate::authcode::CodeId;
+#[cfg(feature = "blind")]
+use crate::blind::CredentialId;
+use crate::contactlog::ContactLog;
+use crate::crypto::SigningPublicKey;
+use crate::federation::{AuthorityId, BatchId};
+use crate::registry::{Exposure, UploadId};
+use crate::subscription::{Notification, SubscriptionToken};
+use crate::transparency::LogEntry;
+use crate::utils::write_atomic;
+
+/// Error for failing storage backends.
+#[derive(Debug, Error, Display, Clone, PartialEq, Eq)]
+pub enum StoreError {
+    /// The backend failed to read or write.
+    #[display(fmt = "storage backend failed: {}", message)]
+    Backend {
+        #[error(not(source))]
+        message: String,
+    },
+    /// A stored record cannot be decoded.
+    #[display(fmt = "corrupted record: {}", message)]
+    Corrupted {
+        #[error(not(source))]
+        message: String,
+    },
+}
+
+/// The record of an infected hashed identity.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct InfectionRecord {
+    /// When the infection was first reported.
+    pub reported_at: DateTime<Utc>,
+    /// The uploads that reported the infection.
+    pub sources: BTreeSet<UploadId>,
+}
+
+/// The record of an exposed hashed identity.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ExposureRecord {
+    /// The effective exposure merged from all sources.
+    pub exposure: Exposure,
+    /// The exposure caused by each upload.
+    pub sources: HashMap<UploadId, Exposure>,
+}
+
+impl ExposureRecord {
+    /// Recomputes the effective exposure.
+    ///
+    /// Returns `false` if no sources are left.
+    pub(crate) fn update(&mut self) -> bool {
+        let mut iter = self.sources.values();
+        match iter.next() {
+            Some(first) => {
+                let mut exposure = *first;
+                for other in iter {
+                    exposure.merge(*other);
+                }
+                self.exposure = exposure;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Where an accepted upload came from.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case", tag = "kind")]
+pub enum UploadSource {
+    /// An infected user with an authorization code.
+    Authorized {
+        /// The key of the authority or lab that issued the code.
+        issuer: SigningPublicKey,
+    },
+    /// An infected user with an anonymous credential.
+    Anonymous,
+    /// An exposed user.
+    Exposed,
+    /// Contacts forwarded by the authority of another region.
+    Forwarded {
+        /// The authority that forwarded the contacts.
+        from: AuthorityId,
+    },
+}
+
+/// The record of an accepted upload.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct UploadRecord {
+    /// When the upload was accepted.
+    pub received: DateTime<Utc>,
+    /// Where the upload came from.
+    ///
+    /// This is `None` for uploads stored before sources were recorded.
+    #[serde(default)]
+    pub source: Option<UploadSource>,
+    /// When the upload was revoked.
+    pub revoked: Option<DateTime<Utc>>,
+    /// The hashed identities the upload marked as infected.
+    pub infected: Vec<HashedIdentity>,
+    /// The hashed identities the upload marked as exposed.
+    pub exposed: Vec<HashedIdentity>,
+    /// For uploads of exposed users the uploads their exposure came from.
+    pub origins: BTreeSet<UploadId>,
+}
+
+/// Lookups shared by stores and their transactions.
+pub trait StoreRead {
+    /// Looks up the infection record of a hashed identity.
+    fn infection(&self, hashed_id: &HashedIdentity) -> Result<Option<InfectionRecord>, StoreError>;
+
+    /// Looks up the exposure record of a hashed identity.
+    fn exposure(&self, hashed_id: &HashedIdentity) -> Result<Option<ExposureRecord>, StoreError>;
+
+    /// Returns when all marks of a hashed identity were revoked.
+    fn revoked_at(&self, hashed_id: &HashedIdentity) -> Result<Option<DateTime<Utc>>, StoreError>;
+
+    /// Looks up an upload.
+    fn upload(&self, upload_id: &UploadId) -> Result<Option<UploadRecord>, StoreError>;
+
+    /// Returns the uploads that list the given upload as origin.
+    fn uploads_from(&self, origin: &UploadId) -> Result<Vec<UploadId>, StoreError>;
+
+    /// Returns when an authorization code was redeemed.
+    fn code_redeemed_at(&self, code_id: &CodeId) -> Result<Option<DateTime<Utc>>, StoreError>;
+
+    /// Returns when an anonymous credential was redeemed.
+    #[cfg(feature = "blind")]
+    fn credential_redeemed_at(
+        &self,
+        credential_id: &CredentialId,
+    ) -> Result<Option<DateTime<Utc>>, StoreError>;
+
+    /// Returns when a batch of forwarded contacts was imported.
+    fn forward_imported_at(&self, batch_id: &BatchId) -> Result<Option<DateTime<Utc>>, StoreError>;
+
+    /// Returns all infection records.
+    fn infections(&self) -> Result<Vec<(HashedIdentity, InfectionRecord)>, StoreError>;
+
+    /// Returns all exposure records.
+    fn exposures(&self) -> Result<Vec<(HashedIdentity, ExposureRecord)>, StoreError>;
+
+    /// Returns all revoked hashed identities.
+    fn revocations(&self) -> Result<Vec<(HashedIdentity, DateTime<Utc>)>, StoreError>;
+
+    /// Returns all uploads.
+    fn uploads(&self) -> Result<Vec<(UploadId, UploadRecord)>, StoreError>;
+
+    /// Checks if there is a subscription for a token.
+    fn is_subscribed(&self, token: &SubscriptionToken) -> Result<bool, StoreError>;
+
+    /// Returns the notifications waiting to be delivered, oldest first.
+    fn notifications(&self) -> Result<Vec<Notification>, StoreError>;
+
+    /// Returns the entries of the transparency log, oldest first.
+    fn log_entries(&self) -> Result<Vec<LogEntry>, StoreError>;
+
+    /// Returns the entries waiting to be appended to the transparency log.
+    fn pending_log_entries(&self) -> Result<Vec<LogEntry>, StoreError>;
+
+    /// Returns the contacts waiting to be forwarded, by receiving authority.
+    fn pending_forwards(&self) -> Result<BTreeMap<AuthorityId, ContactLog>, StoreError>;
+}
+
+/// A transaction on a [`RegistryStore`](trait.RegistryStore.html).
+///
+/// Changes only become visible to others on [`commit`](#tymethod.commit).  A
+/// transaction that is dropped without being committed is rolled back.
+pub trait StoreTransaction: StoreRead {
+    /// Stores the infection record of a hashed identity.
+    fn put_infection(
+        &mut self,
+        hashed_id: &HashedIdentity,
+        record: &InfectionRecord,
+    ) -> Result<(), StoreError>;
+
+    /// Removes the infection record of a hashed identity.
+    fn remove_infection(&mut self, hashed_id: &HashedIdentity) -> Result<(), StoreError>;
+
+    /// Stores the exposure record of a hashed identity.
+    fn put_exposure(
+        &mut self,
+        hashed_id: &HashedIdentity,
+        record: &ExposureRecord,
+    ) -> Result<(), StoreError>;
+
+    /// Removes the exposure record of a hashed identity.
+    fn remove_exposure(&mut self, hashed_id: &HashedIdentity) -> Result<(), StoreError>;
+
+    /// Marks all marks of a hashed identity as revoked.
+    fn put_revoked(
+        &mut self,
+        hashed_id: &HashedIdentity,
+        revoked_at: DateTime<Utc>,
+    ) -> Result<(), StoreError>;
+
+    /// Clears the revocation of a hashed identity.
+    fn remove_revoked(&mut self, hashed_id: &HashedIdentity) -> Result<(), StoreError>;
+
+    /// Stores an upload.
+    fn put_upload(&mut self, upload_id: &UploadId, record: &UploadRecord)
+        -> Result<(), StoreError>;
+
+    /// Records the redemption of an authorization code.
+    fn put_code_redeemed(
+        &mut self,
+        code_id: &CodeId,
+        redeemed_at: DateTime<Utc>,
+    ) -> Result<(), StoreError>;
+
+    /// Records the redemption of an anonymous credential.
+    #[cfg(feature = "blind")]
+    fn put_credential_redeemed(
+        &mut self,
+        credential_id: &CredentialId,
+        redeemed_at: DateTime<Utc>,
+    ) -> Result<(), StoreError>;
+
+    /// Records the import of a batch of forwarded contacts.
+    fn put_forward_imported(
+        &mut self,
+        batch_id: &BatchId,
+        imported_at: DateTime<Utc>,
+    ) -> Result<(), StoreError>;
+
+    /// Stores a subscription.
+    fn put_subscription(&mut self, token: &SubscriptionToken) -> Result<(), StoreError>;
+
+    /// Removes a subscription.
+    fn remove_subscription(&mut self, token: &SubscriptionToken) -> Result<(), StoreError>;
+
+    /// Appends a notification to the queue of notifications to deliver.
+    fn push_notification(&mut self, notification: &Notification) -> Result<(), StoreError>;
+
+    /// Removes and returns the oldest notification waiting to be delivered.
+    fn pop_notification(&mut self) -> Result<Option<Notification>, StoreError>;
+
+    /// Appends an entry to the transparency log.
+    fn append_log_entry(&mut self, entry: &LogEntry) -> Result<(), StoreError>;
+
+    /// Queues an entry to be appended to the transparency log later.
+    fn push_pending_log_entry(&mut self, entry: &LogEntry) -> Result<(), StoreError>;
+
+    /// Removes and returns all entries waiting to be appended to the
+    /// transparency log.
+    fn take_pending_log_entries(&mut self) -> Result<Vec<LogEntry>, StoreError>;
+
+    /// Queues contacts to be forwarded to another authority.
+    ///
+    /// The contacts are merged with those already queued for the authority.
+    fn push_pending_forward(
+        &mut self,
+        to: &AuthorityId,
+        contacts: &ContactLog,
+    ) -> Result<(), StoreError>;
+
+    /// Removes and returns all contacts waiting to be forwarded.
+    fn take_pending_forwards(&mut self) -> Result<BTreeMap<AuthorityId, ContactLog>, StoreError>;
+
+    /// Commits the transaction.
+    fn commit(self: Box<Self>) -> Result<(), StoreError>;
+}
+
+/// A storage backend for the records of a [`Registry`](struct.Registry.html).
+///
+/// The registry only changes records within a transaction so that a failed
+/// upload or revocation never leaves partial state behind.
+pub trait RegistryStore: StoreRead {
+    /// Starts a new transaction.
+    fn transaction(&mut self) -> Result<Box<dyn StoreTransaction + '_>, StoreError>;
+}
+
+/// A store that keeps all records in memory.
+///
+/// This is the default store of the registry.  It can be persisted by
+/// serializing the registry, or be [opened](#method.open) from a file that
+/// it is written back to whenever a transaction changes it.
+///
+/// Lookups are not constant time.  Identities are kept in hash maps with
+/// randomly keyed hashers, so the bucket a supplied identity probes is not
+/// related to how close it is to a stored one, but a lookup that finds a
+/// record takes measurably longer than one that does not.  Servers that
+/// answer lookups for untrusted clients should not expose this difference,
+/// for instance by doing the same amount of work for every query.
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct MemoryStore {
+    infected: HashMap<HashedIdentity, InfectionRecord>,
+    exposures: HashMap<HashedIdentity, ExposureRecord>,
+    uploads: HashMap<UploadId, UploadRecord>,
+    revoked: HashMap<HashedIdentity, DateTime<Utc>>,
+    redeemed: HashMap<CodeId, DateTime<Utc>>,
+    #[cfg(feature = "blind")]
+    #[serde(default)]
+    redeemed_credentials: HashMap<CredentialId, DateTime<Utc>>,
+    #[serde(default)]
+    imported_forwards: HashMap<BatchId, DateTime<Utc>>,
+    #[serde(default)]
+    subscriptions: HashSet<SubscriptionToken>,
+    #[serde(default)]
+    notifications: VecDeque<Notification>,
+    #[serde(default)]
+    log: Vec<LogEntry>,
+    #[serde(default)]
+    pending_log: Vec<LogEntry>,
+    #[serde(default)]
+    pending_forwards: BTreeMap<AuthorityId, ContactLog>,
+    #[serde(skip)]
+    path: Option<PathBuf>,
+}
+
+impl MemoryStore {
+    /// Creates an empty store.
+    pub fn new() -> MemoryStore {
+        MemoryStore::default()
+    }
+
+    /// Loads a store from a JSON file, or creates an empty one if the file
+    /// does not exist.
+    ///
+    /// Every committed transaction that changed the store atomically
+    /// replaces the file with the new contents.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<MemoryStore, StoreError> {
+        let path = path.as_ref();
+        let mut store = match fs::read(path) {
+            Ok(data) => serde_json::from_slice(&data).map_err(|err| StoreError::Corrupted {
+                message: err.to_string(),
+            })?,
+            Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => MemoryStore::new(),
+            Err(err) => return Err(backend_error(err)),
+        };
+        store.path = Some(path.to_path_buf());
+        Ok(store)
+    }
+
+    fn save(&self) -> Result<(), StoreError> {
+        if let Some(ref path) = self.path {
+            let data = serde_json::to_vec(self).map_err(backend_error)?;
+            write_atomic(path, &data).map_err(backend_error)?;
+        }
+        Ok(())
+    }
+}
+
+fn backend_error<E: std::fmt::Display>(err: E) -> StoreError {
+    StoreError::Backend {
+        message: err.to_string(),
+    }
+}
+
+impl StoreRead for MemoryStore {
+    fn infection(&self, hashed_id: &HashedIdentity) -> Result<Option<InfectionRecord>, StoreError> {
+        Ok(self.infected.get(hashed_id).cloned())
+    }
+
+    fn exposure(&self, hashed_id: &HashedIdentity) -> Result<Option<ExposureRecord>, StoreError> {
+        Ok(self.exposures.get(hashed_id).cloned())
+    }
+
+    fn revoked_at(&self, hashed_id: &HashedIdentity) -> Result<Option<DateTime<Utc>>, StoreError> {
+        Ok(self.revoked.get(hashed_id).copied())
+    }
+
+    fn upload(&self, upload_id: &UploadId) -> Result<Option<UploadRecord>, StoreError> {
+        Ok(self.uploads.get(upload_id).cloned())
+    }
+
+    fn uploads_from(&self, origin: &UploadId) -> Result<Vec<UploadId>, StoreError> {
+        Ok(self
+            .uploads
+            .iter()
+            .filter(|(_, record)| record.origins.contains(origin))
+            .map(|(upload_id, _)| *upload_id)
+            .collect())
+    }
+
+    fn code_redeemed_at(&self, code_id: &CodeId) -> Result<Option<DateTime<Utc>>, StoreError> {
+        Ok(self.redeemed.get(code_id).copied())
+    }
+
+    #[cfg(feature = "blind")]
+    fn credential_redeemed_at(
+        &self,
+        credential_id: &CredentialId,
+    ) -> Result<Option<DateTime<Utc>>, StoreError> {
+        Ok(self.redeemed_credentials.get(credential_id).copied())
+    }
+
+    fn forward_imported_at(&self, batch_id: &BatchId) -> Result<Option<DateTime<Utc>>, StoreError> {
+        Ok(self.imported_forwards.get(batch_id).copied())
+    }
+
+    fn infections(&self) -> Result<Vec<(HashedIdentity, InfectionRecord)>, StoreError> {
+        Ok(self
+            .infected
+            .iter()
+            .map(|(hashed_id, record)| (*hashed_id, record.clone()))
+            .collect())
+    }
+
+    fn exposures(&self) -> Result<Vec<(HashedIdentity, ExposureRecord)>, StoreError> {
+        Ok(self
+            .exposures
+            .iter()
+            .map(|(hashed_id, record)| (*hashed_id, record.clone()))
+            .collect())
+    }
+
+    fn revocations(&self) -> Result<Vec<(HashedIdentity, DateTime<Utc>)>, StoreError> {
+        Ok(self
+            .revoked
+            .iter()
+            .map(|(hashed_id, revoked_at)| (*hashed_id, *revoked_at))
+            .collect())
+    }
+
+    fn uploads(&self) -> Result<Vec<(UploadId, UploadRecord)>, StoreError> {
+        Ok(self
+            .uploads
+            .iter()
+            .map(|(upload_id, record)| (*upload_id, record.clone()))
+            .collect())
+    }
+
+    fn is_subscribed(&self, token: &SubscriptionToken) -> Result<bool, StoreError> {
+        Ok(self.subscriptions.contains(token))
+    }
+
+    fn notifications(&self) -> Result<Vec<Notification>, StoreError> {
+        Ok(self.notifications.iter().cloned().collect())
+    }
+
+    fn log_entries(&self) -> Result<Vec<LogEntry>, StoreError> {
+        Ok(self.log.clone())
+    }
+
+    fn pending_log_entries(&self) -> Result<Vec<LogEntry>, StoreError> {
+        Ok(self.pending_log.clone())
+    }
+
+    fn pending_forwards(&self) -> Result<BTreeMap<AuthorityId, ContactLog>, StoreError> {
+        Ok(self.pending_forwards.clone())
+    }
+}
+
+impl RegistryStore for MemoryStore {
+    fn transaction(&mut self) -> Result<Box<dyn StoreTransaction + '_>, StoreError> {
+        Ok(Box::new(MemoryTransaction {
+            store: self,
+            undo: Vec::new(),
+        }))
+    }
+}
+
+/// The previous value of a changed entry.
+enum Undo {
+    Infection(HashedIdentity, Option<InfectionRecord>),
+    Exposure(HashedIdentity, Option<ExposureRecord>),
+    Revoked(HashedIdentity, Option<DateTime<Utc>>),
+    Upload(UploadId, Option<UploadRecord>),
+    Code(CodeId, Option<DateTime<Utc>>),
+    #[cfg(feature = "blind")]
+    Credential(CredentialId, Option<DateTime<Utc>>),
+    Forward(BatchId, Option<DateTime<Utc>>),
+    Subscription(SubscriptionToken, bool),
+    NotificationPushed,
+    NotificationPopped(Notification),
+    LogAppended,
+    PendingLogPushed,
+    PendingLogTaken(Vec<LogEntry>),
+    PendingForward(AuthorityId, Option<ContactLog>),
+    PendingForwardsTaken(BTreeMap<AuthorityId, ContactLog>),
+}
+
+fn restore<K, V>(map: &mut HashMap<K, V>, key: K, value: Option<V>)
+where
+    K: std::hash::Hash + Eq,
+{
+    match value {
+        Some(value) => map.insert(key, value),
+        None => map.remove(&key),
+    };
+}
+
+/// A transaction on a [`MemoryStore`](struct.MemoryStore.html).
+///
+/// Changes are applied directly and undone if the transaction is dropped
+/// without being committed.
+struct MemoryTransaction<'a> {
+    store: &'a mut MemoryStore,
+    undo: Vec<Undo>,
+}
+
+impl Drop for MemoryTransaction<'_> {
+    fn drop(&mut self) {
+        let store = &mut *self.store;
+        for undo in self.undo.drain(..).rev() {
+            match undo {
+                Undo::Infection(key, value) => restore(&mut store.infected, key, value),
+                Undo::Exposure(key, value) => restore(&mut store.exposures, key, value),
+                Undo::Revoked(key, value) => restore(&mut store.revoked, key, value),
+                Undo::Upload(key, value) => restore(&mut store.uploads, key, value),
+                Undo::Code(key, value) => restore(&mut store.redeemed, key, value),
+                #[cfg(feature = "blind")]
+                Undo::Credential(key, value) => {
+                    restore(&mut store.redeemed_credentials, key, value)
+                }
+                Undo::Forward(key, value) => restore(&mut store.imported_forwards, key, value),
+                Undo::Subscription(token, true) => {
+                    store.subscriptions.insert(token);
+                }
+                Undo::Subscription(token, false) => {
+                    store.subscriptions.remove(&token);
+                }
+                Undo::NotificationPushed => {
+                    store.notifications.pop_back();
+                }
+                Undo::NotificationPopped(notification) => {
+                    store.notifications.push_front(notification)
+                }
+                Undo::LogAppended => {
+                    store.log.pop();
+                }
+                Undo::PendingLogPushed => {
+                    store.pending_log.pop();
+                }
+                Undo::PendingLogTaken(entries) => store.pending_log = entries,
+                Undo::PendingForward(to, Some(contacts)) => {
+                    store.pending_forwards.insert(to, contacts);
+                }
+                Undo::PendingForward(to, None) => {
+                    store.pending_forwards.remove(&to);
+                }
+                Undo::PendingForwardsTaken(forwards) => store.pending_forwards = forwards,
+            }
+        }
+    }
+}
+
+impl StoreRead for MemoryTransaction<'_> {
+    fn infection(&self, hashed_id: &HashedIdentity) -> Result<Option<InfectionRecord>, StoreError> {
+        self.store.infection(hashed_id)
+    }
+
+    fn exposure(&self, hashed_id: &HashedIdentity) -> Result<Option<ExposureRecord>, StoreError> {
+        self.store.exposure(hashed_id)
+    }
+
+    fn revoked_at(&self, hashed_id: &HashedIdentity) -> Result<Option<DateTime<Utc>>, StoreError> {
+        self.store.revoked_at(hashed_id)
+    }
+
+    fn upload(&self, upload_id: &UploadId) -> Result<Option<UploadRecord>, StoreError> {
+        self.store.upload(upload_id)
+    }
+
+    fn uploads_from(&self, origin: &UploadId) -> Result<Vec<UploadId>, StoreError> {
+        self.store.uploads_from(origin)
+    }
+
+    fn code_redeemed_at(&self, code_id: &CodeId) -> Result<Option<DateTime<Utc>>, StoreError> {
+        self.store.code_redeemed_at(code_id)
+    }
+
+    #[cfg(feature = "blind")]
+    fn credential_redeemed_at(
+        &self,
+        credential_id: &CredentialId,
+    ) -> Result<Option<DateTime<Utc>>, StoreError> {
+        self.store.credential_redeemed_at(credential_id)
+    }
+
+    fn forward_imported_at(&self, batch_id: &BatchId) -> Result<Option<DateTime<Utc>>, StoreError> {
+        self.store.forward_imported_at(batch_id)
+    }
+
+    fn infections(&self) -> Result<Vec<(HashedIdentity, InfectionRecord)>, StoreError> {
+        self.store.infections()
+    }
+
+    fn exposures(&self) -> Result<Vec<(HashedIdentity, ExposureRecord)>, StoreError> {
+        self.store.exposures()
+    }
+
+    fn revocations(&self) -> Result<Vec<(HashedIdentity, DateTime<Utc>)>, StoreError> {
+        self.store.revocations()
+    }
+
+    fn uploads(&self) -> Result<Vec<(UploadId, UploadRecord)>, StoreError> {
+        self.store.uploads()
+    }
+
+    fn is_subscribed(&self, token: &SubscriptionToken) -> Result<bool, StoreError> {
+        self.store.is_subscribed(token)
+    }
+
+    fn notifications(&self) -> Result<Vec<Notification>, StoreError> {
+        self.store.notifications()
+    }
+
+    fn log_entries(&self) -> Result<Vec<LogEntry>, StoreError> {
+        self.store.log_entries()
+    }
+
+    fn pending_log_entries(&self) -> Result<Vec<LogEntry>, StoreError> {
+        self.store.pending_log_entries()
+    }
+
+    fn pending_forwards(&self) -> Result<BTreeMap<AuthorityId, ContactLog>, StoreError> {
+        self.store.pending_forwards()
+    }
+}
+
+impl StoreTransaction for MemoryTransaction<'_> {
+    fn put_infection(
+        &mut self,
+        hashed_id: &HashedIdentity,
+        record: &InfectionRecord,
+    ) -> Result<(), StoreError> {
+        let old = self.store.infected.insert(*hashed_id, record.clone());
+        self.undo.push(Undo::Infection(*hashed_id, old));
+        Ok(())
+    }
+
+    fn remove_infection(&mut self, hashed_id: &HashedIdentity) -> Result<(), StoreError> {
+        let old = self.store.infected.remove(hashed_id);
+        self.undo.push(Undo::Infection(*hashed_id, old));
+        Ok(())
+    }
+
+    fn put_exposure(
+        &mut self,
+        hashed_id: &HashedIdentity,
+        record: &ExposureRecord,
+    ) -> Result<(), StoreError> {
+        let old = self.store.exposures.insert(*hashed_id, record.clone());
+        self.undo.push(Undo::Exposure(*hashed_id, old));
+        Ok(())
+    }
+
+    fn remove_exposure(&mut self, hashed_id: &HashedIdentity) -> Result<(), StoreError> {
+        let old = self.store.exposures.remove(hashed_id);
+        self.undo.push(Undo::Exposure(*hashed_id, old));
+        Ok(())
+    }
+
+    fn put_revoked(
+        &mut self,
+        hashed_id: &HashedIdentity,
+        revoked_at: DateTime<Utc>,
+    ) -> Result<(), StoreError> {
+        let old = self.store.revoked.insert(*hashed_id, revoked_at);
+        self.undo.push(Undo::Revoked(*hashed_id, old));
+        Ok(())
+    }
+
+    fn remove_revoked(&mut self, hashed_id: &HashedIdentity) -> Result<(), StoreError> {
+        let old = self.store.revoked.remove(hashed_id);
+        self.undo.push(Undo::Revoked(*hashed_id, old));
+        Ok(())
+    }
+
+    fn put_upload(
+        &mut self,
+        upload_id: &UploadId,
+        record: &UploadRecord,
+    ) -> Result<(), StoreError> {
+        let old = self.store.uploads.insert(*upload_id, record.clone());
+        self.undo.push(Undo::Upload(*upload_id, old));
+        Ok(())
+    }
+
+    fn put_code_redeemed(
+        &mut self,
+        code_id: &CodeId,
+        redeemed_at: DateTime<Utc>,
+    ) -> Result<(), StoreError> {
+        let old = self.store.redeemed.insert(*code_id, redeemed_at);
+        self.undo.push(Undo::Code(*code_id, old));
+        Ok(())
+    }
+
+    #[cfg(feature = "blind")]
+    fn put_credential_redeemed(
+        &mut self,
+        credential_id: &CredentialId,
+        redeemed_at: DateTime<Utc>,
+    ) -> Result<(), StoreError> {
+        let old = self
+            .store
+            .redeemed_credentials
+            .insert(*credential_id, redeemed_at);
+        self.undo.push(Undo::Credential(*credential_id, old));
+        Ok(())
+    }
+
+    fn put_forward_imported(
+        &mut self,
+        batch_id: &BatchId,
+        imported_at: DateTime<Utc>,
+    ) -> Result<(), StoreError> {
+        let old = self.store.imported_forwards.insert(*batch_id, imported_at);
+        self.undo.push(Undo::Forward(*batch_id, old));
+        Ok(())
+    }
+
+    fn put_subscription(&mut self, token: &SubscriptionToken) -> Result<(), StoreError> {
+        let old = !self.store.subscriptions.insert(*token);
+        self.undo.push(Undo::Subscription(*token, old));
+        Ok(())
+    }
+
+    fn remove_subscription(&mut self, token: &SubscriptionToken) -> Result<(), StoreError> {
+        let old = self.store.subscriptions.remove(token);
+        self.undo.push(Undo::Subscription(*token, old));
+        Ok(())
+    }
+
+    fn push_notification(&mut self, notification: &Notification) -> Result<(), StoreError> {
+        self.store.notifications.push_back(notification.clone());
+        self.undo.push(Undo::NotificationPushed);
+        Ok(())
+    }
+
+    fn pop_notification(&mut self) -> Result<Option<Notification>, StoreError> {
+        let old = self.store.notifications.pop_front();
+        if let Some(ref notification) = old {
+            self.undo
+                .push(Undo::NotificationPopped(notification.clone()));
+        }
+        Ok(old)
+    }
+
+    fn append_log_entry(&mut self, entry: &LogEntry) -> Result<(), StoreError> {
+        self.store.log.push(entry.clone());
+        self.undo.push(Undo::LogAppended);
+        Ok(())
+    }
+
+    fn push_pending_log_entry(&mut self, entry: &LogEntry) -> Result<(), StoreError> {
+        self.store.pending_log.push(entry.clone());
+        self.undo.push(Undo::PendingLogPushed);
+        Ok(())
+    }
+
+    fn take_pending_log_entries(&mut self) -> Result<Vec<LogEntry>, StoreError> {
+        let entries = std::mem::take(&mut self.store.pending_log);
+        self.undo.push(Undo::PendingLogTaken(entries.clone()));
+        Ok(entries)
+    }
+
+    fn push_pending_forward(
+        &mut self,
+        to: &AuthorityId,
+        contacts: &ContactLog,
+    ) -> Result<(), StoreError> {
+        let old = self.store.pending_forwards.get(to).cloned();
+        self.store
+            .pending_forwards
+            .entry(*to)
+            .or_default()
+            .merge(contacts);
+        self.undo.push(Undo::PendingForward(*to, old));
+        Ok(())
+    }
+
+    fn take_pending_forwards(&mut self) -> Result<BTreeMap<AuthorityId, ContactLog>, StoreError> {
+        let forwards = std::mem::take(&mut self.store.pending_forwards);
+        self.undo.push(Undo::PendingForwardsTaken(forwards.clone()));
+        Ok(forwards)
+    }
+
+    fn commit(mut self: Box<Self>) -> Result<(), StoreError> {
+        // if the file cannot be written the transaction is rolled back so
+        // that the store does not get ahead of it
+        if !self.undo.is_empty() {
+            self.store.save()?;
+        }
+        self.undo.clear();
+        Ok(())
+    }
+}
@@ -0,0 +1,363 @@
+//! Implements push subscriptions for hashed identities.
+use std::collections::VecDeque;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::str::FromStr;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use derive_more::{Display, Error};
+use serde::{de, ser, Deserialize, Serialize};
+use serde_plain::forward_display_to_serde;
+use sha2::{Digest, Sha256};
+
+use crate::auth::HashedIdentity;
+use crate::client::{ClientError, HttpTransport, Method, Transport};
+use crate::crypto::ct_eq;
+use crate::envelope::{self, ArtifactKind};
+use crate::error::Error;
+use crate::store::StoreError;
+
+const TOKEN_CONTEXT: &[u8] = b"covidcotra-subscription\x00";
+
+/// The delay before a failed delivery of a background sink is retried.
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// The longest delay between retries of a background sink.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(300);
+
+/// An opaque token a device subscribes to push notifications with.
+///
+/// The token is derived from a [`HashedIdentity`](struct.HashedIdentity.html)
+/// with [`subscription_token`](struct.HashedIdentity.html#method.subscription_token).
+/// The registry can derive the token of every hashed identity it marks, but
+/// the hashed identity cannot be recovered from the token.  This means tokens
+/// can be handed to a push service without letting it poll the status.
+#[derive(Copy, Clone, Debug)]
+pub struct SubscriptionToken([u8; 32]);
+
+impl PartialEq for SubscriptionToken {
+    fn eq(&self, other: &SubscriptionToken) -> bool {
+        ct_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for SubscriptionToken {}
+
+impl Hash for SubscriptionToken {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash(state);
+    }
+}
+
+forward_display_to_serde!(SubscriptionToken);
+
+impl FromStr for SubscriptionToken {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<SubscriptionToken, Error> {
+        let (_, bytes) = envelope::parse(ArtifactKind::SubscriptionToken, s, None)?;
+        SubscriptionToken::from_slice(&bytes)
+    }
+}
+
+impl Serialize for SubscriptionToken {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: ser::Serializer,
+    {
+        envelope::serialize(ArtifactKind::SubscriptionToken, &self.0[..], serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for SubscriptionToken {
+    fn deserialize<D>(deserializer: D) -> Result<SubscriptionToken, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        let (_, bytes) =
+            envelope::deserialize(ArtifactKind::SubscriptionToken, None, deserializer)?;
+        SubscriptionToken::from_slice(&bytes).map_err(de::Error::custom)
+    }
+}
+
+impl SubscriptionToken {
+    pub(crate) fn derive(hashed_id: &HashedIdentity) -> SubscriptionToken {
+        let mut hasher = Sha256::new();
+        hasher.input(TOKEN_CONTEXT);
+        hasher.input(hashed_id.as_bytes());
+        let mut token = [0u8; 32];
+        token.copy_from_slice(&hasher.result());
+        SubscriptionToken(token)
+    }
+
+    fn from_slice(bytes: &[u8]) -> Result<SubscriptionToken, Error> {
+        Error::check_length(32, bytes.len())?;
+        let mut token = [0u8; 32];
+        token.copy_from_slice(bytes);
+        Ok(SubscriptionToken(token))
+    }
+}
+
+/// Tells a subscribed device that the status of one of its hashed identities
+/// changed.
+///
+/// The notification deliberately does not carry the new status.  The device
+/// is expected to check its status (for instance with
+/// [`Client::check_status`](struct.Client.html#method.check_status)) when it
+/// receives one.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Notification {
+    /// The token the device subscribed with.
+    pub token: SubscriptionToken,
+    /// When the status changed.
+    pub changed: DateTime<Utc>,
+}
+
+/// Error for notifications that could not be delivered.
+#[derive(Debug, Error, Display)]
+pub enum DeliveryError {
+    /// The endpoint could not be reached.
+    #[display(fmt = "notification delivery failed: {}", _0)]
+    Transport(io::Error),
+    /// The endpoint did not accept the notification.
+    #[display(fmt = "notification endpoint responded with status {}", status)]
+    Rejected { status: u16 },
+    /// The queued notifications could not be read or updated.
+    #[display(fmt = "{}", _0)]
+    Store(StoreError),
+}
+
+/// Delivers notifications emitted by the [`Registry`](struct.Registry.html).
+///
+/// Sinks are installed with
+/// [`Registry::set_notification_sink`](struct.Registry.html#method.set_notification_sink).
+pub trait NotificationSink {
+    /// Delivers a single notification.
+    fn deliver(&mut self, notification: &Notification) -> Result<(), DeliveryError>;
+
+    /// Delivers notifications from the queue of the registry.
+    ///
+    /// `queued` holds all queued notifications, oldest first.  Returns how
+    /// many of them, counted from the front, were delivered.  Only those are
+    /// removed from the queue, the others are handed to the sink again on
+    /// the next call.  The default delivers the notifications one by one and
+    /// stops at the first failure, which is returned if nothing was
+    /// delivered.
+    fn deliver_queued(&mut self, queued: &[Notification]) -> Result<usize, DeliveryError> {
+        for (idx, notification) in queued.iter().enumerate() {
+            if let Err(err) = self.deliver(notification) {
+                return if idx == 0 { Err(err) } else { Ok(idx) };
+            }
+        }
+        Ok(queued.len())
+    }
+}
+
+/// A sink that keeps notifications in memory.
+///
+/// Clones share the same notifications, so a clone can be kept to look at
+/// the notifications after the sink was handed to the registry.
+#[derive(Clone, Debug, Default)]
+pub struct MemorySink {
+    notifications: Arc<Mutex<Vec<Notification>>>,
+}
+
+impl MemorySink {
+    /// Creates an empty sink.
+    pub fn new() -> MemorySink {
+        MemorySink::default()
+    }
+
+    /// Returns the notifications delivered so far.
+    pub fn notifications(&self) -> Vec<Notification> {
+        self.notifications.lock().unwrap().clone()
+    }
+
+    /// Removes and returns the notifications delivered so far.
+    pub fn take(&self) -> Vec<Notification> {
+        std::mem::take(&mut *self.notifications.lock().unwrap())
+    }
+}
+
+impl NotificationSink for MemorySink {
+    fn deliver(&mut self, notification: &Notification) -> Result<(), DeliveryError> {
+        self.notifications
+            .lock()
+            .unwrap()
+            .push(notification.clone());
+        Ok(())
+    }
+}
+
+/// The notifications handed to the thread of a background sink.
+#[derive(Debug, Default)]
+struct Outbox {
+    /// The notifications not yet delivered, oldest first, and whether they
+    /// are still queued in the registry.
+    pending: VecDeque<(Notification, bool)>,
+    /// The number of notifications queued in the registry that were
+    /// delivered since the registry last asked.
+    delivered: usize,
+    /// Set when the sink was dropped.
+    closed: bool,
+}
+
+/// A sink that delivers notifications on a background thread.
+///
+/// Handing notifications to the sink does not wait for the delivery, so a
+/// slow or unreachable endpoint never holds up the registry.  The thread
+/// delivers them in order through the wrapped sink and retries failed
+/// deliveries with a growing delay.  Notifications from the queue of the
+/// registry are only removed from it once the thread delivered them, which
+/// the registry learns on its next flush.  Until then they stay in the store,
+/// so they are delivered again after a restart rather than lost.
+#[derive(Debug)]
+pub struct BackgroundSink {
+    shared: Arc<(Mutex<Outbox>, Condvar)>,
+}
+
+impl BackgroundSink {
+    /// Starts a thread that delivers through a sink.
+    pub fn spawn<N: NotificationSink + Send + 'static>(mut sink: N) -> BackgroundSink {
+        let shared = Arc::new((Mutex::new(Outbox::default()), Condvar::new()));
+        let worker = shared.clone();
+        thread::spawn(move || {
+            let (ref outbox, ref wakeup) = *worker;
+            let mut delay = RETRY_DELAY;
+            loop {
+                let notification = {
+                    let mut outbox = outbox.lock().unwrap();
+                    loop {
+                        if outbox.closed {
+                            return;
+                        }
+                        if let Some((notification, _)) = outbox.pending.front() {
+                            break notification.clone();
+                        }
+                        outbox = wakeup.wait(outbox).unwrap();
+                    }
+                };
+                if sink.deliver(&notification).is_err() {
+                    thread::sleep(delay);
+                    delay = (delay * 2).min(MAX_RETRY_DELAY);
+                    continue;
+                }
+                delay = RETRY_DELAY;
+                let mut outbox = outbox.lock().unwrap();
+                if let Some((_, true)) = outbox.pending.pop_front() {
+                    outbox.delivered += 1;
+                }
+            }
+        });
+        BackgroundSink { shared }
+    }
+
+    fn outbox(&self) -> Result<MutexGuard<'_, Outbox>, DeliveryError> {
+        self.shared
+            .0
+            .lock()
+            .map_err(|_| DeliveryError::Transport(io::Error::other("notification thread stopped")))
+    }
+}
+
+impl Drop for BackgroundSink {
+    fn drop(&mut self) {
+        if let Ok(mut outbox) = self.shared.0.lock() {
+            outbox.closed = true;
+        }
+        self.shared.1.notify_one();
+    }
+}
+
+impl NotificationSink for BackgroundSink {
+    /// Hands a notification to the thread.
+    ///
+    /// The notification is lost if it is not delivered before the sink is
+    /// dropped.
+    fn deliver(&mut self, notification: &Notification) -> Result<(), DeliveryError> {
+        self.outbox()?
+            .pending
+            .push_back((notification.clone(), false));
+        self.shared.1.notify_one();
+        Ok(())
+    }
+
+    fn deliver_queued(&mut self, queued: &[Notification]) -> Result<usize, DeliveryError> {
+        let mut outbox = self.outbox()?;
+        let delivered = std::mem::take(&mut outbox.delivered).min(queued.len());
+        let handed = outbox.pending.iter().filter(|(_, queued)| *queued).count();
+        let start = (delivered + handed).min(queued.len());
+        outbox.pending.extend(
+            queued[start..]
+                .iter()
+                .map(|notification| (notification.clone(), true)),
+        );
+        drop(outbox);
+        self.shared.1.notify_one();
+        Ok(delivered)
+    }
+}
+
+/// A sink that posts notifications as JSON to an HTTP endpoint.
+///
+/// This is meant for a local endpoint (such as a gateway to a push service)
+/// since requests are sent over plain HTTP.  Every delivery waits for the
+/// endpoint to respond, so the sink should be wrapped in a
+/// [`BackgroundSink`](struct.BackgroundSink.html) when it is installed on a
+/// registry that serves requests.
+#[derive(Debug)]
+pub struct WebhookSink<T = HttpTransport> {
+    transport: T,
+    path: String,
+}
+
+impl WebhookSink {
+    /// Creates a sink for an endpoint URL (for instance
+    /// `http://127.0.0.1:9000/notify`).
+    pub fn new(url: &str) -> Result<WebhookSink, ClientError> {
+        let rest = url
+            .strip_prefix("http://")
+            .ok_or_else(|| ClientError::InvalidUrl { url: url.into() })?;
+        let (host, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+        Ok(WebhookSink::with_transport(
+            HttpTransport::new(&format!("http://{}", host))?,
+            if path.is_empty() { "/" } else { path },
+        ))
+    }
+}
+
+impl<T: Transport> WebhookSink<T> {
+    /// Creates a sink that posts to a path through a transport.
+    pub fn with_transport(transport: T, path: &str) -> WebhookSink<T> {
+        WebhookSink {
+            transport,
+            path: path.into(),
+        }
+    }
+
+    /// Returns the transport.
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+}
+
+impl<T: Transport> NotificationSink for WebhookSink<T> {
+    fn deliver(&mut self, notification: &Notification) -> Result<(), DeliveryError> {
+        // notifications only consist of strings so this cannot fail
+        let body = serde_json::to_vec(notification).unwrap();
+        let response = self
+            .transport
+            .send(Method::Post, &self.path, &body)
+            .map_err(DeliveryError::Transport)?;
+        if (200..300).contains(&response.status) {
+            Ok(())
+        } else {
+            Err(DeliveryError::Rejected {
+                status: response.status,
+            })
+        }
+    }
+}
@@ -0,0 +1,490 @@
+//! Implements the transparency log of registry changes.
+use std::fmt;
+
+use chrono::{DateTime, Utc};
+use derive_more::{Display, Error};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::auth::HashedIdentity;
+use crate::crypto::{Signature, SigningPublicKey, SigningSecretKey};
+use crate::utils::base64;
+
+const SIGNATURE_CONTEXT: &[u8] = b"covidcotra-tree-head\x00";
+const LEAF_PREFIX: u8 = 0;
+const NODE_PREFIX: u8 = 1;
+
+/// Error for tree heads and proofs that do not verify.
+#[derive(Debug, Error, Display, Clone, PartialEq, Eq)]
+pub enum TransparencyError {
+    /// The tree head was not signed by the authority.
+    #[display(fmt = "tree head has an invalid signature")]
+    InvalidSignature,
+    /// The tree head cannot be decoded.
+    #[display(fmt = "malformed tree head")]
+    Malformed,
+    /// The proof does not match the tree head.
+    #[display(fmt = "invalid transparency proof")]
+    InvalidProof,
+    /// The requested entry or tree size lies beyond the end of the log.
+    #[display(fmt = "log has only {} entries", size)]
+    OutOfRange { size: u64 },
+}
+
+/// The hash of a node of the Merkle tree.
+#[derive(Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TreeHash(#[serde(with = "base64")] [u8; 32]);
+
+impl fmt::Debug for TreeHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "TreeHash(")?;
+        for byte in &self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        write!(f, ")")
+    }
+}
+
+impl TreeHash {
+    /// Returns the hash as bytes.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    fn from_digest(hasher: Sha256) -> TreeHash {
+        let mut hash = [0u8; 32];
+        hash.copy_from_slice(&hasher.result());
+        TreeHash(hash)
+    }
+
+    fn empty() -> TreeHash {
+        TreeHash::from_digest(Sha256::new())
+    }
+
+    fn node(left: &TreeHash, right: &TreeHash) -> TreeHash {
+        let mut hasher = Sha256::new();
+        hasher.input([NODE_PREFIX]);
+        hasher.input(left.0);
+        hasher.input(right.0);
+        TreeHash::from_digest(hasher)
+    }
+}
+
+/// What happened to a hashed identity.
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LogAction {
+    /// The hashed identity was reported as infected.
+    Infected,
+    /// The hashed identity was marked as exposed.
+    Exposed {
+        /// The degree of the exposure.
+        degree: u8,
+    },
+    /// A mark of the hashed identity was removed by revoking an upload.
+    Revoked,
+}
+
+/// A change of the registry recorded in the transparency log.
+///
+/// Entries deliberately do not name the upload that caused the change.
+/// The log is public, so entries that can be grouped by upload would tell
+/// which infected user exposed which contacts.  For the same reason the
+/// time of an entry only gives the day, and the entries of all uploads
+/// between two [flushes](struct.Registry.html#method.flush_log) are
+/// appended together in random order.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct LogEntry {
+    /// The hashed identity that changed.
+    pub hashed_id: HashedIdentity,
+    /// What happened.
+    pub action: LogAction,
+    /// The start of the day on which it happened.
+    pub at: DateTime<Utc>,
+}
+
+impl LogEntry {
+    /// Returns the hash of the entry as a leaf of the tree.
+    pub fn leaf_hash(&self) -> TreeHash {
+        let (tag, degree) = match self.action {
+            LogAction::Infected => (0u8, 0u8),
+            LogAction::Exposed { degree } => (1, degree),
+            LogAction::Revoked => (2, 0),
+        };
+        let mut hasher = Sha256::new();
+        hasher.input([LEAF_PREFIX]);
+        hasher.input(self.hashed_id.as_bytes());
+        hasher.input([tag, degree]);
+        hasher.input(self.at.timestamp().to_be_bytes());
+        hasher.input(self.at.timestamp_subsec_nanos().to_be_bytes());
+        TreeHash::from_digest(hasher)
+    }
+}
+
+/// The state of the log at a certain size.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TreeHead {
+    /// The number of entries in the tree.
+    pub tree_size: u64,
+    /// The root hash of the tree.
+    pub root_hash: TreeHash,
+    /// When the head was signed.
+    pub timestamp: DateTime<Utc>,
+}
+
+/// A [`TreeHead`](struct.TreeHead.html) signed by the authority.
+///
+/// Auditors keep the signed heads they saw.  Two heads that cannot be
+/// proven consistent are evidence that the authority rewrote its history.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SignedTreeHead {
+    payload: String,
+    signature: Signature,
+}
+
+impl SignedTreeHead {
+    pub(crate) fn sign(head: &TreeHead, secret_key: &SigningSecretKey) -> SignedTreeHead {
+        // heads only consist of strings and numbers so this cannot fail
+        let payload = serde_json::to_string(head).unwrap();
+        let signature = secret_key.sign(&signed_message(&payload));
+        SignedTreeHead { payload, signature }
+    }
+
+    /// Verifies the signature and returns the tree head.
+    pub fn verify(&self, public_key: &SigningPublicKey) -> Result<TreeHead, TransparencyError> {
+        if !public_key.verify(&signed_message(&self.payload), &self.signature) {
+            return Err(TransparencyError::InvalidSignature);
+        }
+        serde_json::from_str(&self.payload).map_err(|_| TransparencyError::Malformed)
+    }
+}
+
+fn signed_message(payload: &str) -> Vec<u8> {
+    let mut rv = SIGNATURE_CONTEXT.to_vec();
+    rv.extend_from_slice(payload.as_bytes());
+    rv
+}
+
+/// Proves that an entry is part of the tree of a certain size.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct InclusionProof {
+    /// The position of the entry in the log.
+    pub index: u64,
+    /// The size of the tree the proof is for.
+    pub tree_size: u64,
+    /// The hashes needed to compute the root hash.
+    pub path: Vec<TreeHash>,
+}
+
+impl InclusionProof {
+    /// Verifies that the entry is included in the tree of a tree head.
+    pub fn verify(&self, entry: &LogEntry, head: &TreeHead) -> Result<(), TransparencyError> {
+        if self.tree_size != head.tree_size || self.index >= self.tree_size {
+            return Err(TransparencyError::InvalidProof);
+        }
+        let mut node = self.index;
+        let mut last = self.tree_size - 1;
+        let mut hash = entry.leaf_hash();
+        for sibling in &self.path {
+            if last == 0 {
+                return Err(TransparencyError::InvalidProof);
+            }
+            if node & 1 == 1 || node == last {
+                hash = TreeHash::node(sibling, &hash);
+                while node & 1 == 0 && node != 0 {
+                    node >>= 1;
+                    last >>= 1;
+                }
+            } else {
+                hash = TreeHash::node(&hash, sibling);
+            }
+            node >>= 1;
+            last >>= 1;
+        }
+        if last != 0 || hash != head.root_hash {
+            return Err(TransparencyError::InvalidProof);
+        }
+        Ok(())
+    }
+}
+
+/// Proves that a tree is an extension of an older tree.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ConsistencyProof {
+    /// The size of the older tree.
+    pub old_size: u64,
+    /// The size of the newer tree.
+    pub new_size: u64,
+    /// The hashes needed to compute both root hashes.
+    pub path: Vec<TreeHash>,
+}
+
+impl ConsistencyProof {
+    /// Verifies that the tree of the newer head only appends to the tree of
+    /// the older head.
+    pub fn verify(&self, old: &TreeHead, new: &TreeHead) -> Result<(), TransparencyError> {
+        if self.old_size != old.tree_size
+            || self.new_size != new.tree_size
+            || self.old_size > self.new_size
+        {
+            return Err(TransparencyError::InvalidProof);
+        }
+        if self.old_size == self.new_size {
+            return if self.path.is_empty() && old.root_hash == new.root_hash {
+                Ok(())
+            } else {
+                Err(TransparencyError::InvalidProof)
+            };
+        }
+        if self.old_size == 0 {
+            return if self.path.is_empty() {
+                Ok(())
+            } else {
+                Err(TransparencyError::InvalidProof)
+            };
+        }
+
+        let mut path = self.path.iter();
+        let first = if self.old_size.is_power_of_two() {
+            old.root_hash
+        } else {
+            *path.next().ok_or(TransparencyError::InvalidProof)?
+        };
+        let mut node = self.old_size - 1;
+        let mut last = self.new_size - 1;
+        while node & 1 == 1 {
+            node >>= 1;
+            last >>= 1;
+        }
+        let (mut old_hash, mut new_hash) = (first, first);
+        for sibling in path {
+            if last == 0 {
+                return Err(TransparencyError::InvalidProof);
+            }
+            if node & 1 == 1 || node == last {
+                old_hash = TreeHash::node(sibling, &old_hash);
+                new_hash = TreeHash::node(sibling, &new_hash);
+                while node & 1 == 0 && node != 0 {
+                    node >>= 1;
+                    last >>= 1;
+                }
+            } else {
+                new_hash = TreeHash::node(&new_hash, sibling);
+            }
+            node >>= 1;
+            last >>= 1;
+        }
+        if last != 0 || old_hash != old.root_hash || new_hash != new.root_hash {
+            return Err(TransparencyError::InvalidProof);
+        }
+        Ok(())
+    }
+}
+
+/// An entry of the log together with the proof that it is included.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LogInclusion {
+    /// The entry.
+    pub entry: LogEntry,
+    /// The proof of inclusion.
+    pub proof: InclusionProof,
+}
+
+/// An append-only Merkle tree of registry changes.
+///
+/// The registry appends an entry for every hashed identity an upload marks
+/// as infected or exposed and for every mark removed by a revocation.  The
+/// entries are kept in the store of the registry and written in the same
+/// transaction as the change they record.  The tree is built like the one
+/// of certificate transparency (RFC 6962) so that devices can check that
+/// the marks they are shown are part of the public record and auditors can
+/// check that no entry was ever removed.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct TransparencyLog {
+    entries: Vec<LogEntry>,
+}
+
+impl TransparencyLog {
+    /// Creates an empty log.
+    pub fn new() -> TransparencyLog {
+        TransparencyLog::default()
+    }
+
+    /// Creates a log from entries in the order they were appended.
+    pub fn from_entries(entries: Vec<LogEntry>) -> TransparencyLog {
+        TransparencyLog { entries }
+    }
+
+    /// Returns the number of entries.
+    pub fn len(&self) -> u64 {
+        self.entries.len() as u64
+    }
+
+    /// Returns `true` if nothing was logged yet.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns all entries in the order they were appended.
+    pub fn entries(&self) -> &[LogEntry] {
+        &self.entries
+    }
+
+    /// Appends an entry and returns its index.
+    pub fn append(&mut self, entry: LogEntry) -> u64 {
+        self.entries.push(entry);
+        self.len() - 1
+    }
+
+    /// Returns the head of the tree with the current entries.
+    pub fn head(&self) -> TreeHead {
+        TreeHead {
+            tree_size: self.len(),
+            root_hash: root(&self.leaves(self.len())),
+            timestamp: Utc::now(),
+        }
+    }
+
+    /// Returns the root hash of the tree of the first `tree_size` entries.
+    pub fn root_hash(&self, tree_size: u64) -> Result<TreeHash, TransparencyError> {
+        self.check_size(tree_size)?;
+        Ok(root(&self.leaves(tree_size)))
+    }
+
+    /// Proves that an entry is part of the tree of the first `tree_size`
+    /// entries.
+    pub fn inclusion_proof(
+        &self,
+        index: u64,
+        tree_size: u64,
+    ) -> Result<InclusionProof, TransparencyError> {
+        self.check_size(tree_size)?;
+        if index >= tree_size {
+            return Err(TransparencyError::OutOfRange { size: tree_size });
+        }
+        let mut path = Vec::new();
+        inclusion_path(index as usize, &self.leaves(tree_size), &mut path);
+        Ok(InclusionProof {
+            index,
+            tree_size,
+            path,
+        })
+    }
+
+    /// Returns the entries of a hashed identity within the first
+    /// `tree_size` entries together with their inclusion proofs.
+    pub fn prove_entries(
+        &self,
+        hashed_id: &HashedIdentity,
+        tree_size: u64,
+    ) -> Result<Vec<LogInclusion>, TransparencyError> {
+        self.check_size(tree_size)?;
+        let leaves = self.leaves(tree_size);
+        Ok(self.entries[..tree_size as usize]
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| entry.hashed_id == *hashed_id)
+            .map(|(index, entry)| {
+                let mut path = Vec::new();
+                inclusion_path(index, &leaves, &mut path);
+                LogInclusion {
+                    entry: entry.clone(),
+                    proof: InclusionProof {
+                        index: index as u64,
+                        tree_size,
+                        path,
+                    },
+                }
+            })
+            .collect())
+    }
+
+    /// Proves that the tree of the first `new_size` entries extends the
+    /// tree of the first `old_size` entries.
+    pub fn consistency_proof(
+        &self,
+        old_size: u64,
+        new_size: u64,
+    ) -> Result<ConsistencyProof, TransparencyError> {
+        self.check_size(new_size)?;
+        if old_size > new_size {
+            return Err(TransparencyError::OutOfRange { size: new_size });
+        }
+        let mut path = Vec::new();
+        if old_size > 0 && old_size < new_size {
+            consistency_path(old_size as usize, &self.leaves(new_size), true, &mut path);
+        }
+        Ok(ConsistencyProof {
+            old_size,
+            new_size,
+            path,
+        })
+    }
+
+    fn check_size(&self, tree_size: u64) -> Result<(), TransparencyError> {
+        if tree_size > self.len() {
+            Err(TransparencyError::OutOfRange { size: self.len() })
+        } else {
+            Ok(())
+        }
+    }
+
+    fn leaves(&self, tree_size: u64) -> Vec<TreeHash> {
+        self.entries[..tree_size as usize]
+            .iter()
+            .map(LogEntry::leaf_hash)
+            .collect()
+    }
+}
+
+/// Returns the largest power of two smaller than `n`.
+fn split(n: usize) -> usize {
+    let mut k = 1;
+    while k * 2 < n {
+        k *= 2;
+    }
+    k
+}
+
+fn root(leaves: &[TreeHash]) -> TreeHash {
+    match leaves.len() {
+        0 => TreeHash::empty(),
+        1 => leaves[0],
+        n => {
+            let k = split(n);
+            TreeHash::node(&root(&leaves[..k]), &root(&leaves[k..]))
+        }
+    }
+}
+
+fn inclusion_path(index: usize, leaves: &[TreeHash], path: &mut Vec<TreeHash>) {
+    if leaves.len() <= 1 {
+        return;
+    }
+    let k = split(leaves.len());
+    if index < k {
+        inclusion_path(index, &leaves[..k], path);
+        path.push(root(&leaves[k..]));
+    } else {
+        inclusion_path(index - k, &leaves[k..], path);
+        path.push(root(&leaves[..k]));
+    }
+}
+
+fn consistency_path(m: usize, leaves: &[TreeHash], complete: bool, path: &mut Vec<TreeHash>) {
+    let n = leaves.len();
+    if m == n {
+        if !complete {
+            path.push(root(leaves));
+        }
+        return;
+    }
+    let k = split(n);
+    if m <= k {
+        consistency_path(m, &leaves[..k], complete, path);
+        path.push(root(&leaves[k..]));
+    } else {
+        consistency_path(m - k, &leaves[k..], false, path);
+        path.push(root(&leaves[..k]));
+    }
+}
@@ -0,0 +1,216 @@
+//! Implements the upload bundle sent to the authority after a positive test.
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
+use derive_more::{Display, Error};
+use serde::{de, ser, Deserialize, Serialize};
+use serde_plain::forward_display_to_serde;
+use sha2::{Digest, Sha256};
+use zeroize::Zeroize;
+
+use crate::auth::{Identity, UniqueIdentity};
+use crate::contactlog::ContactLog;
+use crate::crypto::{random_below, seal, unseal, PublicKey, SecretKey, SEAL_OVERHEAD};
+use crate::envelope::{self, ArtifactKind};
+use crate::error::Error;
+use crate::utils::to_json_zeroizing;
+
+/// The maximum size of the plaintext of an upload bundle in bytes.
+pub const MAX_UPLOAD_SIZE: usize = 1024 * 1024;
+
+const CHECKSUM_LEN: usize = 32;
+
+/// Sealed contents are padded to a multiple of this so that the size of a
+/// bundle only gives a rough idea of the number of contacts in it.
+const PADDING_BLOCK: usize = 1024;
+
+/// Error for upload bundles that cannot be created or opened.
+#[derive(Debug, Error, Display, Clone, PartialEq, Eq)]
+pub enum UploadError {
+    /// The bundle exceeds [`MAX_UPLOAD_SIZE`](constant.MAX_UPLOAD_SIZE.html).
+    #[display(fmt = "upload bundle too large ({} bytes, maximum is {})", size, max)]
+    TooLarge { size: usize, max: usize },
+    /// The bundle was not sealed to this authority or was tampered with.
+    #[display(fmt = "cannot decrypt upload bundle")]
+    Decryption,
+    /// The checksum of the contents does not match.
+    #[display(fmt = "upload bundle checksum mismatch")]
+    ChecksumMismatch,
+    /// The contents of the bundle are malformed.
+    #[display(fmt = "malformed upload bundle")]
+    Malformed,
+}
+
+/// The data a user uploads to the authority after testing positive.
+///
+/// The bundle only contains what the authority needs: the unique IDs of the
+/// infected user (so their hashed IDs can be marked as infected) and the
+/// contact log (which only the authority can decode).  Private
+/// [`Identity`](struct.Identity.html) values never leave the device.
+///
+/// A [decoy](#method.decoy) bundle looks the same once sealed but is
+/// discarded by the registry.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct UploadBundle {
+    created: DateTime<Utc>,
+    unique_ids: Vec<UniqueIdentity>,
+    contacts: ContactLog,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    decoy: bool,
+}
+
+impl UploadBundle {
+    /// Creates a new upload bundle from the user's identities and contacts.
+    pub fn new(identities: &[Identity], contacts: &ContactLog) -> UploadBundle {
+        UploadBundle {
+            created: Utc::now(),
+            unique_ids: identities.iter().map(|x| x.unique_id().clone()).collect(),
+            contacts: contacts.clone(),
+            decoy: false,
+        }
+    }
+
+    /// Creates a decoy bundle with random identities and contacts.
+    ///
+    /// Decoys are sent as cover traffic so that real uploads do not stand
+    /// out.  The registry processes them like real uploads but rolls back
+    /// everything they changed.
+    pub fn decoy(public_key: &PublicKey, contacts: usize) -> UploadBundle {
+        let mut log = ContactLog::new();
+        for _ in 0..contacts {
+            log.add(&UniqueIdentity::unique().new_share_id(public_key));
+        }
+        UploadBundle {
+            created: Utc::now(),
+            unique_ids: (0..=random_below(3))
+                .map(|_| UniqueIdentity::unique())
+                .collect(),
+            contacts: log,
+            decoy: true,
+        }
+    }
+
+    /// Returns the timestamp of when the bundle was created.
+    pub fn created(&self) -> DateTime<Utc> {
+        self.created
+    }
+
+    /// Returns the unique IDs of the uploading user.
+    pub fn unique_ids(&self) -> &[UniqueIdentity] {
+        &self.unique_ids
+    }
+
+    /// Returns the contacts of the uploading user.
+    pub fn contacts(&self) -> &ContactLog {
+        &self.contacts
+    }
+
+    /// Returns `true` if this is a decoy bundle.
+    pub fn is_decoy(&self) -> bool {
+        self.decoy
+    }
+
+    /// Seals the bundle to the public key of the authority.
+    ///
+    /// The sealed data carries a SHA-256 checksum of the contents, which are
+    /// padded with whitespace to a multiple of 1 KiB.  Fails if the bundle
+    /// exceeds [`MAX_UPLOAD_SIZE`](constant.MAX_UPLOAD_SIZE.html).  The
+    /// plaintext buffers are zeroized once the bundle is sealed.
+    pub fn seal(&self, public_key: &PublicKey) -> Result<SealedUploadBundle, UploadError> {
+        let body = to_json_zeroizing(self).map_err(|_| UploadError::Malformed)?;
+        let size = body.len();
+        if size > MAX_UPLOAD_SIZE {
+            return Err(UploadError::TooLarge {
+                size,
+                max: MAX_UPLOAD_SIZE,
+            });
+        }
+        // the padded contents are assembled in a buffer of the final size so
+        // that no copies of the plaintext are left behind by reallocation
+        let padding = size.div_ceil(PADDING_BLOCK) * PADDING_BLOCK - size;
+        let mut hasher = Sha256::new();
+        hasher.input(&*body);
+        hasher.input(vec![b' '; padding]);
+        let mut plain = Vec::with_capacity(CHECKSUM_LEN + size + padding);
+        plain.extend_from_slice(&hasher.result());
+        plain.extend_from_slice(&body);
+        plain.resize(plain.len() + padding, b' ');
+        let sealed = seal(&plain, public_key);
+        plain.zeroize();
+        Ok(SealedUploadBundle(sealed))
+    }
+}
+
+/// An upload bundle sealed to the authority.
+///
+/// This is what is transmitted from the device to the authority.
+#[derive(Clone)]
+pub struct SealedUploadBundle(Vec<u8>);
+
+forward_display_to_serde!(SealedUploadBundle);
+
+impl FromStr for SealedUploadBundle {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<SealedUploadBundle, Error> {
+        let (_, bytes) = envelope::parse(ArtifactKind::UploadBundle, s, None)?;
+        Ok(SealedUploadBundle(bytes))
+    }
+}
+
+impl Serialize for SealedUploadBundle {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: ser::Serializer,
+    {
+        envelope::serialize(ArtifactKind::UploadBundle, &self.0, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for SealedUploadBundle {
+    fn deserialize<D>(deserializer: D) -> Result<SealedUploadBundle, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        let (_, bytes) = envelope::deserialize(ArtifactKind::UploadBundle, None, deserializer)?;
+        Ok(SealedUploadBundle(bytes))
+    }
+}
+
+impl SealedUploadBundle {
+    /// Returns the size of the sealed bundle in bytes.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Returns `true` if the sealed bundle is empty.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Opens the bundle with the secret key of the authority.
+    ///
+    /// This enforces the size limit before decrypting and verifies the
+    /// checksum of the contents.
+    pub fn open(&self, secret_key: &SecretKey) -> Result<UploadBundle, UploadError> {
+        let max = MAX_UPLOAD_SIZE + CHECKSUM_LEN + SEAL_OVERHEAD;
+        if self.0.len() > max {
+            return Err(UploadError::TooLarge {
+                size: self.0.len(),
+                max,
+            });
+        }
+        let mut plain = unseal(&self.0, secret_key).ok_or(UploadError::Decryption)?;
+        if plain.len() < CHECKSUM_LEN {
+            return Err(UploadError::Malformed);
+        }
+        let (checksum, body) = plain.split_at(CHECKSUM_LEN);
+        let rv = if Sha256::digest(body).as_slice() != checksum {
+            Err(UploadError::ChecksumMismatch)
+        } else {
+            serde_json::from_slice(body).map_err(|_| UploadError::Malformed)
+        };
+        plain.zeroize();
+        rv
+    }
+}
@@ -1,26 +1,78 @@
 pub mod base64 {
-    use serde::private::de::{Content, ContentDeserializer};
+    use serde::de::value::SeqDeserializer;
     use serde::{de::Deserializer, de::Error, ser::Serializer, Deserialize};
+    use serde_bytes::ByteBuf;
 
+    /// Serializes bytes as base64 in human readable formats and as native
+    /// bytes in binary formats.
     pub fn serialize<T, S>(buffer: &T, serializer: S) -> Result<S::Ok, S::Error>
     where
         T: AsRef<[u8]> + ?Sized,
         S: Serializer,
     {
-        serializer.serialize_str(&base64::encode(buffer.as_ref()))
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&base64::encode(buffer.as_ref()))
+        } else {
+            serializer.serialize_bytes(buffer.as_ref())
+        }
     }
 
+    /// Deserializes bytes written by [`serialize`].
     pub fn deserialize<'de, D, T>(deserializer: D) -> Result<T, D::Error>
     where
         D: Deserializer<'de>,
         T: Deserialize<'de>,
     {
-        let bytes = String::deserialize(deserializer).and_then(|string| {
-            base64::decode(&string).map_err(|err| Error::custom(err.to_string()))
-        })?;
-        // this is stupid but i'm lazy
-        T::deserialize(ContentDeserializer::new(Content::Seq(
-            bytes.into_iter().map(Content::U8).collect(),
-        )))
+        let bytes = if deserializer.is_human_readable() {
+            String::deserialize(deserializer).and_then(|string| {
+                base64::decode(&string).map_err(|err| Error::custom(err.to_string()))
+            })?
+        } else {
+            ByteBuf::deserialize(deserializer)?.into_vec()
+        };
+        T::deserialize(SeqDeserializer::new(bytes.into_iter()))
     }
 }
+
+/// Replaces a file atomically.
+///
+/// The data is first written to a temporary file next to the target which is
+/// then renamed over it, so a crash never leaves a half written file behind.
+pub fn write_atomic(path: &std::path::Path, data: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+    let mut tmp_path = std::ffi::OsString::from(path.as_os_str());
+    tmp_path.push(".tmp");
+    let mut file = std::fs::File::create(&tmp_path)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    drop(file);
+    std::fs::rename(&tmp_path, path)
+}
+
+/// Serializes a value to JSON in a buffer that is zeroized on drop.
+///
+/// The value is serialized twice, first to measure it, so that the buffer
+/// is allocated at its final size and never leaves copies of the secret
+/// behind when it grows.
+pub fn to_json_zeroizing<T: serde::Serialize + ?Sized>(
+    value: &T,
+) -> serde_json::Result<zeroize::Zeroizing<Vec<u8>>> {
+    struct Counter(usize);
+
+    impl std::io::Write for Counter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0 += buf.len();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let mut counter = Counter(0);
+    serde_json::to_writer(&mut counter, value)?;
+    let mut buffer = zeroize::Zeroizing::new(Vec::with_capacity(counter.0));
+    serde_json::to_writer(&mut *buffer, value)?;
+    Ok(buffer)
+}
@@ -0,0 +1,286 @@
+//! Implements encrypted storage of identities and contacts on the device.
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use derive_more::{Display, Error, From};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sodiumoxide::crypto::pwhash::argon2id13;
+use sodiumoxide::crypto::secretbox;
+use zeroize::Zeroizing;
+
+use crate::auth::Identity;
+use crate::contactlog::ContactLog;
+use crate::envelope::{self, ArtifactKind, EnvelopeError};
+use crate::error::Error;
+use crate::utils::{to_json_zeroizing, write_atomic};
+
+const CHECKSUM_LEN: usize = 32;
+const METHOD_DEVICE_KEY: u8 = 1;
+const METHOD_PASSPHRASE: u8 = 2;
+
+/// Error for wallets that cannot be stored or opened.
+#[derive(Debug, Error, Display, From)]
+pub enum WalletError {
+    /// The wallet file cannot be read or written.
+    #[display(fmt = "cannot access wallet: {}", _0)]
+    Io(io::Error),
+    /// The wallet file is not a wallet of a supported version.
+    #[display(fmt = "{}", _0)]
+    Envelope(EnvelopeError),
+    /// The wallet file was damaged.
+    #[display(fmt = "wallet is corrupted")]
+    #[from(ignore)]
+    Corrupted,
+    /// The passphrase or device key does not unlock the wallet.
+    #[display(fmt = "wrong key for wallet")]
+    #[from(ignore)]
+    WrongKey,
+    /// No key could be derived from the passphrase.
+    #[display(fmt = "cannot derive key from passphrase")]
+    #[from(ignore)]
+    KeyDerivation,
+}
+
+/// A key that is kept by the device (for instance in a platform keystore).
+#[derive(Clone)]
+pub struct DeviceKey(secretbox::Key);
+
+impl DeviceKey {
+    /// Creates a new random device key.
+    pub fn unique() -> DeviceKey {
+        DeviceKey(secretbox::gen_key())
+    }
+
+    /// Loads a device key from bytes.
+    pub fn from_slice(bytes: &[u8]) -> Result<DeviceKey, Error> {
+        Error::check_length(secretbox::KEYBYTES, bytes.len())?;
+        Ok(DeviceKey(secretbox::Key::from_slice(bytes).unwrap()))
+    }
+
+    /// Returns the bytes of the device key.
+    pub fn as_bytes(&self) -> &[u8] {
+        &(self.0).0[..]
+    }
+}
+
+/// The key a [`Wallet`](struct.Wallet.html) is encrypted with.
+///
+/// The passphrase is zeroed out when dropped and redacted from debug output.
+#[derive(Clone)]
+pub enum WalletKey {
+    /// A key derived from a passphrase with Argon2id.
+    Passphrase(Zeroizing<String>),
+    /// A key supplied by the device.
+    Device(DeviceKey),
+}
+
+impl fmt::Debug for WalletKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WalletKey::Passphrase(..) => write!(f, "Passphrase(****)"),
+            WalletKey::Device(..) => write!(f, "Device(****)"),
+        }
+    }
+}
+
+impl WalletKey {
+    /// Creates a key that is derived from a passphrase.
+    pub fn from_passphrase<S: Into<String>>(passphrase: S) -> WalletKey {
+        WalletKey::Passphrase(Zeroizing::new(passphrase.into()))
+    }
+
+    fn method(&self) -> u8 {
+        match self {
+            WalletKey::Passphrase(..) => METHOD_PASSPHRASE,
+            WalletKey::Device(..) => METHOD_DEVICE_KEY,
+        }
+    }
+
+    // secretbox keys zero themselves out when dropped
+    fn derive(&self, salt: &argon2id13::Salt) -> Result<secretbox::Key, WalletError> {
+        match self {
+            WalletKey::Passphrase(passphrase) => {
+                let mut key = secretbox::Key([0; secretbox::KEYBYTES]);
+                argon2id13::derive_key(
+                    &mut key.0,
+                    passphrase.as_bytes(),
+                    salt,
+                    argon2id13::OPSLIMIT_INTERACTIVE,
+                    argon2id13::MEMLIMIT_INTERACTIVE,
+                )
+                .map_err(|_| WalletError::KeyDerivation)?;
+                Ok(key)
+            }
+            WalletKey::Device(device_key) => Ok(device_key.0.clone()),
+        }
+    }
+}
+
+/// A secretbox key derived from a [`WalletKey`](enum.WalletKey.html).
+///
+/// Deriving a key from a passphrase is slow on purpose, so data that is
+/// encrypted piece by piece (like the contact journal) derives the key once
+/// and keeps it around.  The header written in front of sealed data records
+/// the method and salt the key was derived with.
+pub(crate) struct SealingKey {
+    method: u8,
+    salt: argon2id13::Salt,
+    key: secretbox::Key,
+}
+
+impl SealingKey {
+    /// Derives a key with a fresh salt.
+    pub(crate) fn generate(key: &WalletKey) -> Result<SealingKey, WalletError> {
+        let salt = argon2id13::gen_salt();
+        Ok(SealingKey {
+            method: key.method(),
+            key: key.derive(&salt)?,
+            salt,
+        })
+    }
+
+    /// Derives the key described by a header and returns the data after it.
+    pub(crate) fn from_header<'a>(
+        key: &WalletKey,
+        bytes: &'a [u8],
+    ) -> Result<(SealingKey, &'a [u8]), WalletError> {
+        let (method, mut rest) = bytes.split_first().ok_or(WalletError::Corrupted)?;
+        if *method != key.method() {
+            return Err(WalletError::WrongKey);
+        }
+        let mut salt = argon2id13::Salt([0; argon2id13::SALTBYTES]);
+        if *method == METHOD_PASSPHRASE {
+            if rest.len() < argon2id13::SALTBYTES {
+                return Err(WalletError::Corrupted);
+            }
+            salt.0.copy_from_slice(&rest[..argon2id13::SALTBYTES]);
+            rest = &rest[argon2id13::SALTBYTES..];
+        }
+        let sealing_key = SealingKey {
+            method: *method,
+            key: key.derive(&salt)?,
+            salt,
+        };
+        Ok((sealing_key, rest))
+    }
+
+    /// Returns the header that describes the key.
+    pub(crate) fn header(&self) -> Vec<u8> {
+        let mut rv = vec![self.method];
+        if self.method == METHOD_PASSPHRASE {
+            rv.extend_from_slice(&self.salt.0[..]);
+        }
+        rv
+    }
+
+    /// Encrypts data with a fresh nonce.
+    pub(crate) fn seal(&self, plaintext: &[u8]) -> Vec<u8> {
+        let nonce = secretbox::gen_nonce();
+        let mut rv = nonce.0.to_vec();
+        rv.extend_from_slice(&secretbox::seal(plaintext, &nonce, &self.key));
+        rv
+    }
+
+    /// Decrypts data sealed with [`seal`](#method.seal).
+    ///
+    /// The plaintext is zeroed out when dropped.
+    pub(crate) fn open(&self, sealed: &[u8]) -> Result<Zeroizing<Vec<u8>>, WalletError> {
+        if sealed.len() < secretbox::NONCEBYTES {
+            return Err(WalletError::Corrupted);
+        }
+        let (nonce, ciphertext) = sealed.split_at(secretbox::NONCEBYTES);
+        let nonce = secretbox::Nonce::from_slice(nonce).ok_or(WalletError::Corrupted)?;
+        secretbox::open(ciphertext, &nonce, &self.key)
+            .map(Zeroizing::new)
+            .map_err(|_| WalletError::WrongKey)
+    }
+}
+
+/// The identities and contacts stored on a device.
+///
+/// Unique IDs let anyone who obtains them "become" the user, so the wallet
+/// is only ever written to disk encrypted with a [`WalletKey`](enum.WalletKey.html).
+/// The file carries a checksum so that a damaged file is reported as
+/// corrupted rather than as a wrong key.
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct Wallet {
+    identities: Vec<Identity>,
+    contacts: ContactLog,
+}
+
+impl Wallet {
+    /// Creates an empty wallet.
+    pub fn new() -> Wallet {
+        Wallet::default()
+    }
+
+    /// Returns the identities in the wallet.
+    pub fn identities(&self) -> &[Identity] {
+        &self.identities
+    }
+
+    /// Returns the identity that was added last.
+    pub fn current_identity(&self) -> Option<&Identity> {
+        self.identities.last()
+    }
+
+    /// Adds an identity to the wallet.
+    pub fn add_identity(&mut self, identity: Identity) {
+        self.identities.push(identity);
+    }
+
+    /// Returns the contact log.
+    pub fn contacts(&self) -> &ContactLog {
+        &self.contacts
+    }
+
+    /// Returns the contact log for adding contacts.
+    pub fn contacts_mut(&mut self) -> &mut ContactLog {
+        &mut self.contacts
+    }
+
+    /// Encrypts the wallet.
+    pub fn seal(&self, key: &WalletKey) -> Result<Vec<u8>, WalletError> {
+        let sealing_key = SealingKey::generate(key)?;
+        let plaintext = to_json_zeroizing(self).map_err(|_| WalletError::Corrupted)?;
+        let mut body = sealing_key.header();
+        body.extend_from_slice(&sealing_key.seal(&plaintext));
+
+        let mut payload = Sha256::digest(&body).to_vec();
+        payload.extend_from_slice(&body);
+        Ok(envelope::wrap(ArtifactKind::Wallet, &payload))
+    }
+
+    /// Decrypts a wallet.
+    pub fn open(bytes: &[u8], key: &WalletKey) -> Result<Wallet, WalletError> {
+        let (_, payload) = envelope::open(ArtifactKind::Wallet, bytes, None)?;
+        if payload.len() < CHECKSUM_LEN + 1 {
+            return Err(WalletError::Corrupted);
+        }
+        let (checksum, body) = payload.split_at(CHECKSUM_LEN);
+        if Sha256::digest(body).as_slice() != checksum {
+            return Err(WalletError::Corrupted);
+        }
+        let (sealing_key, sealed) = SealingKey::from_header(key, body)?;
+        let plaintext = sealing_key.open(sealed)?;
+        serde_json::from_slice(&plaintext).map_err(|_| WalletError::Corrupted)
+    }
+
+    /// Encrypts the wallet and writes it to a file.
+    ///
+    /// The file is replaced atomically: the wallet is first written to a
+    /// temporary file next to it which is then renamed over the old file, so
+    /// a crash never leaves a half written wallet behind.
+    pub fn save<P: AsRef<Path>>(&self, path: P, key: &WalletKey) -> Result<(), WalletError> {
+        write_atomic(path.as_ref(), &self.seal(key)?)?;
+        Ok(())
+    }
+
+    /// Reads and decrypts a wallet from a file.
+    pub fn load<P: AsRef<Path>>(path: P, key: &WalletKey) -> Result<Wallet, WalletError> {
+        Wallet::open(&fs::read(path)?, key)
+    }
+}
@@ -0,0 +1,61 @@
+//! Fixtures shared by the integration tests.
+#![allow(dead_code)]
+
+use chrono::Duration;
+use covidcotra::*;
+
+/// Seals an upload of `uploader` that lists `contact` as its only contact.
+pub fn upload(
+    authority: &Authority,
+    uploader: &Identity,
+    contact: &Identity,
+) -> SealedUploadBundle {
+    let mut log = ContactLog::new();
+    log.add(&contact.new_share_id(authority.public_key()));
+    upload_log(authority, uploader, &log)
+}
+
+/// Seals an upload of `uploader` with the contacts in `log`.
+pub fn upload_log(
+    authority: &Authority,
+    uploader: &Identity,
+    log: &ContactLog,
+) -> SealedUploadBundle {
+    UploadBundle::new(std::slice::from_ref(uploader), log)
+        .seal(authority.public_key())
+        .unwrap()
+}
+
+/// Submits an upload of `infected` with `contact` as its only contact.
+pub fn submit<S: RegistryStore>(
+    authority: &Authority,
+    registry: &mut Registry<S>,
+    infected: &Identity,
+    contact: &Identity,
+) -> UploadId {
+    let code = authority.issue_code(Duration::hours(1)).unwrap();
+    registry
+        .submit(authority, &code, &upload(authority, infected, contact))
+        .unwrap()
+}
+
+/// Submits an upload of `infected` with the contacts in `log`.
+pub fn submit_log<S: RegistryStore>(
+    authority: &Authority,
+    registry: &mut Registry<S>,
+    infected: &Identity,
+    log: &ContactLog,
+) -> UploadId {
+    let code = authority.issue_code(Duration::hours(1)).unwrap();
+    registry
+        .submit(authority, &code, &upload_log(authority, infected, log))
+        .unwrap()
+}
+
+/// Returns the public keys of an authority.
+pub fn keys(authority: &Authority) -> AuthorityKeys {
+    AuthorityKeys {
+        public_key: *authority.public_key(),
+        signing_public_key: *authority.signing_public_key(),
+    }
+}
@@ -0,0 +1,96 @@
+use chrono::Duration;
+use covidcotra::*;
+
+mod common;
+
+use common::upload;
+
+#[test]
+fn test_authority_code() {
+    let authority = Authority::unique();
+    let mut registry = Registry::new();
+    let (infected, contact) = (Identity::unique(), Identity::unique());
+    let bundle = upload(&authority, &infected, &contact);
+
+    let code: AuthorizationCode = authority
+        .issue_code(Duration::hours(1))
+        .unwrap()
+        .to_string()
+        .parse()
+        .unwrap();
+    registry.submit(&authority, &code, &bundle).unwrap();
+    assert!(registry.is_infected(infected.hashed_id()).unwrap());
+    assert!(registry.is_tainted(contact.hashed_id()).unwrap());
+    assert!(registry.is_redeemed(&code.id()).unwrap());
+
+    match registry.submit(&authority, &code, &bundle) {
+        Err(SubmitError::Authorization(AuthorizationError::Reused { .. })) => {}
+        _ => panic!("expected code to be rejected"),
+    }
+}
+
+#[test]
+fn test_lab_code() {
+    let authority = Authority::unique();
+    let lab = Lab::unique();
+    let mut registry = Registry::new();
+    let bundle = upload(&authority, &Identity::unique(), &Identity::unique());
+
+    let code = lab.issue_code(Duration::hours(1)).unwrap();
+    assert_eq!(
+        registry.submit(&authority, &code, &bundle),
+        Err(SubmitError::Authorization(
+            AuthorizationError::UntrustedIssuer
+        ))
+    );
+    assert!(!registry.is_redeemed(&code.id()).unwrap());
+
+    registry.trust_lab(lab.public_key());
+    registry.submit(&authority, &code, &bundle).unwrap();
+}
+
+#[test]
+fn test_rejected_codes() {
+    let authority = Authority::unique();
+    let mut registry = Registry::new();
+    let bundle = upload(&authority, &Identity::unique(), &Identity::unique());
+
+    let code = authority.issue_code(Duration::seconds(-1)).unwrap();
+    assert_eq!(
+        registry.submit(&authority, &code, &bundle),
+        Err(SubmitError::Authorization(AuthorizationError::Expired {
+            expired: code.expires()
+        }))
+    );
+
+    // expiration dates past the representable range are rejected
+    assert_eq!(
+        authority.issue_code(Duration::days(365 * 1_000_000)),
+        Err(AuthorizationError::InvalidValidity)
+    );
+
+    // tamper with the expiration date
+    let mut bytes = base64::decode(
+        authority
+            .issue_code(Duration::hours(1))
+            .unwrap()
+            .to_string(),
+    )
+    .unwrap();
+    bytes[5 + 16 + 8 + 7] ^= 1;
+    let forged: AuthorizationCode = base64::encode(&bytes).parse().unwrap();
+    assert_eq!(
+        registry.submit(&authority, &forged, &bundle),
+        Err(SubmitError::Authorization(AuthorizationError::Forged))
+    );
+
+    // a failed upload does not burn the code
+    let other_authority = Authority::unique();
+    let foreign_bundle = upload(&other_authority, &Identity::unique(), &Identity::unique());
+    let code = authority.issue_code(Duration::hours(1)).unwrap();
+    assert_eq!(
+        registry.submit(&authority, &code, &foreign_bundle),
+        Err(SubmitError::Upload(UploadError::Decryption))
+    );
+    registry.submit(&authority, &code, &bundle).unwrap();
+}
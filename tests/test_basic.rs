@@ -14,3 +14,37 @@ fn test_tracking() {
     assert_eq!(&log[0].0, user_2.unique_id());
     assert_eq!(&log[0].0.hash(), user_2.hashed_id());
 }
+
+#[test]
+fn test_secrets_are_redacted() {
+    let authority = Authority::unique();
+    let identity = Identity::unique();
+
+    let unique_id = serde_json::to_string(identity.unique_id()).unwrap();
+    let unique_id = unique_id.trim_matches('"');
+    assert_eq!(
+        format!("{:?}", identity.unique_id()),
+        "UniqueIdentity(****)"
+    );
+    assert!(!format!("{:?}", identity).contains(unique_id));
+
+    let secret_key = authority.secret_key().to_string();
+    assert_eq!(format!("{:?}", authority.secret_key()), "SecretKey(****)");
+    assert!(!format!("{:?}", authority.secret_key()).contains(&secret_key));
+}
+
+#[test]
+fn test_identity_equality() {
+    let identity = Identity::unique();
+    let other = Identity::unique();
+
+    assert_eq!(identity.unique_id(), &identity.unique_id().clone());
+    assert_ne!(identity.unique_id(), other.unique_id());
+    assert_eq!(identity.hashed_id(), &identity.unique_id().hash());
+    assert_ne!(identity.hashed_id(), other.hashed_id());
+
+    let parsed: HashedIdentity = identity.hashed_id().to_string().parse().unwrap();
+    let ids: std::collections::HashSet<_> = vec![parsed].into_iter().collect();
+    assert!(ids.contains(identity.hashed_id()));
+    assert!(!ids.contains(other.hashed_id()));
+}
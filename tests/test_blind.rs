@@ -0,0 +1,63 @@
+#![cfg(feature = "blind")]
+use covidcotra::*;
+
+#[test]
+fn test_anonymous_upload() {
+    let authority = Authority::unique();
+    let lab = BlindIssuer::unique();
+    let mut registry = Registry::new();
+    registry.trust_blind_issuer(lab.public_key());
+
+    // device asks the lab to sign a blinded token
+    let request = CredentialRequest::new(lab.public_key()).unwrap();
+    let blinded_sig = lab.sign(request.blinded_token()).unwrap();
+    let credential = request.finalize(&blinded_sig).unwrap();
+
+    // the credential travels as JSON with the upload
+    let credential: AnonymousCredential =
+        serde_json::from_str(&serde_json::to_string(&credential).unwrap()).unwrap();
+
+    let infected = Identity::unique();
+    let bundle = UploadBundle::new(std::slice::from_ref(&infected), &ContactLog::new())
+        .seal(authority.public_key())
+        .unwrap();
+    registry
+        .submit_anonymous(&authority, &credential, &bundle)
+        .unwrap();
+    assert!(registry.is_infected(infected.hashed_id()).unwrap());
+
+    match registry.submit_anonymous(&authority, &credential, &bundle) {
+        Err(SubmitError::Authorization(AuthorizationError::Reused { .. })) => {}
+        _ => panic!("expected credential to be rejected"),
+    }
+}
+
+#[test]
+fn test_untrusted_issuer() {
+    let authority = Authority::unique();
+    let lab = BlindIssuer::unique();
+    let mut registry = Registry::new();
+
+    let request = CredentialRequest::new(lab.public_key()).unwrap();
+    let blinded_sig = lab.sign(request.blinded_token()).unwrap();
+    let credential = request.finalize(&blinded_sig).unwrap();
+
+    let bundle = UploadBundle::new(&[], &ContactLog::new())
+        .seal(authority.public_key())
+        .unwrap();
+    assert_eq!(
+        registry.submit_anonymous(&authority, &credential, &bundle),
+        Err(SubmitError::Authorization(AuthorizationError::Forged))
+    );
+
+    // a signature from another issuer does not unblind
+    let other_lab = BlindIssuer::unique();
+    let request = CredentialRequest::new(lab.public_key()).unwrap();
+    let wrong_sig = other_lab.sign(request.blinded_token());
+    assert!(wrong_sig.map_or(true, |sig| request.finalize(&sig).is_err()));
+
+    // issuers survive a serialization roundtrip
+    let restored: BlindIssuer =
+        serde_json::from_str(&serde_json::to_string(&lab).unwrap()).unwrap();
+    assert_eq!(restored.public_key(), lab.public_key());
+}
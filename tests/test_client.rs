@@ -0,0 +1,345 @@
+use std::io;
+
+use chrono::Duration;
+use covidcotra::*;
+
+mod common;
+
+use common::{keys, submit};
+
+fn client() -> Client<MockTransport> {
+    let mut client = Client::new(MockTransport::new());
+    client.set_retry_delay(std::time::Duration::from_millis(0));
+    client
+}
+
+#[test]
+fn test_client_taint_list() {
+    let authority = Authority::unique();
+    let mut registry = Registry::new();
+    let (infected_1, contact_1) = (Identity::unique(), Identity::unique());
+    submit(&authority, &mut registry, &infected_1, &contact_1);
+
+    let mut client = client();
+    let transport = client.transport_mut();
+    transport.push_error(io::ErrorKind::ConnectionReset);
+    transport.push_response(503, b"unavailable".to_vec());
+    let full = registry.taint_list().unwrap();
+    transport.push_json(&authority.sign_taint_list(&full, &TaintListScope::Full));
+    // the keys are fetched when the first list is verified
+    transport.push_json(&keys(&authority));
+    let mut list = client.fetch_taint_list().unwrap();
+    assert_eq!(list.len(), 2);
+    assert_eq!(client.transport().requests().len(), 4);
+
+    let (infected_2, contact_2) = (Identity::unique(), Identity::unique());
+    submit(&authority, &mut registry, &infected_2, &contact_2);
+    let since = list.generated();
+    let delta = registry.taint_delta(since).unwrap();
+    for offset in 0..2 {
+        client
+            .transport_mut()
+            .push_json(&authority.sign_taint_list_page(&delta, since, offset, 1));
+    }
+    client.update_taint_list(&mut list).unwrap();
+    assert_eq!(list.len(), 4);
+    assert_eq!(list.generated(), delta.generated());
+    match check_status(vec![contact_2.hashed_id()], &list).unwrap() {
+        ExposureStatus::Exposed { .. } => {}
+        status => panic!("unexpected status {:?}", status),
+    }
+    let requests = client.transport().requests();
+    assert!(requests[5].path.contains("&offset=1&generated="));
+}
+
+#[test]
+fn test_client_check_status() {
+    let authority = Authority::unique();
+    let mut registry = Registry::new();
+    let (infected, contact) = (Identity::unique(), Identity::unique());
+    submit(&authority, &mut registry, &infected, &contact);
+
+    let mut client = Client::with_keys(MockTransport::new(), keys(&authority));
+    let prefix = encode_prefix(contact.hashed_id(), DEFAULT_PREFIX_LEN);
+    let matches = registry
+        .taint_list()
+        .unwrap()
+        .with_prefix(&contact.hashed_id().as_bytes()[..DEFAULT_PREFIX_LEN]);
+    client
+        .transport_mut()
+        .push_json(&authority.sign_taint_list(&matches, &TaintListScope::Prefix(prefix.clone())));
+    match client.check_status(vec![contact.hashed_id()]).unwrap() {
+        ExposureStatus::Exposed { .. } => {}
+        status => panic!("unexpected status {:?}", status),
+    }
+    assert_eq!(
+        client.transport().requests()[0].path,
+        format!("/status/{}", prefix)
+    );
+}
+
+#[test]
+fn test_client_rejects_forged_lists() {
+    let authority = Authority::unique();
+    let list = Registry::new().taint_list().unwrap();
+
+    let mut client = Client::with_keys(MockTransport::new(), keys(&authority));
+    let forger = Authority::unique();
+    client
+        .transport_mut()
+        .push_json(&forger.sign_taint_list(&list, &TaintListScope::Full));
+    match client.fetch_taint_list() {
+        Err(ClientError::InvalidSignature) => {}
+        rv => panic!("unexpected result {:?}", rv),
+    }
+
+    // a status response cannot be passed off as the full list
+    client
+        .transport_mut()
+        .push_json(&authority.sign_taint_list(&list, &TaintListScope::Prefix("00".into())));
+    match client.fetch_taint_list() {
+        Err(ClientError::InvalidSignature) => {}
+        rv => panic!("unexpected result {:?}", rv),
+    }
+}
+
+#[test]
+fn test_client_rejects_replayed_and_truncated_lists() {
+    let authority = Authority::unique();
+    let mut registry = Registry::new();
+    let old = registry.taint_list().unwrap();
+    submit(
+        &authority,
+        &mut registry,
+        &Identity::unique(),
+        &Identity::unique(),
+    );
+    let current = registry.taint_list().unwrap();
+
+    let mut client = Client::with_keys(MockTransport::new(), keys(&authority));
+    client
+        .transport_mut()
+        .push_json(&authority.sign_taint_list(&current, &TaintListScope::Full));
+    let mut list = client.fetch_taint_list().unwrap();
+    assert_eq!(client.last_accepted(), Some(current.generated()));
+
+    // a list from before the last accepted one is rejected
+    client
+        .transport_mut()
+        .push_json(&authority.sign_taint_list(&old, &TaintListScope::Full));
+    match client.fetch_taint_list() {
+        Err(ClientError::OutdatedTaintList { generated }) => {
+            assert_eq!(generated, old.generated())
+        }
+        rv => panic!("unexpected result {:?}", rv),
+    }
+
+    // the server cannot stop paging before the signed total is reached
+    submit(
+        &authority,
+        &mut registry,
+        &Identity::unique(),
+        &Identity::unique(),
+    );
+    let since = list.generated();
+    let delta = registry.taint_delta(since).unwrap();
+    let mut page = authority.sign_taint_list_page(&delta, since, 0, 1);
+    assert_eq!(page.list.total(), 2);
+    page.next_offset = None;
+    client.transport_mut().push_json(&page);
+    match client.update_taint_list(&mut list) {
+        Err(ClientError::IncompleteTaintList) => {}
+        rv => panic!("unexpected result {:?}", rv),
+    }
+    assert_eq!(list.len(), 2);
+
+    // all pages have to come from the same delta
+    let first = authority.sign_taint_list_page(&delta, since, 0, 1);
+    let later = registry.taint_delta(since).unwrap();
+    client.transport_mut().push_json(&first);
+    client
+        .transport_mut()
+        .push_json(&authority.sign_taint_list_page(&later, since, 1, 1));
+    match client.update_taint_list(&mut list) {
+        Err(ClientError::IncompleteTaintList) => {}
+        rv => panic!("unexpected result {:?}", rv),
+    }
+    assert_eq!(list.len(), 2);
+}
+
+#[test]
+fn test_client_errors() {
+    let authority = Authority::unique();
+    let mut client = client();
+    client.set_max_retries(1);
+    client.transport_mut().push_error(io::ErrorKind::TimedOut);
+    client.transport_mut().push_error(io::ErrorKind::TimedOut);
+    match client.public_key() {
+        Err(ClientError::Transport(err)) => assert_eq!(err.kind(), io::ErrorKind::TimedOut),
+        rv => panic!("unexpected result {:?}", rv),
+    }
+    assert_eq!(client.transport().requests().len(), 2);
+
+    // uploads are not retried and report the error of the server
+    let mut client = Client::with_keys(MockTransport::new(), keys(&authority));
+    client.transport_mut().push_response(
+        403,
+        serde_json::to_vec(&ErrorResponse {
+            error: "authorization code expired".into(),
+        })
+        .unwrap(),
+    );
+    let bundle = UploadBundle::new(&[Identity::unique()], &ContactLog::new())
+        .seal(authority.public_key())
+        .unwrap();
+    let request = UploadRequest {
+        bundle,
+        code: Some(authority.issue_code(Duration::hours(1)).unwrap()),
+        #[cfg(feature = "blind")]
+        credential: None,
+    };
+    match client.upload(&request) {
+        Err(ClientError::Status { status, message }) => {
+            assert_eq!(status, 403);
+            assert_eq!(message, "authorization code expired");
+        }
+        rv => panic!("unexpected result {:?}", rv),
+    }
+    assert_eq!(client.transport().requests().len(), 1);
+
+    assert!(HttpTransport::new("https://example.com").is_err());
+    assert!(HttpTransport::new("http://").is_err());
+}
+
+#[cfg(feature = "server")]
+#[test]
+fn test_client_http() {
+    let authority = Authority::unique();
+    let mut server = Server::new(authority, Registry::new());
+    server.set_page_size(1);
+    let code = server.authority().issue_code(Duration::hours(1)).unwrap();
+    let sink = MemorySink::new();
+    server.registry_mut().set_notification_sink(sink.clone());
+    let server = server.bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", server.local_addr().unwrap());
+    std::thread::spawn(move || server.run());
+
+    let mut client = Client::new(HttpTransport::new(&url).unwrap());
+    let public_key = client.public_key().unwrap();
+    let mut list = client.fetch_taint_list().unwrap();
+    assert!(list.is_empty());
+
+    let (infected, contact) = (Identity::unique(), Identity::unique());
+    let mut log = ContactLog::new();
+    log.add(&contact.new_share_id(&public_key));
+    client.subscribe(vec![contact.hashed_id()]).unwrap();
+    let mut request = UploadRequest {
+        bundle: UploadBundle::new(std::slice::from_ref(&infected), &log)
+            .seal(&public_key)
+            .unwrap(),
+        code: None,
+        #[cfg(feature = "blind")]
+        credential: None,
+    };
+    match client.upload(&request) {
+        Err(ClientError::Status { status: 403, .. }) => {}
+        rv => panic!("unexpected result {:?}", rv),
+    }
+    request.code = Some(code);
+    client.upload(&request).unwrap();
+    let notifications = sink.take();
+    assert_eq!(notifications.len(), 1);
+    assert_eq!(
+        notifications[0].token,
+        contact.hashed_id().subscription_token()
+    );
+    client.unsubscribe(contact.hashed_id()).unwrap();
+
+    client.update_taint_list(&mut list).unwrap();
+    assert_eq!(list.len(), 2);
+    match client.check_status(vec![contact.hashed_id()]).unwrap() {
+        ExposureStatus::Exposed { .. } => {}
+        status => panic!("unexpected status {:?}", status),
+    }
+}
+
+#[test]
+fn test_client_cover_traffic() {
+    let authority = Authority::unique();
+    let mut registry = Registry::new();
+    let (infected, contact) = (Identity::unique(), Identity::unique());
+    submit(&authority, &mut registry, &infected, &contact);
+    let list = registry.taint_list().unwrap();
+
+    let mut transport = MockTransport::new();
+    let signer: Authority =
+        serde_json::from_str(&serde_json::to_string(&authority).unwrap()).unwrap();
+    transport.set_handler(move |request| {
+        let prefix = request.path.trim_start_matches("/status/").to_string();
+        let bytes: Vec<u8> = (0..prefix.len())
+            .step_by(2)
+            .map(|idx| u8::from_str_radix(&prefix[idx..idx + 2], 16).unwrap())
+            .collect();
+        let signed =
+            signer.sign_taint_list(&list.with_prefix(&bytes), &TaintListScope::Prefix(prefix));
+        Response {
+            status: 200,
+            body: serde_json::to_vec(&signed).unwrap(),
+        }
+    });
+    let mut client = Client::with_keys(transport, keys(&authority));
+    client.set_cover(Some(CoverConfig {
+        min_status_queries: 6,
+        max_extra_queries: 0,
+        ..CoverConfig::default()
+    }));
+    match client.check_status(vec![contact.hashed_id()]).unwrap() {
+        ExposureStatus::Exposed { .. } => {}
+        status => panic!("unexpected status {:?}", status),
+    }
+    let prefix = format!(
+        "/status/{}",
+        encode_prefix(contact.hashed_id(), DEFAULT_PREFIX_LEN)
+    );
+    let requests = client.transport().requests();
+    assert_eq!(requests.len(), 6);
+    assert!(requests.iter().any(|request| request.path == prefix));
+
+    // decoys repeat between checks just like the real prefixes
+    client.check_status(vec![contact.hashed_id()]).unwrap();
+    let mut paths: Vec<_> = client
+        .transport()
+        .requests()
+        .iter()
+        .map(|request| request.path.clone())
+        .collect();
+    let mut second = paths.split_off(6);
+    paths.sort();
+    second.sort();
+    assert_eq!(paths, second);
+    assert!(format!("{:?}", client.transport()).contains("MockHandler"));
+
+    // no decoy upload is due right after enabling cover traffic
+    assert!(client.next_decoy_upload().unwrap() > chrono::Utc::now());
+    assert!(!client.send_cover_traffic().unwrap());
+
+    client
+        .transport_mut()
+        .push_json(&serde_json::json!({ "upload_id": "6f3ed2a4-8b9e-4a4c-9b62-0c2d3d2f3b8a" }));
+    client.send_decoy_upload().unwrap();
+    let request = client.transport().requests().last().unwrap();
+    let request: UploadRequest = serde_json::from_slice(&request.body).unwrap();
+    let bundle = request.bundle.open(authority.secret_key()).unwrap();
+    assert!(bundle.is_decoy());
+    // the code names the authority like a real one but does not verify
+    let code = request.code.as_ref().unwrap();
+    assert_eq!(code.issuer(), authority.signing_public_key());
+    assert_eq!(
+        code.verify(&[*authority.signing_public_key()]),
+        Err(AuthorizationError::Forged)
+    );
+    assert!(registry
+        .submit(&authority, request.code.as_ref().unwrap(), &request.bundle)
+        .is_ok());
+    assert_eq!(registry.store().uploads().unwrap().len(), 1);
+}
@@ -0,0 +1,158 @@
+use chrono::{Duration, Utc};
+use covidcotra::*;
+
+fn descriptor(authority: &Authority) -> AuthorityDescriptor {
+    let mut descriptor =
+        AuthorityDescriptor::new("Ministry of Health", "AT", authority, Duration::days(30));
+    descriptor
+        .endpoints
+        .push("https://tracing.example.com".into());
+    descriptor
+}
+
+#[test]
+fn test_descriptor_verify() {
+    let (root, authority) = (RootKey::unique(), Authority::unique());
+    let signed = root.sign(&descriptor(&authority));
+    let now = Utc::now();
+
+    let verified = signed.verify(root.public_key(), now).unwrap();
+    assert_eq!(verified.name, "Ministry of Health");
+    assert_eq!(verified.region, "AT");
+    assert_eq!(verified.endpoints, vec!["https://tracing.example.com"]);
+    assert_eq!(verified.public_key_at(now).authority_id(), authority.id());
+    assert!(verified.is_valid_at(now));
+
+    let restored: SignedDescriptor =
+        serde_json::from_str(&serde_json::to_string(&signed).unwrap()).unwrap();
+    assert!(restored.verify(root.public_key(), now).is_ok());
+
+    assert_eq!(
+        signed
+            .verify(RootKey::unique().public_key(), now)
+            .unwrap_err(),
+        DescriptorError::InvalidSignature
+    );
+    assert!(matches!(
+        signed.verify(root.public_key(), now + Duration::days(31)),
+        Err(DescriptorError::Expired { .. })
+    ));
+    assert!(matches!(
+        signed.verify(root.public_key(), now - Duration::days(1)),
+        Err(DescriptorError::NotYetValid { .. })
+    ));
+
+    // tampering with the payload breaks the signature
+    let mut json: serde_json::Value = serde_json::to_value(&signed).unwrap();
+    let payload = json["payload"].as_str().unwrap().replace("AT", "DE");
+    json["payload"] = payload.into();
+    let tampered: SignedDescriptor = serde_json::from_value(json).unwrap();
+    assert_eq!(
+        tampered.verify(root.public_key(), now).unwrap_err(),
+        DescriptorError::InvalidSignature
+    );
+}
+
+#[test]
+fn test_descriptor_rollover() {
+    let (root, old, new) = (RootKey::unique(), Authority::unique(), Authority::unique());
+    let mut descriptor = descriptor(&old);
+    let now = Utc::now();
+    let switch = now + Duration::days(7);
+    descriptor.next = Some(KeyRollover {
+        at: switch,
+        keys: AuthorityKeys {
+            public_key: *new.public_key(),
+            signing_public_key: *new.signing_public_key(),
+        },
+    });
+    let verified = root
+        .sign(&descriptor)
+        .verify(root.public_key(), now)
+        .unwrap();
+
+    assert_eq!(verified.public_key_at(now).authority_id(), old.id());
+    assert_eq!(verified.public_key_at(switch).authority_id(), new.id());
+    assert_eq!(
+        verified.keys_at(switch).signing_public_key,
+        *new.signing_public_key()
+    );
+
+    // share IDs created after the switch are sealed to the new key
+    let share_id = Identity::unique().new_share_id(verified.public_key_at(switch));
+    assert_eq!(share_id.authority_id(), Some(&new.id()));
+    assert!(share_id.reveal(new.secret_key()).is_ok());
+}
+
+#[test]
+fn test_descriptor_cache() {
+    let (root, authority) = (RootKey::unique(), Authority::unique());
+    let mut older = descriptor(&authority);
+    older.issued -= Duration::hours(1);
+    let older = root.sign(&older);
+    let newer = root.sign(&descriptor(&authority));
+    let now = Utc::now();
+
+    let mut cache = DescriptorCache::new(root.public_key());
+    assert!(cache.current(now).is_none());
+    assert_eq!(cache.root(), root.public_key());
+    cache.update(&newer, now).unwrap();
+    assert!(cache.current(now).is_some());
+
+    // older descriptors are refused and leave the cache alone
+    assert!(matches!(
+        cache.update(&older, now),
+        Err(DescriptorError::Outdated { .. })
+    ));
+    let forged = RootKey::unique().sign(&descriptor(&Authority::unique()));
+    assert_eq!(
+        cache.update(&forged, now).unwrap_err(),
+        DescriptorError::InvalidSignature
+    );
+    assert_eq!(
+        cache
+            .current(now)
+            .unwrap()
+            .public_key_at(now)
+            .authority_id(),
+        authority.id()
+    );
+
+    let restored: DescriptorCache =
+        serde_json::from_str(&serde_json::to_string(&cache).unwrap()).unwrap();
+    assert!(restored.current(now).is_some());
+    assert!(restored.current(now + Duration::days(31)).is_none());
+}
+
+#[test]
+fn test_client_descriptor() {
+    let (root, authority) = (RootKey::unique(), Authority::unique());
+    let mut cache = DescriptorCache::new(root.public_key());
+    let mut client = Client::new(MockTransport::new());
+    client.set_max_retries(0);
+
+    client
+        .transport_mut()
+        .push_json(&root.sign(&descriptor(&authority)));
+    let verified = client.update_descriptor(&mut cache).unwrap();
+    assert_eq!(verified.region, "AT");
+    assert_eq!(client.public_key().unwrap().authority_id(), authority.id());
+    assert_eq!(client.transport().requests().len(), 1);
+
+    // a forged descriptor falls back to the cached one
+    client
+        .transport_mut()
+        .push_json(&RootKey::unique().sign(&descriptor(&Authority::unique())));
+    client.update_descriptor(&mut cache).unwrap();
+    assert_eq!(client.public_key().unwrap().authority_id(), authority.id());
+
+    // without a cached descriptor the error is reported
+    let mut empty = DescriptorCache::new(root.public_key());
+    client
+        .transport_mut()
+        .push_json(&RootKey::unique().sign(&descriptor(&authority)));
+    match client.update_descriptor(&mut empty) {
+        Err(ClientError::Descriptor(DescriptorError::InvalidSignature)) => {}
+        rv => panic!("unexpected result {:?}", rv.map(|x| x.name)),
+    }
+}
@@ -0,0 +1,86 @@
+#![cfg(feature = "dp3t")]
+use chrono::{Duration, NaiveDate, TimeZone, Utc};
+use covidcotra::*;
+
+fn day(d: u32) -> NaiveDate {
+    NaiveDate::from_ymd_opt(2020, 4, d).unwrap()
+}
+
+#[test]
+fn test_day_key_chain() {
+    let mut chain = DayKeyChain::new(day(1));
+    let first = chain.day_key(day(1)).unwrap().clone();
+    assert_eq!(chain.day_key(day(3)).unwrap(), &first.next().next());
+    assert_eq!(first.ephemeral_ids().len(), EPOCHS_PER_DAY);
+    assert_eq!(format!("{:?}", first), "DayKey(****)");
+
+    let at = Utc.with_ymd_and_hms(2020, 4, 3, 10, 20, 0).unwrap();
+    assert_eq!(epoch_of(at), (day(3), 41));
+    assert_eq!(
+        chain.ephemeral_id(at).unwrap(),
+        first.next().next().ephemeral_ids()[41]
+    );
+
+    // old keys are dropped once past the retention period
+    chain.day_key(day(20)).unwrap();
+    assert!(chain.publish(day(1)).is_none());
+    assert!(chain.day_key(day(1)).is_none());
+    assert!(chain.publish(day(6)).is_some());
+
+    let json = serde_json::to_string(&chain).unwrap();
+    let mut restored: DayKeyChain = serde_json::from_str(&json).unwrap();
+    assert_eq!(restored.day_key(day(20)), chain.day_key(day(20)));
+}
+
+#[test]
+fn test_matching() {
+    let mut infected = DayKeyChain::new(day(1));
+    let mut bystander = DayKeyChain::new(day(1));
+    let mut log = EphemeralContactLog::new();
+
+    let contact_at = Utc.with_ymd_and_hms(2020, 4, 4, 12, 0, 0).unwrap();
+    log.add(infected.ephemeral_id(contact_at).unwrap(), contact_at);
+    let other_at = contact_at + Duration::hours(2);
+    log.add(bystander.ephemeral_id(other_at).unwrap(), other_at);
+
+    // a replay on a later day does not count
+    let replayed_at = contact_at + Duration::days(1);
+    log.add(infected.ephemeral_id(contact_at).unwrap(), replayed_at);
+    // the epoch within the day does not matter and clocks may be off across
+    // midnight
+    let shuffled_at = Utc.with_ymd_and_hms(2020, 4, 4, 23, 50, 0).unwrap();
+    log.add(infected.ephemeral_id(contact_at).unwrap(), shuffled_at);
+    let late_at = Utc.with_ymd_and_hms(2020, 4, 5, 0, 10, 0).unwrap();
+    log.add(infected.ephemeral_id(contact_at).unwrap(), late_at);
+    assert_eq!(log.len(), 5);
+
+    let published = infected.publish(day(2)).unwrap();
+    let published: PublishedDayKey =
+        serde_json::from_str(&serde_json::to_string(&published).unwrap()).unwrap();
+    infected.rotate(day(5));
+    assert!(infected.publish(day(2)).is_none());
+
+    assert_eq!(
+        log.match_published(std::slice::from_ref(&published)),
+        vec![contact_at, shuffled_at, late_at]
+    );
+    assert_eq!(
+        log.exposure_status(&[published]),
+        ExposureStatus::Exposed {
+            at: late_at,
+            risk: 1.0,
+            degree: 1
+        }
+    );
+
+    // keys published for a later day do not match earlier contacts
+    let later = DayKeyChain::new(day(1)).publish(day(1)).unwrap();
+    let too_late = infected.publish(day(5)).unwrap();
+    assert_eq!(
+        log.exposure_status(&[later, too_late]),
+        ExposureStatus::Clear
+    );
+
+    log.prune(replayed_at + Duration::days(RETENTION_DAYS + 1));
+    assert!(log.is_empty());
+}
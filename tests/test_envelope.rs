@@ -0,0 +1,113 @@
+use covidcotra::*;
+
+#[test]
+fn test_roundtrip() {
+    let authority = Authority::unique();
+    let identity = Identity::unique();
+    let share_id = identity.new_share_id(authority.public_key());
+
+    let encoded = share_id.to_string();
+    assert_eq!(&base64::decode(&encoded).unwrap()[..3], &MAGIC[..]);
+    assert!(encoded.parse::<ShareIdentity>().unwrap() == share_id);
+
+    let hashed_id: HashedIdentity = identity.hashed_id().to_string().parse().unwrap();
+    assert_eq!(&hashed_id, identity.hashed_id());
+
+    let mut log = ContactLog::new();
+    log.add(&share_id);
+    let json = serde_json::to_value(&log).unwrap();
+    assert_eq!(json["magic"], MAGIC_STR);
+    assert_eq!(json["version"], 1);
+    assert_eq!(json["type"], "contact_log");
+    let log: ContactLog = serde_json::from_value(json).unwrap();
+    let decoded = log.decode(authority.secret_key()).unwrap();
+    assert_eq!(&decoded[0].0, identity.unique_id());
+
+    let json = serde_json::to_string(&authority).unwrap();
+    let restored: Authority = serde_json::from_str(&json).unwrap();
+    assert_eq!(
+        restored.public_key().to_string(),
+        authority.public_key().to_string()
+    );
+}
+
+#[test]
+fn test_legacy_migration() {
+    let authority = Authority::unique();
+    let identity = Identity::unique();
+    let share_id = identity.new_share_id(authority.public_key());
+
+    // strip the envelope header to simulate artifacts from before versioning
+    let strip = |s: String| base64::encode(&base64::decode(s).unwrap()[5..]);
+
+    let legacy_hashed: HashedIdentity = strip(identity.hashed_id().to_string()).parse().unwrap();
+    assert_eq!(&legacy_hashed, identity.hashed_id());
+
+    let legacy_key: PublicKey = strip(authority.public_key().to_string()).parse().unwrap();
+    assert_eq!(legacy_key.to_string(), authority.public_key().to_string());
+
+    // legacy share IDs also lack the authority ID
+    let legacy_share = base64::encode(&base64::decode(share_id.to_string()).unwrap()[13..]);
+    let log: ContactLog = serde_json::from_value(serde_json::json!({
+        "seen": { legacy_share: "2020-04-10T12:00:00Z" }
+    }))
+    .unwrap();
+    let decoded = log.decode(authority.secret_key()).unwrap();
+    assert_eq!(&decoded[0].0, identity.unique_id());
+
+    let mut json = serde_json::to_value(&authority).unwrap();
+    let obj = json.as_object_mut().unwrap();
+    obj.remove("magic");
+    obj.remove("version");
+    obj.remove("type");
+    let restored: Authority = serde_json::from_value(json).unwrap();
+    assert_eq!(
+        restored.public_key().to_string(),
+        authority.public_key().to_string()
+    );
+}
+
+#[test]
+fn test_rejects_unknown_versions() {
+    let identity = Identity::unique();
+
+    let mut bytes = base64::decode(identity.hashed_id().to_string()).unwrap();
+    bytes[3] = 42;
+    let err = base64::encode(&bytes).parse::<HashedIdentity>();
+    assert!(err.is_err());
+
+    let err = serde_json::from_value::<ContactLog>(serde_json::json!({
+        "magic": MAGIC_STR,
+        "version": 42,
+        "type": "contact_log",
+        "seen": {}
+    }))
+    .err()
+    .unwrap();
+    assert_eq!(
+        err.to_string(),
+        "unsupported contact log version 42 (supported up to 1)"
+    );
+
+    let err = serde_json::from_value::<ContactLog>(serde_json::json!({
+        "magic": MAGIC_STR,
+        "version": 1,
+        "type": "authority",
+        "seen": {}
+    }))
+    .err()
+    .unwrap();
+    assert_eq!(err.to_string(), "expected contact log but found authority");
+
+    // a hashed identity is not a public key
+    let err = identity
+        .hashed_id()
+        .to_string()
+        .parse::<PublicKey>()
+        .err()
+        .unwrap();
+    assert_eq!(
+        err.to_string(),
+        "expected public key but found hashed identity"
+    );
+}
@@ -0,0 +1,84 @@
+use chrono::Duration;
+use covidcotra::*;
+
+#[test]
+fn test_parse_errors() {
+    let authority = Authority::unique();
+    let identity = Identity::unique();
+
+    match "not base64!".parse::<HashedIdentity>() {
+        Err(Error::Base64(_)) => {}
+        rv => panic!("unexpected result {:?}", rv),
+    }
+
+    let mut bytes = base64::decode(identity.hashed_id().to_string()).unwrap();
+    bytes.pop();
+    match base64::encode(&bytes).parse::<HashedIdentity>() {
+        Err(Error::InvalidLength {
+            expected: 32,
+            found: 31,
+        }) => {}
+        rv => panic!("unexpected result {:?}", rv),
+    }
+
+    let mut bytes = base64::decode(identity.hashed_id().to_string()).unwrap();
+    bytes[3] = 42;
+    match base64::encode(&bytes).parse::<HashedIdentity>() {
+        Err(Error::Envelope(EnvelopeError::UnsupportedVersion { version: 42, .. })) => {}
+        rv => panic!("unexpected result {:?}", rv),
+    }
+
+    match "not an id".parse::<UploadId>() {
+        Err(Error::InvalidUuid(_)) => {}
+        rv => panic!("unexpected result {:?}", rv),
+    }
+
+    match authority
+        .public_key()
+        .to_string()
+        .parse::<SigningPublicKey>()
+    {
+        Err(Error::Envelope(EnvelopeError::WrongKind { .. })) => {}
+        rv => panic!("unexpected result {:?}", rv),
+    }
+
+    assert!(DeviceKey::from_slice(b"too short").is_err());
+}
+
+#[test]
+fn test_decryption_errors() {
+    let authority = Authority::unique();
+    let other = Authority::unique();
+    let share_id = Identity::unique().new_share_id(authority.public_key());
+
+    match share_id.reveal(other.secret_key()) {
+        Err(Error::Decryption) => {}
+        rv => panic!("unexpected result {:?}", rv),
+    }
+
+    let mut log = ContactLog::new();
+    log.add(&share_id);
+    assert!(log.decode(authority.secret_key()).is_ok());
+    match log.decode(other.secret_key()) {
+        Err(Error::Decryption) => {}
+        rv => panic!("unexpected result {:?}", rv),
+    }
+}
+
+#[test]
+fn test_converts_into_error() {
+    fn submit_twice() -> Result<UploadId, Error> {
+        let authority = Authority::unique();
+        let mut registry = Registry::new();
+        let code = authority.issue_code(Duration::hours(1)).unwrap();
+        let bundle = UploadBundle::new(&[Identity::unique()], &ContactLog::new())
+            .seal(authority.public_key())?;
+        registry.submit(&authority, &code, &bundle)?;
+        Ok(registry.submit(&authority, &code, &bundle)?)
+    }
+
+    match submit_twice() {
+        Err(Error::Submit(SubmitError::Authorization(AuthorizationError::Reused { .. }))) => {}
+        rv => panic!("unexpected result {:?}", rv),
+    }
+}
@@ -0,0 +1,166 @@
+use chrono::Duration;
+use covidcotra::*;
+
+mod common;
+
+use common::{keys, submit_log};
+
+#[test]
+fn test_tagged_share_ids() {
+    let authority = Authority::unique();
+    let share_id = Identity::unique().new_share_id(authority.public_key());
+    assert_eq!(share_id.authority_id(), Some(&authority.id()));
+    assert_eq!(authority.public_key().authority_id(), authority.id());
+    assert_ne!(authority.id(), Authority::unique().id());
+
+    let restored: ShareIdentity = share_id.to_string().parse().unwrap();
+    assert!(restored == share_id);
+    assert_eq!(restored.authority_id(), Some(&authority.id()));
+
+    // version 1 share IDs carry no authority ID
+    let mut bytes = base64::decode(share_id.to_string()).unwrap();
+    bytes[3] = 1;
+    bytes.drain(5..13);
+    let untagged: ShareIdentity = base64::encode(&bytes).parse().unwrap();
+    assert_eq!(untagged.authority_id(), None);
+    assert!(untagged.reveal(authority.secret_key()).is_ok());
+    let restored: ShareIdentity = untagged.to_string().parse().unwrap();
+    assert!(restored == untagged);
+
+    let encoded = authority.id().to_string();
+    assert_eq!(encoded.len(), 16);
+    assert_eq!(encoded.parse::<AuthorityId>().unwrap(), authority.id());
+    assert!("not an authority".parse::<AuthorityId>().is_err());
+    assert!("zzzzzzzzzzzzzzzz".parse::<AuthorityId>().is_err());
+}
+
+#[test]
+fn test_partition() {
+    let (home, abroad) = (Authority::unique(), Authority::unique());
+    let mut log = ContactLog::new();
+    log.add(&Identity::unique().new_share_id(home.public_key()));
+    log.add(&Identity::unique().new_share_id(abroad.public_key()));
+    log.add(&Identity::unique().new_share_id(abroad.public_key()));
+    assert!(log.decode(home.secret_key()).is_err());
+
+    let (own, others) = log.partition(&home.id());
+    assert_eq!(own.len(), 1);
+    assert_eq!(own.decode(home.secret_key()).unwrap().len(), 1);
+    assert_eq!(others.len(), 1);
+    assert_eq!(
+        others[&abroad.id()]
+            .decode(abroad.secret_key())
+            .unwrap()
+            .len(),
+        2
+    );
+
+    let mut merged = own.clone();
+    merged.merge(&others[&abroad.id()]);
+    merged.merge(&own);
+    assert_eq!(merged.len(), 3);
+}
+
+#[test]
+fn test_forwarding() {
+    let (home, abroad) = (Authority::unique(), Authority::unique());
+    let mut home_registry = Registry::new();
+    let mut abroad_registry = Registry::new();
+
+    let infected = Identity::unique();
+    let (local, traveller) = (Identity::unique(), Identity::unique());
+    let mut log = ContactLog::new();
+    log.add(&local.new_share_id(home.public_key()));
+    log.add(&traveller.new_share_id(abroad.public_key()));
+    submit_log(&home, &mut home_registry, &infected, &log);
+    assert!(home_registry.is_tainted(local.hashed_id()).unwrap());
+    assert!(!home_registry.is_tainted(traveller.hashed_id()).unwrap());
+    let pending = home_registry.pending_forwards().unwrap();
+    assert_eq!(pending[&abroad.id()].len(), 1);
+
+    // batches that cannot be sent stay queued
+    assert_eq!(home_registry.send_forwards(&home, |_| false).unwrap(), 0);
+    assert_eq!(home_registry.pending_forwards().unwrap().len(), 1);
+
+    let forwards = home_registry.take_forwards(&home).unwrap();
+    assert!(home_registry.pending_forwards().unwrap().is_empty());
+    assert_eq!(forwards.len(), 1);
+    let forwarded: ForwardedContacts =
+        serde_json::from_str(&serde_json::to_string(&forwards[0]).unwrap()).unwrap();
+    let batch = forwarded.peek().unwrap();
+    assert_eq!((batch.from, batch.to), (home.id(), abroad.id()));
+
+    match abroad_registry.import_forwarded(&abroad, &forwarded) {
+        Err(SubmitError::UntrustedPeer) => {}
+        rv => panic!("unexpected result {:?}", rv),
+    }
+    abroad_registry.trust_peer(FederationPeer::from(&keys(&home)));
+    abroad_registry.trust_peer(FederationPeer::from(&keys(&home)));
+    assert_eq!(abroad_registry.peers().len(), 1);
+    let upload_id = abroad_registry
+        .import_forwarded(&abroad, &forwarded)
+        .unwrap();
+    assert!(abroad_registry.is_tainted(traveller.hashed_id()).unwrap());
+    assert_eq!(
+        abroad_registry
+            .store()
+            .upload(&upload_id)
+            .unwrap()
+            .unwrap()
+            .source,
+        Some(UploadSource::Forwarded { from: home.id() })
+    );
+    abroad_registry.revoke(&upload_id).unwrap();
+    assert!(!abroad_registry.is_tainted(traveller.hashed_id()).unwrap());
+
+    // batches are only accepted by the authority they are addressed to
+    let mut other_registry = Registry::new();
+    other_registry.trust_peer(FederationPeer::from(&keys(&home)));
+    match other_registry.import_forwarded(&Authority::unique(), &forwarded) {
+        Err(SubmitError::Misdirected) => {}
+        rv => panic!("unexpected result {:?}", rv),
+    }
+
+    // a batch claiming to come from a peer must be signed by it
+    let forged = abroad.sign_forward(&batch);
+    match abroad_registry.import_forwarded(&abroad, &forged) {
+        Err(SubmitError::Authorization(AuthorizationError::Forged)) => {}
+        rv => panic!("unexpected result {:?}", rv),
+    }
+}
+
+#[test]
+fn test_client_forward() {
+    let (home, abroad) = (Authority::unique(), Authority::unique());
+    let mut log = ContactLog::new();
+    log.add(&Identity::unique().new_share_id(abroad.public_key()));
+    let forwarded = home.sign_forward(&ForwardBatch {
+        from: home.id(),
+        to: abroad.id(),
+        created: chrono::Utc::now(),
+        contacts: log,
+    });
+
+    let mut registry = Registry::new();
+    registry.trust_peer(FederationPeer::from(&keys(&home)));
+    let upload_id = registry.import_forwarded(&abroad, &forwarded).unwrap();
+
+    // batches are only accepted within the retention period
+    let created = chrono::Utc::now() - Duration::days(FORWARD_RETENTION_DAYS + 1);
+    let expired = home.sign_forward(&ForwardBatch {
+        from: home.id(),
+        to: abroad.id(),
+        created,
+        contacts: ContactLog::new(),
+    });
+    assert_eq!(
+        registry.import_forwarded(&abroad, &expired),
+        Err(SubmitError::ExpiredForward { created })
+    );
+
+    let mut transport = MockTransport::new();
+    transport.push_json(&UploadResponse { upload_id });
+    let mut client = Client::with_keys(&mut transport, keys(&abroad));
+    assert_eq!(client.forward(&forwarded).unwrap(), upload_id);
+    assert_eq!(transport.requests()[0].path, "/forwards");
+}
@@ -0,0 +1,72 @@
+#![cfg(any(feature = "cbor", feature = "msgpack"))]
+use covidcotra::formats::FormatError;
+use covidcotra::*;
+use serde::{de::DeserializeOwned, Serialize};
+
+fn roundtrip<T, E, D>(value: &T, encode: E, decode: D) -> T
+where
+    T: Serialize + DeserializeOwned,
+    E: Fn(&T) -> Result<Vec<u8>, FormatError>,
+    D: Fn(&[u8]) -> Result<T, FormatError>,
+{
+    let bytes = encode(value).unwrap();
+    // binary fields must not be written as base64 strings
+    let json = serde_json::to_vec(value).unwrap();
+    assert!(bytes.len() < json.len());
+    decode(&bytes).unwrap()
+}
+
+fn check_format<E, D>(encode: E, decode: D)
+where
+    E: Fn(&serde_json::Value) -> Result<Vec<u8>, FormatError>,
+    D: Fn(&[u8]) -> Result<serde_json::Value, FormatError>,
+{
+    // sanity check that the format helpers work with arbitrary values
+    let value = serde_json::json!({"a": [1, 2, 3]});
+    assert_eq!(decode(&encode(&value).unwrap()).unwrap(), value);
+}
+
+macro_rules! format_tests {
+    ($name:ident, $module:path) => {
+        #[test]
+        fn $name() {
+            use $module as fmt;
+
+            check_format(fmt::to_vec, fmt::from_slice);
+
+            let authority = Authority::unique();
+            let identity = Identity::unique();
+            let share_id = identity.new_share_id(authority.public_key());
+
+            let restored = roundtrip(&share_id, fmt::to_vec, fmt::from_slice);
+            assert!(restored == share_id);
+
+            let restored = roundtrip(identity.hashed_id(), fmt::to_vec, fmt::from_slice);
+            assert_eq!(&restored, identity.hashed_id());
+
+            let restored = roundtrip(identity.unique_id(), fmt::to_vec, fmt::from_slice);
+            assert_eq!(&restored, identity.unique_id());
+
+            let restored = roundtrip(authority.public_key(), fmt::to_vec, fmt::from_slice);
+            assert_eq!(restored.to_string(), authority.public_key().to_string());
+
+            let restored = roundtrip(&authority, fmt::to_vec, fmt::from_slice);
+            assert_eq!(
+                restored.public_key().to_string(),
+                authority.public_key().to_string()
+            );
+
+            let mut log = ContactLog::new();
+            log.add(&share_id);
+            let restored = roundtrip(&log, fmt::to_vec, fmt::from_slice);
+            let decoded = restored.decode(authority.secret_key()).unwrap();
+            assert_eq!(&decoded[0].0, identity.unique_id());
+        }
+    };
+}
+
+#[cfg(feature = "cbor")]
+format_tests!(test_cbor_roundtrip, covidcotra::formats::cbor);
+
+#[cfg(feature = "msgpack")]
+format_tests!(test_msgpack_roundtrip, covidcotra::formats::msgpack);
@@ -0,0 +1,117 @@
+#![cfg(feature = "gaen")]
+use chrono::{Duration, TimeZone, Utc};
+use covidcotra::*;
+
+const TEK: [u8; 16] = [
+    0x75, 0xc7, 0x34, 0xc6, 0xdd, 0x1a, 0x78, 0x2d, 0xe7, 0xa9, 0x65, 0xda, 0x5e, 0xb9, 0x31, 0x25,
+];
+
+#[test]
+fn test_vectors() {
+    // published test vectors of the Exposure Notification framework
+    let start = Utc.timestamp_opt(1585785600, 0).unwrap();
+    assert_eq!(interval_number(start), 2642976);
+    let key = TemporaryExposureKey::new(TEK, 2642976);
+    let rpis = key.rolling_proximity_ids();
+    assert_eq!(rpis.len(), 144);
+    assert_eq!(rpis[0].0, 2642976);
+    assert_eq!(
+        rpis[0].1.as_bytes(),
+        &[
+            0x8b, 0xe6, 0xcd, 0x37, 0x1c, 0x5c, 0x89, 0x16, 0x04, 0xbf, 0xbe, 0x49, 0xdf, 0x84,
+            0x50, 0x96
+        ]
+    );
+    let mut metadata = [0x40, 0x08, 0x00, 0x00];
+    key.apply_metadata_keystream(&rpis[0].1, &mut metadata);
+    assert_eq!(metadata, [0x72, 0x03, 0x38, 0x74]);
+    assert_eq!(key.rolling_proximity_id(2642976 + 143), rpis[143].1);
+    assert!(key.is_valid_at(2642976 + 143));
+    assert!(!key.is_valid_at(2642976 + 144));
+    assert!(format!("{:?}", key).contains("****"));
+}
+
+#[test]
+fn test_key_export() {
+    let now = Utc::now();
+    let mut key = TemporaryExposureKey::generate(now);
+    key.transmission_risk_level = 4;
+    let mut export = KeyExport::new("AT", now - Duration::days(1), now, vec![key]);
+    let mut revised = TemporaryExposureKey::new(TEK, 2642976);
+    revised.report_type = Some(ReportType::ConfirmedClinicalDiagnosis);
+    revised.days_since_onset_of_symptoms = Some(-3);
+    export.revised_keys.push(revised);
+    let bytes = export.to_bytes();
+    assert!(bytes.starts_with(EXPORT_HEADER));
+
+    let mut parsed = KeyExport::parse(&bytes).unwrap();
+    assert_eq!(
+        parsed.start_timestamp.timestamp(),
+        export.start_timestamp.timestamp()
+    );
+    parsed.start_timestamp = export.start_timestamp;
+    parsed.end_timestamp = export.end_timestamp;
+    assert_eq!(parsed, export);
+
+    assert_eq!(KeyExport::parse(b"nope"), Err(GaenError::InvalidHeader));
+    assert_eq!(
+        KeyExport::parse(&bytes[..bytes.len() - 3]),
+        Err(GaenError::Malformed)
+    );
+}
+
+#[test]
+fn test_matching() {
+    let now = Utc.with_ymd_and_hms(2020, 7, 1, 12, 0, 0).unwrap();
+    let infected = TemporaryExposureKey::generate(now);
+    let bystander = TemporaryExposureKey::generate(now);
+    let mut log = SightingLog::new();
+
+    let interval = interval_number(now);
+    let rpi = infected.rolling_proximity_id(interval);
+    let mut metadata = vec![0x40, 0x08, 0x00, 0x00];
+    infected.apply_metadata_keystream(&rpi, &mut metadata);
+    assert_ne!(metadata, [0x40, 0x08, 0x00, 0x00]);
+    log.add(rpi, &metadata, now);
+    log.add(
+        bystander.rolling_proximity_id(interval),
+        &[0; 4],
+        now + Duration::minutes(5),
+    );
+    // identifiers replayed outside of the tolerance do not match
+    log.add(rpi, &metadata, now + Duration::hours(3));
+    assert_eq!(log.len(), 3);
+
+    let matches = log.match_keys(std::slice::from_ref(&infected));
+    assert_eq!(matches.len(), 1);
+    assert_eq!(matches[0].at, now);
+    assert_eq!(matches[0].metadata, [0x40, 0x08, 0x00, 0x00]);
+
+    let export =
+        KeyExport::parse(&KeyExport::new("AT", now, now, vec![infected.clone()]).to_bytes())
+            .unwrap();
+    assert_eq!(
+        log.exposure_status(&export),
+        ExposureStatus::Exposed {
+            at: now,
+            risk: 1.0,
+            degree: 1
+        }
+    );
+    let export = KeyExport::new("AT", now, now, vec![TemporaryExposureKey::generate(now)]);
+    assert_eq!(log.exposure_status(&export), ExposureStatus::Clear);
+
+    // a revoked diagnosis withdraws the key
+    let mut revoked = infected.clone();
+    revoked.report_type = Some(ReportType::Revoked);
+    let mut export = KeyExport::new("AT", now, now, vec![infected.clone()]);
+    export.revised_keys.push(revoked.clone());
+    let export = KeyExport::parse(&export.to_bytes()).unwrap();
+    assert!(export.revised_keys[0].is_revoked());
+    assert_eq!(log.exposure_status(&export), ExposureStatus::Clear);
+    let export = KeyExport::new("AT", now, now, vec![revoked]);
+    assert_eq!(log.exposure_status(&export), ExposureStatus::Clear);
+
+    log.prune(now + Duration::hours(1));
+    assert_eq!(log.len(), 1);
+}
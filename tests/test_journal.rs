@@ -0,0 +1,146 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use covidcotra::*;
+
+fn temp_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("covidcotra-{}-{}", std::process::id(), name));
+    let _ = fs::remove_file(&path);
+    let _ = fs::remove_file(journal_path(&path));
+    path
+}
+
+fn journal_path(path: &Path) -> PathBuf {
+    let mut rv = path.to_path_buf().into_os_string();
+    rv.push(".journal");
+    rv.into()
+}
+
+fn key() -> WalletKey {
+    WalletKey::Device(DeviceKey::from_slice(&[7; 32]).unwrap())
+}
+
+fn contacts(authority: &Authority, log: &ContactLog) -> usize {
+    log.decode(authority.secret_key()).unwrap().len()
+}
+
+#[test]
+fn test_journal_replay() {
+    let authority = Authority::unique();
+    let path = temp_path("replay");
+    {
+        let mut journal = ContactJournal::open(&path, &key()).unwrap();
+        journal
+            .add(&Identity::unique().new_share_id(authority.public_key()))
+            .unwrap();
+        journal
+            .add(&Identity::unique().new_share_id(authority.public_key()))
+            .unwrap();
+        assert_eq!(journal.records(), 2);
+    }
+    assert!(fs::metadata(&path).is_err());
+
+    let journal = ContactJournal::open(&path, &key()).unwrap();
+    assert_eq!(journal.records(), 2);
+    assert_eq!(contacts(&authority, journal.contacts()), 2);
+    fs::remove_file(journal_path(&path)).unwrap();
+}
+
+#[test]
+fn test_journal_compaction() {
+    let authority = Authority::unique();
+    let path = temp_path("compaction");
+    let mut journal = ContactJournal::open(&path, &key()).unwrap();
+    journal.set_compact_after(3);
+    for _ in 0..4 {
+        journal
+            .add(&Identity::unique().new_share_id(authority.public_key()))
+            .unwrap();
+    }
+    assert_eq!(journal.records(), 1);
+    drop(journal);
+
+    // the snapshot is encrypted as well
+    assert!(serde_json::from_slice::<ContactLog>(&fs::read(&path).unwrap()).is_err());
+    let journal = ContactJournal::open(&path, &key()).unwrap();
+    assert_eq!(contacts(&authority, journal.contacts()), 4);
+    fs::remove_file(&path).unwrap();
+    fs::remove_file(journal_path(&path)).unwrap();
+}
+
+#[test]
+fn test_torn_write_is_discarded() {
+    let authority = Authority::unique();
+    let path = temp_path("torn");
+    let mut journal = ContactJournal::open(&path, &key()).unwrap();
+    journal
+        .add(&Identity::unique().new_share_id(authority.public_key()))
+        .unwrap();
+    drop(journal);
+
+    // simulate a crash half way through writing a record
+    let mut file = OpenOptions::new()
+        .append(true)
+        .open(journal_path(&path))
+        .unwrap();
+    file.write_all(&[200, 0, 0, 0, 1, 2, 3]).unwrap();
+    drop(file);
+
+    let mut journal = ContactJournal::open(&path, &key()).unwrap();
+    assert_eq!(journal.records(), 1);
+    journal
+        .add(&Identity::unique().new_share_id(authority.public_key()))
+        .unwrap();
+    drop(journal);
+    let journal = ContactJournal::open(&path, &key()).unwrap();
+    assert_eq!(contacts(&authority, journal.contacts()), 2);
+    fs::remove_file(journal_path(&path)).unwrap();
+}
+
+#[test]
+fn test_corrupted_record() {
+    let authority = Authority::unique();
+    let path = temp_path("corrupted");
+    let mut journal = ContactJournal::open(&path, &key()).unwrap();
+    for _ in 0..2 {
+        journal
+            .add(&Identity::unique().new_share_id(authority.public_key()))
+            .unwrap();
+    }
+    drop(journal);
+
+    let mut bytes = fs::read(journal_path(&path)).unwrap();
+    // envelope, key header and key check come before the first record
+    bytes[5 + 1 + 40 + 20] ^= 0xff;
+    fs::write(journal_path(&path), &bytes).unwrap();
+    match ContactJournal::open(&path, &key()) {
+        Err(JournalError::CorruptedRecord { offset: 46 }) => {}
+        rv => panic!("unexpected result {:?}", rv.err()),
+    }
+    fs::remove_file(journal_path(&path)).unwrap();
+}
+
+#[test]
+fn test_journal_is_encrypted() {
+    let authority = Authority::unique();
+    let path = temp_path("encrypted");
+    let share_id = Identity::unique().new_share_id(authority.public_key());
+    let mut journal = ContactJournal::open(&path, &key()).unwrap();
+    journal.add(&share_id).unwrap();
+    drop(journal);
+
+    let bytes = fs::read(journal_path(&path)).unwrap();
+    let needle = share_id.to_string();
+    let needle = &needle.as_bytes()[..16];
+    assert!(!bytes.windows(needle.len()).any(|x| x == needle));
+
+    let wrong_key = WalletKey::Device(DeviceKey::unique());
+    match ContactJournal::open(&path, &wrong_key) {
+        Err(JournalError::Key(WalletError::WrongKey)) => {}
+        rv => panic!("unexpected result {:?}", rv.err()),
+    }
+    let journal = ContactJournal::open(&path, &key()).unwrap();
+    assert_eq!(contacts(&authority, journal.contacts()), 1);
+    fs::remove_file(journal_path(&path)).unwrap();
+}
@@ -0,0 +1,74 @@
+use chrono::Duration;
+use covidcotra::*;
+
+mod common;
+
+use common::upload;
+
+#[test]
+fn test_indirect_exposure() {
+    let authority = Authority::unique();
+    let mut registry = Registry::new();
+    registry.set_propagation(PropagationConfig {
+        max_depth: 2,
+        decay: 0.25,
+    });
+
+    let user_1 = Identity::unique();
+    let user_2 = Identity::unique();
+    let user_3 = Identity::unique();
+    let user_4 = Identity::unique();
+
+    let code = authority.issue_code(Duration::hours(1)).unwrap();
+    registry
+        .submit(&authority, &code, &upload(&authority, &user_1, &user_2))
+        .unwrap();
+    let exposure = registry.exposure(user_2.hashed_id()).unwrap().unwrap();
+    assert!(exposure.is_direct());
+    assert_eq!(exposure.risk(), 1.0);
+
+    // the directly exposed user uploads their own contacts
+    registry
+        .submit_exposed(&authority, &upload(&authority, &user_2, &user_3))
+        .unwrap();
+    let exposure = registry.exposure(user_3.hashed_id()).unwrap().unwrap();
+    assert!(!exposure.is_direct());
+    assert_eq!(exposure.degree(), 2);
+    assert_eq!(exposure.risk(), 0.25);
+    assert!(!registry.is_tainted(user_3.hashed_id()).unwrap());
+    assert_eq!(
+        check_status(vec![user_3.hashed_id()], &registry).unwrap(),
+        ExposureStatus::Exposed {
+            at: exposure.at(),
+            risk: 0.25,
+            degree: 2
+        }
+    );
+
+    // propagation stops at the configured depth
+    assert_eq!(
+        registry.submit_exposed(&authority, &upload(&authority, &user_3, &user_4)),
+        Err(SubmitError::NotExposed)
+    );
+    assert!(registry.exposure(user_4.hashed_id()).unwrap().is_none());
+}
+
+#[test]
+fn test_no_propagation_by_default() {
+    let authority = Authority::unique();
+    let mut registry = Registry::new();
+
+    let user_1 = Identity::unique();
+    let user_2 = Identity::unique();
+    let user_3 = Identity::unique();
+
+    let code = authority.issue_code(Duration::hours(1)).unwrap();
+    registry
+        .submit(&authority, &code, &upload(&authority, &user_1, &user_2))
+        .unwrap();
+    assert!(registry.is_tainted(user_2.hashed_id()).unwrap());
+    assert_eq!(
+        registry.submit_exposed(&authority, &upload(&authority, &user_2, &user_3)),
+        Err(SubmitError::NotExposed)
+    );
+}
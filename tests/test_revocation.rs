@@ -0,0 +1,102 @@
+use covidcotra::*;
+
+mod common;
+
+use common::{submit, submit_log, upload};
+
+#[test]
+fn test_revoke_upload() {
+    let authority = Authority::unique();
+    let mut registry = Registry::new();
+
+    let infected = Identity::unique();
+    let other_infected = Identity::unique();
+    let contact = Identity::unique();
+    let shared_contact = Identity::unique();
+
+    let upload_id = submit(&authority, &mut registry, &infected, &contact);
+    let mut log = ContactLog::new();
+    log.add(&contact.new_share_id(authority.public_key()));
+    log.add(&shared_contact.new_share_id(authority.public_key()));
+    let other_id = submit_log(&authority, &mut registry, &other_infected, &log);
+
+    registry.revoke(&upload_id).unwrap();
+    assert!(registry.is_upload_revoked(&upload_id).unwrap());
+    assert!(!registry.is_upload_revoked(&other_id).unwrap());
+    assert!(!registry.is_infected(infected.hashed_id()).unwrap());
+    assert!(registry.is_revoked(infected.hashed_id()).unwrap());
+    assert_eq!(
+        check_status(vec![infected.hashed_id()], &registry).unwrap(),
+        ExposureStatus::Revoked
+    );
+
+    // the contact stays exposed through the other upload
+    assert!(registry.is_tainted(contact.hashed_id()).unwrap());
+    assert!(!registry.is_revoked(contact.hashed_id()).unwrap());
+
+    match registry.revoke(&upload_id) {
+        Err(RevokeError::AlreadyRevoked { .. }) => {}
+        rv => panic!("unexpected result {:?}", rv),
+    }
+
+    registry.revoke(&other_id).unwrap();
+    assert!(!registry.is_tainted(contact.hashed_id()).unwrap());
+    let list: TaintList =
+        serde_json::from_str(&serde_json::to_string(&registry.taint_list().unwrap()).unwrap())
+            .unwrap();
+    assert_eq!(
+        check_status(vec![shared_contact.hashed_id()], &list).unwrap(),
+        ExposureStatus::Revoked
+    );
+}
+
+#[test]
+fn test_revocation_cascades() {
+    let authority = Authority::unique();
+    let mut registry = Registry::new();
+    registry.set_propagation(PropagationConfig {
+        max_depth: 2,
+        decay: 0.5,
+    });
+
+    let user_1 = Identity::unique();
+    let user_2 = Identity::unique();
+    let user_3 = Identity::unique();
+
+    let upload_id = submit(&authority, &mut registry, &user_1, &user_2);
+    let exposed_id = registry
+        .submit_exposed(&authority, &upload(&authority, &user_2, &user_3))
+        .unwrap();
+    assert!(registry.exposure(user_3.hashed_id()).unwrap().is_some());
+
+    registry.revoke(&upload_id).unwrap();
+    assert!(registry.is_upload_revoked(&exposed_id).unwrap());
+    assert!(registry.exposure(user_3.hashed_id()).unwrap().is_none());
+    assert!(registry.is_revoked(user_3.hashed_id()).unwrap());
+}
+
+#[test]
+fn test_revoke_unknown_upload() {
+    let mut registry = Registry::new();
+    let upload_id: UploadId = "a4b1e7c8-0cbb-4c3f-9a6b-5d3bb7d0b1f2".parse().unwrap();
+    assert_eq!(registry.revoke(&upload_id), Err(RevokeError::UnknownUpload));
+    assert!("not an id".parse::<UploadId>().is_err());
+}
+
+#[test]
+fn test_taint_list_v1_migration() {
+    let json = serde_json::json!({
+        "magic": "covidcotra",
+        "version": 1,
+        "type": "taint_list",
+        "generated": "2020-04-01T00:00:00Z",
+        "infected": {},
+        "exposures": {},
+    });
+    let list: TaintList = serde_json::from_value(json).unwrap();
+    let identity = Identity::unique();
+    assert_eq!(
+        check_status(vec![identity.hashed_id()], &list).unwrap(),
+        ExposureStatus::Clear
+    );
+}
@@ -0,0 +1,88 @@
+use chrono::Utc;
+use covidcotra::*;
+
+mod common;
+
+use common::submit_log;
+
+/// Simulates two devices meeting and returns the status of the second one.
+fn meet<S: Scheme>(
+    scheme: &S,
+    infected: &mut S::State,
+    contact: &mut S::State,
+    publish: impl FnOnce(&S::Log) -> S::Published,
+) -> ExposureStatus {
+    let now = Utc::now();
+    let mut infected_log = S::Log::default();
+    let mut contact_log = S::Log::default();
+    let broadcast = scheme.broadcast(infected, now).unwrap();
+    scheme.record(&mut contact_log, &broadcast, now);
+    let broadcast = scheme.broadcast(contact, now).unwrap();
+    scheme.record(&mut infected_log, &broadcast, now);
+    scheme
+        .exposure_status(contact, &contact_log, &publish(&infected_log))
+        .unwrap()
+}
+
+#[test]
+fn test_sealed_scheme() {
+    let authority = Authority::unique();
+    let scheme = SealedScheme::new(authority.public_key());
+    let mut infected = Identity::unique();
+    let infected_copy = infected.clone();
+    let status = meet(&scheme, &mut infected, &mut Identity::unique(), |log| {
+        let mut registry = Registry::new();
+        submit_log(&authority, &mut registry, &infected_copy, log);
+        registry.taint_list().unwrap()
+    });
+    match status {
+        ExposureStatus::Exposed { .. } => {}
+        status => panic!("unexpected status {:?}", status),
+    }
+
+    let status = scheme
+        .exposure_status(
+            &Identity::unique(),
+            &ContactLog::new(),
+            &Registry::new().taint_list().unwrap(),
+        )
+        .unwrap();
+    assert_eq!(status, ExposureStatus::Clear);
+}
+
+#[cfg(all(feature = "dp3t", feature = "gaen"))]
+#[test]
+fn test_schemes_side_by_side() {
+    let now = Utc::now();
+    let today = now.date_naive();
+    let scheme = (Dp3tScheme, GaenScheme::new(&[0x40, 0x08, 0x00, 0x00]));
+    let mut infected = (DayKeyChain::new(today), Vec::new());
+    let mut contact = (DayKeyChain::new(today), Vec::new());
+
+    let infected_keys = infected.0.clone();
+    let status = meet(&scheme, &mut infected, &mut contact, |_| {
+        (
+            vec![infected_keys.publish(today).unwrap()],
+            KeyExport::new("AT", now, now, Vec::new()),
+        )
+    });
+    match status {
+        ExposureStatus::Exposed { risk, degree, .. } => {
+            assert_eq!(risk, 1.0);
+            assert_eq!(degree, 1);
+        }
+        status => panic!("unexpected status {:?}", status),
+    }
+    assert_eq!(infected.1.len(), 1);
+
+    let keys = infected.1.clone();
+    let status = meet(&scheme, &mut infected, &mut contact, |_| {
+        (Vec::new(), KeyExport::new("AT", now, now, keys))
+    });
+    assert_ne!(status, ExposureStatus::Clear);
+
+    let status = meet(&scheme, &mut infected, &mut contact, |_| {
+        (Vec::new(), KeyExport::new("AT", now, now, Vec::new()))
+    });
+    assert_eq!(status, ExposureStatus::Clear);
+}
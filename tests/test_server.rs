@@ -0,0 +1,260 @@
+#![cfg(feature = "server")]
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::thread;
+
+use chrono::{Duration, SecondsFormat, Utc};
+use covidcotra::*;
+
+fn start(server: Server) -> SocketAddr {
+    let server = server.bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    thread::spawn(move || server.run());
+    addr
+}
+
+fn request(addr: SocketAddr, method: &str, path: &str, body: &[u8]) -> (u16, Vec<u8>) {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(
+        stream,
+        "{} {} HTTP/1.1\r\nHost: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        method,
+        path,
+        addr,
+        body.len()
+    )
+    .unwrap();
+    stream.write_all(body).unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).unwrap();
+    let split = response.windows(4).position(|x| x == b"\r\n\r\n").unwrap();
+    let head = String::from_utf8_lossy(&response[..split]).to_string();
+    let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+    (status, response[split + 4..].to_vec())
+}
+
+fn get<T: serde::de::DeserializeOwned>(addr: SocketAddr, path: &str) -> T {
+    let (status, body) = request(addr, "GET", path, b"");
+    assert_eq!(status, 200, "{}", String::from_utf8_lossy(&body));
+    serde_json::from_slice(&body).unwrap()
+}
+
+#[test]
+fn test_server() {
+    let authority = Authority::unique();
+    let public_key = *authority.public_key();
+    let code = authority.issue_code(Duration::hours(1)).unwrap();
+    let second_code = authority.issue_code(Duration::hours(1)).unwrap();
+    let mut server = Server::new(authority, Registry::new());
+    server.set_page_size(1);
+    server.set_log_flush_interval(Duration::zero());
+    let addr = start(server);
+    let started = Utc::now();
+
+    let keys: AuthorityKeys = get(addr, "/keys");
+    assert_eq!(keys.public_key.to_string(), public_key.to_string());
+
+    let infected = Identity::unique();
+    let contact = Identity::unique();
+    let mut log = ContactLog::new();
+    log.add(&contact.new_share_id(&keys.public_key));
+    let upload = serde_json::to_vec(&UploadRequest {
+        bundle: UploadBundle::new(std::slice::from_ref(&infected), &log)
+            .seal(&keys.public_key)
+            .unwrap(),
+        code: Some(code),
+        #[cfg(feature = "blind")]
+        credential: None,
+    })
+    .unwrap();
+    let prefix = encode_prefix(contact.hashed_id(), 2);
+    let signed: SignedTaintList = get(addr, &format!("/status/{}", prefix));
+    let before = signed
+        .verify(&keys.signing_public_key, &TaintListScope::Prefix(prefix))
+        .unwrap();
+    assert!(before.is_empty());
+    let (status, body) = request(addr, "POST", "/uploads", &upload);
+    assert_eq!(status, 200);
+    let _: UploadResponse = serde_json::from_slice(&body).unwrap();
+
+    // codes can only be redeemed once
+    let (status, body) = request(addr, "POST", "/uploads", &upload);
+    assert_eq!(status, 403);
+    let err: ErrorResponse = serde_json::from_slice(&body).unwrap();
+    assert!(err.error.contains("already redeemed"));
+
+    let signed: SignedTaintList = get(addr, "/taint-list");
+    let list = signed
+        .verify(&keys.signing_public_key, &TaintListScope::Full)
+        .unwrap();
+    assert_eq!(list.len(), 2);
+    assert!(signed
+        .verify(
+            &keys.signing_public_key,
+            &TaintListScope::Prefix("00".into())
+        )
+        .is_err());
+
+    let since = started.to_rfc3339_opts(SecondsFormat::Nanos, true);
+    let page: TaintListPage = get(addr, &format!("/taint-list/delta?since={}", since));
+    let scope = TaintListScope::Delta {
+        since: started,
+        offset: 0,
+    };
+    assert_eq!(
+        page.list
+            .verify(&keys.signing_public_key, &scope)
+            .unwrap()
+            .len(),
+        1
+    );
+    assert_eq!(page.list.total(), 2);
+    assert_eq!(page.next_offset, Some(1));
+    let generated = page
+        .list
+        .generated()
+        .to_rfc3339_opts(SecondsFormat::Nanos, true);
+
+    // later pages come from the delta of the first page even if more
+    // changes arrive in between
+    let mut log = ContactLog::new();
+    log.add(&Identity::unique().new_share_id(&keys.public_key));
+    let second_upload = serde_json::to_vec(&UploadRequest {
+        bundle: UploadBundle::new(&[Identity::unique()], &log)
+            .seal(&keys.public_key)
+            .unwrap(),
+        code: Some(second_code),
+        #[cfg(feature = "blind")]
+        credential: None,
+    })
+    .unwrap();
+    assert_eq!(request(addr, "POST", "/uploads", &second_upload).0, 200);
+    let path = format!("/taint-list/delta?since={}&offset=1", since);
+    assert_eq!(request(addr, "GET", &path, b"").0, 400);
+    let (status, _) = request(addr, "GET", &format!("{}&generated={}", path, since), b"");
+    assert_eq!(status, 410);
+    let page: TaintListPage = get(addr, &format!("{}&generated={}", path, generated));
+    assert_eq!(page.list.total(), 2);
+    let scope = TaintListScope::Delta {
+        since: started,
+        offset: 1,
+    };
+    assert_eq!(
+        page.list
+            .verify(&keys.signing_public_key, &scope)
+            .unwrap()
+            .len(),
+        1
+    );
+    assert_eq!(page.next_offset, None);
+
+    let signed: SignedTreeHead = get(addr, "/transparency/head");
+    let head = signed.verify(&keys.signing_public_key).unwrap();
+    assert_eq!(head.tree_size, 4);
+    let body = serde_json::to_vec(&InclusionRequest {
+        hashed_id: *contact.hashed_id(),
+        tree_size: head.tree_size,
+    })
+    .unwrap();
+    let (status, body) = request(addr, "POST", "/transparency/inclusion", &body);
+    assert_eq!(status, 200);
+    let inclusions: Vec<LogInclusion> = serde_json::from_slice(&body).unwrap();
+    assert_eq!(inclusions.len(), 1);
+    inclusions[0]
+        .proof
+        .verify(&inclusions[0].entry, &head)
+        .unwrap();
+    let proof: ConsistencyProof = get(addr, "/transparency/consistency?from=1&to=2");
+    assert_eq!(proof.new_size, 2);
+
+    // status queries see the uploads made after the first query
+    let prefix = encode_prefix(contact.hashed_id(), 2);
+    let signed: SignedTaintList = get(addr, &format!("/status/{}", prefix));
+    let matches = signed
+        .verify(&keys.signing_public_key, &TaintListScope::Prefix(prefix))
+        .unwrap();
+    assert!(matches.generated() > before.generated());
+    match check_status(vec![contact.hashed_id()], &matches).unwrap() {
+        ExposureStatus::Exposed { .. } => {}
+        status => panic!("unexpected status {:?}", status),
+    }
+
+    assert_eq!(request(addr, "GET", "/status/xyz", b"").0, 400);
+    assert_eq!(request(addr, "GET", "/taint-list/delta", b"").0, 400);
+    assert_eq!(request(addr, "POST", "/uploads", b"{}").0, 400);
+    assert_eq!(request(addr, "DELETE", "/keys", b"").0, 405);
+    assert_eq!(request(addr, "GET", "/subscriptions", b"").0, 405);
+    assert_eq!(request(addr, "DELETE", "/subscriptions/xyz", b"").0, 400);
+    let tokens = vec![contact.hashed_id().subscription_token(); MAX_SUBSCRIPTION_TOKENS + 1];
+    let body = serde_json::to_vec(&SubscriptionRequest { tokens }).unwrap();
+    assert_eq!(request(addr, "POST", "/subscriptions", &body).0, 400);
+    assert_eq!(request(addr, "POST", "/forwards", b"{}").0, 400);
+    assert_eq!(request(addr, "GET", "/forwards", b"").0, 405);
+    assert_eq!(request(addr, "GET", "/descriptor", b"").0, 404);
+    assert_eq!(request(addr, "POST", "/descriptor", b"").0, 405);
+    assert_eq!(request(addr, "POST", "/transparency/head", b"").0, 405);
+    assert_eq!(
+        request(addr, "POST", "/transparency/inclusion", b"{}").0,
+        400
+    );
+    assert_eq!(
+        request(addr, "GET", "/transparency/consistency", b"").0,
+        400
+    );
+    assert_eq!(
+        request(addr, "GET", "/transparency/consistency?from=0&to=999", b"").0,
+        400
+    );
+    assert_eq!(request(addr, "GET", "/nope", b"").0, 404);
+}
+
+#[test]
+fn test_server_forwards() {
+    let (home, abroad) = (Authority::unique(), Authority::unique());
+    let home_keys = AuthorityKeys {
+        public_key: *home.public_key(),
+        signing_public_key: *home.signing_public_key(),
+    };
+    let abroad_keys = AuthorityKeys {
+        public_key: *abroad.public_key(),
+        signing_public_key: *abroad.signing_public_key(),
+    };
+    let abroad_id = abroad.id();
+    let code = home.issue_code(Duration::hours(1)).unwrap();
+
+    let mut abroad_registry = Registry::new();
+    abroad_registry.trust_peer(FederationPeer::from(&home_keys));
+    let abroad_addr = start(Server::new(abroad, abroad_registry));
+    let mut server = Server::new(home, Registry::new());
+    server
+        .set_peer_url(abroad_id, &format!("http://{}", abroad_addr))
+        .unwrap();
+    server.set_forward_interval(Duration::zero());
+    let home_addr = start(server);
+
+    let traveller = Identity::unique();
+    let mut log = ContactLog::new();
+    log.add(&traveller.new_share_id(&abroad_keys.public_key));
+    let upload = serde_json::to_vec(&UploadRequest {
+        bundle: UploadBundle::new(&[Identity::unique()], &log)
+            .seal(&home_keys.public_key)
+            .unwrap(),
+        code: Some(code),
+        #[cfg(feature = "blind")]
+        credential: None,
+    })
+    .unwrap();
+    assert_eq!(request(home_addr, "POST", "/uploads", &upload).0, 200);
+
+    // the contacts are forwarded after the response to the upload, so they
+    // have arrived once the next request is answered
+    let _: AuthorityKeys = get(home_addr, "/keys");
+    let signed: SignedTaintList = get(abroad_addr, "/taint-list");
+    let list = signed
+        .verify(&abroad_keys.signing_public_key, &TaintListScope::Full)
+        .unwrap();
+    match check_status(vec![traveller.hashed_id()], &list).unwrap() {
+        ExposureStatus::Exposed { degree: 1, .. } => {}
+        status => panic!("unexpected status {:?}", status),
+    }
+}
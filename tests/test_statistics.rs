@@ -0,0 +1,148 @@
+use chrono::{Duration, Utc};
+use covidcotra::*;
+
+mod common;
+
+use common::submit_log;
+
+/// Submits an upload of a new infected user with `contacts` new contacts.
+fn submit(authority: &Authority, registry: &mut Registry, contacts: usize) -> UploadId {
+    let mut log = ContactLog::new();
+    for _ in 0..contacts {
+        log.add(&Identity::unique().new_share_id(authority.public_key()));
+    }
+    submit_log(authority, registry, &Identity::unique(), &log)
+}
+
+#[test]
+fn test_exact_statistics() {
+    let authority = Authority::unique();
+    let mut registry = Registry::new();
+    submit(&authority, &mut registry, 3);
+    let upload_id = submit(&authority, &mut registry, 1);
+    registry.revoke(&upload_id).unwrap();
+
+    let today = Utc::now().date_naive();
+    let stats = registry
+        .statistics(today - Duration::days(6), today, NoiseMechanism::None, 10)
+        .unwrap();
+    assert_eq!(stats.infections, 1);
+    assert_eq!(stats.exposures, 3);
+    assert_eq!(stats.revocations, 2);
+    assert_eq!(stats.uploads, 2);
+    assert_eq!(stats.revoked_uploads, 1);
+    assert_eq!(stats.days.len(), 7);
+    assert_eq!(stats.days[0].day, today - Duration::days(6));
+    let last = stats.days.last().unwrap();
+    assert_eq!(last.day, today);
+    assert_eq!((last.infections, last.exposures, last.uploads), (1, 3, 2));
+    assert!(stats.days[..6]
+        .iter()
+        .all(|day| day.infections + day.exposures + day.uploads == 0));
+
+    let csv = stats.to_csv();
+    let mut lines = csv.lines();
+    assert_eq!(lines.next(), Some("day,infections,exposures,uploads"));
+    assert_eq!(lines.last(), Some(&format!("{},1,3,2", today)[..]));
+
+    let json = serde_json::to_value(&stats).unwrap();
+    assert_eq!(json["noise"]["mechanism"], "none");
+    let restored: Statistics = serde_json::from_value(json).unwrap();
+    assert_eq!(restored, stats);
+
+    // every upload only counts up to the bound
+    let stats = registry
+        .statistics(today, today, NoiseMechanism::None, 2)
+        .unwrap();
+    assert_eq!((stats.infections, stats.exposures), (1, 2));
+    assert_eq!(stats.days[0].exposures, 2);
+}
+
+#[test]
+fn test_noisy_statistics() {
+    let authority = Authority::unique();
+    let mut registry = Registry::new();
+    submit(&authority, &mut registry, 5);
+    let today = Utc::now().date_naive();
+    let first_day = today - Duration::days(29);
+
+    // with a huge epsilon the noise is far below rounding
+    let stats = registry
+        .statistics(
+            first_day,
+            today,
+            NoiseMechanism::Laplace { epsilon: 100000.0 },
+            10,
+        )
+        .unwrap();
+    assert_eq!((stats.infections, stats.exposures), (1, 5));
+
+    let exact = registry
+        .statistics(first_day, today, NoiseMechanism::None, 10)
+        .unwrap();
+    for noise in &[
+        NoiseMechanism::Laplace { epsilon: 0.1 },
+        NoiseMechanism::Gaussian {
+            epsilon: 0.5,
+            delta: 1e-5,
+        },
+    ] {
+        let stats = registry.statistics(first_day, today, *noise, 10).unwrap();
+        assert_eq!(stats.noise, *noise);
+        assert_eq!(stats.days.len(), 30);
+        assert_ne!(stats.days, exact.days);
+    }
+
+    assert_eq!(
+        NoiseMechanism::Laplace { epsilon: 0.0 }.validate(),
+        Err(StatisticsError::InvalidEpsilon(0.0))
+    );
+    assert_eq!(
+        NoiseMechanism::Gaussian {
+            epsilon: 2.0,
+            delta: 1e-5
+        }
+        .validate(),
+        Err(StatisticsError::InvalidEpsilon(2.0))
+    );
+    assert_eq!(
+        NoiseMechanism::Gaussian {
+            epsilon: 0.5,
+            delta: 1.0
+        }
+        .validate(),
+        Err(StatisticsError::InvalidDelta(1.0))
+    );
+    assert!(registry
+        .statistics(today, first_day, NoiseMechanism::None, 10)
+        .is_err());
+    assert!(registry
+        .statistics(
+            first_day,
+            today,
+            NoiseMechanism::Laplace { epsilon: -1.0 },
+            10
+        )
+        .is_err());
+}
+
+#[test]
+fn test_noise_distribution() {
+    let noise = NoiseMechanism::Laplace { epsilon: 1.0 };
+    let samples: Vec<f64> = (0..2000).map(|_| noise.apply(100, 1.0) as f64).collect();
+    let mean = samples.iter().sum::<f64>() / samples.len() as f64;
+    assert!((mean - 100.0).abs() < 0.5, "mean {}", mean);
+    assert!(samples.iter().any(|&x| x != 100.0));
+    assert_eq!(NoiseMechanism::None.apply(42, 1.0), 42);
+    assert!((0..100).all(|_| noise.apply(0, 1.0) < 1000));
+
+    // the noise grows with how much a single upload can change the counts
+    assert_eq!(noise.sensitivity(10), 42.0);
+    let spread = |sensitivity: f64| {
+        (0..2000)
+            .map(|_| (noise.apply(1000, sensitivity) as f64 - 1000.0).abs())
+            .sum::<f64>()
+            / 2000.0
+    };
+    assert!(spread(20.0) > 5.0 * spread(1.0));
+}
@@ -0,0 +1,98 @@
+use covidcotra::*;
+
+mod common;
+
+use common::submit;
+
+#[test]
+fn test_check_status() {
+    let authority = Authority::unique();
+    let mut registry = Registry::new();
+
+    let infected = Identity::unique();
+    let contact = Identity::unique();
+    let bystander = Identity::unique();
+
+    submit(&authority, &mut registry, &infected, &contact);
+
+    match check_status(vec![bystander.hashed_id(), infected.hashed_id()], &registry).unwrap() {
+        ExposureStatus::Infected { .. } => {}
+        status => panic!("unexpected status {:?}", status),
+    }
+    match check_status(vec![contact.hashed_id()], &registry).unwrap() {
+        ExposureStatus::Exposed { risk, degree, .. } => {
+            assert_eq!(risk, 1.0);
+            assert_eq!(degree, 1);
+        }
+        status => panic!("unexpected status {:?}", status),
+    }
+    assert_eq!(
+        check_status(vec![bystander.hashed_id()], &registry).unwrap(),
+        ExposureStatus::Clear
+    );
+    assert_eq!(
+        check_status(vec![], &registry).unwrap(),
+        ExposureStatus::Unknown
+    );
+
+    // the same answers come from a downloaded taint list
+    let json = serde_json::to_string(&registry.taint_list().unwrap()).unwrap();
+    let list: TaintList = serde_json::from_str(&json).unwrap();
+    assert_eq!(
+        check_status(vec![contact.hashed_id()], &list).unwrap(),
+        check_status(vec![contact.hashed_id()], &registry).unwrap()
+    );
+    assert_eq!(
+        check_status(vec![bystander.hashed_id()], &list).unwrap(),
+        ExposureStatus::Clear
+    );
+
+    let value =
+        serde_json::to_value(check_status(vec![contact.hashed_id()], &list).unwrap()).unwrap();
+    assert_eq!(value["status"], "exposed");
+    assert_eq!(value["degree"], 1);
+}
+
+#[test]
+fn test_taint_delta() {
+    let authority = Authority::unique();
+    let mut registry = Registry::new();
+
+    let (infected_1, contact_1) = (Identity::unique(), Identity::unique());
+    let first = submit(&authority, &mut registry, &infected_1, &contact_1);
+    let mut list = registry.taint_list().unwrap();
+    assert_eq!(list.len(), 2);
+
+    let (infected_2, contact_2) = (Identity::unique(), Identity::unique());
+    submit(&authority, &mut registry, &infected_2, &contact_2);
+    registry.revoke(&first).unwrap();
+
+    let delta = registry.taint_delta(list.generated()).unwrap();
+    assert_eq!(delta.len(), 4);
+    let pages: Vec<_> = (0..4).map(|offset| delta.page(offset, 1)).collect();
+    for page in &pages {
+        assert_eq!(page.len(), 1);
+        list.apply(page);
+    }
+    for identity in &[&infected_1, &contact_1, &infected_2, &contact_2] {
+        assert_eq!(
+            check_status(vec![identity.hashed_id()], &list).unwrap(),
+            check_status(vec![identity.hashed_id()], &registry).unwrap()
+        );
+    }
+    assert_eq!(
+        check_status(vec![infected_1.hashed_id()], &list).unwrap(),
+        ExposureStatus::Revoked
+    );
+
+    let later = registry.taint_delta(delta.generated()).unwrap();
+    assert!(later.is_empty());
+
+    let prefix = &infected_2.hashed_id().as_bytes()[..2];
+    let matches = registry.taint_list().unwrap().with_prefix(prefix);
+    assert!(!matches.is_empty());
+    match check_status(vec![infected_2.hashed_id()], &matches).unwrap() {
+        ExposureStatus::Infected { .. } => {}
+        status => panic!("unexpected status {:?}", status),
+    }
+}
@@ -0,0 +1,244 @@
+use chrono::{Duration, Utc};
+use covidcotra::*;
+
+mod common;
+
+use common::{keys, upload, upload_log};
+
+fn check_registry<S: RegistryStore>(mut registry: Registry<S>) -> Registry<S> {
+    let authority = Authority::unique();
+    let infected = Identity::unique();
+    let contact = Identity::unique();
+
+    let sink = MemorySink::new();
+    registry.set_notification_sink(sink.clone());
+    let token = contact.hashed_id().subscription_token();
+    registry.subscribe(token).unwrap();
+    assert!(registry.store().is_subscribed(&token).unwrap());
+
+    let code = authority.issue_code(Duration::hours(1)).unwrap();
+    let upload_id = registry
+        .submit(&authority, &code, &upload(&authority, &infected, &contact))
+        .unwrap();
+    assert_eq!(sink.take().len(), 1);
+    assert!(registry.pending_notifications().unwrap().is_empty());
+    assert!(registry.is_infected(infected.hashed_id()).unwrap());
+    assert!(registry.is_tainted(contact.hashed_id()).unwrap());
+    assert!(registry.is_redeemed(&code.id()).unwrap());
+    assert_eq!(
+        registry.store().infection(infected.hashed_id()).unwrap(),
+        Some(InfectionRecord {
+            reported_at: registry.reported_at(infected.hashed_id()).unwrap().unwrap(),
+            sources: vec![upload_id].into_iter().collect(),
+        })
+    );
+    assert_eq!(
+        registry.store().upload(&upload_id).unwrap().unwrap().source,
+        Some(UploadSource::Authorized {
+            issuer: *authority.signing_public_key()
+        })
+    );
+
+    registry.revoke(&upload_id).unwrap();
+    assert!(registry.is_upload_revoked(&upload_id).unwrap());
+    assert!(registry.is_revoked(contact.hashed_id()).unwrap());
+    assert_eq!(
+        check_status(vec![infected.hashed_id()], &registry).unwrap(),
+        ExposureStatus::Revoked
+    );
+    assert_eq!(registry.store().revocations().unwrap().len(), 2);
+
+    assert!(registry.store().log_entries().unwrap().is_empty());
+    let actions: Vec<_> = registry
+        .store()
+        .pending_log_entries()
+        .unwrap()
+        .into_iter()
+        .map(|entry| entry.action)
+        .collect();
+    assert_eq!(
+        actions,
+        vec![
+            LogAction::Infected,
+            LogAction::Exposed { degree: 1 },
+            LogAction::Revoked,
+            LogAction::Revoked
+        ]
+    );
+    assert_eq!(registry.flush_log().unwrap(), 4);
+    assert!(registry.store().pending_log_entries().unwrap().is_empty());
+    let entries = registry.store().log_entries().unwrap();
+    assert_eq!(entries.len(), 4);
+    assert!(entries
+        .iter()
+        .all(|entry| entry.at.timestamp() % 86400 == 0));
+
+    // forwarded batches are only imported once
+    let peer = Authority::unique();
+    registry.trust_peer(FederationPeer::from(&keys(&peer)));
+    let mut log = ContactLog::new();
+    log.add(&Identity::unique().new_share_id(authority.public_key()));
+    let forwarded = peer.sign_forward(&ForwardBatch {
+        from: peer.id(),
+        to: authority.id(),
+        created: Utc::now(),
+        contacts: log,
+    });
+    registry.import_forwarded(&authority, &forwarded).unwrap();
+    let imported = registry
+        .store()
+        .forward_imported_at(&forwarded.batch_id())
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        registry.import_forwarded(&authority, &forwarded),
+        Err(SubmitError::ReplayedForward { imported })
+    );
+    registry
+}
+
+#[test]
+fn test_memory_store() {
+    let registry = check_registry(Registry::new());
+
+    // the memory store is persisted together with the registry
+    let json = serde_json::to_string(&registry).unwrap();
+    let restored: Registry = serde_json::from_str(&json).unwrap();
+    assert_eq!(restored.store().revocations().unwrap().len(), 2);
+}
+
+#[test]
+fn test_memory_store_file() {
+    let path = std::env::temp_dir().join(format!("covidcotra-{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let identity = Identity::unique();
+    {
+        let mut store = MemoryStore::open(&path).unwrap();
+        let mut tx = store.transaction().unwrap();
+        tx.put_revoked(identity.hashed_id(), Utc::now()).unwrap();
+        tx.commit().unwrap();
+        let mut tx = store.transaction().unwrap();
+        tx.put_revoked(Identity::unique().hashed_id(), Utc::now())
+            .unwrap();
+    }
+    let store = MemoryStore::open(&path).unwrap();
+    assert_eq!(store.revocations().unwrap().len(), 1);
+    assert!(store.revoked_at(identity.hashed_id()).unwrap().is_some());
+    std::fs::remove_file(&path).unwrap();
+
+    // a registry on top of the file keeps its records across restarts
+    let registry = check_registry(Registry::with_store(MemoryStore::open(&path).unwrap()));
+    drop(registry);
+    let store = MemoryStore::open(&path).unwrap();
+    assert_eq!(store.revocations().unwrap().len(), 2);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_transaction_rollback() {
+    let mut store = MemoryStore::new();
+    let identity = Identity::unique();
+    let mut tx = store.transaction().unwrap();
+    tx.put_revoked(identity.hashed_id(), Utc::now()).unwrap();
+    assert!(tx.revoked_at(identity.hashed_id()).unwrap().is_some());
+    drop(tx);
+    assert!(store.revoked_at(identity.hashed_id()).unwrap().is_none());
+
+    let mut tx = store.transaction().unwrap();
+    tx.put_revoked(identity.hashed_id(), Utc::now()).unwrap();
+    tx.commit().unwrap();
+    assert!(store.revoked_at(identity.hashed_id()).unwrap().is_some());
+
+    let notification = Notification {
+        token: identity.hashed_id().subscription_token(),
+        changed: Utc::now(),
+    };
+    let mut tx = store.transaction().unwrap();
+    tx.put_subscription(&notification.token).unwrap();
+    tx.push_notification(&notification).unwrap();
+    tx.commit().unwrap();
+    let mut tx = store.transaction().unwrap();
+    assert_eq!(tx.pop_notification().unwrap(), Some(notification.clone()));
+    tx.remove_subscription(&notification.token).unwrap();
+    drop(tx);
+    assert!(store.is_subscribed(&notification.token).unwrap());
+    assert_eq!(store.notifications().unwrap(), vec![notification]);
+}
+
+#[test]
+fn test_rejected_upload_leaves_no_records() {
+    let authority = Authority::unique();
+    let mut registry = Registry::new();
+    let infected = Identity::unique();
+
+    // untagged share IDs are attributed to the authority itself, so one
+    // sealed to another authority cannot be decoded or forwarded
+    let share_id = Identity::unique().new_share_id(Authority::unique().public_key());
+    let mut bytes = base64::decode(share_id.to_string()).unwrap();
+    bytes[3] = 1;
+    bytes.drain(5..13);
+    let mut log = ContactLog::new();
+    log.add(&base64::encode(&bytes).parse().unwrap());
+    let bundle = upload_log(&authority, &infected, &log);
+    let code = authority.issue_code(Duration::hours(1)).unwrap();
+    assert_eq!(
+        registry.submit(&authority, &code, &bundle),
+        Err(SubmitError::UndecodableContacts)
+    );
+    assert!(registry.pending_forwards().unwrap().is_empty());
+    assert!(!registry.is_infected(infected.hashed_id()).unwrap());
+    assert!(!registry.is_redeemed(&code.id()).unwrap());
+    assert!(registry.store().pending_log_entries().unwrap().is_empty());
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn test_sqlite_store() {
+    check_registry(Registry::with_store(SqliteStore::open_in_memory().unwrap()));
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn test_sqlite_store_persists() {
+    let path = std::env::temp_dir().join(format!("covidcotra-{}.sqlite3", std::process::id()));
+    let identity = Identity::unique();
+    let notification = Notification {
+        token: identity.hashed_id().subscription_token(),
+        changed: Utc::now(),
+    };
+    let entry = LogEntry {
+        hashed_id: *identity.hashed_id(),
+        action: LogAction::Revoked,
+        at: Utc::now(),
+    };
+    let peer = Authority::unique();
+    let mut contacts = ContactLog::new();
+    contacts.add(&identity.new_share_id(peer.public_key()));
+    {
+        let mut store = SqliteStore::open(&path).unwrap();
+        let mut tx = store.transaction().unwrap();
+        tx.put_revoked(identity.hashed_id(), Utc::now()).unwrap();
+        tx.put_subscription(&notification.token).unwrap();
+        tx.push_notification(&notification).unwrap();
+        tx.append_log_entry(&entry).unwrap();
+        tx.push_pending_forward(&peer.id(), &contacts).unwrap();
+        tx.commit().unwrap();
+        let mut tx = store.transaction().unwrap();
+        tx.append_log_entry(&entry).unwrap();
+        tx.push_pending_forward(&Authority::unique().id(), &contacts)
+            .unwrap();
+    }
+    let mut store = SqliteStore::open(&path).unwrap();
+    assert!(store.revoked_at(identity.hashed_id()).unwrap().is_some());
+    assert_eq!(store.log_entries().unwrap(), vec![entry]);
+    let forwards = store.pending_forwards().unwrap();
+    assert_eq!(forwards.len(), 1);
+    assert_eq!(forwards[&peer.id()].len(), 1);
+    assert!(store.is_subscribed(&notification.token).unwrap());
+    let mut tx = store.transaction().unwrap();
+    assert_eq!(tx.pop_notification().unwrap(), Some(notification));
+    tx.commit().unwrap();
+    assert!(store.notifications().unwrap().is_empty());
+    drop(store);
+    std::fs::remove_file(&path).unwrap();
+}
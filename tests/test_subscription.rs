@@ -0,0 +1,167 @@
+use std::io;
+use std::time::{Duration, Instant};
+
+use covidcotra::*;
+
+mod common;
+use common::submit;
+
+#[test]
+fn test_subscription_token() {
+    let identity = Identity::unique();
+    let token = identity.hashed_id().subscription_token();
+    assert_eq!(token, identity.hashed_id().subscription_token());
+    assert_ne!(token, Identity::unique().hashed_id().subscription_token());
+
+    let encoded = token.to_string();
+    assert_ne!(encoded, identity.hashed_id().to_string());
+    assert_eq!(encoded.parse::<SubscriptionToken>().unwrap(), token);
+    assert!(identity
+        .hashed_id()
+        .to_string()
+        .parse::<SubscriptionToken>()
+        .is_err());
+}
+
+#[test]
+fn test_notifications() {
+    let authority = Authority::unique();
+    let mut registry = Registry::new();
+    let sink = MemorySink::new();
+    registry.set_notification_sink(sink.clone());
+
+    let (infected, contact, bystander) =
+        (Identity::unique(), Identity::unique(), Identity::unique());
+    let token = contact.hashed_id().subscription_token();
+    registry.subscribe(token).unwrap();
+    registry
+        .subscribe(bystander.hashed_id().subscription_token())
+        .unwrap();
+    assert!(registry.is_subscribed(&token).unwrap());
+
+    let upload_id = submit(&authority, &mut registry, &infected, &contact);
+    let notifications = sink.take();
+    assert_eq!(notifications.len(), 1);
+    assert_eq!(notifications[0].token, token);
+
+    registry.revoke(&upload_id).unwrap();
+    let notifications = sink.take();
+    assert_eq!(notifications.len(), 1);
+    assert_eq!(notifications[0].token, token);
+
+    assert!(registry.unsubscribe(&token).unwrap());
+    assert!(!registry.unsubscribe(&token).unwrap());
+    submit(&authority, &mut registry, &infected, &contact);
+    assert!(sink.notifications().is_empty());
+}
+
+#[test]
+fn test_notifications_queue_without_sink() {
+    let authority = Authority::unique();
+    let mut registry = Registry::new();
+    let (infected, contact) = (Identity::unique(), Identity::unique());
+    let token = contact.hashed_id().subscription_token();
+    registry.subscribe(token).unwrap();
+    submit(&authority, &mut registry, &infected, &contact);
+    assert_eq!(registry.pending_notifications().unwrap().len(), 1);
+
+    let sink = MemorySink::new();
+    registry.set_notification_sink(sink.clone());
+    registry.flush_notifications().unwrap();
+    assert!(registry.pending_notifications().unwrap().is_empty());
+    assert_eq!(sink.take()[0].token, token);
+}
+
+#[test]
+fn test_failed_deliveries_stay_pending() {
+    let authority = Authority::unique();
+    let mut registry = Registry::new();
+    let mut transport = MockTransport::new();
+    transport.push_error(io::ErrorKind::ConnectionRefused);
+    transport.push_response(500, Vec::new());
+    transport.push_response(200, Vec::new());
+    registry.set_notification_sink(WebhookSink::with_transport(transport, "/notify"));
+
+    let (infected, contact) = (Identity::unique(), Identity::unique());
+    registry
+        .subscribe(contact.hashed_id().subscription_token())
+        .unwrap();
+    submit(&authority, &mut registry, &infected, &contact);
+    assert_eq!(registry.pending_notifications().unwrap().len(), 1);
+
+    match registry.flush_notifications() {
+        Err(DeliveryError::Rejected { status: 500 }) => {}
+        rv => panic!("unexpected result {:?}", rv),
+    }
+    registry.flush_notifications().unwrap();
+    assert!(registry.pending_notifications().unwrap().is_empty());
+
+    assert!(WebhookSink::new("https://127.0.0.1/notify").is_err());
+    assert!(WebhookSink::new("http://127.0.0.1:9000/notify").is_ok());
+}
+
+#[test]
+fn test_background_delivery() {
+    let authority = Authority::unique();
+    let mut registry = Registry::new();
+    let sink = MemorySink::new();
+    let mut transport = MockTransport::new();
+    transport.push_error(io::ErrorKind::ConnectionRefused);
+    let delivered = sink.clone();
+    transport.set_handler(move |request| {
+        let notification = serde_json::from_slice(&request.body).unwrap();
+        delivered.clone().deliver(&notification).unwrap();
+        Response {
+            status: 200,
+            body: Vec::new(),
+        }
+    });
+    registry.set_notification_sink(BackgroundSink::spawn(WebhookSink::with_transport(
+        transport, "/notify",
+    )));
+
+    let (infected, contact) = (Identity::unique(), Identity::unique());
+    registry
+        .subscribe(contact.hashed_id().subscription_token())
+        .unwrap();
+    submit(&authority, &mut registry, &infected, &contact);
+
+    // the first delivery fails and is retried on the background thread, the
+    // notification stays queued until the thread delivered it
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !registry.pending_notifications().unwrap().is_empty() && Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(50));
+        registry.flush_notifications().unwrap();
+    }
+    assert!(registry.pending_notifications().unwrap().is_empty());
+    let notifications = sink.take();
+    assert_eq!(notifications.len(), 1);
+    assert_eq!(
+        notifications[0].token,
+        contact.hashed_id().subscription_token()
+    );
+}
+
+#[test]
+fn test_client_subscribes_one_token_per_request() {
+    let mut transport = MockTransport::new();
+    transport.set_handler(|_| Response {
+        status: 200,
+        body: b"{}".to_vec(),
+    });
+    let mut client = Client::new(transport);
+    let identities = [Identity::unique(), Identity::unique(), Identity::unique()];
+    let hashed_ids: Vec<_> = identities.iter().map(|x| x.hashed_id()).collect();
+    client.subscribe(hashed_ids.iter().copied()).unwrap();
+
+    let mut tokens = Vec::new();
+    for request in client.transport().requests() {
+        let request: SubscriptionRequest = serde_json::from_slice(&request.body).unwrap();
+        assert_eq!(request.tokens.len(), 1);
+        tokens.extend(request.tokens);
+    }
+    assert_eq!(tokens.len(), 3);
+    for hashed_id in &hashed_ids {
+        assert!(tokens.contains(&hashed_id.subscription_token()));
+    }
+}
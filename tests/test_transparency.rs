@@ -0,0 +1,205 @@
+use chrono::Utc;
+use covidcotra::*;
+
+mod common;
+
+use common::{keys, submit};
+
+fn entry(action: LogAction) -> LogEntry {
+    LogEntry {
+        hashed_id: *Identity::unique().hashed_id(),
+        action,
+        at: Utc::now(),
+    }
+}
+
+fn head(log: &TransparencyLog, tree_size: u64) -> TreeHead {
+    TreeHead {
+        tree_size,
+        root_hash: log.root_hash(tree_size).unwrap(),
+        timestamp: Utc::now(),
+    }
+}
+
+#[test]
+fn test_merkle_proofs() {
+    let mut log = TransparencyLog::new();
+    assert!(log.is_empty());
+    assert_eq!(
+        base64::encode(log.root_hash(0).unwrap().as_bytes()),
+        "47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU="
+    );
+
+    for size in 1..=13u64 {
+        log.append(entry(LogAction::Exposed { degree: 1 }));
+        assert_eq!(log.len(), size);
+        let new = head(&log, size);
+        for index in 0..size {
+            let proof = log.inclusion_proof(index, size).unwrap();
+            proof.verify(&log.entries()[index as usize], &new).unwrap();
+        }
+        for old_size in 0..=size {
+            let proof = log.consistency_proof(old_size, size).unwrap();
+            proof.verify(&head(&log, old_size), &new).unwrap();
+        }
+    }
+
+    let new = head(&log, 13);
+    let proof = log.inclusion_proof(5, 13).unwrap();
+    assert_eq!(
+        proof.verify(&entry(LogAction::Infected), &new),
+        Err(TransparencyError::InvalidProof)
+    );
+    assert_eq!(
+        proof.verify(&log.entries()[6], &new),
+        Err(TransparencyError::InvalidProof)
+    );
+    assert_eq!(
+        proof.verify(&log.entries()[5], &head(&log, 12)),
+        Err(TransparencyError::InvalidProof)
+    );
+
+    // a log that rewrote an old entry is not consistent with its old head
+    let old = head(&log, 6);
+    let mut forked = TransparencyLog::new();
+    for (index, logged) in log.entries().iter().enumerate() {
+        forked.append(if index == 2 {
+            entry(LogAction::Revoked)
+        } else {
+            logged.clone()
+        });
+    }
+    let proof = forked.consistency_proof(6, 13).unwrap();
+    assert_eq!(
+        proof.verify(&old, &head(&forked, 13)),
+        Err(TransparencyError::InvalidProof)
+    );
+
+    assert_eq!(
+        log.inclusion_proof(13, 13).unwrap_err(),
+        TransparencyError::OutOfRange { size: 13 }
+    );
+    assert!(log.consistency_proof(3, 14).is_err());
+    assert!(log.consistency_proof(4, 3).is_err());
+}
+
+#[test]
+fn test_registry_log() {
+    let authority = Authority::unique();
+    let mut registry = Registry::new();
+    let (infected, contact) = (Identity::unique(), Identity::unique());
+    let upload_id = submit(&authority, &mut registry, &infected, &contact);
+    assert!(registry.transparency_log().unwrap().is_empty());
+
+    // the entries of another upload are mixed in on the same flush
+    let (other_infected, other_contact) = (Identity::unique(), Identity::unique());
+    submit(&authority, &mut registry, &other_infected, &other_contact);
+    assert_eq!(registry.flush_log().unwrap(), 4);
+    let log = registry.transparency_log().unwrap();
+    assert_eq!(log.len(), 4);
+    let action = |hashed_id: &HashedIdentity| {
+        let entries: Vec<_> = log
+            .entries()
+            .iter()
+            .filter(|entry| entry.hashed_id == *hashed_id)
+            .collect();
+        assert_eq!(entries.len(), 1);
+        // entries only tell the day of the change
+        assert_eq!(entries[0].at.timestamp() % 86400, 0);
+        entries[0].action
+    };
+    assert_eq!(action(infected.hashed_id()), LogAction::Infected);
+    assert_eq!(
+        action(contact.hashed_id()),
+        LogAction::Exposed { degree: 1 }
+    );
+    let old = log.head();
+
+    registry.revoke(&upload_id).unwrap();
+    assert_eq!(registry.flush_log().unwrap(), 2);
+    let log = registry.transparency_log().unwrap();
+    assert_eq!(log.len(), 6);
+    assert!(log.entries()[4..]
+        .iter()
+        .all(|entry| entry.action == LogAction::Revoked));
+    assert!(!serde_json::to_string(log.entries())
+        .unwrap()
+        .contains(&upload_id.to_string()));
+
+    let signed = authority.sign_tree_head(&log.head());
+    let new = signed.verify(authority.signing_public_key()).unwrap();
+    assert_eq!(new.tree_size, 6);
+    assert_eq!(
+        signed.verify(Authority::unique().signing_public_key()),
+        Err(TransparencyError::InvalidSignature)
+    );
+    log.consistency_proof(old.tree_size, new.tree_size)
+        .unwrap()
+        .verify(&old, &new)
+        .unwrap();
+
+    let proven = log
+        .prove_entries(contact.hashed_id(), new.tree_size)
+        .unwrap();
+    assert_eq!(proven.len(), 2);
+    for inclusion in &proven {
+        inclusion.proof.verify(&inclusion.entry, &new).unwrap();
+    }
+
+    let restored: Registry =
+        serde_json::from_str(&serde_json::to_string(&registry).unwrap()).unwrap();
+    assert_eq!(
+        restored.transparency_log().unwrap().root_hash(6).unwrap(),
+        new.root_hash
+    );
+}
+
+#[test]
+fn test_client_transparency() {
+    let authority = Authority::unique();
+    let mut registry = Registry::new();
+    let (infected, contact) = (Identity::unique(), Identity::unique());
+    submit(&authority, &mut registry, &infected, &contact);
+    registry.flush_log().unwrap();
+    let log = registry.transparency_log().unwrap();
+    let mut client = Client::with_keys(MockTransport::new(), keys(&authority));
+
+    client
+        .transport_mut()
+        .push_json(&authority.sign_tree_head(&log.head()));
+    let head = client.tree_head().unwrap();
+    assert_eq!(head.tree_size, 2);
+
+    client
+        .transport_mut()
+        .push_json(&log.prove_entries(contact.hashed_id(), 2).unwrap());
+    let entries = client.log_entries(contact.hashed_id(), &head).unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].action, LogAction::Exposed { degree: 1 });
+
+    // entries of another hashed identity are refused
+    client
+        .transport_mut()
+        .push_json(&log.prove_entries(infected.hashed_id(), 2).unwrap());
+    match client.log_entries(contact.hashed_id(), &head) {
+        Err(ClientError::Transparency(TransparencyError::InvalidProof)) => {}
+        rv => panic!("unexpected result {:?}", rv),
+    }
+
+    client
+        .transport_mut()
+        .push_json(&log.consistency_proof(1, 2).unwrap());
+    let old = TreeHead {
+        tree_size: 1,
+        root_hash: log.root_hash(1).unwrap(),
+        timestamp: Utc::now(),
+    };
+    client.verify_consistency(&old, &head).unwrap();
+    let request = client.transport().requests().last().unwrap();
+    assert_eq!(request.path, "/transparency/consistency?from=1&to=2");
+
+    client
+        .transport_mut()
+        .push_json(&Authority::unique().sign_tree_head(&log.head()));
+    assert!(client.tree_head().is_err());
+}
@@ -0,0 +1,77 @@
+use covidcotra::*;
+
+#[test]
+fn test_upload_roundtrip() {
+    let authority = Authority::unique();
+    let user_1 = Identity::unique();
+    let user_2 = Identity::unique();
+
+    let mut log = ContactLog::new();
+    log.add(&user_2.new_share_id(authority.public_key()));
+
+    let bundle = UploadBundle::new(std::slice::from_ref(&user_1), &log);
+    let sealed = bundle.seal(authority.public_key()).unwrap();
+
+    let sealed: SealedUploadBundle = sealed.to_string().parse().unwrap();
+    let opened = sealed.open(authority.secret_key()).unwrap();
+    assert_eq!(opened.created(), bundle.created());
+    assert_eq!(opened.unique_ids(), &[user_1.unique_id().clone()][..]);
+
+    let contacts = opened.contacts().decode(authority.secret_key()).unwrap();
+    assert_eq!(&contacts[0].0, user_2.unique_id());
+}
+
+#[test]
+fn test_upload_errors() {
+    let authority = Authority::unique();
+    let other_authority = Authority::unique();
+    let user = Identity::unique();
+
+    let sealed = UploadBundle::new(&[user], &ContactLog::new())
+        .seal(authority.public_key())
+        .unwrap();
+    assert_eq!(
+        sealed.open(other_authority.secret_key()).err(),
+        Some(UploadError::Decryption)
+    );
+
+    let mut log = ContactLog::new();
+    let other = Identity::unique();
+    for _ in 0..10_000 {
+        log.add(&other.new_share_id(authority.public_key()));
+    }
+    match UploadBundle::new(&[], &log).seal(authority.public_key()) {
+        Err(UploadError::TooLarge { max, .. }) => assert_eq!(max, MAX_UPLOAD_SIZE),
+        _ => panic!("expected upload to be rejected"),
+    }
+}
+
+#[test]
+fn test_decoy_uploads() {
+    let authority = Authority::unique();
+    let mut registry = Registry::new();
+
+    let user = Identity::unique();
+    let real = UploadBundle::new(&[user], &ContactLog::new())
+        .seal(authority.public_key())
+        .unwrap();
+    let decoy = UploadBundle::decoy(authority.public_key(), 0);
+    assert!(decoy.is_decoy());
+    let decoy = decoy.seal(authority.public_key()).unwrap();
+    // contents are padded so small bundles cannot be told apart by size
+    assert_eq!(real.len(), decoy.len());
+
+    let opened = decoy.open(authority.secret_key()).unwrap();
+    assert!(opened.is_decoy());
+    assert!(!opened.unique_ids().is_empty());
+
+    // decoys are accepted without a valid code and leave no trace
+    let code = Authority::unique()
+        .issue_code(chrono::Duration::hours(1))
+        .unwrap();
+    registry.submit(&authority, &code, &decoy).unwrap();
+    registry.submit_exposed(&authority, &decoy).unwrap();
+    assert!(!registry.is_redeemed(&code.id()).unwrap());
+    assert!(registry.taint_list().unwrap().is_empty());
+    assert!(registry.store().uploads().unwrap().is_empty());
+}
@@ -0,0 +1,85 @@
+use covidcotra::*;
+
+fn sample_wallet() -> Wallet {
+    let authority = Authority::unique();
+    let mut wallet = Wallet::new();
+    wallet.add_identity(Identity::unique());
+    wallet
+        .contacts_mut()
+        .add(&Identity::unique().new_share_id(authority.public_key()));
+    wallet
+}
+
+#[test]
+fn test_passphrase_roundtrip() {
+    let wallet = sample_wallet();
+    let key = WalletKey::from_passphrase("correct horse battery staple");
+    assert_eq!(format!("{:?}", key), "Passphrase(****)");
+    let sealed = wallet.seal(&key).unwrap();
+    let unique_id = serde_json::to_string(wallet.current_identity().unwrap().unique_id()).unwrap();
+    let unique_id = unique_id.trim_matches('"');
+    assert!(!String::from_utf8_lossy(&sealed).contains(unique_id));
+
+    let opened = Wallet::open(&sealed, &key).unwrap();
+    assert_eq!(
+        opened.current_identity().unwrap().hashed_id(),
+        wallet.current_identity().unwrap().hashed_id()
+    );
+    assert_eq!(
+        serde_json::to_string(opened.contacts()).unwrap(),
+        serde_json::to_string(wallet.contacts()).unwrap()
+    );
+
+    match Wallet::open(&sealed, &WalletKey::from_passphrase("wrong")) {
+        Err(WalletError::WrongKey) => {}
+        rv => panic!("unexpected result {:?}", rv.err()),
+    }
+}
+
+#[test]
+fn test_device_key() {
+    let wallet = sample_wallet();
+    let device_key = DeviceKey::unique();
+    let restored = DeviceKey::from_slice(device_key.as_bytes()).unwrap();
+    let sealed = wallet.seal(&WalletKey::Device(device_key)).unwrap();
+    assert!(Wallet::open(&sealed, &WalletKey::Device(restored)).is_ok());
+    match Wallet::open(&sealed, &WalletKey::Device(DeviceKey::unique())) {
+        Err(WalletError::WrongKey) => {}
+        rv => panic!("unexpected result {:?}", rv.err()),
+    }
+}
+
+#[test]
+fn test_corruption() {
+    let key = WalletKey::Device(DeviceKey::unique());
+    let mut sealed = sample_wallet().seal(&key).unwrap();
+    let len = sealed.len();
+    sealed[len - 1] ^= 1;
+    match Wallet::open(&sealed, &key) {
+        Err(WalletError::Corrupted) => {}
+        rv => panic!("unexpected result {:?}", rv.err()),
+    }
+    match Wallet::open(&sealed[..10], &key) {
+        Err(WalletError::Corrupted) => {}
+        rv => panic!("unexpected result {:?}", rv.err()),
+    }
+    match Wallet::open(b"{}", &key) {
+        Err(WalletError::Envelope(..)) => {}
+        rv => panic!("unexpected result {:?}", rv.err()),
+    }
+}
+
+#[test]
+fn test_save_and_load() {
+    let path = std::env::temp_dir().join(format!("covidcotra-{}.wallet", std::process::id()));
+    let key = WalletKey::Device(DeviceKey::unique());
+    let wallet = sample_wallet();
+    wallet.save(&path, &key).unwrap();
+    wallet.save(&path, &key).unwrap();
+    let loaded = Wallet::load(&path, &key).unwrap();
+    assert_eq!(loaded.identities().len(), 1);
+    let mut tmp_path = path.clone().into_os_string();
+    tmp_path.push(".tmp");
+    assert!(std::fs::metadata(&tmp_path).is_err());
+    std::fs::remove_file(&path).unwrap();
+}